@@ -0,0 +1,99 @@
+use enet_sys as enet;
+
+use core::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
+
+use crate::error::{Error, Result};
+
+/// An IP address and port. The C implementation only speaks IPv4 and refuses IPv6 addresses with
+/// [`Error::UnsupportedAddress`], the pure one handles both.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Address(SocketAddr);
+
+impl Address {
+    pub fn new(ip: impl Into<IpAddr>, port: u16) -> Self {
+        Address(SocketAddr::new(ip.into(), port))
+    }
+
+    /// An address that binds to all IPv4 interfaces.
+    pub fn any(port: u16) -> Self {
+        Address::new(Ipv4Addr::UNSPECIFIED, port)
+    }
+
+    /// Parses `host` as an IP address, falling back to looking it up as a hostname. IPv4 results
+    /// are preferred as both implementations can use them.
+    pub fn resolve(host: &str, port: u16) -> Result<Self> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(Address::new(ip, port));
+        }
+        let addresses = (host, port)
+            .to_socket_addrs()
+            .map_err(|_| Error::Resolve)?
+            .collect::<Vec<_>>();
+        addresses
+            .iter()
+            .find(|address| address.is_ipv4())
+            .or_else(|| addresses.first())
+            .map(|&address| Address(address))
+            .ok_or(Error::Resolve)
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.0.ip()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.0.port()
+    }
+
+    pub(crate) fn to_raw(self) -> Result<enet::ENetAddress> {
+        match self.0 {
+            // enet expects the host in network byte order
+            SocketAddr::V4(addr) => Ok(enet::ENetAddress {
+                host: u32::from_ne_bytes(addr.ip().octets()),
+                port: addr.port(),
+            }),
+            SocketAddr::V6(_) => Err(Error::UnsupportedAddress),
+        }
+    }
+
+    pub(crate) fn from_raw(raw: enet::ENetAddress) -> Self {
+        Address::new(Ipv4Addr::from(raw.host.to_ne_bytes()), raw.port)
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(addr: SocketAddr) -> Self {
+        Address(addr)
+    }
+}
+
+impl From<SocketAddrV4> for Address {
+    fn from(addr: SocketAddrV4) -> Self {
+        Address(addr.into())
+    }
+}
+
+impl From<SocketAddrV6> for Address {
+    fn from(addr: SocketAddrV6) -> Self {
+        Address(addr.into())
+    }
+}
+
+impl From<Address> for SocketAddr {
+    fn from(addr: Address) -> Self {
+        addr.0
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, fmt)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, fmt)
+    }
+}
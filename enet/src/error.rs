@@ -0,0 +1,38 @@
+use core::fmt;
+use std::error;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    Initialization,
+    HostCreation,
+    Service,
+    PacketCreation,
+    Connect,
+    Send,
+    /// The peer this handle pointed to has disconnected and its slot might've been reused already.
+    PeerDisconnected,
+    /// A hostname couldn't be resolved to an address.
+    Resolve,
+    /// The address family isn't supported by this implementation.
+    UnsupportedAddress,
+}
+
+impl error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            Error::Initialization => "failed to initialize enet",
+            Error::HostCreation => "failed to create the enet host",
+            Error::Service => "failed to service the enet host",
+            Error::PacketCreation => "failed to allocate a packet",
+            Error::Connect => "failed to initiate a connection",
+            Error::Send => "failed to queue a packet for sending",
+            Error::PeerDisconnected => "the peer is no longer connected",
+            Error::Resolve => "failed to resolve the hostname",
+            Error::UnsupportedAddress => "IPv6 addresses are not supported by the C implementation",
+        })
+    }
+}
@@ -0,0 +1,173 @@
+use enet_sys as enet;
+
+use core::{fmt, marker::PhantomData, mem, ptr, ptr::NonNull};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{
+    address::Address,
+    error::{Error, Result},
+    packet::Packet,
+    peer::{self, PeerHandle},
+};
+
+pub(crate) struct RawHost(pub(crate) NonNull<enet::ENetHost>);
+
+// the raw host is only ever accessed through the mutex in `HostInner`
+unsafe impl Send for RawHost {}
+
+/// The part of a host that is shared between the host itself and all of its peer handles.
+pub(crate) struct HostInner<T> {
+    raw: Mutex<RawHost>,
+    _data: PhantomData<T>,
+}
+
+// the user data is only ever accessed while holding the lock, which makes this behave like a Mutex<T>
+unsafe impl<T: Send> Send for HostInner<T> {}
+unsafe impl<T: Send> Sync for HostInner<T> {}
+
+impl<T> HostInner<T> {
+    pub(crate) fn lock(&self) -> MutexGuard<'_, RawHost> {
+        // a panic while holding the lock can't leave enet in a broken state since we never call back
+        // into user code while holding it, so just ignore the poisoning
+        self.raw.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> Drop for HostInner<T> {
+    fn drop(&mut self) {
+        let raw = self.raw.get_mut().unwrap_or_else(|e| e.into_inner());
+        unsafe {
+            let host = raw.0.as_ptr();
+            for i in 0..(*host).peerCount {
+                peer::take_data::<T>((*host).peers.add(i));
+            }
+            enet::enet_host_destroy(host);
+        }
+    }
+}
+
+/// Something that happened on a [`Host`].
+#[derive(Debug)]
+pub enum Event<T> {
+    /// A new peer connected, its user data is always `None` at this point.
+    Connect(PeerHandle<T>),
+    /// A peer disconnected or timed out. The handle is no longer valid at this point, so the user
+    /// data it had attached is handed out here instead.
+    Disconnect {
+        peer: PeerHandle<T>,
+        data: Option<T>,
+        reason: u32,
+    },
+    /// A packet has been received from a peer.
+    Receive {
+        peer: PeerHandle<T>,
+        channel_id: u8,
+        packet: Packet,
+    },
+}
+
+/// An enet host that owns all of its peers. Every peer can carry a piece of user data of type `T`
+/// which is dropped together with the peer.
+pub struct Host<T> {
+    inner: Arc<HostInner<T>>,
+}
+
+impl<T> Host<T> {
+    /// Creates a new host, binding it to `address` if given. A host without an address can only be
+    /// used to connect to other hosts.
+    pub fn new(address: Option<Address>, peer_count: usize) -> Result<Self> {
+        crate::initialize()?;
+        let address = address.map(Address::to_raw).transpose()?;
+        let raw = unsafe {
+            enet::enet_host_create(
+                address
+                    .as_ref()
+                    .map_or(ptr::null(), |address| address as *const _),
+                peer_count,
+                0,
+                0,
+            )
+        };
+        let raw = NonNull::new(raw).ok_or(Error::HostCreation)?;
+        Ok(Host {
+            inner: Arc::new(HostInner {
+                raw: Mutex::new(RawHost(raw)),
+                _data: PhantomData,
+            }),
+        })
+    }
+
+    pub fn address(&self) -> Address {
+        Address::from_raw(unsafe { (*self.inner.lock().0.as_ptr()).address })
+    }
+
+    /// Sends all queued packets and dispatches incoming ones, returning the first event that
+    /// happened. Waits up to `timeout` milliseconds for an event to occur.
+    pub fn service(&mut self, timeout: u32) -> Result<Option<Event<T>>> {
+        let mut event: enet::ENetEvent = unsafe { mem::zeroed() };
+        let result = {
+            let raw = self.inner.lock();
+            unsafe { enet::enet_host_service(raw.0.as_ptr(), &mut event, timeout) }
+        };
+        if result < 0 {
+            return Err(Error::Service);
+        }
+        let peer = match NonNull::new(event.peer) {
+            Some(peer) => peer,
+            None => return Ok(None),
+        };
+        Ok(match event.type_ {
+            enet::_ENetEventType_ENET_EVENT_TYPE_CONNECT => {
+                // the slot might still carry data from a previous connection
+                drop(unsafe { peer::take_data::<T>(peer.as_ptr()) });
+                Some(Event::Connect(PeerHandle::new(self.inner.clone(), peer)))
+            },
+            enet::_ENetEventType_ENET_EVENT_TYPE_DISCONNECT => {
+                // enet resets the peer before handing out the event, which doesn't clear the data
+                let data = unsafe { peer::take_data::<T>(peer.as_ptr()) };
+                Some(Event::Disconnect {
+                    peer: PeerHandle::new(self.inner.clone(), peer),
+                    data,
+                    reason: event.data,
+                })
+            },
+            enet::_ENetEventType_ENET_EVENT_TYPE_RECEIVE => {
+                NonNull::new(event.packet).map(|packet| {
+                    Event::Receive {
+                        peer: PeerHandle::new(self.inner.clone(), peer),
+                        channel_id: event.channelID,
+                        // enet gives up its reference on the packet when handing it out
+                        packet: unsafe { Packet::from_raw(packet) },
+                    }
+                })
+            },
+            _ => None,
+        })
+    }
+
+    /// Sends all queued packets without receiving anything.
+    pub fn flush(&mut self) {
+        let raw = self.inner.lock();
+        unsafe { enet::enet_host_flush(raw.0.as_ptr()) };
+    }
+
+    /// Initiates a connection to `address`, the connection is established once the returned peer
+    /// shows up in an [`Event::Connect`]. Any data attached to the peer before that is discarded.
+    pub fn connect(&mut self, address: Address, channel_count: usize) -> Result<PeerHandle<T>> {
+        let address = address.to_raw()?;
+        let peer = {
+            let raw = self.inner.lock();
+            unsafe { enet::enet_host_connect(raw.0.as_ptr(), &address, channel_count) }
+        };
+        let peer = NonNull::new(peer).ok_or(Error::Connect)?;
+        Ok(PeerHandle::new(self.inner.clone(), peer))
+    }
+}
+
+impl<T> fmt::Debug for Host<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Host")
+            .field("address", &self.address())
+            .finish()
+    }
+}
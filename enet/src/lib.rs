@@ -0,0 +1,37 @@
+//! Safe wrapper around the LENet bindings found in `enet-sys`.
+//!
+//! All raw pointers are owned by the types in here, everything outside of this crate should be
+//! able to talk to peers without ever touching `unsafe`.
+#![deny(bare_trait_objects, missing_debug_implementations)]
+
+mod address;
+mod error;
+mod host;
+mod packet;
+mod peer;
+#[cfg(feature = "pure")]
+pub mod pure;
+
+pub use self::{
+    address::Address,
+    error::{Error, Result},
+    host::{Event, Host},
+    packet::{Packet, PacketMode},
+    peer::{PeerHandle, PeerStats},
+};
+
+use std::sync::Once;
+
+static INIT: Once = Once::new();
+
+/// Initializes enet globally, this only does something on the first call. There is no need to
+/// call this manually as creating a [`Host`] already does so.
+pub(crate) fn initialize() -> Result<()> {
+    let mut result = Ok(());
+    INIT.call_once(|| {
+        if unsafe { enet_sys::enet_initialize() } < 0 {
+            result = Err(Error::Initialization);
+        }
+    });
+    result
+}
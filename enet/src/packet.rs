@@ -0,0 +1,111 @@
+use enet_sys as enet;
+
+use core::{fmt, mem, ops, ptr::NonNull, slice};
+
+use crate::error::{Error, Result};
+
+/// How enet should deliver a packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PacketMode {
+    /// Resent until acknowledged and delivered in order.
+    Reliable,
+    /// Delivered in order, but might get dropped along the way.
+    Unreliable,
+    /// Neither ordered nor guaranteed to arrive.
+    Unsequenced,
+}
+
+impl PacketMode {
+    fn flags(self) -> enet::_ENetPacketFlag {
+        match self {
+            PacketMode::Reliable => enet::_ENetPacketFlag_ENET_PACKET_FLAG_RELIABLE,
+            PacketMode::Unreliable => 0,
+            PacketMode::Unsequenced => enet::_ENetPacketFlag_ENET_PACKET_FLAG_UNSEQUENCED,
+        }
+    }
+
+    fn from_flags(flags: enet::_ENetPacketFlag) -> Self {
+        if flags & enet::_ENetPacketFlag_ENET_PACKET_FLAG_RELIABLE != 0 {
+            PacketMode::Reliable
+        } else if flags & enet::_ENetPacketFlag_ENET_PACKET_FLAG_UNSEQUENCED != 0 {
+            PacketMode::Unsequenced
+        } else {
+            PacketMode::Unreliable
+        }
+    }
+}
+
+/// An owned enet packet. The packet is destroyed on drop unless it has been handed over to enet
+/// by sending it.
+pub struct Packet(NonNull<enet::ENetPacket>);
+
+impl Packet {
+    /// Allocates a new packet and copies `data` into it.
+    pub fn new(data: &[u8], mode: PacketMode) -> Result<Self> {
+        let raw = unsafe { enet::enet_packet_create(data.as_ptr(), data.len(), mode.flags()) };
+        NonNull::new(raw).map(Packet).ok_or(Error::PacketCreation)
+    }
+
+    /// Takes ownership of a packet enet handed out to us.
+    ///
+    /// # Safety
+    ///
+    /// The packet must not be referenced by enet anymore, that is its reference count has to be 0.
+    pub(crate) unsafe fn from_raw(raw: NonNull<enet::ENetPacket>) -> Self {
+        Packet(raw)
+    }
+
+    /// Gives up ownership of the packet, the caller is responsible for destroying it.
+    pub(crate) fn into_raw(self) -> NonNull<enet::ENetPacket> {
+        let raw = self.0;
+        mem::forget(self);
+        raw
+    }
+
+    pub fn mode(&self) -> PacketMode {
+        PacketMode::from_flags(unsafe { self.0.as_ref().flags })
+    }
+}
+
+impl ops::Deref for Packet {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe {
+            let packet = self.0.as_ref();
+            slice::from_raw_parts(packet.data, packet.dataLength)
+        }
+    }
+}
+
+impl ops::DerefMut for Packet {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe {
+            let packet = self.0.as_mut();
+            slice::from_raw_parts_mut(packet.data, packet.dataLength)
+        }
+    }
+}
+
+impl Drop for Packet {
+    fn drop(&mut self) {
+        unsafe {
+            if self.0.as_ref().referenceCount == 0 {
+                enet::enet_packet_destroy(self.0.as_ptr());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Packet {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Packet")
+            .field("mode", &self.mode())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+// we are the sole owner of the packet, enet itself doesn't touch it until we hand it back
+unsafe impl Send for Packet {}
+unsafe impl Sync for Packet {}
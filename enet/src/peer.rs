@@ -0,0 +1,196 @@
+use enet_sys as enet;
+
+use core::{fmt, ptr, ptr::NonNull};
+use std::sync::Arc;
+
+use crate::{
+    address::Address,
+    error::{Error, Result},
+    host::HostInner,
+    packet::Packet,
+};
+
+/// Takes the user data out of a peer, leaving a null pointer behind.
+///
+/// # Safety
+///
+/// The data pointer of the peer has to be either null or point to a `Box<T>` created by this
+/// crate. The host owning the peer has to be locked.
+pub(crate) unsafe fn take_data<T>(peer: *mut enet::ENetPeer) -> Option<T> {
+    let data = (*peer).data as *mut T;
+    (*peer).data = ptr::null_mut();
+    if data.is_null() {
+        None
+    } else {
+        Some(*Box::from_raw(data))
+    }
+}
+
+/// Connection quality of a peer as measured by enet.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PeerStats {
+    /// Mean round trip time in milliseconds.
+    pub round_trip_time: u32,
+    /// Mean deviation of the round trip time in milliseconds.
+    pub round_trip_time_variance: u32,
+    /// Fraction of reliable packets that had to be resent, between 0 and 1.
+    pub packet_loss: f32,
+}
+
+/// A handle to a peer of a [`Host`](crate::Host).
+///
+/// Enet reuses peer slots for new connections, so a handle remembers the connection it was created
+/// for and becomes invalid once that connection is gone. All operations on an invalid handle fail
+/// with [`Error::PeerDisconnected`] or do nothing.
+pub struct PeerHandle<T> {
+    host: Arc<HostInner<T>>,
+    peer: NonNull<enet::ENetPeer>,
+    session_id: u32,
+}
+
+impl<T> PeerHandle<T> {
+    pub(crate) fn new(host: Arc<HostInner<T>>, peer: NonNull<enet::ENetPeer>) -> Self {
+        let session_id = {
+            let _raw = host.lock();
+            unsafe { peer.as_ref().sessionID }
+        };
+        PeerHandle {
+            host,
+            peer,
+            session_id,
+        }
+    }
+
+    /// Locks the host and runs `f` on the raw peer if this handle is still valid.
+    fn with_peer<R>(&self, f: impl FnOnce(*mut enet::ENetPeer) -> R) -> Result<R> {
+        let _raw = self.host.lock();
+        let peer = self.peer.as_ptr();
+        let valid = unsafe {
+            (*peer).state != enet::_ENetPeerState_ENET_PEER_STATE_DISCONNECTED
+                && (*peer).sessionID == self.session_id
+        };
+        if valid {
+            Ok(f(peer))
+        } else {
+            Err(Error::PeerDisconnected)
+        }
+    }
+
+    /// Whether the connection this handle was created for is still alive.
+    pub fn is_connected(&self) -> bool {
+        self.with_peer(|_| ()).is_ok()
+    }
+
+    /// The index of this peer in its host.
+    pub fn id(&self) -> u16 {
+        let _raw = self.host.lock();
+        unsafe { self.peer.as_ref().incomingPeerID }
+    }
+
+    pub fn address(&self) -> Result<Address> {
+        self.with_peer(|peer| Address::from_raw(unsafe { (*peer).address }))
+    }
+
+    pub fn stats(&self) -> Result<PeerStats> {
+        self.with_peer(|peer| unsafe {
+            PeerStats {
+                round_trip_time: (*peer).roundTripTime,
+                round_trip_time_variance: (*peer).roundTripTimeVariance,
+                packet_loss: (*peer).packetLoss as f32 / enet::ENET_PEER_PACKET_LOSS_SCALE as f32,
+            }
+        })
+    }
+
+    /// Queues a packet to be sent on the given channel.
+    pub fn send(&self, channel_id: u8, packet: Packet) -> Result<()> {
+        self.with_peer(|peer| {
+            let packet = packet.into_raw();
+            if unsafe { enet::enet_peer_send(peer, channel_id, packet.as_ptr()) } < 0 {
+                // enet didn't take a reference so we still own it
+                drop(unsafe { Packet::from_raw(packet) });
+                Err(Error::Send)
+            } else {
+                Ok(())
+            }
+        })?
+    }
+
+    /// Requests a disconnection, the peer will show up in an [`Event::Disconnect`](crate::Event)
+    /// once the remote acknowledged it.
+    pub fn disconnect(&self, data: u32) {
+        let _ = self.with_peer(|peer| unsafe { enet::enet_peer_disconnect(peer, data) });
+    }
+
+    /// Requests a disconnection once all queued packets have been sent.
+    pub fn disconnect_later(&self, data: u32) {
+        let _ = self.with_peer(|peer| unsafe { enet::enet_peer_disconnect_later(peer, data) });
+    }
+
+    /// Disconnects the peer immediately without waiting for the remote to acknowledge it. No
+    /// disconnect event is generated for this, so the attached data is returned instead.
+    pub fn disconnect_now(&self, data: u32) -> Option<T> {
+        self.with_peer(|peer| unsafe {
+            enet::enet_peer_disconnect_now(peer, data);
+            take_data(peer)
+        })
+        .ok()
+        .and_then(|data| data)
+    }
+
+    /// Attaches user data to the peer, dropping the previously attached data.
+    pub fn set_data(&self, data: Option<T>) -> Result<()> {
+        self.with_peer(|peer| unsafe {
+            drop(take_data::<T>(peer));
+            if let Some(data) = data {
+                (*peer).data = Box::into_raw(Box::new(data)) as *mut _;
+            }
+        })
+    }
+
+    pub fn data(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.with_peer(|peer| unsafe {
+            let data = (*peer).data as *const T;
+            if data.is_null() {
+                None
+            } else {
+                Some((*data).clone())
+            }
+        })
+        .ok()
+        .and_then(|data| data)
+    }
+}
+
+impl<T> Clone for PeerHandle<T> {
+    fn clone(&self) -> Self {
+        PeerHandle {
+            host: self.host.clone(),
+            peer: self.peer,
+            session_id: self.session_id,
+        }
+    }
+}
+
+impl<T> PartialEq for PeerHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.peer == other.peer && self.session_id == other.session_id
+    }
+}
+
+impl<T> Eq for PeerHandle<T> {}
+
+impl<T> fmt::Debug for PeerHandle<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("PeerHandle")
+            .field("id", &self.id())
+            .field("session_id", &self.session_id)
+            .finish()
+    }
+}
+
+// the peer is only ever accessed while holding the lock of its host
+unsafe impl<T: Send> Send for PeerHandle<T> {}
+unsafe impl<T: Send> Sync for PeerHandle<T> {}
@@ -0,0 +1,1310 @@
+use core::fmt;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use super::{
+    packet::Packet,
+    peer::{CommandError, DispatchQueue, OutgoingCommand, Peer, PeerHandle, PeerState},
+    protocol::{self, Command, CommandBody, ConnectParams, Header},
+    *,
+};
+
+type CommandResult = core::result::Result<(), CommandError>;
+
+/// Something that happened on a [`Host`].
+#[derive(Debug)]
+pub enum Event<T> {
+    /// A new peer connected, its user data is always `None` at this point.
+    Connect(PeerHandle<T>),
+    /// A peer disconnected or timed out. The handle is no longer valid at this point, so the user
+    /// data it had attached is handed out here instead.
+    Disconnect {
+        peer: PeerHandle<T>,
+        data: Option<T>,
+        reason: u32,
+    },
+    /// A packet has been received from a peer.
+    Receive {
+        peer: PeerHandle<T>,
+        channel_id: u8,
+        packet: Packet,
+    },
+}
+
+/// An event that still refers to its peer by index, turned into an [`Event`] once the host has
+/// been unlocked.
+enum RawEvent<T> {
+    Connect {
+        index: usize,
+        generation: u32,
+    },
+    Disconnect {
+        index: usize,
+        generation: u32,
+        data: Option<T>,
+        reason: u32,
+    },
+    Receive {
+        index: usize,
+        generation: u32,
+        channel_id: u8,
+        packet: Packet,
+    },
+}
+
+/// The datagram that is currently being assembled for a peer.
+struct Datagram {
+    header_flags: u16,
+    command_count: usize,
+    buffer_count: usize,
+    packet_size: usize,
+    /// Everything following the header.
+    data: Vec<u8>,
+    /// Set if there was more to send than fit into this datagram.
+    full: bool,
+}
+
+impl Datagram {
+    fn new() -> Self {
+        Datagram {
+            header_flags: 0,
+            command_count: 0,
+            buffer_count: 1,
+            packet_size: protocol::HEADER_SIZE,
+            data: Vec::new(),
+            full: false,
+        }
+    }
+
+    fn push(&mut self, command: &Command, payload: Option<&[u8]>) {
+        command.write(&mut self.data);
+        self.packet_size += command.size();
+        if let Some(payload) = payload {
+            self.data.extend_from_slice(payload);
+            self.packet_size += payload.len();
+            self.buffer_count += 1;
+        }
+        self.command_count += 1;
+        self.buffer_count += 1;
+    }
+}
+
+pub(crate) struct HostState<T> {
+    socket: UdpSocket,
+    address: Address,
+    pub(crate) peers: Vec<Peer<T>>,
+    dispatch_queue: DispatchQueue,
+    mtu: u16,
+    channel_limit: usize,
+    incoming_bandwidth: u32,
+    outgoing_bandwidth: u32,
+    service_time: u32,
+    epoch: Instant,
+    continue_sending: bool,
+    random: RandomState,
+    random_counter: u64,
+}
+
+impl<T> HostState<T> {
+    fn time_get(&self) -> u32 {
+        // 0 is used as "unset" for several timestamps, so never hand it out
+        (self.epoch.elapsed().as_millis() as u32).wrapping_add(1)
+    }
+
+    fn random(&mut self) -> u32 {
+        self.random_counter += 1;
+        let mut hasher = self.random.build_hasher();
+        hasher.write_u64(self.random_counter);
+        hasher.write_u32(self.time_get());
+        hasher.finish() as u32
+    }
+
+    fn connect(&mut self, address: Address, channel_count: usize) -> Option<usize> {
+        let channel_count = channel_count.clamp(
+            protocol::MINIMUM_CHANNEL_COUNT,
+            protocol::MAXIMUM_CHANNEL_COUNT,
+        );
+        let index = self
+            .peers
+            .iter()
+            .position(|peer| peer.state == PeerState::Disconnected)?;
+        let session_id = self.random();
+        let window_size = clamp_window_size(if self.outgoing_bandwidth == 0 {
+            protocol::MAXIMUM_WINDOW_SIZE
+        } else {
+            (self.outgoing_bandwidth / PEER_WINDOW_SIZE_SCALE) * protocol::MINIMUM_WINDOW_SIZE
+        });
+
+        let peer = &mut self.peers[index];
+        peer.setup_channels(channel_count);
+        peer.state = PeerState::Connecting;
+        peer.address = address;
+        peer.session_id = session_id;
+        peer.window_size = window_size;
+        let command = Command::new(
+            protocol::COMMAND_FLAG_ACKNOWLEDGE,
+            0xFF,
+            CommandBody::Connect {
+                params: ConnectParams {
+                    outgoing_peer_id: peer.incoming_peer_id,
+                    mtu: peer.mtu,
+                    window_size: peer.window_size,
+                    channel_count: channel_count as u32,
+                    incoming_bandwidth: self.incoming_bandwidth,
+                    outgoing_bandwidth: self.outgoing_bandwidth,
+                    packet_throttle_interval: peer.packet_throttle_interval,
+                    packet_throttle_acceleration: peer.packet_throttle_acceleration,
+                    packet_throttle_deceleration: peer.packet_throttle_deceleration,
+                },
+                session_id,
+            },
+        );
+        peer.queue_outgoing_command(command, None, 0, 0);
+        Some(index)
+    }
+
+    pub(crate) fn disconnect(&mut self, index: usize, data: u32) {
+        if self.peers[index].disconnect(data) {
+            self.flush();
+            self.peers[index].reset(self.mtu);
+        }
+    }
+
+    pub(crate) fn disconnect_later(&mut self, index: usize, data: u32) {
+        let peer = &mut self.peers[index];
+        if (peer.state == PeerState::Connected || peer.state == PeerState::DisconnectLater)
+            && peer.has_pending_outgoing()
+        {
+            peer.state = PeerState::DisconnectLater;
+            peer.disconnect_data = data;
+        } else {
+            self.disconnect(index, data);
+        }
+    }
+
+    pub(crate) fn disconnect_now(&mut self, index: usize, data: u32) -> Option<T> {
+        let peer = &mut self.peers[index];
+        if peer.state == PeerState::Disconnected {
+            return None;
+        }
+        if peer.state != PeerState::Zombie && peer.state != PeerState::Disconnecting {
+            peer.reset_queues();
+            let command = Command::new(
+                protocol::COMMAND_FLAG_UNSEQUENCED,
+                0xFF,
+                CommandBody::Disconnect { data },
+            );
+            peer.queue_outgoing_command(command, None, 0, 0);
+            self.flush();
+        }
+        let peer = &mut self.peers[index];
+        let data = peer.data.take();
+        peer.reset(self.mtu);
+        data
+    }
+
+    fn flush(&mut self) {
+        self.service_time = self.time_get();
+        // just like the C implementation errors are dropped here, the next service call will
+        // report them
+        let _ = self.send_outgoing_commands(false);
+    }
+
+    fn dispatch_incoming_commands(&mut self) -> Option<RawEvent<T>> {
+        while let Some(index) = self.dispatch_queue.pop(&mut self.peers) {
+            let peer = &mut self.peers[index];
+            match peer.state {
+                PeerState::ConnectionPending | PeerState::ConnectionSucceeded => {
+                    peer.state = PeerState::Connected;
+                    // mirror the C host, which never hands out data attached before the connect
+                    peer.data = None;
+                    return Some(RawEvent::Connect {
+                        index,
+                        generation: peer.generation,
+                    });
+                },
+                PeerState::Zombie => {
+                    let event = RawEvent::Disconnect {
+                        index,
+                        generation: peer.generation,
+                        data: peer.data.take(),
+                        reason: peer.disconnect_data,
+                    };
+                    peer.reset(self.mtu);
+                    return Some(event);
+                },
+                PeerState::Connected => {
+                    if let Some((channel_id, packet)) = peer.receive() {
+                        if !peer.dispatched_commands.is_empty() {
+                            self.dispatch_queue.push(peer);
+                        }
+                        return Some(RawEvent::Receive {
+                            index,
+                            generation: peer.generation,
+                            channel_id,
+                            packet,
+                        });
+                    }
+                },
+                _ => (),
+            }
+        }
+        None
+    }
+
+    fn service(&mut self, timeout: u32) -> Result<Option<RawEvent<T>>> {
+        if let Some(event) = self.dispatch_incoming_commands() {
+            return Ok(Some(event));
+        }
+        self.service_time = self.time_get();
+        let deadline = self.service_time.wrapping_add(timeout);
+        loop {
+            self.send_outgoing_commands(true)
+                .map_err(|_| Error::Service)?;
+            self.receive_incoming_commands()?;
+            self.send_outgoing_commands(true)
+                .map_err(|_| Error::Service)?;
+            if let Some(event) = self.dispatch_incoming_commands() {
+                return Ok(Some(event));
+            }
+
+            self.service_time = self.time_get();
+            if time_greater_equal(self.service_time, deadline) {
+                return Ok(None);
+            }
+            let received = self.wait(time_difference(deadline, self.service_time))?;
+            self.service_time = self.time_get();
+            if !received {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Blocks for up to `timeout` milliseconds until a datagram arrives and handles it. Returns
+    /// whether a datagram has been received.
+    fn wait(&mut self, timeout: u32) -> Result<bool> {
+        let mut buf = [0; protocol::MAXIMUM_MTU as usize];
+        let result = self.socket.set_nonblocking(false).and_then(|_| {
+            self.socket
+                .set_read_timeout(Some(Duration::from_millis(u64::from(timeout.max(1)))))?;
+            let result = self.socket.recv_from(&mut buf);
+            self.socket.set_nonblocking(true)?;
+            result
+        });
+        match result {
+            Ok((len, address)) => {
+                self.handle_incoming_commands(&buf[..len], address.into());
+                Ok(true)
+            },
+            Err(e) if is_ignored(&e) || e.kind() == io::ErrorKind::TimedOut => Ok(false),
+            Err(_) => Err(Error::Service),
+        }
+    }
+
+    fn receive_incoming_commands(&mut self) -> Result<()> {
+        let mut buf = [0; protocol::MAXIMUM_MTU as usize];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, address)) => self.handle_incoming_commands(&buf[..len], address.into()),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(ref e) if is_ignored(e) => (),
+                Err(_) => return Err(Error::Service),
+            }
+        }
+    }
+
+    fn handle_incoming_commands(&mut self, data: &[u8], address: Address) {
+        let (header, header_size) = match Header::read(data) {
+            Some(header) => header,
+            None => return,
+        };
+        let mut peer_index = if header.peer_id == protocol::MAXIMUM_PEER_ID {
+            None
+        } else if header.peer_id as usize >= self.peers.len() {
+            return;
+        } else {
+            let peer = &mut self.peers[header.peer_id as usize];
+            if peer.state == PeerState::Disconnected
+                || peer.state == PeerState::Zombie
+                || peer.address != address
+                || peer.session_id != header.session_id
+            {
+                return;
+            }
+            peer.incoming_data_total = peer.incoming_data_total.wrapping_add(data.len() as u32);
+            Some(header.peer_id as usize)
+        };
+
+        let mut offset = header_size;
+        while offset < data.len() {
+            let command = match Command::read(&data[offset..]) {
+                Some(command) => command,
+                None => break,
+            };
+            offset += command.size();
+
+            let data_length = command.body.data_length();
+            let payload = match data.get(offset..offset + data_length) {
+                Some(payload) => payload,
+                None => break,
+            };
+            offset += data_length;
+
+            let result = match (command.body, peer_index) {
+                (CommandBody::Connect { params, session_id }, _) => {
+                    match self.handle_connect(&params, session_id, address) {
+                        Some(index) => {
+                            peer_index = Some(index);
+                            Ok(())
+                        },
+                        None => Err(CommandError),
+                    }
+                },
+                (_, None) => break,
+                (_, Some(index)) => self.handle_command(index, &command, payload),
+            };
+            if result.is_err() {
+                break;
+            }
+
+            if let Some(index) = peer_index {
+                if command.is_acknowledged() {
+                    let sent_time = match header.sent_time {
+                        Some(sent_time) => sent_time,
+                        None => break,
+                    };
+                    let peer = &mut self.peers[index];
+                    match peer.state {
+                        PeerState::Disconnecting | PeerState::AcknowledgingConnect => (),
+                        PeerState::AcknowledgingDisconnect => {
+                            if command.number() == protocol::COMMAND_DISCONNECT {
+                                peer.queue_acknowledgement(&command, sent_time);
+                            }
+                        },
+                        _ => peer.queue_acknowledgement(&command, sent_time),
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_command(&mut self, index: usize, command: &Command, payload: &[u8]) -> CommandResult {
+        match command.body {
+            CommandBody::Acknowledge {
+                received_reliable_sequence_number,
+                received_sent_time,
+            } => self.handle_acknowledge(
+                index,
+                command.channel_id,
+                received_reliable_sequence_number,
+                received_sent_time,
+            ),
+            CommandBody::VerifyConnect(params) => self.handle_verify_connect(index, &params),
+            CommandBody::Disconnect { data } => {
+                self.handle_disconnect(index, command.is_acknowledged(), data);
+                Ok(())
+            },
+            CommandBody::Ping => Ok(()),
+            CommandBody::SendReliable { .. } | CommandBody::SendUnreliable { .. } => {
+                self.handle_send(index, command, payload)
+            },
+            CommandBody::SendUnsequenced {
+                unsequenced_group, ..
+            } => self.handle_send_unsequenced(index, command, unsequenced_group, payload),
+            CommandBody::SendFragment {
+                start_sequence_number,
+                fragment_count,
+                fragment_number,
+                total_length,
+                fragment_offset,
+                ..
+            } => self.handle_send_fragment(
+                index,
+                command,
+                start_sequence_number,
+                fragment_count,
+                fragment_number,
+                total_length,
+                fragment_offset,
+                payload,
+            ),
+            CommandBody::BandwidthLimit {
+                incoming_bandwidth,
+                outgoing_bandwidth,
+            } => {
+                let host_outgoing_bandwidth = self.outgoing_bandwidth;
+                let peer = &mut self.peers[index];
+                peer.incoming_bandwidth = incoming_bandwidth;
+                peer.outgoing_bandwidth = outgoing_bandwidth;
+                peer.window_size =
+                    clamp_window_size(if incoming_bandwidth == 0 && host_outgoing_bandwidth == 0 {
+                        protocol::MAXIMUM_WINDOW_SIZE
+                    } else {
+                        (incoming_bandwidth.min(host_outgoing_bandwidth) / PEER_WINDOW_SIZE_SCALE)
+                            * protocol::MINIMUM_WINDOW_SIZE
+                    });
+                Ok(())
+            },
+            CommandBody::ThrottleConfigure {
+                packet_throttle_interval,
+                packet_throttle_acceleration,
+                packet_throttle_deceleration,
+            } => {
+                let peer = &mut self.peers[index];
+                peer.packet_throttle_interval = packet_throttle_interval;
+                peer.packet_throttle_acceleration = packet_throttle_acceleration;
+                peer.packet_throttle_deceleration = packet_throttle_deceleration;
+                Ok(())
+            },
+            CommandBody::Connect { .. } => {
+                unreachable!("connect commands are handled by the caller")
+            },
+        }
+    }
+
+    fn handle_connect(
+        &mut self,
+        params: &ConnectParams,
+        session_id: u32,
+        address: Address,
+    ) -> Option<usize> {
+        let channel_count = params.channel_count as usize;
+        if !(protocol::MINIMUM_CHANNEL_COUNT..=protocol::MAXIMUM_CHANNEL_COUNT)
+            .contains(&channel_count)
+        {
+            return None;
+        }
+        if self.peers.iter().any(|peer| {
+            peer.state != PeerState::Disconnected
+                && peer.address == address
+                && peer.session_id == session_id
+        }) {
+            return None;
+        }
+        let index = self
+            .peers
+            .iter()
+            .position(|peer| peer.state == PeerState::Disconnected)?;
+        let channel_count = channel_count.min(self.channel_limit);
+
+        let peer = &mut self.peers[index];
+        peer.setup_channels(channel_count);
+        peer.state = PeerState::AcknowledgingConnect;
+        peer.session_id = session_id;
+        peer.address = address;
+        peer.outgoing_peer_id = params.outgoing_peer_id;
+        peer.incoming_bandwidth = params.incoming_bandwidth;
+        peer.outgoing_bandwidth = params.outgoing_bandwidth;
+        peer.packet_throttle_interval = params.packet_throttle_interval;
+        peer.packet_throttle_acceleration = params.packet_throttle_acceleration;
+        peer.packet_throttle_deceleration = params.packet_throttle_deceleration;
+        peer.mtu = params
+            .mtu
+            .clamp(protocol::MINIMUM_MTU, protocol::MAXIMUM_MTU);
+
+        peer.window_size = clamp_window_size(
+            if self.outgoing_bandwidth == 0 && peer.incoming_bandwidth == 0 {
+                protocol::MAXIMUM_WINDOW_SIZE
+            } else if self.outgoing_bandwidth == 0 || peer.incoming_bandwidth == 0 {
+                (self.outgoing_bandwidth.max(peer.incoming_bandwidth) / PEER_WINDOW_SIZE_SCALE)
+                    * protocol::MINIMUM_WINDOW_SIZE
+            } else {
+                (self.outgoing_bandwidth.min(peer.incoming_bandwidth) / PEER_WINDOW_SIZE_SCALE)
+                    * protocol::MINIMUM_WINDOW_SIZE
+            },
+        );
+        let window_size = if self.incoming_bandwidth == 0 {
+            protocol::MAXIMUM_WINDOW_SIZE
+        } else {
+            (self.incoming_bandwidth / PEER_WINDOW_SIZE_SCALE) * protocol::MINIMUM_WINDOW_SIZE
+        };
+        let window_size = clamp_window_size(window_size.min(params.window_size));
+
+        let command = Command::new(
+            protocol::COMMAND_FLAG_ACKNOWLEDGE,
+            0xFF,
+            CommandBody::VerifyConnect(ConnectParams {
+                outgoing_peer_id: peer.incoming_peer_id,
+                mtu: peer.mtu,
+                window_size,
+                channel_count: channel_count as u32,
+                incoming_bandwidth: self.incoming_bandwidth,
+                outgoing_bandwidth: self.outgoing_bandwidth,
+                packet_throttle_interval: peer.packet_throttle_interval,
+                packet_throttle_acceleration: peer.packet_throttle_acceleration,
+                packet_throttle_deceleration: peer.packet_throttle_deceleration,
+            }),
+        );
+        peer.queue_outgoing_command(command, None, 0, 0);
+        Some(index)
+    }
+
+    fn handle_verify_connect(&mut self, index: usize, params: &ConnectParams) -> CommandResult {
+        let peer = &mut self.peers[index];
+        if peer.state != PeerState::Connecting {
+            return Ok(());
+        }
+        let channel_count = params.channel_count as usize;
+        if !(protocol::MINIMUM_CHANNEL_COUNT..=protocol::MAXIMUM_CHANNEL_COUNT)
+            .contains(&channel_count)
+            || params.packet_throttle_interval != peer.packet_throttle_interval
+            || params.packet_throttle_acceleration != peer.packet_throttle_acceleration
+            || params.packet_throttle_deceleration != peer.packet_throttle_deceleration
+        {
+            dispatch_state(peer, &mut self.dispatch_queue, PeerState::Zombie);
+            return Err(CommandError);
+        }
+
+        remove_sent_reliable_command(peer, 1, 0xFF);
+        peer.channels.truncate(channel_count);
+        peer.outgoing_peer_id = params.outgoing_peer_id;
+        let mtu = params
+            .mtu
+            .clamp(protocol::MINIMUM_MTU, protocol::MAXIMUM_MTU);
+        peer.mtu = peer.mtu.min(mtu);
+        peer.window_size = peer.window_size.min(clamp_window_size(params.window_size));
+        peer.incoming_bandwidth = params.incoming_bandwidth;
+        peer.outgoing_bandwidth = params.outgoing_bandwidth;
+
+        notify_connect(peer, &mut self.dispatch_queue);
+        Ok(())
+    }
+
+    fn handle_acknowledge(
+        &mut self,
+        index: usize,
+        channel_id: u8,
+        received_reliable_sequence_number: u16,
+        received_sent_time: u16,
+    ) -> CommandResult {
+        let service_time = self.service_time;
+        let peer = &mut self.peers[index];
+
+        // the sent time only carries the lower 16 bits, restore the rest from the current time
+        let mut received_sent_time = u32::from(received_sent_time) | (service_time & 0xFFFF_0000);
+        if (received_sent_time & 0x8000) > (service_time & 0x8000) {
+            received_sent_time = received_sent_time.wrapping_sub(0x10000);
+        }
+        if time_less(service_time, received_sent_time) {
+            return Ok(());
+        }
+
+        peer.last_receive_time = service_time;
+        peer.earliest_timeout = 0;
+
+        let round_trip_time = time_difference(service_time, received_sent_time);
+        peer.throttle(round_trip_time);
+        peer.round_trip_time_variance -= peer.round_trip_time_variance / 4;
+        if round_trip_time >= peer.round_trip_time {
+            peer.round_trip_time += (round_trip_time - peer.round_trip_time) / 8;
+            peer.round_trip_time_variance += (round_trip_time - peer.round_trip_time) / 4;
+        } else {
+            peer.round_trip_time -= (peer.round_trip_time - round_trip_time) / 8;
+            peer.round_trip_time_variance += (peer.round_trip_time - round_trip_time) / 4;
+        }
+        if peer.round_trip_time < peer.lowest_round_trip_time {
+            peer.lowest_round_trip_time = peer.round_trip_time;
+        }
+        if peer.round_trip_time_variance > peer.highest_round_trip_time_variance {
+            peer.highest_round_trip_time_variance = peer.round_trip_time_variance;
+        }
+        if peer.packet_throttle_epoch == 0
+            || time_difference(service_time, peer.packet_throttle_epoch)
+                >= peer.packet_throttle_interval
+        {
+            peer.last_round_trip_time = peer.lowest_round_trip_time;
+            peer.last_round_trip_time_variance = peer.highest_round_trip_time_variance;
+            peer.lowest_round_trip_time = peer.round_trip_time;
+            peer.highest_round_trip_time_variance = peer.round_trip_time_variance;
+            peer.packet_throttle_epoch = service_time;
+        }
+
+        let command_number =
+            remove_sent_reliable_command(peer, received_reliable_sequence_number, channel_id);
+        match peer.state {
+            PeerState::AcknowledgingConnect => {
+                if command_number != Some(protocol::COMMAND_VERIFY_CONNECT) {
+                    return Err(CommandError);
+                }
+                notify_connect(peer, &mut self.dispatch_queue);
+            },
+            PeerState::Disconnecting => {
+                if command_number != Some(protocol::COMMAND_DISCONNECT) {
+                    return Err(CommandError);
+                }
+                notify_disconnect(peer, &mut self.dispatch_queue, self.mtu);
+            },
+            PeerState::DisconnectLater if !peer.has_pending_outgoing() => {
+                // the peer is connected, so this never requires a reset
+                peer.disconnect(peer.disconnect_data);
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn handle_disconnect(&mut self, index: usize, acknowledged: bool, data: u32) {
+        let peer = &mut self.peers[index];
+        if peer.state == PeerState::Zombie || peer.state == PeerState::AcknowledgingDisconnect {
+            return;
+        }
+        peer.reset_queues();
+        match peer.state {
+            PeerState::ConnectionSucceeded | PeerState::Disconnecting => {
+                dispatch_state(peer, &mut self.dispatch_queue, PeerState::Zombie)
+            },
+            PeerState::Connected | PeerState::DisconnectLater => {
+                if acknowledged {
+                    peer.state = PeerState::AcknowledgingDisconnect;
+                } else {
+                    dispatch_state(peer, &mut self.dispatch_queue, PeerState::Zombie);
+                }
+            },
+            _ => peer.reset(self.mtu),
+        }
+        peer.disconnect_data = data;
+    }
+
+    /// Checks whether the peer is able to receive a packet on the command's channel.
+    fn check_receive(&self, index: usize, command: &Command) -> CommandResult {
+        let peer = &self.peers[index];
+        if (command.channel_id as usize) < peer.channels.len()
+            && (peer.state == PeerState::Connected || peer.state == PeerState::DisconnectLater)
+        {
+            Ok(())
+        } else {
+            Err(CommandError)
+        }
+    }
+
+    fn handle_send(&mut self, index: usize, command: &Command, payload: &[u8]) -> CommandResult {
+        self.check_receive(index, command)?;
+        let mode = if command.number() == protocol::COMMAND_SEND_RELIABLE {
+            PacketMode::Reliable
+        } else {
+            PacketMode::Unreliable
+        };
+        let packet = Packet::from_vec(payload.to_vec(), mode);
+        self.peers[index]
+            .queue_incoming_command(command, packet, 0, &mut self.dispatch_queue)
+            .map(drop)
+    }
+
+    fn handle_send_unsequenced(
+        &mut self,
+        index: usize,
+        command: &Command,
+        unsequenced_group: u16,
+        payload: &[u8],
+    ) -> CommandResult {
+        self.check_receive(index, command)?;
+        let peer = &mut self.peers[index];
+        let mut unsequenced_group = u32::from(unsequenced_group);
+        let window_index = unsequenced_group % PEER_UNSEQUENCED_WINDOW_SIZE;
+        let incoming_group = u32::from(peer.incoming_unsequenced_group);
+        if unsequenced_group < incoming_group {
+            unsequenced_group += 0x10000;
+        }
+        if unsequenced_group
+            >= incoming_group + PEER_FREE_UNSEQUENCED_WINDOWS * PEER_UNSEQUENCED_WINDOW_SIZE
+        {
+            return Ok(());
+        }
+        unsequenced_group &= 0xFFFF;
+
+        let word = (window_index / 32) as usize;
+        let bit = 1 << (window_index % 32);
+        if unsequenced_group - window_index != incoming_group {
+            peer.incoming_unsequenced_group = (unsequenced_group - window_index) as u16;
+            peer.unsequenced_window = Default::default();
+        } else if peer.unsequenced_window[word] & bit != 0 {
+            return Ok(());
+        }
+
+        let packet = Packet::from_vec(payload.to_vec(), PacketMode::Unsequenced);
+        peer.queue_incoming_command(command, packet, 0, &mut self.dispatch_queue)?;
+        peer.unsequenced_window[word] |= bit;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_send_fragment(
+        &mut self,
+        index: usize,
+        command: &Command,
+        start_sequence_number: u16,
+        fragment_count: u32,
+        fragment_number: u32,
+        total_length: u32,
+        fragment_offset: u32,
+        payload: &[u8],
+    ) -> CommandResult {
+        self.check_receive(index, command)?;
+        let peer = &mut self.peers[index];
+        let channel_id = command.channel_id as usize;
+        let channel = &mut peer.channels[channel_id];
+
+        let mut start_window = start_sequence_number / PEER_RELIABLE_WINDOW_SIZE;
+        let current_window = channel.incoming_reliable_sequence_number / PEER_RELIABLE_WINDOW_SIZE;
+        if start_sequence_number < channel.incoming_reliable_sequence_number {
+            start_window += PEER_RELIABLE_WINDOWS;
+        }
+        if start_window < current_window
+            || start_window >= current_window + PEER_FREE_RELIABLE_WINDOWS - 1
+        {
+            return Ok(());
+        }
+
+        let fragment_length = payload.len() as u32;
+        if fragment_offset >= total_length
+            || u64::from(fragment_offset) + u64::from(fragment_length) > u64::from(total_length)
+            || fragment_number >= fragment_count
+            || total_length as usize > HOST_DEFAULT_MAXIMUM_PACKET_SIZE
+            || fragment_count > total_length
+        {
+            return Err(CommandError);
+        }
+
+        let incoming_rsn = channel.incoming_reliable_sequence_number;
+        let mut found = false;
+        for incoming in channel.incoming_reliable_commands.iter().rev() {
+            if start_sequence_number >= incoming_rsn {
+                if incoming.reliable_sequence_number < incoming_rsn {
+                    continue;
+                }
+            } else if incoming.reliable_sequence_number >= incoming_rsn {
+                break;
+            }
+            if incoming.reliable_sequence_number <= start_sequence_number {
+                if incoming.reliable_sequence_number < start_sequence_number {
+                    break;
+                }
+                if incoming.command.number() != protocol::COMMAND_SEND_FRAGMENT
+                    || total_length as usize != incoming.packet.len()
+                    || fragment_count != incoming.fragment_count
+                {
+                    return Err(CommandError);
+                }
+                found = true;
+                break;
+            }
+        }
+
+        if !found {
+            let mut start_command = *command;
+            start_command.reliable_sequence_number = start_sequence_number;
+            let packet = Packet::from_vec(vec![0; total_length as usize], PacketMode::Reliable);
+            peer.queue_incoming_command(
+                &start_command,
+                packet,
+                fragment_count,
+                &mut self.dispatch_queue,
+            )?;
+        }
+
+        let start_command = peer.channels[channel_id]
+            .incoming_reliable_commands
+            .iter_mut()
+            .find(|incoming| {
+                incoming.reliable_sequence_number == start_sequence_number
+                    && incoming.command.number() == protocol::COMMAND_SEND_FRAGMENT
+            })
+            .ok_or(CommandError)?;
+        let word = (fragment_number / 32) as usize;
+        let bit = 1 << (fragment_number % 32);
+        if start_command.fragments[word] & bit == 0 {
+            start_command.fragments_remaining -= 1;
+            start_command.fragments[word] |= bit;
+            let offset = fragment_offset as usize;
+            let length = payload.len().min(start_command.packet.len() - offset);
+            start_command.packet[offset..offset + length].copy_from_slice(&payload[..length]);
+            if start_command.fragments_remaining == 0 {
+                peer.dispatch_incoming_reliable_commands(channel_id, &mut self.dispatch_queue);
+            }
+        }
+        Ok(())
+    }
+
+    fn send_outgoing_commands(&mut self, check_for_timeouts: bool) -> io::Result<()> {
+        self.continue_sending = true;
+        while self.continue_sending {
+            self.continue_sending = false;
+            for index in 0..self.peers.len() {
+                self.send_peer_commands(index, check_for_timeouts)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_peer_commands(&mut self, index: usize, check_for_timeouts: bool) -> io::Result<()> {
+        let service_time = self.service_time;
+        let peer = &mut self.peers[index];
+        if peer.state == PeerState::Disconnected || peer.state == PeerState::Zombie {
+            return Ok(());
+        }
+        let mut datagram = Datagram::new();
+
+        if !peer.acknowledgements.is_empty() {
+            send_acknowledgements(peer, &mut self.dispatch_queue, &mut datagram);
+        }
+
+        if check_for_timeouts
+            && !peer.sent_reliable_commands.is_empty()
+            && time_greater_equal(service_time, peer.next_timeout)
+            && check_timeouts(peer, &mut self.dispatch_queue, service_time, self.mtu)
+        {
+            return Ok(());
+        }
+
+        if !peer.outgoing_reliable_commands.is_empty() {
+            send_reliable_outgoing_commands(peer, &mut datagram, service_time);
+        } else if peer.sent_reliable_commands.is_empty()
+            && time_difference(service_time, peer.last_receive_time) >= PEER_PING_INTERVAL
+            && peer.mtu as usize - datagram.packet_size
+                >= protocol::command_size(protocol::COMMAND_PING)
+        {
+            peer.ping();
+            send_reliable_outgoing_commands(peer, &mut datagram, service_time);
+        }
+
+        if !peer.outgoing_unreliable_commands.is_empty() {
+            send_unreliable_outgoing_commands(peer, &mut datagram);
+        }
+
+        self.continue_sending |= datagram.full;
+        if datagram.command_count == 0 {
+            return Ok(());
+        }
+
+        if peer.packet_loss_epoch == 0 {
+            peer.packet_loss_epoch = service_time;
+        } else if time_difference(service_time, peer.packet_loss_epoch) >= PEER_PACKET_LOSS_INTERVAL
+            && peer.packets_sent > 0
+        {
+            let packet_loss = (u64::from(peer.packets_lost) * u64::from(PEER_PACKET_LOSS_SCALE)
+                / u64::from(peer.packets_sent)) as u32;
+            peer.packet_loss_variance -= peer.packet_loss_variance / 4;
+            if packet_loss >= peer.packet_loss {
+                peer.packet_loss += (packet_loss - peer.packet_loss) / 8;
+                peer.packet_loss_variance += (packet_loss - peer.packet_loss) / 4;
+            } else {
+                peer.packet_loss -= (peer.packet_loss - packet_loss) / 8;
+                peer.packet_loss_variance += (peer.packet_loss - packet_loss) / 4;
+            }
+            peer.packet_loss_epoch = service_time;
+            peer.packets_sent = 0;
+            peer.packets_lost = 0;
+        }
+
+        let header = Header {
+            session_id: peer.session_id,
+            peer_id: peer.outgoing_peer_id | datagram.header_flags,
+            sent_time: if datagram.header_flags & protocol::HEADER_FLAG_SENT_TIME != 0 {
+                Some(service_time as u16)
+            } else {
+                None
+            },
+        };
+        peer.last_send_time = service_time;
+        let mut buf = Vec::with_capacity(protocol::HEADER_SIZE + datagram.data.len());
+        header.write(&mut buf);
+        buf.extend_from_slice(&datagram.data);
+
+        match self.socket.send_to(&buf, SocketAddr::from(peer.address)) {
+            Ok(_) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || is_ignored(e) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Errors that only tell us about a previous datagram not being delivered, which the protocol
+/// deals with on its own.
+fn is_ignored(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+    )
+}
+
+fn clamp_window_size(window_size: u32) -> u32 {
+    window_size.clamp(protocol::MINIMUM_WINDOW_SIZE, protocol::MAXIMUM_WINDOW_SIZE)
+}
+
+fn dispatch_state<T>(peer: &mut Peer<T>, queue: &mut DispatchQueue, state: PeerState) {
+    peer.state = state;
+    queue.push(peer);
+}
+
+fn notify_connect<T>(peer: &mut Peer<T>, queue: &mut DispatchQueue) {
+    let state = if peer.state == PeerState::Connecting {
+        PeerState::ConnectionSucceeded
+    } else {
+        PeerState::ConnectionPending
+    };
+    dispatch_state(peer, queue, state);
+}
+
+fn notify_disconnect<T>(peer: &mut Peer<T>, queue: &mut DispatchQueue, mtu: u16) {
+    if peer.state != PeerState::Connecting && peer.state < PeerState::ConnectionSucceeded {
+        // the user never learned about this peer, so there is nobody to tell about it either
+        peer.reset(mtu);
+    } else {
+        dispatch_state(peer, queue, PeerState::Zombie);
+    }
+}
+
+/// Removes an acknowledged command, returning its command number if it was found.
+fn remove_sent_reliable_command<T>(
+    peer: &mut Peer<T>,
+    reliable_sequence_number: u16,
+    channel_id: u8,
+) -> Option<u8> {
+    let matches = |outgoing: &OutgoingCommand| {
+        outgoing.reliable_sequence_number == reliable_sequence_number
+            && outgoing.command.channel_id == channel_id
+    };
+    let (outgoing, was_sent) = match peer.sent_reliable_commands.iter().position(matches) {
+        Some(position) => (peer.sent_reliable_commands.remove(position)?, true),
+        None => {
+            let mut found = None;
+            for (position, outgoing) in peer.outgoing_reliable_commands.iter().enumerate() {
+                if outgoing.send_attempts < 1 {
+                    return None;
+                }
+                if matches(outgoing) {
+                    found = Some(position);
+                    break;
+                }
+            }
+            (peer.outgoing_reliable_commands.remove(found?)?, false)
+        },
+    };
+
+    if let Some(channel) = peer.channels.get_mut(channel_id as usize) {
+        let reliable_window = (reliable_sequence_number / PEER_RELIABLE_WINDOW_SIZE) as usize;
+        if channel.reliable_windows[reliable_window] > 0 {
+            channel.reliable_windows[reliable_window] -= 1;
+            if channel.reliable_windows[reliable_window] == 0 {
+                channel.used_reliable_windows &= !(1 << reliable_window);
+            }
+        }
+    }
+    if outgoing.packet.is_some() && was_sent {
+        peer.reliable_data_in_transit -= u32::from(outgoing.fragment_length);
+    }
+    if let Some(front) = peer.sent_reliable_commands.front() {
+        peer.next_timeout = front.sent_time.wrapping_add(front.round_trip_timeout);
+    }
+    Some(outgoing.command.number())
+}
+
+fn send_acknowledgements<T>(
+    peer: &mut Peer<T>,
+    queue: &mut DispatchQueue,
+    datagram: &mut Datagram,
+) {
+    let size = protocol::command_size(protocol::COMMAND_ACKNOWLEDGE);
+    while let Some(acknowledgement) = peer.acknowledgements.front() {
+        if datagram.command_count >= protocol::MAXIMUM_PACKET_COMMANDS
+            || datagram.buffer_count >= protocol::MAXIMUM_BUFFERS
+            || (peer.mtu as usize) < datagram.packet_size + size
+        {
+            datagram.full = true;
+            break;
+        }
+        let command = Command::new(
+            0,
+            acknowledgement.command.channel_id,
+            CommandBody::Acknowledge {
+                received_reliable_sequence_number: acknowledgement.command.reliable_sequence_number,
+                received_sent_time: acknowledgement.sent_time,
+            },
+        );
+        datagram.push(&command, None);
+        if acknowledgement.command.number() == protocol::COMMAND_DISCONNECT {
+            dispatch_state(peer, queue, PeerState::Zombie);
+        }
+        peer.acknowledgements.pop_front();
+    }
+}
+
+/// Requeues all reliable commands that haven't been acknowledged in time. Returns `true` if the
+/// peer timed out and has been disconnected.
+fn check_timeouts<T>(
+    peer: &mut Peer<T>,
+    queue: &mut DispatchQueue,
+    service_time: u32,
+    mtu: u16,
+) -> bool {
+    let mut expired = Vec::new();
+    let mut i = 0;
+    while i < peer.sent_reliable_commands.len() {
+        let outgoing = &peer.sent_reliable_commands[i];
+        if time_difference(service_time, outgoing.sent_time) < outgoing.round_trip_timeout {
+            i += 1;
+            continue;
+        }
+        if peer.earliest_timeout == 0 || time_less(outgoing.sent_time, peer.earliest_timeout) {
+            peer.earliest_timeout = outgoing.sent_time;
+        }
+        if peer.earliest_timeout != 0 {
+            let waited = time_difference(service_time, peer.earliest_timeout);
+            if waited >= PEER_TIMEOUT_MAXIMUM
+                || (outgoing.round_trip_timeout >= outgoing.round_trip_timeout_limit
+                    && waited >= PEER_TIMEOUT_MINIMUM)
+            {
+                notify_disconnect(peer, queue, mtu);
+                return true;
+            }
+        }
+
+        let mut outgoing = match peer.sent_reliable_commands.remove(i) {
+            Some(outgoing) => outgoing,
+            None => break,
+        };
+        if outgoing.packet.is_some() {
+            peer.reliable_data_in_transit -= u32::from(outgoing.fragment_length);
+        }
+        peer.packets_lost += 1;
+        outgoing.round_trip_timeout *= 2;
+        expired.push(outgoing);
+
+        if i == 0 {
+            if let Some(front) = peer.sent_reliable_commands.front() {
+                peer.next_timeout = front.sent_time.wrapping_add(front.round_trip_timeout);
+            }
+        }
+    }
+    // resend in the original order, ahead of everything that hasn't been sent yet
+    for outgoing in expired.into_iter().rev() {
+        peer.outgoing_reliable_commands.push_front(outgoing);
+    }
+    false
+}
+
+fn send_reliable_outgoing_commands<T>(
+    peer: &mut Peer<T>,
+    datagram: &mut Datagram,
+    service_time: u32,
+) {
+    while let Some(outgoing) = peer.outgoing_reliable_commands.front() {
+        let reliable_window = outgoing.reliable_sequence_number / PEER_RELIABLE_WINDOW_SIZE;
+        let channel_id = outgoing.command.channel_id as usize;
+        if let Some(channel) = peer.channels.get(channel_id) {
+            let free_windows = (1u32 << PEER_FREE_RELIABLE_WINDOWS) - 1;
+            let used = u32::from(channel.used_reliable_windows)
+                & ((free_windows << reliable_window)
+                    | (free_windows >> (PEER_RELIABLE_WINDOWS - reliable_window)));
+            let previous = (reliable_window + PEER_RELIABLE_WINDOWS - 1) % PEER_RELIABLE_WINDOWS;
+            if outgoing.send_attempts < 1
+                && outgoing.reliable_sequence_number % PEER_RELIABLE_WINDOW_SIZE == 0
+                && (channel.reliable_windows[previous as usize] >= PEER_RELIABLE_WINDOW_SIZE
+                    || used != 0)
+            {
+                break;
+            }
+        }
+
+        let size = outgoing.command.size();
+        if datagram.command_count >= protocol::MAXIMUM_PACKET_COMMANDS
+            || datagram.buffer_count + 1 >= protocol::MAXIMUM_BUFFERS
+            || (peer.mtu as usize) < datagram.packet_size + size
+        {
+            datagram.full = true;
+            break;
+        }
+        if outgoing.packet.is_some() {
+            let window_size = peer.packet_throttle * peer.window_size / PEER_PACKET_THROTTLE_SCALE;
+            if peer.reliable_data_in_transit + u32::from(outgoing.fragment_length)
+                > window_size.max(u32::from(peer.mtu))
+            {
+                break;
+            }
+            if (peer.mtu as usize) < datagram.packet_size + size + outgoing.fragment_length as usize
+            {
+                datagram.full = true;
+                break;
+            }
+        }
+
+        let mut outgoing = match peer.outgoing_reliable_commands.pop_front() {
+            Some(outgoing) => outgoing,
+            None => break,
+        };
+        if outgoing.send_attempts < 1 {
+            if let Some(channel) = peer.channels.get_mut(channel_id) {
+                channel.used_reliable_windows |= 1 << reliable_window;
+                channel.reliable_windows[reliable_window as usize] += 1;
+            }
+        }
+        outgoing.send_attempts += 1;
+        if outgoing.round_trip_timeout == 0 {
+            outgoing.round_trip_timeout = peer.round_trip_time + 4 * peer.round_trip_time_variance;
+            outgoing.round_trip_timeout_limit = PEER_TIMEOUT_LIMIT * outgoing.round_trip_timeout;
+        }
+        if peer.sent_reliable_commands.is_empty() {
+            peer.next_timeout = service_time.wrapping_add(outgoing.round_trip_timeout);
+        }
+        outgoing.sent_time = service_time;
+
+        datagram.push(&outgoing.command, outgoing.payload());
+        datagram.header_flags |= protocol::HEADER_FLAG_SENT_TIME;
+        if outgoing.packet.is_some() {
+            peer.reliable_data_in_transit += u32::from(outgoing.fragment_length);
+        }
+        peer.packets_sent += 1;
+        peer.sent_reliable_commands.push_back(outgoing);
+    }
+}
+
+fn send_unreliable_outgoing_commands<T>(peer: &mut Peer<T>, datagram: &mut Datagram) {
+    while let Some(outgoing) = peer.outgoing_unreliable_commands.front() {
+        let size = outgoing.command.size();
+        if datagram.command_count >= protocol::MAXIMUM_PACKET_COMMANDS
+            || datagram.buffer_count + 1 >= protocol::MAXIMUM_BUFFERS
+            || (peer.mtu as usize) < datagram.packet_size + size + outgoing.fragment_length as usize
+        {
+            datagram.full = true;
+            break;
+        }
+        let outgoing = match peer.outgoing_unreliable_commands.pop_front() {
+            Some(outgoing) => outgoing,
+            None => break,
+        };
+        if outgoing.packet.is_some() {
+            peer.packet_throttle_counter += PEER_PACKET_THROTTLE_COUNTER;
+            peer.packet_throttle_counter %= PEER_PACKET_THROTTLE_SCALE;
+            if peer.packet_throttle_counter > peer.packet_throttle {
+                continue;
+            }
+        }
+        datagram.push(&outgoing.command, outgoing.payload());
+    }
+
+    if peer.state == PeerState::DisconnectLater && !peer.has_pending_outgoing() {
+        // the peer is connected, so this never requires a reset
+        peer.disconnect(peer.disconnect_data);
+    }
+}
+
+/// A pure Rust LENet host, see [`crate::Host`].
+pub struct Host<T> {
+    inner: Arc<Mutex<HostState<T>>>,
+}
+
+impl<T> Host<T> {
+    /// Creates a new host, binding it to `address` if given. A host without an address gets bound
+    /// to an ephemeral port and can only be used to connect to other hosts.
+    pub fn new(address: Option<Address>, peer_count: usize) -> Result<Self> {
+        if peer_count > protocol::MAXIMUM_PEER_ID as usize {
+            return Err(Error::HostCreation);
+        }
+        let bind_address = SocketAddr::from(address.unwrap_or_else(|| Address::any(0)));
+        let socket = UdpSocket::bind(bind_address).map_err(|_| Error::HostCreation)?;
+        socket
+            .set_nonblocking(true)
+            .and_then(|_| socket.set_broadcast(true))
+            .map_err(|_| Error::HostCreation)?;
+        let address = socket.local_addr().unwrap_or(bind_address).into();
+        Ok(Host {
+            inner: Arc::new(Mutex::new(HostState {
+                socket,
+                address,
+                peers: (0..peer_count)
+                    .map(|id| Peer::new(id as u16, HOST_DEFAULT_MTU))
+                    .collect(),
+                dispatch_queue: DispatchQueue::default(),
+                mtu: HOST_DEFAULT_MTU,
+                channel_limit: protocol::MAXIMUM_CHANNEL_COUNT,
+                incoming_bandwidth: 0,
+                outgoing_bandwidth: 0,
+                service_time: 0,
+                epoch: Instant::now(),
+                continue_sending: false,
+                random: RandomState::new(),
+                random_counter: 0,
+            })),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HostState<T>> {
+        // the host state is never left inconsistent across calls into user code
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn handle(&self, index: usize, generation: u32) -> PeerHandle<T> {
+        PeerHandle::new(self.inner.clone(), index, generation)
+    }
+
+    /// The address the host's socket is bound to. Unlike the C host this reports the actual port
+    /// if the host was bound to port 0.
+    pub fn address(&self) -> Address {
+        self.lock().address
+    }
+
+    /// Sends all queued packets and dispatches incoming ones, returning the first event that
+    /// happened. Waits up to `timeout` milliseconds for an event to occur.
+    pub fn service(&mut self, timeout: u32) -> Result<Option<Event<T>>> {
+        let event = self.lock().service(timeout)?;
+        Ok(event.map(|event| match event {
+            RawEvent::Connect { index, generation } => {
+                Event::Connect(self.handle(index, generation))
+            },
+            RawEvent::Disconnect {
+                index,
+                generation,
+                data,
+                reason,
+            } => Event::Disconnect {
+                peer: self.handle(index, generation),
+                data,
+                reason,
+            },
+            RawEvent::Receive {
+                index,
+                generation,
+                channel_id,
+                packet,
+            } => Event::Receive {
+                peer: self.handle(index, generation),
+                channel_id,
+                packet,
+            },
+        }))
+    }
+
+    /// Sends all queued packets without receiving anything.
+    pub fn flush(&mut self) {
+        self.lock().flush();
+    }
+
+    /// Initiates a connection to `address`, the connection is established once the returned peer
+    /// shows up in an [`Event::Connect`]. Any data attached to the peer before that is discarded.
+    pub fn connect(&mut self, address: Address, channel_count: usize) -> Result<PeerHandle<T>> {
+        let (index, generation) = {
+            let mut state = self.lock();
+            let index = state
+                .connect(address, channel_count)
+                .ok_or(Error::Connect)?;
+            (index, state.peers[index].generation)
+        };
+        Ok(self.handle(index, generation))
+    }
+}
+
+impl<T> fmt::Debug for Host<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Host")
+            .field("address", &self.address())
+            .finish()
+    }
+}
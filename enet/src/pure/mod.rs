@@ -0,0 +1,68 @@
+//! A pure Rust implementation of the LENet protocol.
+//!
+//! This mirrors the API of the C backed types in the crate root, so switching between the two only
+//! requires changing imports. The protocol logic is a port of the bundled LENet sources, keeping
+//! their names where possible so the two can be compared side by side. Bandwidth limiting is not
+//! implemented, hosts always advertise unlimited bandwidth just like the C host we create does.
+
+mod host;
+mod packet;
+mod peer;
+mod protocol;
+
+pub use self::{
+    host::{Event, Host},
+    packet::Packet,
+    peer::PeerHandle,
+};
+pub use crate::{
+    address::Address,
+    error::{Error, Result},
+    packet::PacketMode,
+    peer::PeerStats,
+};
+
+const HOST_DEFAULT_MTU: u16 = 1400;
+/// Upper bound for reassembled packets, the C implementation simply tries to allocate whatever
+/// the remote announces. Taken from later enet versions.
+const HOST_DEFAULT_MAXIMUM_PACKET_SIZE: usize = 32 * 1024 * 1024;
+const PEER_WINDOW_SIZE_SCALE: u32 = 64 * 1024;
+const PEER_DEFAULT_ROUND_TRIP_TIME: u32 = 500;
+const PEER_DEFAULT_PACKET_THROTTLE: u32 = 32;
+const PEER_PACKET_THROTTLE_SCALE: u32 = 32;
+const PEER_PACKET_THROTTLE_COUNTER: u32 = 7;
+const PEER_PACKET_THROTTLE_ACCELERATION: u32 = 2;
+const PEER_PACKET_THROTTLE_DECELERATION: u32 = 2;
+const PEER_PACKET_THROTTLE_INTERVAL: u32 = 5000;
+const PEER_PACKET_LOSS_SCALE: u32 = 1 << 16;
+const PEER_PACKET_LOSS_INTERVAL: u32 = 10000;
+const PEER_TIMEOUT_LIMIT: u32 = 32;
+const PEER_TIMEOUT_MINIMUM: u32 = 5000;
+const PEER_TIMEOUT_MAXIMUM: u32 = 30000;
+const PEER_PING_INTERVAL: u32 = 500;
+const PEER_UNSEQUENCED_WINDOW_SIZE: u32 = 1024;
+const PEER_FREE_UNSEQUENCED_WINDOWS: u32 = 32;
+const PEER_RELIABLE_WINDOWS: u16 = 16;
+const PEER_RELIABLE_WINDOW_SIZE: u16 = 0x1000;
+const PEER_FREE_RELIABLE_WINDOWS: u16 = 8;
+
+const TIME_OVERFLOW: u32 = 86_400_000;
+
+#[inline]
+fn time_less(a: u32, b: u32) -> bool {
+    a.wrapping_sub(b) >= TIME_OVERFLOW
+}
+
+#[inline]
+fn time_greater_equal(a: u32, b: u32) -> bool {
+    !time_less(a, b)
+}
+
+#[inline]
+fn time_difference(a: u32, b: u32) -> u32 {
+    if a.wrapping_sub(b) >= TIME_OVERFLOW {
+        b.wrapping_sub(a)
+    } else {
+        a.wrapping_sub(b)
+    }
+}
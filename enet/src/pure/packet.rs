@@ -0,0 +1,44 @@
+use core::ops;
+
+use crate::{error::Result, packet::PacketMode};
+
+/// An owned packet, the pure counterpart of [`crate::Packet`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    data: Vec<u8>,
+    mode: PacketMode,
+}
+
+impl Packet {
+    /// Creates a new packet by copying `data`. This can't fail, the `Result` only exists to mirror
+    /// the C backed packet.
+    pub fn new(data: &[u8], mode: PacketMode) -> Result<Self> {
+        Ok(Packet::from_vec(data.to_vec(), mode))
+    }
+
+    pub fn from_vec(data: Vec<u8>, mode: PacketMode) -> Self {
+        Packet { data, mode }
+    }
+
+    pub fn mode(&self) -> PacketMode {
+        self.mode
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl ops::Deref for Packet {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl ops::DerefMut for Packet {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.data
+    }
+}
@@ -0,0 +1,807 @@
+use core::fmt;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    host::HostState,
+    packet::Packet,
+    protocol::{self, Command, CommandBody},
+    *,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum PeerState {
+    Disconnected,
+    Connecting,
+    AcknowledgingConnect,
+    ConnectionPending,
+    ConnectionSucceeded,
+    Connected,
+    DisconnectLater,
+    Disconnecting,
+    AcknowledgingDisconnect,
+    Zombie,
+}
+
+/// Raised when a datagram contains a command that doesn't make sense, the rest of the datagram is
+/// dropped in that case.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct CommandError;
+
+pub(crate) struct OutgoingCommand {
+    pub reliable_sequence_number: u16,
+    pub unreliable_sequence_number: u16,
+    pub sent_time: u32,
+    pub round_trip_timeout: u32,
+    pub round_trip_timeout_limit: u32,
+    pub fragment_offset: usize,
+    pub fragment_length: u16,
+    pub send_attempts: u16,
+    pub command: Command,
+    pub packet: Option<Arc<[u8]>>,
+}
+
+impl OutgoingCommand {
+    pub(crate) fn payload(&self) -> Option<&[u8]> {
+        self.packet.as_ref().map(|packet| {
+            &packet[self.fragment_offset..self.fragment_offset + self.fragment_length as usize]
+        })
+    }
+}
+
+pub(crate) struct IncomingCommand {
+    pub reliable_sequence_number: u16,
+    pub unreliable_sequence_number: u16,
+    pub command: Command,
+    pub fragment_count: u32,
+    pub fragments_remaining: u32,
+    pub fragments: Vec<u32>,
+    pub packet: Packet,
+}
+
+#[derive(Copy, Clone)]
+pub(crate) struct Acknowledgement {
+    pub sent_time: u16,
+    pub command: Command,
+}
+
+#[derive(Default)]
+pub(crate) struct Channel {
+    pub outgoing_reliable_sequence_number: u16,
+    pub outgoing_unreliable_sequence_number: u16,
+    pub used_reliable_windows: u16,
+    pub reliable_windows: [u16; PEER_RELIABLE_WINDOWS as usize],
+    pub incoming_reliable_sequence_number: u16,
+    pub incoming_unreliable_sequence_number: u16,
+    pub incoming_reliable_commands: VecDeque<IncomingCommand>,
+    pub incoming_unreliable_commands: VecDeque<IncomingCommand>,
+}
+
+/// The peers that have something to hand out in the next service call.
+#[derive(Default)]
+pub(crate) struct DispatchQueue(VecDeque<u16>);
+
+impl DispatchQueue {
+    pub(crate) fn push<T>(&mut self, peer: &mut Peer<T>) {
+        if !peer.needs_dispatch {
+            self.0.push_back(peer.incoming_peer_id);
+            peer.needs_dispatch = true;
+        }
+    }
+
+    /// Pops the next peer, skipping the ones whose queues have been reset in the meantime.
+    pub(crate) fn pop<T>(&mut self, peers: &mut [Peer<T>]) -> Option<usize> {
+        while let Some(id) = self.0.pop_front() {
+            let peer = &mut peers[id as usize];
+            if peer.needs_dispatch {
+                peer.needs_dispatch = false;
+                return Some(id as usize);
+            }
+        }
+        None
+    }
+}
+
+pub(crate) struct Peer<T> {
+    pub incoming_peer_id: u16,
+    pub outgoing_peer_id: u16,
+    pub session_id: u32,
+    /// Bumped every time the peer is reset so that stale handles can be detected.
+    pub generation: u32,
+    pub address: Address,
+    pub data: Option<T>,
+    pub state: PeerState,
+    pub channels: Vec<Channel>,
+    pub incoming_bandwidth: u32,
+    pub outgoing_bandwidth: u32,
+    pub incoming_data_total: u32,
+    pub outgoing_data_total: u32,
+    pub last_send_time: u32,
+    pub last_receive_time: u32,
+    pub next_timeout: u32,
+    pub earliest_timeout: u32,
+    pub packet_loss_epoch: u32,
+    pub packets_sent: u32,
+    pub packets_lost: u32,
+    pub packet_loss: u32,
+    pub packet_loss_variance: u32,
+    pub packet_throttle: u32,
+    pub packet_throttle_limit: u32,
+    pub packet_throttle_counter: u32,
+    pub packet_throttle_epoch: u32,
+    pub packet_throttle_acceleration: u32,
+    pub packet_throttle_deceleration: u32,
+    pub packet_throttle_interval: u32,
+    pub last_round_trip_time: u32,
+    pub lowest_round_trip_time: u32,
+    pub last_round_trip_time_variance: u32,
+    pub highest_round_trip_time_variance: u32,
+    pub round_trip_time: u32,
+    pub round_trip_time_variance: u32,
+    pub mtu: u16,
+    pub window_size: u32,
+    pub reliable_data_in_transit: u32,
+    pub outgoing_reliable_sequence_number: u16,
+    pub acknowledgements: VecDeque<Acknowledgement>,
+    pub sent_reliable_commands: VecDeque<OutgoingCommand>,
+    pub outgoing_reliable_commands: VecDeque<OutgoingCommand>,
+    pub outgoing_unreliable_commands: VecDeque<OutgoingCommand>,
+    pub dispatched_commands: VecDeque<(u8, Packet)>,
+    pub needs_dispatch: bool,
+    pub incoming_unsequenced_group: u16,
+    pub outgoing_unsequenced_group: u16,
+    pub unsequenced_window: [u32; (PEER_UNSEQUENCED_WINDOW_SIZE / 32) as usize],
+    pub disconnect_data: u32,
+}
+
+impl<T> Peer<T> {
+    pub(crate) fn new(incoming_peer_id: u16, mtu: u16) -> Self {
+        let mut peer = Peer {
+            incoming_peer_id,
+            outgoing_peer_id: protocol::MAXIMUM_PEER_ID,
+            session_id: 0,
+            generation: 0,
+            address: Address::any(0),
+            data: None,
+            state: PeerState::Disconnected,
+            channels: Vec::new(),
+            incoming_bandwidth: 0,
+            outgoing_bandwidth: 0,
+            incoming_data_total: 0,
+            outgoing_data_total: 0,
+            last_send_time: 0,
+            last_receive_time: 0,
+            next_timeout: 0,
+            earliest_timeout: 0,
+            packet_loss_epoch: 0,
+            packets_sent: 0,
+            packets_lost: 0,
+            packet_loss: 0,
+            packet_loss_variance: 0,
+            packet_throttle: 0,
+            packet_throttle_limit: 0,
+            packet_throttle_counter: 0,
+            packet_throttle_epoch: 0,
+            packet_throttle_acceleration: 0,
+            packet_throttle_deceleration: 0,
+            packet_throttle_interval: 0,
+            last_round_trip_time: 0,
+            lowest_round_trip_time: 0,
+            last_round_trip_time_variance: 0,
+            highest_round_trip_time_variance: 0,
+            round_trip_time: 0,
+            round_trip_time_variance: 0,
+            mtu,
+            window_size: 0,
+            reliable_data_in_transit: 0,
+            outgoing_reliable_sequence_number: 0,
+            acknowledgements: VecDeque::new(),
+            sent_reliable_commands: VecDeque::new(),
+            outgoing_reliable_commands: VecDeque::new(),
+            outgoing_unreliable_commands: VecDeque::new(),
+            dispatched_commands: VecDeque::new(),
+            needs_dispatch: false,
+            incoming_unsequenced_group: 0,
+            outgoing_unsequenced_group: 0,
+            unsequenced_window: [0; (PEER_UNSEQUENCED_WINDOW_SIZE / 32) as usize],
+            disconnect_data: 0,
+        };
+        peer.reset(mtu);
+        peer.generation = 0;
+        peer
+    }
+
+    pub(crate) fn reset_queues(&mut self) {
+        // stale dispatch queue entries are skipped once this is unset
+        self.needs_dispatch = false;
+        self.acknowledgements.clear();
+        self.sent_reliable_commands.clear();
+        self.outgoing_reliable_commands.clear();
+        self.outgoing_unreliable_commands.clear();
+        self.dispatched_commands.clear();
+        self.channels.clear();
+    }
+
+    /// Forcefully disconnects the peer without notifying the remote, dropping its user data.
+    pub(crate) fn reset(&mut self, mtu: u16) {
+        self.outgoing_peer_id = protocol::MAXIMUM_PEER_ID;
+        self.session_id = 0;
+        self.generation = self.generation.wrapping_add(1);
+        self.data = None;
+        self.state = PeerState::Disconnected;
+        self.incoming_bandwidth = 0;
+        self.outgoing_bandwidth = 0;
+        self.incoming_data_total = 0;
+        self.outgoing_data_total = 0;
+        self.last_send_time = 0;
+        self.last_receive_time = 0;
+        self.next_timeout = 0;
+        self.earliest_timeout = 0;
+        self.packet_loss_epoch = 0;
+        self.packets_sent = 0;
+        self.packets_lost = 0;
+        self.packet_loss = 0;
+        self.packet_loss_variance = 0;
+        self.packet_throttle = PEER_DEFAULT_PACKET_THROTTLE;
+        self.packet_throttle_limit = PEER_PACKET_THROTTLE_SCALE;
+        self.packet_throttle_counter = 0;
+        self.packet_throttle_epoch = 0;
+        self.packet_throttle_acceleration = PEER_PACKET_THROTTLE_ACCELERATION;
+        self.packet_throttle_deceleration = PEER_PACKET_THROTTLE_DECELERATION;
+        self.packet_throttle_interval = PEER_PACKET_THROTTLE_INTERVAL;
+        self.last_round_trip_time = PEER_DEFAULT_ROUND_TRIP_TIME;
+        self.lowest_round_trip_time = PEER_DEFAULT_ROUND_TRIP_TIME;
+        self.last_round_trip_time_variance = 0;
+        self.highest_round_trip_time_variance = 0;
+        self.round_trip_time = PEER_DEFAULT_ROUND_TRIP_TIME;
+        self.round_trip_time_variance = 0;
+        self.mtu = mtu;
+        self.reliable_data_in_transit = 0;
+        self.outgoing_reliable_sequence_number = 0;
+        self.window_size = protocol::MAXIMUM_WINDOW_SIZE;
+        self.incoming_unsequenced_group = 0;
+        self.outgoing_unsequenced_group = 0;
+        self.disconnect_data = 0;
+        self.unsequenced_window = [0; (PEER_UNSEQUENCED_WINDOW_SIZE / 32) as usize];
+        self.reset_queues();
+    }
+
+    pub(crate) fn setup_channels(&mut self, channel_count: usize) {
+        self.channels = (0..channel_count).map(|_| Channel::default()).collect();
+    }
+
+    pub(crate) fn throttle(&mut self, rtt: u32) -> i32 {
+        if self.last_round_trip_time <= self.last_round_trip_time_variance {
+            self.packet_throttle = self.packet_throttle_limit;
+        } else if rtt < self.last_round_trip_time {
+            self.packet_throttle += self.packet_throttle_acceleration;
+            if self.packet_throttle > self.packet_throttle_limit {
+                self.packet_throttle = self.packet_throttle_limit;
+            }
+            return 1;
+        } else if rtt > self.last_round_trip_time + 2 * self.last_round_trip_time_variance {
+            if self.packet_throttle > self.packet_throttle_deceleration {
+                self.packet_throttle -= self.packet_throttle_deceleration;
+            } else {
+                self.packet_throttle = 0;
+            }
+            return -1;
+        }
+        0
+    }
+
+    /// Queues a packet to be sent on the given channel, fragmenting it if it doesn't fit into a
+    /// single datagram.
+    pub(crate) fn send(&mut self, channel_id: u8, packet: Packet) -> Result<()> {
+        if self.state != PeerState::Connected || channel_id as usize >= self.channels.len() {
+            return Err(Error::Send);
+        }
+        let mode = packet.mode();
+        let data: Arc<[u8]> = packet.into_vec().into();
+        let fragment_length = self.mtu as usize
+            - protocol::HEADER_SIZE
+            - protocol::command_size(protocol::COMMAND_SEND_FRAGMENT);
+
+        if data.len() > fragment_length {
+            let start_sequence_number = self.channels[channel_id as usize]
+                .outgoing_reliable_sequence_number
+                .wrapping_add(1);
+            let fragment_count = data.len().div_ceil(fragment_length);
+            for (fragment_number, fragment_offset) in
+                (0..data.len()).step_by(fragment_length).enumerate()
+            {
+                let length = fragment_length.min(data.len() - fragment_offset);
+                let command = Command::new(
+                    protocol::COMMAND_FLAG_ACKNOWLEDGE,
+                    channel_id,
+                    CommandBody::SendFragment {
+                        start_sequence_number,
+                        data_length: length as u16,
+                        fragment_count: fragment_count as u32,
+                        fragment_number: fragment_number as u32,
+                        total_length: data.len() as u32,
+                        fragment_offset: fragment_offset as u32,
+                    },
+                );
+                self.queue_outgoing_command(
+                    command,
+                    Some(data.clone()),
+                    fragment_offset,
+                    length as u16,
+                );
+            }
+            return Ok(());
+        }
+
+        let channel = &self.channels[channel_id as usize];
+        let data_length = data.len() as u16;
+        let command = match mode {
+            PacketMode::Unsequenced => Command::new(
+                protocol::COMMAND_FLAG_UNSEQUENCED,
+                channel_id,
+                CommandBody::SendUnsequenced {
+                    unsequenced_group: self.outgoing_unsequenced_group.wrapping_add(1),
+                    data_length,
+                },
+            ),
+            PacketMode::Unreliable if channel.outgoing_unreliable_sequence_number < 0xFFFF => {
+                Command::new(
+                    0,
+                    channel_id,
+                    CommandBody::SendUnreliable {
+                        unreliable_sequence_number: channel
+                            .outgoing_unreliable_sequence_number
+                            .wrapping_add(1),
+                        data_length,
+                    },
+                )
+            },
+            // unreliable packets fall back to reliable ones once the sequence number runs out
+            _ => Command::new(
+                protocol::COMMAND_FLAG_ACKNOWLEDGE,
+                channel_id,
+                CommandBody::SendReliable { data_length },
+            ),
+        };
+        self.queue_outgoing_command(command, Some(data), 0, data_length);
+        Ok(())
+    }
+
+    pub(crate) fn queue_outgoing_command(
+        &mut self,
+        command: Command,
+        packet: Option<Arc<[u8]>>,
+        offset: usize,
+        length: u16,
+    ) {
+        self.setup_outgoing_command(OutgoingCommand {
+            reliable_sequence_number: 0,
+            unreliable_sequence_number: 0,
+            sent_time: 0,
+            round_trip_timeout: 0,
+            round_trip_timeout_limit: 0,
+            fragment_offset: offset,
+            fragment_length: length,
+            send_attempts: 0,
+            command,
+            packet,
+        });
+    }
+
+    fn setup_outgoing_command(&mut self, mut outgoing: OutgoingCommand) {
+        self.outgoing_data_total = self
+            .outgoing_data_total
+            .wrapping_add((outgoing.command.size() + outgoing.fragment_length as usize) as u32);
+        let channel_id = outgoing.command.channel_id;
+        if channel_id == 0xFF {
+            self.outgoing_reliable_sequence_number =
+                self.outgoing_reliable_sequence_number.wrapping_add(1);
+            outgoing.reliable_sequence_number = self.outgoing_reliable_sequence_number;
+            outgoing.unreliable_sequence_number = 0;
+        } else if outgoing.command.is_acknowledged() {
+            let channel = &mut self.channels[channel_id as usize];
+            channel.outgoing_reliable_sequence_number =
+                channel.outgoing_reliable_sequence_number.wrapping_add(1);
+            channel.outgoing_unreliable_sequence_number = 0;
+            outgoing.reliable_sequence_number = channel.outgoing_reliable_sequence_number;
+            outgoing.unreliable_sequence_number = 0;
+        } else if outgoing.command.is_unsequenced() {
+            self.outgoing_unsequenced_group = self.outgoing_unsequenced_group.wrapping_add(1);
+            outgoing.reliable_sequence_number = 0;
+            outgoing.unreliable_sequence_number = 0;
+        } else {
+            let channel = &mut self.channels[channel_id as usize];
+            channel.outgoing_unreliable_sequence_number =
+                channel.outgoing_unreliable_sequence_number.wrapping_add(1);
+            outgoing.reliable_sequence_number = channel.outgoing_reliable_sequence_number;
+            outgoing.unreliable_sequence_number = channel.outgoing_unreliable_sequence_number;
+        }
+        outgoing.command.reliable_sequence_number = outgoing.reliable_sequence_number;
+
+        if outgoing.command.is_acknowledged() {
+            self.outgoing_reliable_commands.push_back(outgoing);
+        } else {
+            self.outgoing_unreliable_commands.push_back(outgoing);
+        }
+    }
+
+    pub(crate) fn queue_acknowledgement(&mut self, command: &Command, sent_time: u16) {
+        if let Some(channel) = self.channels.get(command.channel_id as usize) {
+            let mut reliable_window = command.reliable_sequence_number / PEER_RELIABLE_WINDOW_SIZE;
+            let current_window =
+                channel.incoming_reliable_sequence_number / PEER_RELIABLE_WINDOW_SIZE;
+            if command.reliable_sequence_number < channel.incoming_reliable_sequence_number {
+                reliable_window += PEER_RELIABLE_WINDOWS;
+            }
+            if reliable_window >= current_window + PEER_FREE_RELIABLE_WINDOWS - 1
+                && reliable_window <= current_window + PEER_FREE_RELIABLE_WINDOWS
+            {
+                return;
+            }
+        }
+        self.outgoing_data_total = self
+            .outgoing_data_total
+            .wrapping_add(protocol::command_size(protocol::COMMAND_ACKNOWLEDGE) as u32);
+        self.acknowledgements.push_back(Acknowledgement {
+            sent_time,
+            command: *command,
+        });
+    }
+
+    /// Queues a disconnect command. Returns `true` if the peer never finished connecting, the
+    /// command is sent unsequenced then and the host has to flush and reset the peer right away.
+    pub(crate) fn disconnect(&mut self, data: u32) -> bool {
+        match self.state {
+            PeerState::Disconnecting
+            | PeerState::Disconnected
+            | PeerState::AcknowledgingDisconnect
+            | PeerState::Zombie => return false,
+            _ => (),
+        }
+        self.reset_queues();
+        let connected =
+            self.state == PeerState::Connected || self.state == PeerState::DisconnectLater;
+        let flags = if connected {
+            protocol::COMMAND_FLAG_ACKNOWLEDGE
+        } else {
+            protocol::COMMAND_FLAG_UNSEQUENCED
+        };
+        let command = Command::new(flags, 0xFF, CommandBody::Disconnect { data });
+        self.queue_outgoing_command(command, None, 0, 0);
+        if connected {
+            self.state = PeerState::Disconnecting;
+        }
+        !connected
+    }
+
+    pub(crate) fn ping(&mut self) {
+        if self.state != PeerState::Connected {
+            return;
+        }
+        let command = Command::new(protocol::COMMAND_FLAG_ACKNOWLEDGE, 0xFF, CommandBody::Ping);
+        self.queue_outgoing_command(command, None, 0, 0);
+    }
+
+    pub(crate) fn receive(&mut self) -> Option<(u8, Packet)> {
+        self.dispatched_commands.pop_front()
+    }
+
+    fn dispatch_incoming_unreliable_commands(
+        &mut self,
+        channel_id: usize,
+        queue: &mut DispatchQueue,
+    ) {
+        let channel = &mut self.channels[channel_id];
+        let mut count = 0;
+        for incoming in &channel.incoming_unreliable_commands {
+            if incoming.command.number() == protocol::COMMAND_SEND_UNRELIABLE {
+                if incoming.reliable_sequence_number != channel.incoming_reliable_sequence_number {
+                    break;
+                }
+                channel.incoming_unreliable_sequence_number = incoming.unreliable_sequence_number;
+            }
+            count += 1;
+        }
+        if count == 0 {
+            return;
+        }
+        self.dispatched_commands.extend(
+            channel
+                .incoming_unreliable_commands
+                .drain(..count)
+                .map(|incoming| (incoming.command.channel_id, incoming.packet)),
+        );
+        queue.push(self);
+    }
+
+    pub(crate) fn dispatch_incoming_reliable_commands(
+        &mut self,
+        channel_id: usize,
+        queue: &mut DispatchQueue,
+    ) {
+        let channel = &mut self.channels[channel_id];
+        let mut count = 0;
+        for incoming in &channel.incoming_reliable_commands {
+            if incoming.fragments_remaining > 0
+                || incoming.reliable_sequence_number
+                    != channel.incoming_reliable_sequence_number.wrapping_add(1)
+            {
+                break;
+            }
+            channel.incoming_reliable_sequence_number = incoming.reliable_sequence_number;
+            if incoming.fragment_count > 0 {
+                channel.incoming_reliable_sequence_number = channel
+                    .incoming_reliable_sequence_number
+                    .wrapping_add((incoming.fragment_count - 1) as u16);
+            }
+            count += 1;
+        }
+        if count == 0 {
+            return;
+        }
+        channel.incoming_unreliable_sequence_number = 0;
+        self.dispatched_commands.extend(
+            channel
+                .incoming_reliable_commands
+                .drain(..count)
+                .map(|incoming| (incoming.command.channel_id, incoming.packet)),
+        );
+        queue.push(self);
+        self.dispatch_incoming_unreliable_commands(channel_id, queue);
+    }
+
+    /// Queues a received command on its channel. Returns whether the command has been queued, it is
+    /// silently dropped if it is a duplicate or lies outside of the receive window.
+    pub(crate) fn queue_incoming_command(
+        &mut self,
+        command: &Command,
+        packet: Packet,
+        fragment_count: u32,
+        queue: &mut DispatchQueue,
+    ) -> core::result::Result<bool, CommandError> {
+        // fragments can't be dropped silently, as the caller expects to find the start command
+        let discard = if fragment_count > 0 {
+            Err(CommandError)
+        } else {
+            Ok(false)
+        };
+        if self.state == PeerState::DisconnectLater {
+            return discard;
+        }
+        let channel_id = command.channel_id as usize;
+        let channel = &mut self.channels[channel_id];
+        let number = command.number();
+        let reliable_sequence_number = command.reliable_sequence_number;
+        let mut unreliable_sequence_number = 0;
+
+        if number != protocol::COMMAND_SEND_UNSEQUENCED {
+            let mut reliable_window = reliable_sequence_number / PEER_RELIABLE_WINDOW_SIZE;
+            let current_window =
+                channel.incoming_reliable_sequence_number / PEER_RELIABLE_WINDOW_SIZE;
+            if reliable_sequence_number < channel.incoming_reliable_sequence_number {
+                reliable_window += PEER_RELIABLE_WINDOWS;
+            }
+            if reliable_window < current_window
+                || reliable_window >= current_window + PEER_FREE_RELIABLE_WINDOWS - 1
+            {
+                return discard;
+            }
+        }
+
+        let incoming_rsn = channel.incoming_reliable_sequence_number;
+        let (list, position) = match number {
+            protocol::COMMAND_SEND_FRAGMENT | protocol::COMMAND_SEND_RELIABLE => {
+                if reliable_sequence_number == incoming_rsn {
+                    return discard;
+                }
+                let list = &mut channel.incoming_reliable_commands;
+                let mut position = 0;
+                for (i, incoming) in list.iter().enumerate().rev() {
+                    if reliable_sequence_number >= incoming_rsn {
+                        if incoming.reliable_sequence_number < incoming_rsn {
+                            continue;
+                        }
+                    } else if incoming.reliable_sequence_number >= incoming_rsn {
+                        position = i + 1;
+                        break;
+                    }
+                    if incoming.reliable_sequence_number <= reliable_sequence_number {
+                        if incoming.reliable_sequence_number < reliable_sequence_number {
+                            position = i + 1;
+                            break;
+                        }
+                        return discard;
+                    }
+                }
+                (list, position)
+            },
+            protocol::COMMAND_SEND_UNRELIABLE => {
+                if let CommandBody::SendUnreliable {
+                    unreliable_sequence_number: usn,
+                    ..
+                } = command.body
+                {
+                    unreliable_sequence_number = usn;
+                }
+                if reliable_sequence_number == incoming_rsn
+                    && unreliable_sequence_number <= channel.incoming_unreliable_sequence_number
+                {
+                    return discard;
+                }
+                let list = &mut channel.incoming_unreliable_commands;
+                let mut position = 0;
+                for (i, incoming) in list.iter().enumerate().rev() {
+                    if incoming.command.number() != protocol::COMMAND_SEND_UNRELIABLE {
+                        continue;
+                    }
+                    if reliable_sequence_number >= incoming_rsn {
+                        if incoming.reliable_sequence_number < incoming_rsn {
+                            continue;
+                        }
+                    } else if incoming.reliable_sequence_number >= incoming_rsn {
+                        position = i + 1;
+                        break;
+                    }
+                    if incoming.reliable_sequence_number < reliable_sequence_number {
+                        position = i + 1;
+                        break;
+                    }
+                    if incoming.reliable_sequence_number > reliable_sequence_number {
+                        continue;
+                    }
+                    if incoming.unreliable_sequence_number <= unreliable_sequence_number {
+                        if incoming.unreliable_sequence_number < unreliable_sequence_number {
+                            position = i + 1;
+                            break;
+                        }
+                        return discard;
+                    }
+                }
+                (list, position)
+            },
+            // unsequenced commands skip the queue
+            protocol::COMMAND_SEND_UNSEQUENCED => (&mut channel.incoming_unreliable_commands, 0),
+            _ => return discard,
+        };
+
+        list.insert(
+            position,
+            IncomingCommand {
+                reliable_sequence_number,
+                unreliable_sequence_number,
+                command: *command,
+                fragment_count,
+                fragments_remaining: fragment_count,
+                fragments: vec![0; fragment_count.div_ceil(32) as usize],
+                packet,
+            },
+        );
+
+        match number {
+            protocol::COMMAND_SEND_FRAGMENT | protocol::COMMAND_SEND_RELIABLE => {
+                self.dispatch_incoming_reliable_commands(channel_id, queue)
+            },
+            _ => self.dispatch_incoming_unreliable_commands(channel_id, queue),
+        }
+        Ok(true)
+    }
+
+    pub(crate) fn has_pending_outgoing(&self) -> bool {
+        !(self.outgoing_reliable_commands.is_empty()
+            && self.outgoing_unreliable_commands.is_empty()
+            && self.sent_reliable_commands.is_empty())
+    }
+}
+
+/// A handle to a peer of a pure [`Host`](super::Host), see [`crate::PeerHandle`].
+pub struct PeerHandle<T> {
+    host: Arc<Mutex<HostState<T>>>,
+    index: usize,
+    generation: u32,
+}
+
+impl<T> PeerHandle<T> {
+    pub(crate) fn new(host: Arc<Mutex<HostState<T>>>, index: usize, generation: u32) -> Self {
+        PeerHandle {
+            host,
+            index,
+            generation,
+        }
+    }
+
+    /// Locks the host and runs `f` on it if this handle is still valid.
+    fn with_peer<R>(&self, f: impl FnOnce(&mut HostState<T>, usize) -> R) -> Result<R> {
+        let mut host = self.host.lock().unwrap_or_else(|e| e.into_inner());
+        let peer = &host.peers[self.index];
+        if peer.state != PeerState::Disconnected && peer.generation == self.generation {
+            Ok(f(&mut host, self.index))
+        } else {
+            Err(Error::PeerDisconnected)
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.with_peer(|_, _| ()).is_ok()
+    }
+
+    pub fn id(&self) -> u16 {
+        self.index as u16
+    }
+
+    pub fn address(&self) -> Result<Address> {
+        self.with_peer(|host, index| host.peers[index].address)
+    }
+
+    pub fn stats(&self) -> Result<PeerStats> {
+        self.with_peer(|host, index| {
+            let peer = &host.peers[index];
+            PeerStats {
+                round_trip_time: peer.round_trip_time,
+                round_trip_time_variance: peer.round_trip_time_variance,
+                packet_loss: peer.packet_loss as f32 / PEER_PACKET_LOSS_SCALE as f32,
+            }
+        })
+    }
+
+    pub fn send(&self, channel_id: u8, packet: Packet) -> Result<()> {
+        self.with_peer(|host, index| host.peers[index].send(channel_id, packet))?
+    }
+
+    pub fn disconnect(&self, data: u32) {
+        let _ = self.with_peer(|host, index| host.disconnect(index, data));
+    }
+
+    pub fn disconnect_later(&self, data: u32) {
+        let _ = self.with_peer(|host, index| host.disconnect_later(index, data));
+    }
+
+    pub fn disconnect_now(&self, data: u32) -> Option<T> {
+        self.with_peer(|host, index| host.disconnect_now(index, data))
+            .ok()
+            .and_then(|data| data)
+    }
+
+    pub fn set_data(&self, data: Option<T>) -> Result<()> {
+        self.with_peer(|host, index| host.peers[index].data = data)
+    }
+
+    pub fn data(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.with_peer(|host, index| host.peers[index].data.clone())
+            .ok()
+            .and_then(|data| data)
+    }
+}
+
+impl<T> Clone for PeerHandle<T> {
+    fn clone(&self) -> Self {
+        PeerHandle {
+            host: self.host.clone(),
+            index: self.index,
+            generation: self.generation,
+        }
+    }
+}
+
+impl<T> PartialEq for PeerHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.host, &other.host)
+            && self.index == other.index
+            && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for PeerHandle<T> {}
+
+impl<T> fmt::Debug for PeerHandle<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("PeerHandle")
+            .field("id", &self.index)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
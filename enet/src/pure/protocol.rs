@@ -0,0 +1,410 @@
+//! The LENet wire format. All multi-byte fields are sent in network byte order, except for the
+//! session id which the C implementation copies around verbatim.
+
+pub(crate) const MINIMUM_MTU: u16 = 576;
+pub(crate) const MAXIMUM_MTU: u16 = 4096;
+pub(crate) const MAXIMUM_PACKET_COMMANDS: usize = 32;
+/// The header plus a command and its payload for every command in a datagram.
+pub(crate) const MAXIMUM_BUFFERS: usize = 1 + 2 * MAXIMUM_PACKET_COMMANDS;
+pub(crate) const MINIMUM_WINDOW_SIZE: u32 = 4096;
+pub(crate) const MAXIMUM_WINDOW_SIZE: u32 = 32768;
+pub(crate) const MINIMUM_CHANNEL_COUNT: usize = 1;
+pub(crate) const MAXIMUM_CHANNEL_COUNT: usize = 255;
+pub(crate) const MAXIMUM_PEER_ID: u16 = 0x7FFF;
+
+pub(crate) const COMMAND_ACKNOWLEDGE: u8 = 1;
+pub(crate) const COMMAND_CONNECT: u8 = 2;
+pub(crate) const COMMAND_VERIFY_CONNECT: u8 = 3;
+pub(crate) const COMMAND_DISCONNECT: u8 = 4;
+pub(crate) const COMMAND_PING: u8 = 5;
+pub(crate) const COMMAND_SEND_RELIABLE: u8 = 6;
+pub(crate) const COMMAND_SEND_UNRELIABLE: u8 = 7;
+pub(crate) const COMMAND_SEND_FRAGMENT: u8 = 8;
+pub(crate) const COMMAND_SEND_UNSEQUENCED: u8 = 9;
+pub(crate) const COMMAND_BANDWIDTH_LIMIT: u8 = 10;
+pub(crate) const COMMAND_THROTTLE_CONFIGURE: u8 = 11;
+pub(crate) const COMMAND_COUNT: u8 = 12;
+pub(crate) const COMMAND_MASK: u8 = 0x0F;
+
+pub(crate) const COMMAND_FLAG_ACKNOWLEDGE: u8 = 1 << 7;
+pub(crate) const COMMAND_FLAG_UNSEQUENCED: u8 = 1 << 6;
+
+pub(crate) const HEADER_FLAG_SENT_TIME: u16 = 1 << 15;
+pub(crate) const HEADER_FLAG_MASK: u16 = 0x8000;
+
+/// Size of the protocol header including the optional sent time.
+pub(crate) const HEADER_SIZE: usize = 8;
+/// Size of the protocol header without the sent time.
+pub(crate) const HEADER_SIZE_NO_SENT_TIME: usize = 6;
+pub(crate) const COMMAND_HEADER_SIZE: usize = 4;
+
+/// The size of each command including its header, indexed by command number. These match the
+/// struct sizes of the C implementation which is what ends up on the wire.
+const COMMAND_SIZES: [usize; COMMAND_COUNT as usize] = [0, 8, 40, 36, 8, 4, 6, 8, 24, 8, 12, 16];
+
+pub(crate) fn command_size(command: u8) -> usize {
+    COMMAND_SIZES[(command & COMMAND_MASK) as usize]
+}
+
+/// The fields shared by the connect and verify connect commands.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct ConnectParams {
+    pub outgoing_peer_id: u16,
+    pub mtu: u16,
+    pub window_size: u32,
+    pub channel_count: u32,
+    pub incoming_bandwidth: u32,
+    pub outgoing_bandwidth: u32,
+    pub packet_throttle_interval: u32,
+    pub packet_throttle_acceleration: u32,
+    pub packet_throttle_deceleration: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum CommandBody {
+    Acknowledge {
+        received_reliable_sequence_number: u16,
+        received_sent_time: u16,
+    },
+    Connect {
+        params: ConnectParams,
+        session_id: u32,
+    },
+    VerifyConnect(ConnectParams),
+    Disconnect {
+        data: u32,
+    },
+    Ping,
+    SendReliable {
+        data_length: u16,
+    },
+    SendUnreliable {
+        unreliable_sequence_number: u16,
+        data_length: u16,
+    },
+    SendFragment {
+        start_sequence_number: u16,
+        data_length: u16,
+        fragment_count: u32,
+        fragment_number: u32,
+        total_length: u32,
+        fragment_offset: u32,
+    },
+    SendUnsequenced {
+        unsequenced_group: u16,
+        data_length: u16,
+    },
+    BandwidthLimit {
+        incoming_bandwidth: u32,
+        outgoing_bandwidth: u32,
+    },
+    ThrottleConfigure {
+        packet_throttle_interval: u32,
+        packet_throttle_acceleration: u32,
+        packet_throttle_deceleration: u32,
+    },
+}
+
+impl CommandBody {
+    pub(crate) fn number(&self) -> u8 {
+        match self {
+            CommandBody::Acknowledge { .. } => COMMAND_ACKNOWLEDGE,
+            CommandBody::Connect { .. } => COMMAND_CONNECT,
+            CommandBody::VerifyConnect(_) => COMMAND_VERIFY_CONNECT,
+            CommandBody::Disconnect { .. } => COMMAND_DISCONNECT,
+            CommandBody::Ping => COMMAND_PING,
+            CommandBody::SendReliable { .. } => COMMAND_SEND_RELIABLE,
+            CommandBody::SendUnreliable { .. } => COMMAND_SEND_UNRELIABLE,
+            CommandBody::SendFragment { .. } => COMMAND_SEND_FRAGMENT,
+            CommandBody::SendUnsequenced { .. } => COMMAND_SEND_UNSEQUENCED,
+            CommandBody::BandwidthLimit { .. } => COMMAND_BANDWIDTH_LIMIT,
+            CommandBody::ThrottleConfigure { .. } => COMMAND_THROTTLE_CONFIGURE,
+        }
+    }
+
+    /// The length of the payload following this command.
+    pub(crate) fn data_length(&self) -> usize {
+        match *self {
+            CommandBody::SendReliable { data_length }
+            | CommandBody::SendUnreliable { data_length, .. }
+            | CommandBody::SendFragment { data_length, .. }
+            | CommandBody::SendUnsequenced { data_length, .. } => data_length as usize,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Command {
+    /// The command number together with its flags.
+    pub flags: u8,
+    pub channel_id: u8,
+    pub reliable_sequence_number: u16,
+    pub body: CommandBody,
+}
+
+impl Command {
+    pub(crate) fn new(flags: u8, channel_id: u8, body: CommandBody) -> Self {
+        Command {
+            flags: flags | body.number(),
+            channel_id,
+            reliable_sequence_number: 0,
+            body,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn number(&self) -> u8 {
+        self.flags & COMMAND_MASK
+    }
+
+    #[inline]
+    pub(crate) fn size(&self) -> usize {
+        command_size(self.flags)
+    }
+
+    #[inline]
+    pub(crate) fn is_acknowledged(&self) -> bool {
+        self.flags & COMMAND_FLAG_ACKNOWLEDGE != 0
+    }
+
+    #[inline]
+    pub(crate) fn is_unsequenced(&self) -> bool {
+        self.flags & COMMAND_FLAG_UNSEQUENCED != 0
+    }
+
+    pub(crate) fn write(&self, buf: &mut Vec<u8>) {
+        buf.push(self.flags);
+        buf.push(self.channel_id);
+        buf.extend_from_slice(&self.reliable_sequence_number.to_be_bytes());
+        match self.body {
+            CommandBody::Acknowledge {
+                received_reliable_sequence_number,
+                received_sent_time,
+            } => {
+                buf.extend_from_slice(&received_reliable_sequence_number.to_be_bytes());
+                buf.extend_from_slice(&received_sent_time.to_be_bytes());
+            },
+            CommandBody::Connect { params, session_id } => {
+                write_connect_params(buf, &params);
+                buf.extend_from_slice(&session_id.to_ne_bytes());
+            },
+            CommandBody::VerifyConnect(params) => write_connect_params(buf, &params),
+            CommandBody::Disconnect { data } => buf.extend_from_slice(&data.to_be_bytes()),
+            CommandBody::Ping => (),
+            CommandBody::SendReliable { data_length } => {
+                buf.extend_from_slice(&data_length.to_be_bytes())
+            },
+            CommandBody::SendUnreliable {
+                unreliable_sequence_number,
+                data_length,
+            } => {
+                buf.extend_from_slice(&unreliable_sequence_number.to_be_bytes());
+                buf.extend_from_slice(&data_length.to_be_bytes());
+            },
+            CommandBody::SendFragment {
+                start_sequence_number,
+                data_length,
+                fragment_count,
+                fragment_number,
+                total_length,
+                fragment_offset,
+            } => {
+                buf.extend_from_slice(&start_sequence_number.to_be_bytes());
+                buf.extend_from_slice(&data_length.to_be_bytes());
+                buf.extend_from_slice(&fragment_count.to_be_bytes());
+                buf.extend_from_slice(&fragment_number.to_be_bytes());
+                buf.extend_from_slice(&total_length.to_be_bytes());
+                buf.extend_from_slice(&fragment_offset.to_be_bytes());
+            },
+            CommandBody::SendUnsequenced {
+                unsequenced_group,
+                data_length,
+            } => {
+                buf.extend_from_slice(&unsequenced_group.to_be_bytes());
+                buf.extend_from_slice(&data_length.to_be_bytes());
+            },
+            CommandBody::BandwidthLimit {
+                incoming_bandwidth,
+                outgoing_bandwidth,
+            } => {
+                buf.extend_from_slice(&incoming_bandwidth.to_be_bytes());
+                buf.extend_from_slice(&outgoing_bandwidth.to_be_bytes());
+            },
+            CommandBody::ThrottleConfigure {
+                packet_throttle_interval,
+                packet_throttle_acceleration,
+                packet_throttle_deceleration,
+            } => {
+                buf.extend_from_slice(&packet_throttle_interval.to_be_bytes());
+                buf.extend_from_slice(&packet_throttle_acceleration.to_be_bytes());
+                buf.extend_from_slice(&packet_throttle_deceleration.to_be_bytes());
+            },
+        }
+    }
+
+    /// Reads a single command from the start of `data`. Returns `None` if the data is too short or
+    /// the command number is unknown, in which case the rest of the datagram should be ignored.
+    pub(crate) fn read(data: &[u8]) -> Option<Command> {
+        if data.len() < COMMAND_HEADER_SIZE {
+            return None;
+        }
+        let flags = data[0];
+        let number = flags & COMMAND_MASK;
+        if number >= COMMAND_COUNT {
+            return None;
+        }
+        let size = command_size(number);
+        if size == 0 || data.len() < size {
+            return None;
+        }
+        let mut reader = Reader(&data[2..size]);
+        let channel_id = data[1];
+        let reliable_sequence_number = reader.u16();
+        let body = match number {
+            COMMAND_ACKNOWLEDGE => CommandBody::Acknowledge {
+                received_reliable_sequence_number: reader.u16(),
+                received_sent_time: reader.u16(),
+            },
+            COMMAND_CONNECT => CommandBody::Connect {
+                params: read_connect_params(&mut reader),
+                session_id: reader.u32_ne(),
+            },
+            COMMAND_VERIFY_CONNECT => CommandBody::VerifyConnect(read_connect_params(&mut reader)),
+            COMMAND_DISCONNECT => CommandBody::Disconnect { data: reader.u32() },
+            COMMAND_PING => CommandBody::Ping,
+            COMMAND_SEND_RELIABLE => CommandBody::SendReliable {
+                data_length: reader.u16(),
+            },
+            COMMAND_SEND_UNRELIABLE => CommandBody::SendUnreliable {
+                unreliable_sequence_number: reader.u16(),
+                data_length: reader.u16(),
+            },
+            COMMAND_SEND_FRAGMENT => CommandBody::SendFragment {
+                start_sequence_number: reader.u16(),
+                data_length: reader.u16(),
+                fragment_count: reader.u32(),
+                fragment_number: reader.u32(),
+                total_length: reader.u32(),
+                fragment_offset: reader.u32(),
+            },
+            COMMAND_SEND_UNSEQUENCED => CommandBody::SendUnsequenced {
+                unsequenced_group: reader.u16(),
+                data_length: reader.u16(),
+            },
+            COMMAND_BANDWIDTH_LIMIT => CommandBody::BandwidthLimit {
+                incoming_bandwidth: reader.u32(),
+                outgoing_bandwidth: reader.u32(),
+            },
+            COMMAND_THROTTLE_CONFIGURE => CommandBody::ThrottleConfigure {
+                packet_throttle_interval: reader.u32(),
+                packet_throttle_acceleration: reader.u32(),
+                packet_throttle_deceleration: reader.u32(),
+            },
+            _ => return None,
+        };
+        Some(Command {
+            flags,
+            channel_id,
+            reliable_sequence_number,
+            body,
+        })
+    }
+}
+
+fn write_connect_params(buf: &mut Vec<u8>, params: &ConnectParams) {
+    buf.extend_from_slice(&params.outgoing_peer_id.to_be_bytes());
+    buf.extend_from_slice(&params.mtu.to_be_bytes());
+    buf.extend_from_slice(&params.window_size.to_be_bytes());
+    buf.extend_from_slice(&params.channel_count.to_be_bytes());
+    buf.extend_from_slice(&params.incoming_bandwidth.to_be_bytes());
+    buf.extend_from_slice(&params.outgoing_bandwidth.to_be_bytes());
+    buf.extend_from_slice(&params.packet_throttle_interval.to_be_bytes());
+    buf.extend_from_slice(&params.packet_throttle_acceleration.to_be_bytes());
+    buf.extend_from_slice(&params.packet_throttle_deceleration.to_be_bytes());
+}
+
+fn read_connect_params(reader: &mut Reader<'_>) -> ConnectParams {
+    ConnectParams {
+        outgoing_peer_id: reader.u16(),
+        mtu: reader.u16(),
+        window_size: reader.u32(),
+        channel_count: reader.u32(),
+        incoming_bandwidth: reader.u32(),
+        outgoing_bandwidth: reader.u32(),
+        packet_throttle_interval: reader.u32(),
+        packet_throttle_acceleration: reader.u32(),
+        packet_throttle_deceleration: reader.u32(),
+    }
+}
+
+/// A cursor over a command whose size has already been checked.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> &[u8] {
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        head
+    }
+
+    fn u16(&mut self) -> u16 {
+        let bytes = self.take(2);
+        u16::from_be_bytes([bytes[0], bytes[1]])
+    }
+
+    fn u32(&mut self) -> u32 {
+        let bytes = self.take(4);
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn u32_ne(&mut self) -> u32 {
+        let bytes = self.take(4);
+        u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+}
+
+/// The header every datagram starts with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub session_id: u32,
+    /// The peer id together with the header flags.
+    pub peer_id: u16,
+    pub sent_time: Option<u16>,
+}
+
+impl Header {
+    pub(crate) fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.session_id.to_ne_bytes());
+        match self.sent_time {
+            Some(sent_time) => {
+                buf.extend_from_slice(&(self.peer_id | HEADER_FLAG_SENT_TIME).to_be_bytes());
+                buf.extend_from_slice(&sent_time.to_be_bytes());
+            },
+            None => buf.extend_from_slice(&self.peer_id.to_be_bytes()),
+        }
+    }
+
+    /// Reads the header, returning it together with its size.
+    pub(crate) fn read(data: &[u8]) -> Option<(Header, usize)> {
+        // the C implementation drops everything shorter than the full header
+        if data.len() < HEADER_SIZE {
+            return None;
+        }
+        let session_id = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]);
+        let peer_id = u16::from_be_bytes([data[4], data[5]]);
+        let (sent_time, size) = if peer_id & HEADER_FLAG_SENT_TIME != 0 {
+            (Some(u16::from_be_bytes([data[6], data[7]])), HEADER_SIZE)
+        } else {
+            (None, HEADER_SIZE_NO_SENT_TIME)
+        };
+        Some((
+            Header {
+                session_id,
+                peer_id: peer_id & !HEADER_FLAG_MASK,
+                sent_time,
+            },
+            size,
+        ))
+    }
+}
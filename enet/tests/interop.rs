@@ -0,0 +1,245 @@
+//! Talks to the C implementation with the pure one and vice versa over loopback.
+
+use std::{
+    net::{Ipv4Addr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use enet::PacketMode;
+
+#[derive(Debug)]
+enum Seen<P> {
+    Connect(P),
+    Disconnect {
+        data: Option<u32>,
+        reason: u32,
+    },
+    Receive {
+        channel_id: u8,
+        data: Vec<u8>,
+        mode: PacketMode,
+    },
+}
+
+impl<P> Seen<P> {
+    fn is_connect(&self) -> bool {
+        matches!(self, Seen::Connect(_))
+    }
+
+    fn into_receive(self) -> Option<(u8, Vec<u8>, PacketMode)> {
+        match self {
+            Seen::Receive {
+                channel_id,
+                data,
+                mode,
+            } => Some((channel_id, data, mode)),
+            _ => None,
+        }
+    }
+}
+
+/// Generates the same set of helpers for both implementations, they share their API after all.
+macro_rules! backend {
+    ($name:ident, $($path:ident)::+) => {
+        mod $name {
+            use super::Seen;
+            use $($path)::+::{Address, Event, Host, Packet, PacketMode, PeerHandle};
+
+            pub type TestHost = Host<u32>;
+            pub type TestPeer = PeerHandle<u32>;
+
+            pub fn server(port: u16) -> TestHost {
+                Host::new(Some(Address::new(super::Ipv4Addr::LOCALHOST, port)), 4).unwrap()
+            }
+
+            pub fn client(port: u16) -> (TestHost, TestPeer) {
+                let mut host = Host::new(None, 1).unwrap();
+                let peer = connect(&mut host, port);
+                (host, peer)
+            }
+
+            pub fn connect(host: &mut TestHost, port: u16) -> TestPeer {
+                host.connect(Address::new(super::Ipv4Addr::LOCALHOST, port), 4)
+                    .unwrap()
+            }
+
+            pub fn poll(host: &mut TestHost, events: &mut Vec<Seen<TestPeer>>) {
+                while let Some(event) = host.service(0).unwrap() {
+                    events.push(match event {
+                        Event::Connect(peer) => Seen::Connect(peer),
+                        Event::Disconnect { data, reason, .. } => Seen::Disconnect { data, reason },
+                        Event::Receive {
+                            channel_id, packet, ..
+                        } => Seen::Receive {
+                            channel_id,
+                            mode: packet.mode(),
+                            data: packet.to_vec(),
+                        },
+                    });
+                }
+            }
+
+            pub fn send(peer: &TestPeer, packets: &[(u8, Vec<u8>, PacketMode)]) {
+                for (channel_id, data, mode) in packets {
+                    peer.send(*channel_id, Packet::new(data, *mode).unwrap())
+                        .unwrap();
+                }
+            }
+        }
+    };
+}
+
+backend!(c, enet);
+backend!(pure, enet::pure);
+
+/// Grabs a port that is free right now, the C host doesn't tell us which port it got.
+fn free_port() -> u16 {
+    UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|socket| socket.local_addr())
+        .unwrap()
+        .port()
+}
+
+fn packets() -> Vec<(u8, Vec<u8>, PacketMode)> {
+    let mut packets: Vec<_> = (0..20u8)
+        .map(|i| (0, vec![i; 16], PacketMode::Reliable))
+        .collect();
+    packets.push((1, b"unreliable".to_vec(), PacketMode::Unreliable));
+    packets.push((2, b"unsequenced".to_vec(), PacketMode::Unsequenced));
+    // large enough to be split into several fragments
+    let large = (0..10_000u32).map(|i| (i ^ (i >> 8)) as u8).collect();
+    packets.push((3, large, PacketMode::Reliable));
+    packets.push((0, b"after the fragments".to_vec(), PacketMode::Reliable));
+    packets
+}
+
+fn received<P>(events: &mut Vec<Seen<P>>) -> Vec<(u8, Vec<u8>, PacketMode)> {
+    by_channel(events.drain(..).filter_map(Seen::into_receive).collect())
+}
+
+/// Sorts by channel only so the order within each channel is kept.
+fn by_channel(mut packets: Vec<(u8, Vec<u8>, PacketMode)>) -> Vec<(u8, Vec<u8>, PacketMode)> {
+    packets.sort_by_key(|&(channel_id, ..)| channel_id);
+    packets
+}
+
+macro_rules! interop_test {
+    ($name:ident, $server:ident, $client:ident) => {
+        #[test]
+        fn $name() {
+            let port = free_port();
+            let mut server = $server::server(port);
+            let (mut client, client_peer) = $client::client(port);
+            let mut server_events = Vec::new();
+            let mut client_events = Vec::new();
+
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let pump = |server: &mut $server::TestHost,
+                        client: &mut $client::TestHost,
+                        server_events: &mut Vec<Seen<$server::TestPeer>>,
+                        client_events: &mut Vec<Seen<$client::TestPeer>>,
+                        done: &dyn Fn(&[Seen<_>], &[Seen<_>]) -> bool| {
+                while !done(server_events, client_events) {
+                    assert!(Instant::now() < deadline, "timed out");
+                    $server::poll(server, server_events);
+                    $client::poll(client, client_events);
+                    thread::sleep(Duration::from_millis(1));
+                }
+            };
+
+            pump(
+                &mut server,
+                &mut client,
+                &mut server_events,
+                &mut client_events,
+                &|s, c| s.iter().any(Seen::is_connect) && c.iter().any(Seen::is_connect),
+            );
+            let server_peer = match server_events.remove(0) {
+                Seen::Connect(peer) => peer,
+                event => panic!("unexpected event {:?}", event),
+            };
+            client_events.clear();
+            server_peer.set_data(Some(7)).unwrap();
+            client_peer.set_data(Some(9)).unwrap();
+
+            let packets = packets();
+            $client::send(&client_peer, &packets);
+            $server::send(&server_peer, &packets);
+            pump(
+                &mut server,
+                &mut client,
+                &mut server_events,
+                &mut client_events,
+                &|s, c| s.len() == packets.len() && c.len() == packets.len(),
+            );
+            assert_eq!(received(&mut server_events), by_channel(packets.clone()));
+            assert_eq!(received(&mut client_events), by_channel(packets.clone()));
+
+            // the server kicks the client
+            server_peer.disconnect(42);
+            pump(
+                &mut server,
+                &mut client,
+                &mut server_events,
+                &mut client_events,
+                &|s, c| !s.is_empty() && !c.is_empty(),
+            );
+            match (&server_events[..], &client_events[..]) {
+                (
+                    [Seen::Disconnect {
+                        data: Some(7),
+                        reason: 0,
+                    }],
+                    [Seen::Disconnect {
+                        data: Some(9),
+                        reason: 42,
+                    }],
+                ) => (),
+                events => panic!("unexpected events {:?}", events),
+            }
+            assert!(!server_peer.is_connected());
+            assert!(!client_peer.is_connected());
+            server_events.clear();
+            client_events.clear();
+
+            // and the client leaves on its own after reconnecting
+            let client_peer = $client::connect(&mut client, port);
+            pump(
+                &mut server,
+                &mut client,
+                &mut server_events,
+                &mut client_events,
+                &|s, c| s.iter().any(Seen::is_connect) && c.iter().any(Seen::is_connect),
+            );
+            server_events.clear();
+            client_events.clear();
+            client_peer.disconnect(43);
+            pump(
+                &mut server,
+                &mut client,
+                &mut server_events,
+                &mut client_events,
+                &|s, c| !s.is_empty() && !c.is_empty(),
+            );
+            match (&server_events[..], &client_events[..]) {
+                (
+                    [Seen::Disconnect {
+                        data: None,
+                        reason: 43,
+                    }],
+                    [Seen::Disconnect {
+                        data: None,
+                        reason: 0,
+                    }],
+                ) => (),
+                events => panic!("unexpected events {:?}", events),
+            }
+        }
+    };
+}
+
+interop_test!(pure_server_c_client, pure, c);
+interop_test!(c_server_pure_client, c, pure);
+interop_test!(pure_server_pure_client, pure, pure);
+interop_test!(c_server_c_client, c, c);
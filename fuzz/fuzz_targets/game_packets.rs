@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// whole packets as they come off a game channel, batches and headers included
+fuzz_target!(|data: &[u8]| {
+    let _ = rblitz::fuzz::game_packet(data);
+    let _ = rblitz::fuzz::key_check(data);
+});
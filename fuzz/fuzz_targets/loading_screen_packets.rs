@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rblitz_packets::packets::registry::{self, Reencode};
+
+// the first byte picks the packet, the rest is its payload
+fuzz_target!(|data: &[u8]| {
+    if let Some((&id, payload)) = data.split_first() {
+        let _ = registry::loading_screen_packet(id, Reencode(payload));
+    }
+});
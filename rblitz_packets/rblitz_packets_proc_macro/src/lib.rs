@@ -1,8 +1,14 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
+use proc_macro2::{Literal, TokenStream as TokenStream2};
 use quote::quote;
-use syn::{parse_macro_input, Expr, ItemStruct};
+use syn::{
+    parenthesized,
+    parse::{Parse, ParseStream},
+    parse_macro_input, Attribute, Expr, ExprLit, ExprRange, Fields, Ident, ItemStruct, Lit,
+    RangeLimits, Type,
+};
 
 #[proc_macro_attribute]
 pub fn packet_id(args: TokenStream, input: TokenStream) -> TokenStream {
@@ -18,3 +24,188 @@ pub fn packet_id(args: TokenStream, input: TokenStream) -> TokenStream {
     };
     out.into()
 }
+
+/// Packs the fields of a struct into the bits of a single integer, given by `#[bitfield(..)]`.
+/// Every field names the bits it occupies with `#[bits(..)]`, either a single bit like `#[bits(4)]`
+/// or a range like `#[bits(0..4)]`. `bool`s take a single bit, other fields are cut down to the
+/// width of their bits when encoding.
+#[proc_macro_derive(Bitfield, attributes(bitfield, bits))]
+pub fn derive_bitfield(input: TokenStream) -> TokenStream {
+    let strukt: ItemStruct = parse_macro_input!(input as ItemStruct);
+    match bitfield(&strukt) {
+        Ok(out) => out.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct BitfieldField<'a> {
+    ident: &'a Ident,
+    ty: &'a Type,
+    start: u32,
+    len: u32,
+}
+
+fn bitfield(strukt: &ItemStruct) -> syn::Result<TokenStream2> {
+    let ident = &strukt.ident;
+    let storage: Ident = find_attr(&strukt.attrs, "bitfield")
+        .ok_or_else(|| {
+            syn::Error::new(
+                ident.span(),
+                "bitfields need a `#[bitfield(..)]` naming the integer they're packed into",
+            )
+        })
+        .and_then(parse_parenthesized)?;
+    let width = match storage.to_string().as_str() {
+        "u8" => 8,
+        "u16" => 16,
+        "u32" => 32,
+        "u64" => 64,
+        _ => {
+            return Err(syn::Error::new(
+                storage.span(),
+                "bitfields are packed into one of u8, u16, u32 or u64",
+            ))
+        },
+    };
+
+    let named = match &strukt.fields {
+        Fields::Named(named) => named,
+        _ => return Err(syn::Error::new(ident.span(), "bitfields need named fields")),
+    };
+    let mut fields = Vec::new();
+    let mut used = 0u64;
+    for field in &named.named {
+        let field_ident = field.ident.as_ref().unwrap();
+        let attr = find_attr(&field.attrs, "bits").ok_or_else(|| {
+            syn::Error::new(field_ident.span(), "every field needs a `#[bits(..)]`")
+        })?;
+        let (start, len) = bit_range(&parse_parenthesized(attr)?)?;
+        if len == 0 || start.saturating_add(len) > width {
+            return Err(syn::Error::new(
+                field_ident.span(),
+                format!("the bits of this field don't fit into a {}", storage),
+            ));
+        }
+        if is_bool(&field.ty) && len != 1 {
+            return Err(syn::Error::new(
+                field_ident.span(),
+                "`bool`s take exactly one bit",
+            ));
+        }
+        let mask = mask(len) << start;
+        if used & mask != 0 {
+            return Err(syn::Error::new(
+                field_ident.span(),
+                "this field overlaps the bits of another one",
+            ));
+        }
+        used |= mask;
+        fields.push(BitfieldField {
+            ident: field_ident,
+            ty: &field.ty,
+            start,
+            len,
+        });
+    }
+
+    let decoders = fields.iter().map(|field| {
+        let BitfieldField {
+            ident, ty, start, ..
+        } = field;
+        let mask = Literal::u64_unsuffixed(mask(field.len));
+        if is_bool(ty) {
+            quote!(#ident: (bits >> #start) & 1 != 0)
+        } else {
+            quote!(#ident: ((bits >> #start) & #mask) as #ty)
+        }
+    });
+    let encoders = fields.iter().map(|field| {
+        let BitfieldField { ident, start, .. } = field;
+        let mask = Literal::u64_unsuffixed(mask(field.len));
+        quote!(bits |= (self.#ident as #storage & #mask) << #start;)
+    });
+    Ok(quote! {
+        impl<'de> serde::Deserialize<'de> for #ident {
+            fn deserialize<D>(d: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let bits: #storage = serde::Deserialize::deserialize(d)?;
+                Ok(#ident {
+                    #(#decoders,)*
+                })
+            }
+        }
+
+        impl serde::Serialize for #ident {
+            fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                let mut bits: #storage = 0;
+                #(#encoders)*
+                serde::Serialize::serialize(&bits, s)
+            }
+        }
+    })
+}
+
+fn mask(len: u32) -> u64 {
+    if len >= 64 {
+        !0
+    } else {
+        (1 << len) - 1
+    }
+}
+
+fn find_attr<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a Attribute> {
+    attrs.iter().find(|attr| attr.path.is_ident(name))
+}
+
+/// Parses the tokens between the parentheses of `attr`.
+fn parse_parenthesized<T: Parse>(attr: &Attribute) -> syn::Result<T> {
+    syn::parse2::<Parenthesized<T>>(attr.tts.clone()).map(|p| p.0)
+}
+
+struct Parenthesized<T>(T);
+
+impl<T: Parse> Parse for Parenthesized<T> {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let content;
+        parenthesized!(content in input);
+        content.parse().map(Parenthesized)
+    }
+}
+
+/// The first bit and the number of bits of `3`, `0..4` or `0..=3`.
+fn bit_range(expr: &Expr) -> syn::Result<(u32, u32)> {
+    let int = |expr: &Expr| match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(int), ..
+        }) => Ok(int.value() as u32),
+        _ => Err(syn::Error::new_spanned(expr, "expected a bit index")),
+    };
+    match expr {
+        Expr::Range(ExprRange {
+            from: Some(from),
+            to: Some(to),
+            limits,
+            ..
+        }) => {
+            let (from, to) = (int(from)?, int(to)?);
+            let end = match limits {
+                RangeLimits::HalfOpen(_) => to,
+                RangeLimits::Closed(_) => to + 1,
+            };
+            Ok((from, end.saturating_sub(from)))
+        },
+        expr => int(expr).map(|bit| (bit, 1)),
+    }
+}
+
+fn is_bool(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.qself.is_none() && path.path.is_ident("bool"),
+        _ => false,
+    }
+}
@@ -23,7 +23,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        panic!("unsupported")
+        Err(Error::Unsupported("self describing data"))
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
@@ -120,11 +120,11 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             .position(|b| *b == 0)
             .ok_or(Error::UnexpectedEof)?;
         let slice = &self.data[..end];
-        self.data = &self.data[end..];
+        self.data = &self.data[end + 1..];
         visitor.visit_str(std::str::from_utf8(slice)?)
     }
 
-    // Deserializes a null-terminated string without passing the null byte
+    // Deserializes a null-terminated string, the null byte is consumed but not part of the string
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
@@ -135,7 +135,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             .position(|b| *b == 0)
             .ok_or(Error::UnexpectedEof)?;
         let slice = &self.data[..end];
-        self.data = &self.data[end..];
+        self.data = &self.data[end + 1..];
         visitor.visit_string(String::from_utf8(slice.to_owned())?)
     }
 
@@ -143,21 +143,21 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        Err(Error::Unsupported("borrowed bytes"))
     }
 
     fn deserialize_byte_buf<V>(self, _: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        Err(Error::Unsupported("byte buffers"))
     }
 
     fn deserialize_option<V>(self, _: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        panic!("unsupported")
+        Err(Error::Unsupported("options"))
     }
 
     fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
@@ -211,7 +211,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        panic!("unsupported")
+        Err(Error::Unsupported("maps"))
     }
 
     fn deserialize_struct<V>(
@@ -235,22 +235,21 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
-        //visitor.visit_enum(self)
+        Err(Error::Unsupported("enums"))
     }
 
     fn deserialize_identifier<V>(self, _: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        panic!("unsupported")
+        Err(Error::Unsupported("identifiers"))
     }
 
     fn deserialize_ignored_any<V>(self, _: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        panic!("unsupported")
+        Err(Error::Unsupported("ignored values"))
     }
 }
 
@@ -269,6 +268,7 @@ impl<'de, 'a> SeqAccess<'de> for FixedSeq<'a, 'de> {
         if self.len == 0 {
             Ok(None)
         } else {
+            self.len -= 1;
             seed.deserialize(&mut *self.de).map(Some)
         }
     }
@@ -285,10 +285,15 @@ impl<'de, 'a> SeqAccess<'de> for Seq<'a, 'de> {
     where
         T: DeserializeSeed<'de>,
     {
-        if self.de.data.is_empty() {
-            Ok(None)
-        } else {
-            seed.deserialize(&mut *self.de).map(Some)
+        let remaining = self.de.data.len();
+        if remaining == 0 {
+            return Ok(None);
+        }
+        let element = seed.deserialize(&mut *self.de)?;
+        // an element taking up no bytes would have us fill memory with copies of it
+        if self.de.data.len() == remaining {
+            return Err(Error::Unsupported("sequences of empty elements"));
         }
+        Ok(Some(element))
     }
 }
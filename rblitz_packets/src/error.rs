@@ -11,6 +11,8 @@ pub enum Error {
     TooMuchData(usize, usize),
     UnexpectedEof,
     Utf8Error(std::str::Utf8Error),
+    /// The wire format has no way to represent this kind of value.
+    Unsupported(&'static str),
 }
 
 impl ser::Error for Error {
@@ -36,6 +38,7 @@ impl fmt::Display for Error {
             ),
             Error::UnexpectedEof => fmt.write_str("unexpected end of data"),
             Error::Utf8Error(e) => e.fmt(fmt),
+            Error::Unsupported(what) => write!(fmt, "{} can't be (de)serialized", what),
         }
     }
 }
@@ -1,34 +1,3 @@
-macro_rules! make_bitfield {
-    (
-        $(#[$outer:meta])*
-        pub struct $bitfield:ident = $var:ident:$t:ty {
-            $(
-                $field:ident: $fieldtype:ty = $value:expr,
-            )+
-        }
-    ) => {
-        $(#[$outer])*
-        pub struct $bitfield {
-            $(
-                pub $field: $fieldtype,
-            )+
-        }
-
-        impl<'de> serde::Deserialize<'de> for $bitfield {
-            fn deserialize<D>(d: D) -> Result<Self, D::Error>
-                where
-                    D: serde::Deserializer<'de> {
-                let $var: $t = serde::Deserialize::deserialize(d)?;
-                Ok($bitfield {
-                    $(
-                        $field: $value,
-                    )+
-                })
-            }
-        }
-    };
-}
-
 macro_rules! make_fixed_string {
     ($ident:ident $e:expr) => {
         #[allow(dead_code)]
@@ -51,11 +20,15 @@ macro_rules! make_fixed_string {
                         where
                             A: SeqAccess<'de>,
                     {
-                        let string: String = seq.next_element()?.ok_or(Error::custom(crate::Error::UnexpectedEof))?;
-                        for _ in string.len()..$e {
-                            seq.next_element::<u8>()?.ok_or(Error::custom(crate::Error::UnexpectedEof))?;
+                        // the string ends at the first null, whatever follows it is padding
+                        let mut bytes = [0u8; $e];
+                        for byte in bytes.iter_mut() {
+                            *byte = seq.next_element()?.ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?;
                         }
-                        Ok(string)
+                        let len = bytes.iter().position(|&b| b == 0).unwrap_or($e);
+                        std::str::from_utf8(&bytes[..len])
+                            .map(str::to_owned)
+                            .map_err(Error::custom)
                     }
                 }
 
@@ -66,7 +39,11 @@ macro_rules! make_fixed_string {
                     S: serde::Serializer,
             {
                 let mut bytes: [u8; $e] = [0; $e];
-                let len = string.len().min($e - 1);
+                // cut off at a char boundary so the string still decodes
+                let mut len = string.len().min($e - 1);
+                while !string.is_char_boundary(len) {
+                    len -= 1;
+                }
                 bytes[..len].copy_from_slice(&string.as_bytes()[..len]);
                 s.serialize_bytes(&bytes)
             }
@@ -101,7 +78,7 @@ macro_rules! make_sized_vec {
                         let len: $e = seq
                             .next_element()?
                             .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?;
-                        let mut buf: Vec<T> = Vec::with_capacity(len as usize);
+                        let mut buf: Vec<T> = Vec::with_capacity(crate::capacity_hint::<T>(len as usize));
                         for _ in 0..len {
                             buf.push(
                                 seq.next_element()?
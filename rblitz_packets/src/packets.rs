@@ -1,7 +1,8 @@
 pub mod game;
 pub mod loading_screen;
+pub mod registry;
 
-pub(in crate) use rblitz_packets_proc_macro::packet_id;
+pub(in crate) use rblitz_packets_proc_macro::{packet_id, Bitfield};
 
 pub trait PacketId {
     const ID: u8;
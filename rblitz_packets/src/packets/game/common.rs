@@ -4,6 +4,7 @@ pub use super::bitfield::*;
 
 use crate::{Vector2, Vector3};
 use indexmap::IndexMap;
+use std::convert::TryFrom;
 
 // todo
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
@@ -48,19 +49,24 @@ pub struct BuffUpdateCountGroupEntry {
     pub count: u8,
 }
 
-//todo https://github.com/moonshadow565/SiphoningStrike/blob/2410c363a69271af09654fa7773f07f05deedace/SiphoningStrike/Game/Common/CastInfo.cs
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+/// Casts can't hit more targets than this.
+pub const MAX_CAST_TARGETS: usize = 32;
+
+/// Everything the client needs to play a cast, sent with a `u16` of its own size in front which
+/// is worked out on encoding and checked against the bytes read on decoding.
+#[derive(Clone, Debug, Default)]
 pub struct CastInfo {
     pub spell_hash: u32,
     pub spell_net_id: u32,
-    pub spell_level: u32,
-    pub attack_speed_modifier: u32,
+    pub spell_level: u8,
+    pub attack_speed_modifier: f32,
     pub caster_net_id: u32,
+    pub spell_chain_owner_net_id: u32,
+    pub package_hash: u32,
     pub missile_net_id: u32,
     pub target_position: Vector3,
     pub target_position_end: Vector3,
-    #[serde(with = "crate::vec_u8")]
-    pub targets_info: Vec<CastTargetInfo>,
+    pub targets: Vec<CastTargetInfo>,
     pub designer_cast_time: f32,
     pub extra_cast_time: f32,
     pub designer_total_time: f32,
@@ -68,14 +74,147 @@ pub struct CastInfo {
     pub start_cast_time: f32,
     pub bitfield: CastInfoBitfield,
     pub spell_slot: u8,
-    pub mana_cost: u8,
+    pub mana_cost: f32,
     pub caster_position: Vector3,
+    pub ammo_used: i32,
+    pub ammo_recharge_time: f32,
+}
+
+impl CastInfo {
+    /// The size of a cast without targets, the size itself included.
+    const BASE_SIZE: u16 = 102;
+    const TARGET_SIZE: u16 = 5;
+
+    /// The size of a cast with `targets` targets on the wire.
+    fn size(targets: usize) -> u16 {
+        CastInfo::BASE_SIZE + CastInfo::TARGET_SIZE * targets as u16
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for CastInfo {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::{SeqAccess, Visitor};
+
+        struct CastVisitor;
+
+        impl<'de> Visitor<'de> for CastVisitor {
+            type Value = CastInfo;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("cast info")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let size: u16 = next_element(&mut seq)?;
+                let cast = CastInfo {
+                    spell_hash: next_element(&mut seq)?,
+                    spell_net_id: next_element(&mut seq)?,
+                    spell_level: next_element(&mut seq)?,
+                    attack_speed_modifier: next_element(&mut seq)?,
+                    caster_net_id: next_element(&mut seq)?,
+                    spell_chain_owner_net_id: next_element(&mut seq)?,
+                    package_hash: next_element(&mut seq)?,
+                    missile_net_id: next_element(&mut seq)?,
+                    target_position: next_element(&mut seq)?,
+                    target_position_end: next_element(&mut seq)?,
+                    targets: read_cast_targets(&mut seq)?,
+                    designer_cast_time: next_element(&mut seq)?,
+                    extra_cast_time: next_element(&mut seq)?,
+                    designer_total_time: next_element(&mut seq)?,
+                    cooldown: next_element(&mut seq)?,
+                    start_cast_time: next_element(&mut seq)?,
+                    bitfield: next_element(&mut seq)?,
+                    spell_slot: next_element(&mut seq)?,
+                    mana_cost: next_element(&mut seq)?,
+                    caster_position: next_element(&mut seq)?,
+                    ammo_used: next_element(&mut seq)?,
+                    ammo_recharge_time: next_element(&mut seq)?,
+                };
+                let read = CastInfo::size(cast.targets.len());
+                if size != read {
+                    return Err(serde::de::Error::custom(format!(
+                        "the cast claims to be {} bytes long but is {}",
+                        size, read
+                    )));
+                }
+                Ok(cast)
+            }
+        }
+
+        d.deserialize_seq(CastVisitor)
+    }
+}
+
+impl serde::Serialize for CastInfo {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::{Error, SerializeTuple};
+        if self.targets.len() > MAX_CAST_TARGETS {
+            return Err(S::Error::custom(too_many_cast_targets(self.targets.len())));
+        }
+        let size = CastInfo::size(self.targets.len());
+
+        let mut s = s.serialize_tuple(23)?;
+        s.serialize_element(&size)?;
+        s.serialize_element(&self.spell_hash)?;
+        s.serialize_element(&self.spell_net_id)?;
+        s.serialize_element(&self.spell_level)?;
+        s.serialize_element(&self.attack_speed_modifier)?;
+        s.serialize_element(&self.caster_net_id)?;
+        s.serialize_element(&self.spell_chain_owner_net_id)?;
+        s.serialize_element(&self.package_hash)?;
+        s.serialize_element(&self.missile_net_id)?;
+        s.serialize_element(&self.target_position)?;
+        s.serialize_element(&self.target_position_end)?;
+        s.serialize_element(&(self.targets.len() as u8))?;
+        for target in &self.targets {
+            s.serialize_element(target)?;
+        }
+        s.serialize_element(&self.designer_cast_time)?;
+        s.serialize_element(&self.extra_cast_time)?;
+        s.serialize_element(&self.designer_total_time)?;
+        s.serialize_element(&self.cooldown)?;
+        s.serialize_element(&self.start_cast_time)?;
+        s.serialize_element(&self.bitfield)?;
+        s.serialize_element(&self.spell_slot)?;
+        s.serialize_element(&self.mana_cost)?;
+        s.serialize_element(&self.caster_position)?;
+        s.serialize_element(&self.ammo_used)?;
+        s.serialize_element(&self.ammo_recharge_time)?;
+        s.end()
+    }
+}
+
+/// Reads the targets of a cast along with their `u8` count.
+fn read_cast_targets<'de, A>(seq: &mut A) -> Result<Vec<CastTargetInfo>, A::Error>
+where
+    A: serde::de::SeqAccess<'de>,
+{
+    let count = usize::from(next_element::<u8, _>(seq)?);
+    if count > MAX_CAST_TARGETS {
+        return Err(serde::de::Error::custom(too_many_cast_targets(count)));
+    }
+    (0..count).map(|_| next_element(seq)).collect()
+}
+
+fn too_many_cast_targets(count: usize) -> String {
+    format!(
+        "casts hit at most {} targets, not {}",
+        MAX_CAST_TARGETS, count
+    )
 }
 
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct CastTargetInfo {
     pub unit_net_id: u32,
-    pub position: Vector3,
     pub hit_result: u8,
 }
 
@@ -87,9 +226,136 @@ pub struct Color {
     pub alpha: u8,
 }
 
-//todo pub struct CompressedWayPoints
-#[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
-pub struct CompressedWaypoint;
+/// A point of a path in grid coordinates, the cells of the grid are 2 units wide and the origin
+/// lies in the middle of the map.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompressedWaypoint {
+    pub x: i16,
+    pub y: i16,
+}
+
+impl CompressedWaypoint {
+    /// The waypoint closest to `position` on a map whose middle is `center`.
+    pub fn from_world(position: Vector2, center: Vector2) -> Self {
+        CompressedWaypoint {
+            x: ((position.x - center.x) / 2.0).round() as i16,
+            y: ((position.y - center.y) / 2.0).round() as i16,
+        }
+    }
+
+    pub fn to_world(self, center: Vector2) -> Vector2 {
+        Vector2 {
+            x: f32::from(self.x) * 2.0 + center.x,
+            y: f32::from(self.y) * 2.0 + center.y,
+        }
+    }
+}
+
+/// Paths can't be longer than this, their length only gets 7 bits on the wire.
+pub const MAX_WAYPOINTS: usize = 0x7F;
+
+fn next_element<'de, T, A>(seq: &mut A) -> Result<T, A::Error>
+where
+    T: serde::Deserialize<'de>,
+    A: serde::de::SeqAccess<'de>,
+{
+    seq.next_element()?
+        .ok_or_else(|| serde::de::Error::custom(crate::Error::UnexpectedEof))
+}
+
+/// Reads `count` waypoints, `count` has to be at least 1.
+///
+/// The waypoints are preceded by a bit per coordinate after the first waypoint telling whether
+/// the coordinate is sent as an `i8` relative to the one before it or as an absolute `i16`. The
+/// first waypoint is always absolute.
+fn read_waypoints<'de, A>(seq: &mut A, count: usize) -> Result<Vec<CompressedWaypoint>, A::Error>
+where
+    A: serde::de::SeqAccess<'de>,
+{
+    let flags = (0..flag_bytes(count))
+        .map(|_| next_element::<u8, _>(seq))
+        .collect::<Result<Vec<_>, _>>()?;
+    let is_relative = |flag: usize| flags[flag / 8] & 1 << (flag % 8) != 0;
+    let coordinate = |seq: &mut A, last: i16, flag| {
+        if is_relative(flag) {
+            next_element::<i8, _>(seq).map(|delta| last.wrapping_add(i16::from(delta)))
+        } else {
+            next_element(seq)
+        }
+    };
+
+    let mut last = CompressedWaypoint {
+        x: next_element(seq)?,
+        y: next_element(seq)?,
+    };
+    let mut waypoints = Vec::with_capacity(count);
+    waypoints.push(last);
+    for flag in (0..count - 1).map(|i| i * 2) {
+        last = CompressedWaypoint {
+            x: coordinate(seq, last.x, flag)?,
+            y: coordinate(seq, last.y, flag + 1)?,
+        };
+        waypoints.push(last);
+    }
+    Ok(waypoints)
+}
+
+/// Writes `waypoints` the way [`read_waypoints`] reads them, relative wherever the distance to
+/// the waypoint before fits.
+fn write_waypoints<S>(s: &mut S, waypoints: &[CompressedWaypoint]) -> Result<(), S::Error>
+where
+    S: serde::ser::SerializeTuple,
+{
+    let deltas = waypoints
+        .windows(2)
+        .flat_map(|pair| {
+            let delta = |from: i16, to: i16| i8::try_from(i32::from(to) - i32::from(from)).ok();
+            vec![
+                (pair[1].x, delta(pair[0].x, pair[1].x)),
+                (pair[1].y, delta(pair[0].y, pair[1].y)),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let mut flags = vec![0u8; flag_bytes(waypoints.len())];
+    for (flag, (_, delta)) in deltas.iter().enumerate() {
+        if delta.is_some() {
+            flags[flag / 8] |= 1 << (flag % 8);
+        }
+    }
+
+    for byte in &flags {
+        s.serialize_element(byte)?;
+    }
+    s.serialize_element(&waypoints[0].x)?;
+    s.serialize_element(&waypoints[0].y)?;
+    for (coordinate, delta) in deltas {
+        match delta {
+            Some(delta) => s.serialize_element(&delta)?,
+            None => s.serialize_element(&coordinate)?,
+        }
+    }
+    Ok(())
+}
+
+/// The bytes holding the flags of `count` waypoints, the flags of the first one are left out.
+fn flag_bytes(count: usize) -> usize {
+    if count > 1 {
+        (count - 2) / 4 + 1
+    } else {
+        0
+    }
+}
+
+/// The `u16` in front of a path, the waypoint count with whether a teleport id follows in bit 0.
+fn path_bitfield<E: serde::ser::Error>(count: usize, has_teleport_id: bool) -> Result<u16, E> {
+    if count > MAX_WAYPOINTS {
+        return Err(E::custom(format!(
+            "paths have at most {} waypoints, not {}",
+            MAX_WAYPOINTS, count
+        )));
+    }
+    Ok((count as u16) << 1 | has_teleport_id as u16)
+}
 
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct ConnectionInfo {
@@ -158,12 +424,35 @@ pub struct ItemData {
     pub item_id: u32,
 }
 
+/// The movement of a unit prefixed with its kind and the sync id it belongs to.
 #[derive(Clone, Debug)]
 pub enum MovementData {
-    Normal(MovementDataNormal),
-    Stop(MovementDataStop),
-    Speed(MovementDataWithSpeed),
-    None(i32),
+    Normal {
+        sync_id: i32,
+        data: MovementDataNormal,
+    },
+    Stop {
+        sync_id: i32,
+        data: MovementDataStop,
+    },
+    Speed {
+        sync_id: i32,
+        data: MovementDataWithSpeed,
+    },
+    None {
+        sync_id: i32,
+    },
+}
+
+impl MovementData {
+    pub fn sync_id(&self) -> i32 {
+        match *self {
+            MovementData::Normal { sync_id, .. }
+            | MovementData::Stop { sync_id, .. }
+            | MovementData::Speed { sync_id, .. }
+            | MovementData::None { sync_id } => sync_id,
+        }
+    }
 }
 
 impl<'de> serde::Deserialize<'de> for MovementData {
@@ -171,7 +460,7 @@ impl<'de> serde::Deserialize<'de> for MovementData {
     where
         D: serde::Deserializer<'de>,
     {
-        use serde::de::{Error, SeqAccess, Visitor};
+        use serde::de::{SeqAccess, Visitor};
 
         struct MovVisitor;
 
@@ -186,26 +475,22 @@ impl<'de> serde::Deserialize<'de> for MovementData {
             where
                 A: SeqAccess<'de>,
             {
-                let lookahead: u8 = seq
-                    .next_element()?
-                    .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?;
+                let lookahead: u8 = next_element(&mut seq)?;
+                let sync_id = next_element(&mut seq)?;
                 Ok(match lookahead {
-                    1 => MovementData::Speed(
-                        seq.next_element()?
-                            .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?,
-                    ),
-                    2 => MovementData::Normal(
-                        seq.next_element()?
-                            .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?,
-                    ),
-                    3 => MovementData::Stop(
-                        seq.next_element()?
-                            .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?,
-                    ),
-                    _ => MovementData::None(
-                        seq.next_element()?
-                            .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?,
-                    ),
+                    1 => MovementData::Speed {
+                        sync_id,
+                        data: next_element(&mut seq)?,
+                    },
+                    2 => MovementData::Normal {
+                        sync_id,
+                        data: next_element(&mut seq)?,
+                    },
+                    3 => MovementData::Stop {
+                        sync_id,
+                        data: next_element(&mut seq)?,
+                    },
+                    _ => MovementData::None { sync_id },
                 })
             }
         }
@@ -220,18 +505,35 @@ impl serde::Serialize for MovementData {
         S: serde::Serializer,
     {
         use serde::ser::SerializeTuple;
-        let mut s = s.serialize_tuple(2)?;
+        let mut s = s.serialize_tuple(3)?;
         match self {
-            MovementData::Stop(data) => s.serialize_element(data)?,
-            MovementData::Normal(data) => s.serialize_element(data)?,
-            MovementData::Speed(data) => s.serialize_element(data)?,
-            MovementData::None(data) => s.serialize_element(data)?,
+            MovementData::Speed { sync_id, data } => {
+                s.serialize_element(&1u8)?;
+                s.serialize_element(sync_id)?;
+                s.serialize_element(data)?
+            },
+            MovementData::Normal { sync_id, data } => {
+                s.serialize_element(&2u8)?;
+                s.serialize_element(sync_id)?;
+                s.serialize_element(data)?
+            },
+            MovementData::Stop { sync_id, data } => {
+                s.serialize_element(&3u8)?;
+                s.serialize_element(sync_id)?;
+                s.serialize_element(data)?
+            },
+            MovementData::None { sync_id } => {
+                s.serialize_element(&0u8)?;
+                s.serialize_element(sync_id)?
+            },
         }
         s.end()
     }
 }
-// todo implement actual de/serialization
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+
+/// A path along with the teleport that may precede it. The teleport and the waypoints are only
+/// sent for paths with waypoints, `has_teleport_id` always is.
+#[derive(Clone, Debug, Default)]
 pub struct MovementDataNormal {
     pub teleport_net_id: u32,
     pub has_teleport_id: bool,
@@ -239,14 +541,129 @@ pub struct MovementDataNormal {
     pub waypoints: Vec<CompressedWaypoint>,
 }
 
+impl<'de> serde::Deserialize<'de> for MovementDataNormal {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserialize_path(d, false).map(|(path, _)| path)
+    }
+}
+
+impl serde::Serialize for MovementDataNormal {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        Path {
+            teleport_net_id: self.teleport_net_id,
+            has_teleport_id: self.has_teleport_id,
+            teleport_id: self.teleport_id,
+            speed_params: None,
+            waypoints: &self.waypoints,
+        }
+        .serialize(s)
+    }
+}
+
+/// The layout shared by [`MovementDataNormal`] and [`MovementDataWithSpeed`], only the latter
+/// has speed params.
+struct Path<'a> {
+    teleport_net_id: u32,
+    has_teleport_id: bool,
+    teleport_id: u8,
+    speed_params: Option<&'a SpeedParams>,
+    waypoints: &'a [CompressedWaypoint],
+}
+
+impl serde::Serialize for Path<'_> {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeTuple;
+        let mut s = s.serialize_tuple(5)?;
+        s.serialize_element(&path_bitfield::<S::Error>(
+            self.waypoints.len(),
+            self.has_teleport_id,
+        )?)?;
+        if !self.waypoints.is_empty() {
+            s.serialize_element(&self.teleport_net_id)?;
+            if self.has_teleport_id {
+                s.serialize_element(&self.teleport_id)?;
+            }
+            if let Some(speed_params) = self.speed_params {
+                s.serialize_element(speed_params)?;
+            }
+            write_waypoints(&mut s, self.waypoints)?;
+        }
+        s.end()
+    }
+}
+
+/// Reads a [`Path`], with the speed params if `with_speed` is set. Paths without waypoints leave
+/// them at their default.
+fn deserialize_path<'de, D>(
+    d: D,
+    with_speed: bool,
+) -> Result<(MovementDataNormal, SpeedParams), D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::{SeqAccess, Visitor};
+
+    struct PathVisitor {
+        with_speed: bool,
+    }
+
+    impl<'de> Visitor<'de> for PathVisitor {
+        type Value = (MovementDataNormal, SpeedParams);
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str(if self.with_speed {
+                "path with speed"
+            } else {
+                "path"
+            })
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let bitfield: u16 = next_element(&mut seq)?;
+            let count = usize::from(bitfield >> 1) & MAX_WAYPOINTS;
+            let mut path = MovementDataNormal {
+                has_teleport_id: bitfield & 1 != 0,
+                ..Default::default()
+            };
+            let mut speed_params = SpeedParams::default();
+            if count > 0 {
+                path.teleport_net_id = next_element(&mut seq)?;
+                if path.has_teleport_id {
+                    path.teleport_id = next_element(&mut seq)?;
+                }
+                if self.with_speed {
+                    speed_params = next_element(&mut seq)?;
+                }
+                path.waypoints = read_waypoints(&mut seq, count)?;
+            }
+            Ok((path, speed_params))
+        }
+    }
+
+    d.deserialize_seq(PathVisitor { with_speed })
+}
+
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct MovementDataStop {
     pub position: Vector2,
     pub forward: Vector2,
 }
 
-// todo implement actual de/serialization
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+/// Laid out like [`MovementDataNormal`] with the speed params between the teleport and the
+/// waypoints.
+#[derive(Clone, Debug, Default)]
 pub struct MovementDataWithSpeed {
     pub teleport_net_id: u32,
     pub has_teleport_id: bool,
@@ -255,6 +672,38 @@ pub struct MovementDataWithSpeed {
     pub speed_params: SpeedParams,
 }
 
+impl<'de> serde::Deserialize<'de> for MovementDataWithSpeed {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let (path, speed_params) = deserialize_path(d, true)?;
+        Ok(MovementDataWithSpeed {
+            teleport_net_id: path.teleport_net_id,
+            has_teleport_id: path.has_teleport_id,
+            teleport_id: path.teleport_id,
+            waypoints: path.waypoints,
+            speed_params,
+        })
+    }
+}
+
+impl serde::Serialize for MovementDataWithSpeed {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        Path {
+            teleport_net_id: self.teleport_net_id,
+            has_teleport_id: self.has_teleport_id,
+            teleport_id: self.teleport_id,
+            speed_params: Some(&self.speed_params),
+            waypoints: &self.waypoints,
+        }
+        .serialize(s)
+    }
+}
+
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct NavFlagCircle {
     pub position: Vector2,
@@ -1,27 +1,18 @@
 use serde::{Deserialize, Serialize};
 
-use super::packet_id;
+use super::{common::MovementDataNormal, packet_id};
+use crate::{packets::Bitfield, Vector3};
 
 #[packet_id(0x09)]
-#[derive(Serialize, Copy, Clone, Debug, Default)]
+#[derive(Bitfield, Copy, Clone, Debug, Default)]
+#[bitfield(u8)]
 pub struct RemoveItemReq {
+    #[bits(0..7)]
     pub slot: u8,
+    #[bits(7)]
     pub sell: bool,
 }
 
-impl<'de> serde::Deserialize<'de> for RemoveItemReq {
-    fn deserialize<D>(d: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let bitfield: u8 = Deserialize::deserialize(d)?;
-        Ok(RemoveItemReq {
-            slot: bitfield & 0x7F,
-            sell: bitfield & 0x80 != 0,
-        })
-    }
-}
-
 #[packet_id(0x17)]
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct CQueryStatusReq;
@@ -43,6 +34,22 @@ pub struct CNpcUpgradeSpellReq {
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct CStatsUpdateReq;
 
+/// An order given to a unit, [`ORDER_MOVE_TO`](Self::ORDER_MOVE_TO) being the right click on
+/// the ground.
+#[packet_id(0x75)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CNpcIssueOrderReq {
+    pub order_type: u8,
+    pub position: Vector3,
+    pub target_net_id: u32,
+    #[serde(default)]
+    pub movement_data: MovementDataNormal,
+}
+
+impl CNpcIssueOrderReq {
+    pub const ORDER_MOVE_TO: u8 = 2;
+}
+
 #[packet_id(0x87)]
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct CBuyItemReq {
@@ -4,7 +4,10 @@ use super::{
     bitfield::{CharSpawnPetBitfield, ShieldProperties, SpawnMinionBitfield, SpellSlotBitfield},
     packet_id,
 };
-use crate::{packets::game::common::*, Vector2, Vector3};
+use crate::{
+    packets::{game::common::*, Bitfield},
+    Vector2, Vector3,
+};
 use indexmap::IndexMap;
 
 #[packet_id(0x02)]
@@ -247,39 +250,17 @@ pub struct SServerTick {
 }
 
 #[packet_id(0x2B)]
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Bitfield, Copy, Clone, Debug, Default)]
+#[bitfield(u8)]
 pub struct SStopAnimation {
+    #[bits(0)]
     pub fade: bool,
+    #[bits(1)]
     pub ignore_lock: bool,
+    #[bits(2)]
     pub stop_all: bool,
 }
 
-impl<'de> serde::Deserialize<'de> for SStopAnimation {
-    fn deserialize<D>(d: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let var: u8 = serde::Deserialize::deserialize(d)?;
-        Ok(SStopAnimation {
-            fade: var & 0x1 != 0,
-            ignore_lock: var & 0x2 != 0,
-            stop_all: var & 0x4 != 0,
-        })
-    }
-}
-
-impl serde::Serialize for SStopAnimation {
-    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let byte = ((self.fade as u8) << 1)
-            | ((self.ignore_lock as u8) << 2)
-            | ((self.stop_all as u8) << 4);
-        s.serialize_u8(byte)
-    }
-}
-
 #[packet_id(0x2C)]
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct SAvatarInfo {
@@ -290,38 +271,15 @@ pub struct SAvatarInfo {
 }
 
 #[packet_id(0x2D)]
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Bitfield, Copy, Clone, Debug, Default)]
+#[bitfield(u16)]
 pub struct SDampenerSwitch {
+    #[bits(0..15)]
     pub duration: u16,
+    #[bits(15)]
     pub state: bool,
 }
 
-impl<'de> serde::Deserialize<'de> for SDampenerSwitch {
-    fn deserialize<D>(d: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let var: u16 = serde::Deserialize::deserialize(d)?;
-        Ok(SDampenerSwitch {
-            duration: var & 0x7FFF,
-            state: var & 0x8000 != 0,
-        })
-    }
-}
-
-impl serde::Serialize for SDampenerSwitch {
-    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut short = self.duration;
-        if self.state {
-            short |= 0x8000;
-        }
-        s.serialize_u16(short)
-    }
-}
-
 #[packet_id(0x2E)]
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct SWorldSendCameraServerAck {
@@ -387,51 +345,27 @@ pub struct SChangeSpellOffsetTarget {
 }
 
 #[packet_id(0x38)]
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct SRemovePerceptionBubble {
     pub bubble_id: u32,
 }
 
 #[packet_id(0x39)]
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Bitfield, Copy, Clone, Debug, Default)]
+#[bitfield(u8)]
 pub struct SNpcInstantStopAttack {
+    #[bits(0)]
     pub keep_animating: bool,
+    #[bits(1)]
     pub force_spell_cast: bool,
+    #[bits(2)]
     pub force_stop: bool,
+    #[bits(3)]
     pub avatar_spell: bool,
+    #[bits(4)]
     pub destroy_missile: bool,
 }
 
-impl<'de> serde::Deserialize<'de> for SNpcInstantStopAttack {
-    fn deserialize<D>(d: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let var: u8 = serde::Deserialize::deserialize(d)?;
-        Ok(SNpcInstantStopAttack {
-            keep_animating: var & 0x01 != 0,
-            force_spell_cast: var & 0x02 != 0,
-            force_stop: var & 0x04 != 0,
-            avatar_spell: var & 0x08 != 0,
-            destroy_missile: var & 0x10 != 0,
-        })
-    }
-}
-
-impl serde::Serialize for SNpcInstantStopAttack {
-    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let byte = ((self.keep_animating as u8) << 1)
-            | ((self.force_spell_cast as u8) << 2)
-            | ((self.force_stop as u8) << 3)
-            | ((self.avatar_spell as u8) << 4)
-            | ((self.destroy_missile as u8) << 5);
-        s.serialize_u8(byte)
-    }
-}
-
 #[packet_id(0x3A)]
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct SOnLeaveLocalVisiblityClient;
@@ -797,16 +731,6 @@ pub struct SPauseAnimation {
     pub pause: bool,
 }
 
-#[packet_id(0x75)]
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
-pub struct SNpcIssueOrderReq {
-    pub order_type: u8,
-    pub position: Vector3,
-    pub target_net_id: u32,
-    #[serde(default)]
-    pub movement_data: MovementDataNormal,
-}
-
 #[packet_id(0x76)]
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct SCameraBehavior {
@@ -1301,35 +1225,15 @@ pub struct SEndOfGameEvent {
 }
 
 #[packet_id(0xCE)]
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Bitfield, Copy, Clone, Debug, Default)]
+#[bitfield(u8)]
 pub struct SEndGame {
+    #[bits(0)]
     pub is_team_order_win: bool,
+    #[bits(1)]
     pub is_surrender: bool,
 }
 
-impl<'de> serde::Deserialize<'de> for SEndGame {
-    fn deserialize<D>(d: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let var: u8 = serde::Deserialize::deserialize(d)?;
-        Ok(SEndGame {
-            is_team_order_win: var & 0x01 != 0,
-            is_surrender: var & 0x02 != 0,
-        })
-    }
-}
-
-impl serde::Serialize for SEndGame {
-    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let byte = ((self.is_team_order_win as u8) << 1) | ((self.is_surrender as u8) << 2);
-        s.serialize_u8(byte)
-    }
-}
-
 #[packet_id(0xD1)]
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, Default)]
 pub struct SPopAllCharacterData;
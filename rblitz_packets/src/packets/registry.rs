@@ -0,0 +1,315 @@
+//! Looking packets up by their id, for code that only has the id of a packet to go on like the
+//! fuzz targets.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use core::fmt;
+
+use crate::{
+    from_bytes,
+    packets::{
+        game::{answer::*, client::*, request::*, server::*},
+        loading_screen::*,
+        PacketId,
+    },
+    to_bytes, Result,
+};
+
+/// Something to do with a packet type found by [`client_packet`] and friends.
+pub trait PacketVisitor {
+    type Output;
+
+    fn visit<P>(self) -> Self::Output
+    where
+        P: PacketId + Serialize + DeserializeOwned + fmt::Debug;
+}
+
+macro_rules! visit_packet {
+    ($id:expr, $visitor:expr, $($packet:ident),* $(,)?) => {
+        match $id {
+            $($packet::ID => Some($visitor.visit::<$packet>()),)*
+            _ => None,
+        }
+    };
+}
+
+/// Visits the packet clients send with `id`, if there is one.
+pub fn client_packet<V: PacketVisitor>(id: u8, visitor: V) -> Option<V::Output> {
+    visit_packet!(
+        id,
+        visitor,
+        CTutorialAudioEventFinished,
+        CSyncSimTime,
+        CPingLoadInfo,
+        CWriteNavFlagsAcc,
+        CWorldSendCameraServer,
+        CUseObject,
+        CScoreBoardOpened,
+        CPlayEmote,
+        CClientReady,
+        CMapPing,
+        CShopOpened,
+        CTipEvent,
+        CWorldLockCameraServer,
+        CClientFinished,
+        CExit,
+        CClientConnectNamedPipe,
+        CTeamSurrenderVote,
+        CReconnect,
+        CSendSelectedObjID,
+        CSyncVersion,
+        CCharSelected,
+        CTutorialPopupClosed,
+        CQuestEvent,
+        CRespawnPointEvent,
+        RemoveItemReq,
+        CQueryStatusReq,
+        CSwapItemReq,
+        CNpcUpgradeSpellReq,
+        CStatsUpdateReq,
+        CNpcIssueOrderReq,
+        CBuyItemReq,
+    )
+}
+
+/// Visits the packet the server sends with `id`, if there is one.
+pub fn server_packet<V: PacketVisitor>(id: u8, visitor: V) -> Option<V::Output> {
+    visit_packet!(
+        id,
+        visitor,
+        SDisplayLocalizedTutorialChatText,
+        SBarrackSpawnUnit,
+        SSwitchNexusesToOnIdleParticles,
+        SSetCircularMovementRestriction,
+        SUpdateGoldRedirectTarget,
+        SResumePacket,
+        SBasicAttack,
+        SRefreshObjectiveText,
+        SCloseShop,
+        SReconnect,
+        SUnitAddExp,
+        SEndSpawn,
+        SSetFrequency,
+        SHighlightTitanBarElement,
+        SBotAi,
+        STeamSurrenderCountDown,
+        SChangeSlotSpellType,
+        SNpcMessageToClient,
+        SDisplayFloatingText,
+        SBasicAttackPos,
+        SNpcForceDeath,
+        SNpcBuffUpdateCount,
+        SNpcBuffReplaceGroup,
+        SNpcSetAutocast,
+        SNpcDeathEventHistory,
+        SUnitAddGold,
+        SAddUnitPerceptionBubble,
+        SMoveCameraToPoint,
+        SLineMissileHitList,
+        SMuteVolumeCategory,
+        SServerTick,
+        SStopAnimation,
+        SAvatarInfo,
+        SDampenerSwitch,
+        SWorldSendCameraServerAck,
+        SModifyDebugCircleRadius,
+        SHeroReincarnateAlive,
+        SNpcBuffReplace,
+        SPause,
+        SSetFadeOutPop,
+        SChangeSlotSpellName,
+        SChangeSlotSpellIcon,
+        SChangeSpellOffsetTarget,
+        SRemovePerceptionBubble,
+        SNpcInstantStopAttack,
+        SOnLeaveLocalVisiblityClient,
+        SShowObjectiveText,
+        SCharSpawnPet,
+        SFxKill,
+        STurretCreateTurret,
+        SMissileReplication,
+        SResetForSlowLoader,
+        SHighlightHUDElement,
+        SNpcLevelUp,
+        SMapPing,
+        SWriteNavFlags,
+        SPlayEmote,
+        SReconnectDone,
+        SOnEventWorld,
+        SHeroStats,
+        SHeroReincarnate,
+        SCreateHero,
+        SToggleUIHighlight,
+        SFaceDirection,
+        SOnLeaveVisibilityClient,
+        SSetItem,
+        SSyncVersion,
+        SHandleTipUpdate,
+        SRemoveDebugCircle,
+        SCreateUnitHighlight,
+        SDestroyClientMissile,
+        SLevelUpSpell,
+        SStartGame,
+        SNpcHeroDie,
+        SFadeOutMainSFX,
+        SUserMessageStart,
+        SWaypointGroup,
+        SStartSpawn,
+        SCreateNeutral,
+        SWaypointGroupWithSpeed,
+        SUnitApplyDamage,
+        SModifyShield,
+        SPopCharacterData,
+        SNpcBuffAddGroup,
+        SAiTargetSelection,
+        SAiTarget,
+        SSetAnimStates,
+        SChainMissileSync,
+        SMissileReplicationChainMissile,
+        SSetSpellData,
+        SPauseAnimation,
+        SCameraBehavior,
+        SAnimatedBuildingSetCurrentSkin,
+        SConnected,
+        SSyncSimTimeFinal,
+        SWaypointAcc,
+        SAddPosPerceptionBubble,
+        SLockCamera,
+        SPlayVOAudioEvent,
+        SAiCommand,
+        SNpcBuffRemove,
+        SSpawnMinion,
+        SToggleFoW,
+        SToolTipVars,
+        SUnitApplyHeal,
+        SGlobalCombatMessage,
+        SWaypointListHeroWithSpeed,
+        SSetInputLockingFlag,
+        SCharSetCooldown,
+        SCharCancelTargetingReticle,
+        SFxCreateGroup,
+        SBuildingDie,
+        SHandleQuestUpdate,
+        SServerGameSettings,
+        SModifyDebugCircleColor,
+        SWorldSendGameNumber,
+        SChangeParColorOverride,
+        SNpcBuffRemoveGroup,
+        STurretFire,
+        SPingLoadInfo,
+        SChangeCharacterVoice,
+        SChangeCharacterData,
+        SExit,
+        SNpcCastSpellReq,
+        SToggleInputLockingFlag,
+        SCreateTurret,
+        SNpcDie,
+        SShowAuxiliaryText,
+        SPausePacket,
+        SHideObjectiveText,
+        SOnEvent,
+        STeamSurrenderStatus,
+        SHideAuxiliaryText,
+        SOnReplicationAcc,
+        SOnDisconnected,
+        SSetGreyscaleEnabledWhenDead,
+        SAiState,
+        SSetFoWStatus,
+        SOnEnterLocalVisiblityClient,
+        SHighlightShopElement,
+        SPlayAnimation,
+        SRefreshAuxiliaryText,
+        SSetFadeOutPush,
+        SOpenTutorialPopup,
+        SRemoveUnitHighlight,
+        SNpcCastSpellAns,
+        SNpcBuffAdd,
+        SWaypointList,
+        SOnEnterVisibilityClient,
+        SAddDebugCircle,
+        SDisableHUDForEndOfGame,
+        SNpcBuffUpdateCountGroup,
+        SAiTargetHero,
+        SSyncSimTime,
+        SOpenAFKWarningMessage,
+        SSyncMissionStartTime,
+        SNeutralCampEmpty,
+        SOnReplication,
+        SEndOfGameEvent,
+        SEndGame,
+        SPopAllCharacterData,
+        STeamSurrenderVote,
+        SHandleUiHighlight,
+        SFadeMinions,
+        SShowHealthBar,
+        SSpawnBot,
+        SSpawnLevelProp,
+        SUpdateLevelProp,
+        SAttachFlexParticle,
+        SHandleCapturePointUpdate,
+        SHandleGameScore,
+        SHandleRespawnPointUpdate,
+        SUnitChangeTeam,
+        SUnitSetMinimapIcon,
+        SIncrementPlayerScore,
+        SIncrementPlayerStat,
+        SColorRemapFX,
+        SMusicCueCommand,
+        SOnEnterTeamVisibility,
+        SOnLeaveTeamVisibility,
+        SFxOnEnterTeamVisibility,
+        SFxOnLeaveTeamVisibility,
+        SReplayOnlyGoldEarned,
+        SRemoveItemAns,
+        SNpcUpgradeSpellAns,
+        CSwapItemAns,
+        SBuyItemAns,
+        SQueryStatusAns,
+        CUseItemAns,
+    )
+}
+
+/// Visits the packet with `id` sent over the loading screen channel, if there is one.
+pub fn loading_screen_packet<V: PacketVisitor>(id: u8, visitor: V) -> Option<V::Output> {
+    visit_packet!(
+        id,
+        visitor,
+        RequestJoinTeam,
+        RequestReskin,
+        RequestRename,
+        TeamRosterUpdate,
+    )
+}
+
+/// Decodes its bytes as the visited packet and encodes the packet again, the bytes a well formed
+/// packet is sent as.
+#[derive(Copy, Clone, Debug)]
+pub struct Reencode<'a>(pub &'a [u8]);
+
+impl PacketVisitor for Reencode<'_> {
+    type Output = Result<Vec<u8>>;
+
+    fn visit<P>(self) -> Self::Output
+    where
+        P: PacketId + Serialize + DeserializeOwned + fmt::Debug,
+    {
+        let packet: P = from_bytes(self.0)?;
+        to_bytes(&packet)
+    }
+}
+
+/// The name of the visited packet, like `CMapPing`.
+#[derive(Copy, Clone, Debug)]
+pub struct Name;
+
+impl PacketVisitor for Name {
+    type Output = &'static str;
+
+    fn visit<P>(self) -> Self::Output
+    where
+        P: PacketId + Serialize + DeserializeOwned + fmt::Debug,
+    {
+        let path = core::any::type_name::<P>();
+        path.rsplit("::").next().unwrap_or(path)
+    }
+}
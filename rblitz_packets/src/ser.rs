@@ -1,7 +1,7 @@
 use byteorder::{WriteBytesExt, LE};
 use serde::ser::{self, Serialize};
 
-use std::io::Write;
+use std::{convert::TryFrom, io::Write};
 
 use crate::error::{Error, Result};
 
@@ -103,8 +103,10 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
             .map_err(|_| Error::UnexpectedEof)
     }
 
-    fn serialize_char(self, _: char) -> Result<()> {
-        unimplemented!()
+    fn serialize_char(self, v: char) -> Result<()> {
+        // chars are read back as a single byte
+        let byte = u8::try_from(v).map_err(|_| Error::Unsupported("non latin-1 chars"))?;
+        self.serialize_u8(byte)
     }
 
     fn serialize_str(self, s: &str) -> Result<()> {
@@ -117,14 +119,14 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     }
 
     fn serialize_none(self) -> Result<()> {
-        panic!("unsupported")
+        Err(Error::Unsupported("options"))
     }
 
     fn serialize_some<T>(self, _: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        panic!("unsupported")
+        Err(Error::Unsupported("options"))
     }
 
     fn serialize_unit(self) -> Result<()> {
@@ -136,14 +138,14 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     }
 
     fn serialize_unit_variant(self, _: &'static str, _: u32, _: &'static str) -> Result<()> {
-        panic!("unsupported")
+        Err(Error::Unsupported("enums"))
     }
 
-    fn serialize_newtype_struct<T>(self, _: &'static str, _: &T) -> Result<()>
+    fn serialize_newtype_struct<T>(self, _: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        panic!("unsupported")
+        value.serialize(self)
     }
 
     fn serialize_newtype_variant<T>(
@@ -156,7 +158,7 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     where
         T: ?Sized + Serialize,
     {
-        panic!("unsupported")
+        Err(Error::Unsupported("enums"))
     }
 
     fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq> {
@@ -182,11 +184,11 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
         _: &'static str,
         _: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        panic!("unsupported")
+        Err(Error::Unsupported("enums"))
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        panic!("unsupported")
+        Err(Error::Unsupported("maps"))
     }
 
     fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct> {
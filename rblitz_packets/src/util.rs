@@ -1,5 +1,14 @@
 #![allow(dead_code)]
 
+/// How many bytes a length read off the wire may preallocate, anything longer grows as its
+/// elements are actually read so a bogus length can't exhaust memory on its own.
+const MAX_PREALLOCATION: usize = 4096;
+
+/// The capacity to reserve for `len` elements of `T` announced by a packet.
+pub(in crate) fn capacity_hint<T>(len: usize) -> usize {
+    len.min(MAX_PREALLOCATION / core::mem::size_of::<T>().max(1))
+}
+
 // riot decided that some bools can be of garbage value with just the first bit being significant,
 // this fucks us over if we were to just interpret the bytes as bools cause it seems that 0 is false
 // and everything else is true in rust
@@ -32,7 +41,8 @@ pub(in crate) mod f8 {
     where
         S: serde::Serializer,
     {
-        s.serialize_u8(((float * 100.0) as i32 + 128) as u8)
+        // rounded, truncating would turn some of the values it decodes to into the one below
+        s.serialize_u8(((float * 100.0).round() as i32 + 128) as u8)
     }
 }
 
@@ -132,7 +142,7 @@ pub(in crate) mod sized_string {
                 let len: u32 = seq
                     .next_element()?
                     .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?;
-                let mut buf: Vec<u8> = Vec::with_capacity(len as usize);
+                let mut buf: Vec<u8> = Vec::with_capacity(crate::capacity_hint::<u8>(len as usize));
                 for _ in 0..len {
                     buf.push(
                         seq.next_element()?
@@ -181,14 +191,14 @@ pub(in crate) mod sized_string_null {
                 let len: u32 = seq
                     .next_element()?
                     .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?;
-                let mut buf: Vec<u8> = Vec::with_capacity(len as usize);
+                let mut buf: Vec<u8> = Vec::with_capacity(crate::capacity_hint::<u8>(len as usize));
                 for _ in 1..len {
                     buf.push(
                         seq.next_element()?
                             .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?,
                     );
                 }
-                seq.next_element()?
+                seq.next_element::<u8>()?
                     .ok_or_else(|| Error::custom(crate::Error::UnexpectedEof))?;
                 String::from_utf8(buf).map_err(|e| Error::custom(e.utf8_error()))
             }
@@ -203,7 +213,7 @@ pub(in crate) mod sized_string_null {
         let mut s = s.serialize_tuple(3)?;
         s.serialize_element(&(string.len() as u32 + 1))?;
         s.serialize_element(string.as_bytes())?;
-        s.serialize_element(&0)?;
+        s.serialize_element(&0u8)?;
         s.end()
     }
 }
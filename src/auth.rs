@@ -0,0 +1,359 @@
+//! Resolving the players of a game to the keys they encrypt their traffic with and the profiles
+//! the other players see, see [`AuthConfig`].
+//!
+//! Besides the keys listed in the config, identities can come from a local service standing in for
+//! a platform, spoken to over TCP with one JSON object per line. The server sends
+//! `{"player_id": 12}` and the service answers with either
+//! `{"key": "<base64>", "name": "Test", "profile_icon": 0, "summoner_level": 30}` or
+//! `{"error": "<message>"}`.
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use core::fmt;
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use crate::{
+    config::{AuthConfig, AuthKind, PlayerConfig, SpectatorConfig},
+    error::{Error, Result},
+};
+
+/// The length of the keys handed out by [`RandomKeys`], the length the client expects.
+pub const KEY_LEN: usize = 16;
+/// How long the auth service may take to answer.
+const SERVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A blowfish key of a player.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PlayerKey(Vec<u8>);
+
+impl PlayerKey {
+    /// Blowfish takes keys from 4 to 56 bytes long.
+    pub fn new(key: Vec<u8>) -> Result<Self> {
+        if key.len() < 4 || key.len() > 56 {
+            return Err(Error::InvalidConfig(format!(
+                "keys have to be 4 to 56 bytes long, not {}",
+                key.len()
+            )));
+        }
+        Ok(PlayerKey(key))
+    }
+
+    /// Decodes a key in the form the client is launched with.
+    pub fn from_base64(key: &str) -> Result<Self> {
+        let key = base64::decode(key.trim())
+            .map_err(|e| Error::InvalidConfig(format!("key isn't valid base64: {}", e)))?;
+        PlayerKey::new(key)
+    }
+
+    pub fn random() -> Self {
+        let mut key = vec![0; KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        PlayerKey(key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.0)
+    }
+}
+
+impl fmt::Debug for PlayerKey {
+    // keys are secret, keep them out of the logs
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "PlayerKey({} bytes)", self.0.len())
+    }
+}
+
+/// Who a player is as far as the game is concerned.
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub key: PlayerKey,
+    pub name: String,
+    pub profile_icon: i32,
+    pub summoner_level: u16,
+}
+
+pub trait AuthProvider {
+    /// Looks up the key and profile of `player_id`.
+    fn identify(&mut self, player_id: u64) -> Result<Identity>;
+}
+
+impl<P: AuthProvider + ?Sized> AuthProvider for Box<P> {
+    fn identify(&mut self, player_id: u64) -> Result<Identity> {
+        (**self).identify(player_id)
+    }
+}
+
+/// Creates the provider `config` asks for. The static providers take the keys and profiles from
+/// `players` and `spectators`.
+pub fn from_config(
+    config: &AuthConfig,
+    players: &[PlayerConfig],
+    spectators: &[SpectatorConfig],
+) -> Result<Box<dyn AuthProvider + Send>> {
+    let provider: Box<dyn AuthProvider + Send> = match config.provider {
+        AuthKind::Static => Box::new(StaticAuth::from_config(
+            KeyEncoding::Raw,
+            players,
+            spectators,
+        )?),
+        AuthKind::Base64 => Box::new(StaticAuth::from_config(
+            KeyEncoding::Base64,
+            players,
+            spectators,
+        )?),
+        AuthKind::Socket => {
+            let address = config.address.ok_or_else(|| {
+                Error::InvalidConfig("the socket auth provider needs an `address`".to_owned())
+            })?;
+            Box::new(SocketAuth::new(address))
+        },
+    };
+    if config.random_keys {
+        Ok(Box::new(RandomKeys::new(provider)))
+    } else {
+        Ok(provider)
+    }
+}
+
+/// How the keys of a [`StaticAuth`] are written down.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyEncoding {
+    /// The bytes of the string are the key.
+    Raw,
+    Base64,
+}
+
+impl KeyEncoding {
+    fn decode(self, key: &str) -> Result<PlayerKey> {
+        match self {
+            KeyEncoding::Raw => PlayerKey::new(key.as_bytes().to_vec()),
+            KeyEncoding::Base64 => PlayerKey::from_base64(key),
+        }
+    }
+}
+
+/// The identities written down in the config.
+#[derive(Default)]
+pub struct StaticAuth {
+    identities: HashMap<u64, Identity>,
+}
+
+impl StaticAuth {
+    /// Spectators have no profile of their own, they show up at level 1 with the default icon.
+    pub fn from_config(
+        encoding: KeyEncoding,
+        players: &[PlayerConfig],
+        spectators: &[SpectatorConfig],
+    ) -> Result<Self> {
+        let players = players.iter().map(|p| {
+            (
+                p.player_id,
+                &p.key,
+                &p.name,
+                p.profile_icon,
+                p.summoner_level,
+            )
+        });
+        let spectators = spectators
+            .iter()
+            .map(|s| (s.player_id, &s.key, &s.name, 0, 1));
+        let mut identities = HashMap::new();
+        for (player_id, key, name, profile_icon, summoner_level) in players.chain(spectators) {
+            let key = encoding.decode(key).map_err(|e| match e {
+                Error::InvalidConfig(e) => {
+                    Error::InvalidConfig(format!("invalid key of player {}: {}", player_id, e))
+                },
+                e => e,
+            })?;
+            let identity = Identity {
+                key,
+                name: name.clone(),
+                profile_icon,
+                summoner_level,
+            };
+            if identities.insert(player_id, identity).is_some() {
+                return Err(Error::InvalidConfig(format!(
+                    "player {} is listed more than once",
+                    player_id
+                )));
+            }
+        }
+        Ok(StaticAuth { identities })
+    }
+}
+
+impl AuthProvider for StaticAuth {
+    fn identify(&mut self, player_id: u64) -> Result<Identity> {
+        self.identities
+            .get(&player_id)
+            .cloned()
+            .ok_or(Error::UnknownPlayer(player_id))
+    }
+}
+
+#[derive(Serialize)]
+struct ServiceRequest {
+    player_id: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ServiceReply {
+    Identity {
+        key: String,
+        name: String,
+        profile_icon: i32,
+        summoner_level: u16,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Asks a local service for the identities, see the [module docs](self) for the protocol. The
+/// connection is opened on the first lookup and kept for the following ones.
+pub struct SocketAuth {
+    address: SocketAddr,
+    stream: Option<BufReader<TcpStream>>,
+}
+
+impl SocketAuth {
+    pub fn new(address: SocketAddr) -> Self {
+        SocketAuth {
+            address,
+            stream: None,
+        }
+    }
+
+    fn request(&mut self, player_id: u64) -> io::Result<ServiceReply> {
+        if self.stream.is_none() {
+            let stream = TcpStream::connect_timeout(&self.address, SERVICE_TIMEOUT)?;
+            stream.set_read_timeout(Some(SERVICE_TIMEOUT))?;
+            stream.set_write_timeout(Some(SERVICE_TIMEOUT))?;
+            self.stream = Some(BufReader::new(stream));
+        }
+        let stream = self.stream.as_mut().unwrap();
+        let mut request = serde_json::to_vec(&ServiceRequest { player_id })?;
+        request.push(b'\n');
+        stream.get_mut().write_all(&request)?;
+        let mut reply = String::new();
+        if stream.read_line(&mut reply)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        serde_json::from_str(&reply).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl AuthProvider for SocketAuth {
+    fn identify(&mut self, player_id: u64) -> Result<Identity> {
+        // reconnect on the next lookup, the stream might be out of step with the service
+        let reply = self
+            .request(player_id)
+            .inspect_err(|_| self.stream = None)?;
+        match reply {
+            ServiceReply::Identity {
+                key,
+                name,
+                profile_icon,
+                summoner_level,
+            } => Ok(Identity {
+                key: PlayerKey::from_base64(&key)?,
+                name,
+                profile_icon,
+                summoner_level,
+            }),
+            ServiceReply::Error { error } => {
+                log::warn!("auth service refused player {}: {}", player_id, error);
+                Err(Error::UnknownPlayer(player_id))
+            },
+        }
+    }
+}
+
+/// Gives every player a fresh key, taking only the profiles from another provider. A new
+/// `RandomKeys` is meant to be used for every game so keys never outlive their game.
+pub struct RandomKeys<P> {
+    provider: P,
+}
+
+impl<P: AuthProvider> RandomKeys<P> {
+    pub fn new(provider: P) -> Self {
+        RandomKeys { provider }
+    }
+}
+
+impl<P: AuthProvider> AuthProvider for RandomKeys<P> {
+    fn identify(&mut self, player_id: u64) -> Result<Identity> {
+        Ok(Identity {
+            key: PlayerKey::random(),
+            ..self.provider.identify(player_id)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spectator(player_id: u64, key: &str) -> SpectatorConfig {
+        SpectatorConfig {
+            name: "Spectator".to_owned(),
+            key: key.to_owned(),
+            player_id,
+        }
+    }
+
+    #[test]
+    fn keys_are_checked_instead_of_sliced() {
+        assert!(PlayerKey::new(b"abc".to_vec()).is_err());
+        assert!(PlayerKey::new(vec![0; 57]).is_err());
+        let key = PlayerKey::from_base64("17BLOhi6KZsTtldTsizvHg==").unwrap();
+        assert_eq!(key.as_bytes().len(), KEY_LEN);
+        assert_eq!(key.to_base64(), "17BLOhi6KZsTtldTsizvHg==");
+        assert!(PlayerKey::from_base64("not base64!").is_err());
+
+        let short = StaticAuth::from_config(KeyEncoding::Raw, &[], &[spectator(1, "key")]);
+        assert!(short.is_err());
+    }
+
+    #[test]
+    fn static_keys_are_decoded_per_encoding() {
+        let spectators = [spectator(1, "GLzvuWtyCfHyGhF2")];
+        let mut raw = StaticAuth::from_config(KeyEncoding::Raw, &[], &spectators).unwrap();
+        assert_eq!(raw.identify(1).unwrap().key.as_bytes(), b"GLzvuWtyCfHyGhF2");
+        assert!(matches!(raw.identify(2), Err(Error::UnknownPlayer(2))));
+
+        let mut base64 = StaticAuth::from_config(KeyEncoding::Base64, &[], &spectators).unwrap();
+        assert_eq!(base64.identify(1).unwrap().key.as_bytes().len(), 12);
+
+        let duplicate = [
+            spectator(1, "GLzvuWtyCfHyGhF2"),
+            spectator(1, "GLzvuWtyCfHyGhF3"),
+        ];
+        assert!(StaticAuth::from_config(KeyEncoding::Raw, &[], &duplicate).is_err());
+    }
+
+    #[test]
+    fn random_keys_keep_the_profile() {
+        let spectators = [spectator(1, "GLzvuWtyCfHyGhF2")];
+        let provider = StaticAuth::from_config(KeyEncoding::Raw, &[], &spectators).unwrap();
+        let mut random = RandomKeys::new(provider);
+        let first = random.identify(1).unwrap();
+        let second = random.identify(1).unwrap();
+        assert_eq!(first.name, "Spectator");
+        assert_eq!(first.key.as_bytes().len(), KEY_LEN);
+        assert_ne!(first.key, second.key);
+        assert_ne!(first.key.as_bytes(), b"GLzvuWtyCfHyGhF2");
+        assert!(random.identify(2).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+//! Runs games on loopback and fills them with scripted players to see how the server holds up.
+//!
+//! Every player connects and loads like the game would, then keeps sending move orders, camera
+//! updates, unit selections, map pings and emotes for the given duration. Latency is measured by
+//! timing status queries, throughput and tick overruns are taken from the game summaries of the
+//! servers.
+//!
+//! ```text
+//! rblitz-loadtest [--players N] [--duration SECS] [--rate ACTIONS_PER_SEC]
+//! ```
+
+use rblitz::{
+    auth::{KeyEncoding, StaticAuth},
+    config::{PlayerConfig, Team},
+    game_server::{GameServer, GameSummary, ShutdownHandle},
+    headless::{Channel, HeadlessClient},
+};
+use rblitz_packets::{
+    packets::game::{
+        answer::SQueryStatusAns,
+        client::{CMapPing, CPlayEmote, CSendSelectedObjID, CWorldSendCameraServer},
+        request::{CNpcIssueOrderReq, CQueryStatusReq},
+        server::SStartGame,
+    },
+    PacketId, Vector3,
+};
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    process,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The most players a single game can hold.
+const MAX_PLAYERS_PER_GAME: usize = 12;
+const VERSION: &str = "Version 4.20.0.315";
+/// How long loading may take, every player of a game has to be ready before it starts.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often each player measures its latency.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a status query may go unanswered before it counts as lost, it may have been dropped or
+/// rate limited. Long enough that a late answer is unlikely to be taken for the next one.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+struct Options {
+    players: usize,
+    duration: Duration,
+    rate: f64,
+}
+
+impl Options {
+    fn from_args() -> Result<Self, String> {
+        let mut options = Options {
+            players: MAX_PLAYERS_PER_GAME,
+            duration: Duration::from_secs(30),
+            rate: 10.0,
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", arg))?;
+            let invalid = || format!("invalid value for {}: {}", arg, value);
+            match &*arg {
+                "--players" => options.players = value.parse().map_err(|_| invalid())?,
+                "--duration" => {
+                    options.duration = Duration::from_secs(value.parse().map_err(|_| invalid())?)
+                },
+                "--rate" => options.rate = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+        if options.players == 0 || options.rate <= 0.0 {
+            return Err("players and rate have to be positive".to_owned());
+        }
+        Ok(options)
+    }
+}
+
+/// What a single scripted player observed.
+#[derive(Default)]
+struct PlayerReport {
+    packets_sent: u64,
+    packets_received: u64,
+    bytes_received: u64,
+    latencies: Vec<Duration>,
+    probes_lost: u64,
+}
+
+/// A xorshift generator, good enough to pick actions and keeps runs reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn position(&mut self) -> Vector3 {
+        Vector3 {
+            x: (self.next() % 14_000) as f32,
+            y: 0.0,
+            z: (self.next() % 14_000) as f32,
+        }
+    }
+}
+
+fn main() {
+    let options = Options::from_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1)
+    });
+    let games = options.players.div_ceil(MAX_PLAYERS_PER_GAME);
+    println!(
+        "running {} players in {} games for {}s at {} actions/s",
+        options.players,
+        games,
+        options.duration.as_secs(),
+        options.rate
+    );
+
+    let mut servers = Vec::with_capacity(games);
+    let mut players = Vec::with_capacity(options.players);
+    for game in 0..games {
+        let count = (options.players - game * MAX_PLAYERS_PER_GAME).min(MAX_PLAYERS_PER_GAME);
+        let configs = (0..count)
+            .map(|slot| player_config(game * MAX_PLAYERS_PER_GAME + slot))
+            .collect::<Vec<_>>();
+        let logins = configs
+            .iter()
+            .map(|p| (p.key.clone(), p.player_id))
+            .collect::<Vec<_>>();
+        let (address, shutdown, server) = spawn_server(configs);
+        servers.push((shutdown, server));
+        for (key, player_id) in logins {
+            let duration = options.duration;
+            let rate = options.rate;
+            players.push(thread::spawn(move || {
+                run_player(address, key.as_bytes(), player_id, duration, rate)
+            }));
+        }
+    }
+
+    let mut reports = Vec::with_capacity(players.len());
+    for player in players {
+        match player.join().unwrap() {
+            Ok(report) => reports.push(report),
+            Err(e) => eprintln!("player failed: {}", e),
+        }
+    }
+    let summaries = servers
+        .into_iter()
+        .map(|(shutdown, server)| {
+            // the games end on their own once everyone left, unless a player failed to connect
+            shutdown.shutdown();
+            server.join().unwrap()
+        })
+        .collect::<Vec<_>>();
+    print_report(&options, &reports, &summaries);
+}
+
+fn player_config(idx: usize) -> PlayerConfig {
+    PlayerConfig {
+        name: format!("loadtest{}", idx),
+        key: format!("loadtest{:08}", idx),
+        player_id: 1000 + idx as u64,
+        team: if idx.is_multiple_of(2) {
+            Team::Order
+        } else {
+            Team::Chaos
+        },
+        champion: "Blitzcrank".to_owned(),
+        skin_id: 0,
+        summoner_level: 30,
+        summoner_spell0: 97039269,
+        summoner_spell1: 97039269,
+        profile_icon: 0,
+    }
+}
+
+fn spawn_server(
+    players: Vec<PlayerConfig>,
+) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<GameSummary>) {
+    let address = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|socket| socket.local_addr())
+        .unwrap_or_else(|e| {
+            eprintln!("failed to find a free port: {}", e);
+            process::exit(1)
+        });
+    let (tx, rx) = mpsc::channel();
+    // the server isn't Send so it has to be created on the thread running it
+    let server = thread::spawn(move || {
+        let mut auth = StaticAuth::from_config(KeyEncoding::Raw, &players, &[]).unwrap();
+        let mut server = GameServer::new(address.into(), players, &mut auth).unwrap();
+        tx.send(server.shutdown_handle()).unwrap();
+        server.run()
+    });
+    (address, rx.recv().unwrap(), server)
+}
+
+fn run_player(
+    address: SocketAddr,
+    key: &[u8],
+    player_id: u64,
+    duration: Duration,
+    rate: f64,
+) -> rblitz::error::Result<PlayerReport> {
+    let mut client = HeadlessClient::connect(address.into(), key, player_id, LOAD_TIMEOUT)?;
+    client.load(VERSION, LOAD_TIMEOUT)?;
+    client.wait_for::<SStartGame>(LOAD_TIMEOUT)?;
+
+    let client_id = client.client_id();
+    let mut rng = Rng(player_id);
+    let mut report = PlayerReport::default();
+    let action_interval = Duration::from_nanos((1_000_000_000.0 / rate) as u64);
+    let start = Instant::now();
+    let mut next_action = start;
+    let mut next_probe = start;
+    let mut probe_sent = None;
+    while start.elapsed() < duration {
+        let now = Instant::now();
+        if probe_sent.is_some_and(|sent| now - sent >= PROBE_TIMEOUT) {
+            probe_sent = None;
+            report.probes_lost += 1;
+        }
+        if now >= next_probe && probe_sent.is_none() {
+            client.send(Channel::ClientToServer, 0, &CQueryStatusReq)?;
+            report.packets_sent += 1;
+            probe_sent = Some(now);
+            next_probe = now + PROBE_INTERVAL;
+        }
+        if now >= next_action {
+            let action = rng.next() % 20;
+            match action {
+                0..=6 => client.send(
+                    Channel::ClientToServer,
+                    0,
+                    &CNpcIssueOrderReq {
+                        order_type: CNpcIssueOrderReq::ORDER_MOVE_TO,
+                        position: rng.position(),
+                        ..Default::default()
+                    },
+                )?,
+                7..=12 => client.send(
+                    Channel::ClientToServer,
+                    0,
+                    &CWorldSendCameraServer {
+                        camera_position: rng.position(),
+                        camera_direction: Vector3 {
+                            x: 0.0,
+                            y: -1.0,
+                            z: 0.0,
+                        },
+                        client_id,
+                        sync_id: action as u8,
+                    },
+                )?,
+                13..=15 => client.send(
+                    Channel::ClientToServer,
+                    0,
+                    &CSendSelectedObjID {
+                        client_id,
+                        selected_net_id: 0x4000_0001 + (rng.next() % 10) as u32,
+                    },
+                )?,
+                16..=18 => client.send(
+                    Channel::ClientToServer,
+                    0,
+                    &CMapPing {
+                        position: rng.position(),
+                        target_net_id: 0,
+                        ping_category: (rng.next() % 6) as u8,
+                    },
+                )?,
+                _ => client.send(
+                    Channel::ClientToServer,
+                    0,
+                    &CPlayEmote {
+                        emote_id: (rng.next() % 4) as u32,
+                    },
+                )?,
+            }
+            report.packets_sent += 1;
+            next_action += action_interval;
+        }
+
+        let wait = next_action
+            .min(next_probe)
+            .saturating_duration_since(Instant::now());
+        if let Some(packet) = client.recv(wait)? {
+            report.packets_received += 1;
+            report.bytes_received += packet.data.len() as u64;
+            if packet.id == SQueryStatusAns::ID {
+                if let Some(sent) = probe_sent.take() {
+                    report.latencies.push(sent.elapsed());
+                }
+            }
+        }
+    }
+    client.disconnect(LOAD_TIMEOUT)?;
+    Ok(report)
+}
+
+fn print_report(options: &Options, reports: &[PlayerReport], summaries: &[GameSummary]) {
+    let secs = options.duration.as_secs_f64();
+    let sent = reports.iter().map(|r| r.packets_sent).sum::<u64>();
+    let received = reports.iter().map(|r| r.packets_received).sum::<u64>();
+    let bytes_received = reports.iter().map(|r| r.bytes_received).sum::<u64>();
+    println!(
+        "{}/{} players finished, sent {:.0} packets/s, received {:.0} packets/s ({:.1} KiB/s)",
+        reports.len(),
+        options.players,
+        sent as f64 / secs,
+        received as f64 / secs,
+        bytes_received as f64 / secs / 1024.0
+    );
+
+    let mut latencies = reports
+        .iter()
+        .flat_map(|r| r.latencies.iter().cloned())
+        .collect::<Vec<_>>();
+    latencies.sort();
+    let probes_lost = reports.iter().map(|r| r.probes_lost).sum::<u64>();
+    if latencies.is_empty() {
+        println!("latency: no samples, {} probes lost", probes_lost);
+    } else {
+        let percentile =
+            |p: usize| latencies[(latencies.len() - 1) * p / 100].as_secs_f64() * 1000.0;
+        println!(
+            "latency over {} samples ({} probes lost): p50 {:.2}ms, p90 {:.2}ms, p99 {:.2}ms, max \
+             {:.2}ms",
+            latencies.len(),
+            probes_lost,
+            percentile(50),
+            percentile(90),
+            percentile(99),
+            percentile(100)
+        );
+    }
+
+    for (game, summary) in summaries.iter().enumerate() {
+        let packets_out = summary
+            .players
+            .iter()
+            .map(|p| p.traffic.packets_out)
+            .sum::<u64>();
+        let bytes_out = summary
+            .players
+            .iter()
+            .map(|p| p.traffic.bytes_out)
+            .sum::<u64>();
+        println!(
+            "game {}: {} ticks in {:.1}s, {} overruns, dispatched {:.0} packets/s ({:.1} KiB/s)",
+            game,
+            summary.ticks,
+            summary.game_time,
+            summary.overruns,
+            packets_out as f64 / summary.game_time,
+            bytes_out as f64 / summary.game_time / 1024.0
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Recording of the decrypted traffic of a game, see [`GameServer::capture_to`].
+//!
+//! A capture starts with the 8 byte magic `RBLZCAP\0` followed by the format version as a `u16`,
+//! then one record per packet until the end of the file. All integers are little endian.
+//!
+//! | field     | type         | description                                                |
+//! |-----------|--------------|------------------------------------------------------------|
+//! | direction | `u8`         | 0 if the packet was received from the client, 1 if sent   |
+//! | client id | `u32`        | the client the packet was exchanged with                   |
+//! | channel   | `u8`         | the enet channel the packet was exchanged on               |
+//! | game time | `f64`        | the game time in seconds at which the packet was processed |
+//! | length    | `u32`        | the length of the data                                     |
+//! | data      | `[u8; len]`  | the decrypted packet                                       |
+//!
+//! The data is what went over the wire minus the encryption, so outgoing game packets might be
+//! batches which [`Record::packets`] takes apart.
+//!
+//! [`GameServer::capture_to`]: crate::game_server::GameServer::capture_to
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use std::io::{self, Read, Write};
+
+use crate::{error::Result, packet::batch};
+
+pub use crate::{client::ClientId, packet::Channel};
+
+pub const MAGIC: [u8; 8] = *b"RBLZCAP\0";
+pub const VERSION: u16 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Received from the client.
+    Inbound = 0,
+    /// Sent to the client.
+    Outbound = 1,
+}
+
+/// A single captured packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub direction: Direction,
+    pub client_id: ClientId,
+    pub channel: Channel,
+    /// Game time in seconds.
+    pub game_time: f64,
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    /// The game packets of the record, split out of its batch if it is one. Empty if its channel
+    /// doesn't carry game packets.
+    pub fn packets(&self) -> Result<Vec<Vec<u8>>> {
+        if !self.channel.carries_game_packets() {
+            Ok(Vec::new())
+        } else if self.data.first() == Some(&batch::BATCH_PACKET_ID) {
+            batch::split(&self.data)
+        } else {
+            Ok(vec![self.data.clone()])
+        }
+    }
+}
+
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Starts a new capture by writing the header.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_u16::<LE>(VERSION)?;
+        Ok(CaptureWriter { writer })
+    }
+
+    pub fn write(
+        &mut self,
+        direction: Direction,
+        client_id: ClientId,
+        channel: Channel,
+        game_time: f64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        self.writer.write_u8(direction as u8)?;
+        self.writer.write_u32::<LE>(client_id.0)?;
+        self.writer.write_u8(channel as u8)?;
+        self.writer.write_f64::<LE>(game_time)?;
+        self.writer.write_u32::<LE>(data.len() as u32)?;
+        self.writer.write_all(data)
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        self.write(
+            record.direction,
+            record.client_id,
+            record.channel,
+            record.game_time,
+            &record.data,
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads the records of a capture, iterating over it yields them in the order they were written.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Checks the header of the capture.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("not a packet capture"));
+        }
+        let version = reader.read_u16::<LE>()?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported capture version {}",
+                version
+            )));
+        }
+        Ok(CaptureReader { reader })
+    }
+
+    /// Reads the next record, returning `None` once the capture ended.
+    pub fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let direction = match self.reader.read_u8() {
+            Ok(0) => Direction::Inbound,
+            Ok(1) => Direction::Outbound,
+            Ok(direction) => return Err(invalid_data(format!("invalid direction {}", direction))),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let client_id = ClientId(self.reader.read_u32::<LE>()?);
+        let channel = self.reader.read_u8()?;
+        let channel = Channel::try_from(channel)
+            .ok_or_else(|| invalid_data(format!("invalid channel {}", channel)))?;
+        let game_time = self.reader.read_f64::<LE>()?;
+        let len = self.reader.read_u32::<LE>()?;
+        let mut data = Vec::new();
+        self.reader
+            .by_ref()
+            .take(len as u64)
+            .read_to_end(&mut data)?;
+        if data.len() != len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Some(Record {
+            direction,
+            client_id,
+            channel,
+            game_time,
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(direction: Direction, channel: Channel, data: &[u8]) -> Record {
+        Record {
+            direction,
+            client_id: ClientId(3),
+            channel,
+            game_time: 12.5,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn records_roundtrip() {
+        let records = vec![
+            record(
+                Direction::Inbound,
+                Channel::ClientToServer,
+                &[0xC5, 1, 2, 3],
+            ),
+            record(Direction::Outbound, Channel::LoadingScreen, &[]),
+            record(Direction::Outbound, Channel::Broadcast, &[0xFF; 300]),
+        ];
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for record in &records {
+            writer.write_record(record).unwrap();
+        }
+        let data = writer.into_inner();
+        assert_eq!(&data[..8], b"RBLZCAP\0");
+
+        let read = CaptureReader::new(&data[..])
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn batches_are_split_into_packets() {
+        let first = [0x12, 1, 0, 0, 0x40, 7];
+        let second = [0x13, 1, 0, 0, 0x40, 8, 9];
+        let mut batch = batch::BatchBuilder::new();
+        assert!(batch.push(&first));
+        assert!(batch.push(&second));
+        let batch = record(Direction::Outbound, Channel::Broadcast, &batch.finish());
+        assert_eq!(
+            batch.packets().unwrap(),
+            vec![first.to_vec(), second.to_vec()]
+        );
+
+        let single = record(Direction::Inbound, Channel::ClientToServer, &first);
+        assert_eq!(single.packets().unwrap(), vec![first.to_vec()]);
+        let handshake = record(Direction::Outbound, Channel::Handshake, &[0; 16]);
+        assert!(handshake.packets().unwrap().is_empty());
+    }
+
+    #[test]
+    fn truncated_record_is_an_error() {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer
+            .write_record(&record(Direction::Inbound, Channel::Chat, &[1, 2, 3, 4]))
+            .unwrap();
+        let data = writer.into_inner();
+        let mut reader = CaptureReader::new(&data[..data.len() - 1]).unwrap();
+        assert!(reader.read_record().is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_files() {
+        assert!(CaptureReader::new(&b"RBLZCAQ\0\x01\x00"[..]).is_err());
+        assert!(CaptureReader::new(&b"RBLZCAP\0\x02\x00"[..]).is_err());
+    }
+}
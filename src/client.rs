@@ -1,58 +1,53 @@
-use block_modes::BlockMode;
-use enet_sys as enet;
 use rblitz_packets::packets::{
     game::server::SWorldSendGameNumber,
     loading_screen::{RequestRename, RequestReskin, TeamRosterUpdate},
 };
 use specs::{world::Builder, Entity, ReadStorage, World};
 
-use core::{cell::UnsafeCell, mem, ops, ptr::NonNull, slice};
+use core::ops;
 
 use crate::{
+    auth::{AuthProvider, Identity, PlayerKey},
     config::PlayerConfig,
+    crypto::Blowfish,
     error::{Error, Result},
+    game_server::GAME_ID,
+    lenet_server::{Connection, PacketMode, PeerStats, PendingConnection},
     packet::{
-        game::GamePacket, loading_screen::LoadingScreenPacket, packet_dispatcher_sys::PacketSender,
-        Channel, KeyCheck,
+        loading_screen::LoadingScreenPacket, packet_dispatcher_sys::PacketSender, Channel, KeyCheck,
     },
-    world::components::{NetId, SummonerSpells, Team, UnitName},
+    world::components::{NetId, Position, SummonerSpells, Team, UnitName},
 };
 
-type Blowfish = block_modes::Ecb<blowfish::Blowfish, block_modes::block_padding::ZeroPadding>;
-
 pub struct ClientMap {
     clients: indexmap::IndexMap<ClientId, Client>,
 }
 
 impl ClientMap {
-    pub fn init_from_config(world: &mut World, players: Vec<PlayerConfig>) {
-        let clients = players
-            .into_iter()
-            .take(12)
-            .enumerate()
-            .map(|(cid, p)| {
-                let ent = world
-                    .create_entity()
-                    .with(NetId::new_spawned(cid as u32 + 1))
-                    .with(p.team)
-                    .with(UnitName(p.champion))
-                    .with(SummonerSpells(p.summoner_spell0, p.summoner_spell1))
-                    .build();
-                (
-                    ClientId(cid as u32),
-                    Client::new(
-                        &p.key.as_bytes()[..16],
-                        ent,
-                        p.name,
-                        p.profile_icon,
-                        p.summoner_level,
-                        p.player_id,
-                        p.skin_id,
-                    ),
-                )
-            })
-            .collect::<indexmap::IndexMap<_, _>>();
+    /// Creates the champions of `players`, taking their keys and profiles from `auth`.
+    pub fn init_from_config(
+        world: &mut World,
+        players: Vec<PlayerConfig>,
+        auth: &mut dyn AuthProvider,
+    ) -> Result<()> {
+        let mut clients = indexmap::IndexMap::new();
+        for (cid, p) in players.into_iter().take(12).enumerate() {
+            let identity = auth.identify(p.player_id)?;
+            let ent = world
+                .create_entity()
+                .with(NetId::new_spawned(cid as u32 + 1))
+                .with(Position::SPAWN)
+                .with(p.team)
+                .with(UnitName(p.champion))
+                .with(SummonerSpells(p.summoner_spell0, p.summoner_spell1))
+                .build();
+            clients.insert(
+                ClientId(cid as u32),
+                Client::new(identity, ent, p.player_id, p.skin_id),
+            );
+        }
         world.add_resource(ClientMap { clients });
+        Ok(())
     }
 
     pub(super) fn send_roster_update(
@@ -119,25 +114,22 @@ impl ClientMap {
         }
     }
 
-    pub fn broadcast_keycheck(&mut self, cid: ClientId) {
+    /// Sends `cid` the keychecks of all other players.
+    pub fn broadcast_keycheck(&self, sender: &PacketSender, cid: ClientId) {
         let packets = self
+            .clients
             .iter()
             .filter(|(cid2, _)| **cid2 != cid)
-            .map(|(cid, c)| {
-                let mut check_id = c.player_id.to_le_bytes();
-                c.blowfish().encrypt_nopad(&mut check_id).unwrap();
-                KeyCheck {
-                    action: 0,
-                    pad: [0, 0, 0],
-                    client_id: cid.0,
-                    player_id: c.player_id,
-                    check_id,
-                }
+            .map(|(cid, c)| KeyCheck {
+                action: 0,
+                pad: [0, 0, 0],
+                client_id: cid.0,
+                player_id: c.player_id,
+                check_id: c.session.check_id(c.player_id),
             })
             .collect::<Vec<_>>();
-        let client = self.get_mut(&cid).unwrap();
         for packet in packets {
-            client.send_key_check(packet);
+            send_key_check(sender, cid, packet);
         }
     }
 }
@@ -173,128 +165,157 @@ pub enum ClientStatus {
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct ClientId(pub u32);
 
-// Make sure that any access to the peer that might mutate is behind a &mut self access for the client,
-// otherwise we might end up with data races in enet itself. Also make sure to not access the LEnetServer together with mutable clients
-pub struct Client {
-    pub peer: Option<NonNull<enet::ENetPeer>>,
-    blowfish: UnsafeCell<Blowfish>,
-    pub name: String,
-    pub player_id: u64,
-    pub summoner_level: u16,
-    pub profile_icon: i32,
-    pub status: ClientStatus,
-    pub champ_skin_id: u32,
-    pub champion: Entity,
+/// Traffic exchanged with a client, counting only the payloads without the enet overhead.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
 }
 
-impl Client {
-    pub fn new(
-        key: &[u8],
-        champion: Entity,
-        name: String,
-        profile_icon: i32,
-        summoner_level: u16,
-        player_id: u64,
-        skin_id: u32,
-    ) -> Self {
-        Client {
-            peer: None,
-            blowfish: UnsafeCell::new(Blowfish::new_varkey(key).unwrap()),
-            name,
-            player_id,
-            summoner_level,
-            profile_icon,
-            status: ClientStatus::Disconnected,
-            champ_skin_id: skin_id,
-            champion,
+/// The connection of a player or spectator along with the cipher of its key, counting the traffic
+/// passing through.
+pub struct Session {
+    /// Missing while disconnected and for replayed clients, which are only ever sent to.
+    pub connection: Option<Connection>,
+    key: PlayerKey,
+    blowfish: Blowfish,
+    traffic: Traffic,
+}
+
+impl Session {
+    pub fn new(key: PlayerKey) -> Self {
+        Session {
+            connection: None,
+            blowfish: Blowfish::new(&key),
+            key,
+            traffic: Traffic::default(),
         }
     }
 
-    pub fn disconnect(&mut self) {
-        if let Some(peer) = self.peer.take() {
-            unsafe { enet_sys::enet_peer_disconnect(peer.as_ptr(), 0) }
-        }
+    /// The key the client has to connect with.
+    pub fn key(&self) -> &PlayerKey {
+        &self.key
     }
 
+    #[inline]
+    pub fn traffic(&self) -> Traffic {
+        self.traffic
+    }
+
+    /// Checks that the keycheck proves `player_id` knows the key, then attaches `connection` and
+    /// answers with the keycheck and the game number. Replays authenticate without a connection.
+    /// Returns whether the connection replaced one that was still open.
     pub fn auth(
         &mut self,
+        sender: &PacketSender,
         cid: ClientId,
+        player_id: u64,
         mut keycheck: KeyCheck,
-        peer: *mut enet::ENetPeer,
-    ) -> Result<()> {
-        let mut check = keycheck.check_id;
-        let _ = self.blowfish().decrypt_nopad(&mut check);
-        if check != keycheck.player_id.to_le_bytes() || self.player_id != keycheck.player_id {
+        connection: Option<&PendingConnection>,
+    ) -> Result<bool> {
+        if self.check_id(player_id) != keycheck.check_id || player_id != keycheck.player_id {
             return Err(Error::AuthError);
         }
-        log::info!("client {:?} authenticated [{:?}]", cid.0, keycheck);
-        crate::lenet_server::set_peer_data(peer, Some(cid));
-        self.peer = NonNull::new(peer);
+        // the network thread drops the old connection if the client reconnected before it timed
+        // out
+        let replaced = connection
+            .and_then(|connection| self.connection.replace(connection.accept(cid)))
+            .is_some();
 
         keycheck.client_id = cid.0;
-        self.send_key_check(keycheck);
-        self.send_data(
-            // FIXME use PacketSender
+        send_key_check(sender, cid, keycheck);
+        sender.single_packet(
+            cid,
             Channel::Broadcast,
-            &mut SWorldSendGameNumber { game_id: 12314 }.to_bytes(0),
+            0,
+            &SWorldSendGameNumber { game_id: GAME_ID },
         );
-        Ok(())
+        Ok(replaced)
     }
 
-    pub fn send_key_check(&mut self, mut keycheck: KeyCheck) {
-        log::info!(
-            "sending keycheck to player {} {:?}",
-            self.player_id,
-            keycheck
-        );
-        unsafe {
-            let data = slice::from_raw_parts_mut(
-                &mut keycheck as *mut _ as *mut u8,
-                mem::size_of::<KeyCheck>(),
-            );
-            self.send_data(Channel::Handshake, data);
+    /// The check id of `player_id` encrypted with the key, as sent in keychecks.
+    pub fn check_id(&self, player_id: u64) -> [u8; 8] {
+        let mut check_id = player_id.to_le_bytes();
+        self.blowfish.encrypt_in_place(&mut check_id);
+        check_id
+    }
+
+    pub fn disconnect(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            connection.disconnect();
         }
     }
 
-    // FIXME shitty hack cause of how this blowfish works
-    #[inline]
-    #[allow(clippy::mut_from_ref)]
-    fn blowfish(&self) -> &mut Blowfish {
-        unsafe { &mut *self.blowfish.get() }
+    /// Decrypts a packet received through this session.
+    pub fn receive_data(&mut self, data: &mut [u8]) {
+        self.traffic.bytes_in += data.len() as u64;
+        self.traffic.packets_in += 1;
+        self.blowfish.decrypt_in_place(data);
     }
 
-    pub fn decrypt(&self, data: &mut [u8]) {
-        let nopad_len = data.len() - (data.len() & 0x07);
-        self.blowfish()
-            .decrypt_nopad(&mut data[..nopad_len])
-            .unwrap();
+    /// Encrypts and sends a packet, nothing is sent or counted without a connection.
+    pub(crate) fn send_data(&mut self, channel: Channel, mode: PacketMode, data: &mut [u8]) {
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return,
+        };
+        self.traffic.bytes_out += data.len() as u64;
+        self.traffic.packets_out += 1;
+        self.blowfish.encrypt_in_place(data);
+        connection.send(channel as u8, mode, data);
     }
+}
+
+fn send_key_check(sender: &PacketSender, cid: ClientId, keycheck: KeyCheck) {
+    log::info!("sending keycheck {:?}", keycheck);
+    sender.single(cid, Channel::Handshake, Box::new(keycheck.to_bytes()));
+}
 
-    pub fn encrypt(&self, data: &mut [u8]) {
-        let nopadlen = data.len() - (data.len() & 0x07);
-        self.blowfish()
-            .encrypt_nopad(&mut data[..nopadlen])
-            .unwrap();
+pub struct Client {
+    pub session: Session,
+    /// The connection quality last reported by the network thread.
+    pub peer_stats: Option<PeerStats>,
+    pub name: String,
+    pub player_id: u64,
+    pub summoner_level: u16,
+    pub profile_icon: i32,
+    pub status: ClientStatus,
+    pub champ_skin_id: u32,
+    pub champion: Entity,
+}
+
+impl Client {
+    pub fn new(identity: Identity, champion: Entity, player_id: u64, skin_id: u32) -> Self {
+        Client {
+            session: Session::new(identity.key),
+            peer_stats: None,
+            name: identity.name,
+            player_id,
+            summoner_level: identity.summoner_level,
+            profile_icon: identity.profile_icon,
+            status: ClientStatus::Disconnected,
+            champ_skin_id: skin_id,
+            champion,
+        }
     }
 
-    pub(super) fn send_data(&mut self, channel: Channel, data: &mut [u8]) {
-        if self.peer == None {
-            return;
+    /// Checks the keycheck of the client and attaches its connection, see [`Session::auth`].
+    pub fn auth(
+        &mut self,
+        sender: &PacketSender,
+        cid: ClientId,
+        keycheck: KeyCheck,
+        connection: Option<&PendingConnection>,
+    ) -> Result<()> {
+        let replaced = self
+            .session
+            .auth(sender, cid, self.player_id, keycheck, connection)?;
+        log::info!("client {:?} authenticated [{:?}]", cid.0, keycheck);
+        if replaced {
+            log::info!("client {:?} replaced its previous connection", cid.0);
         }
-        self.encrypt(data);
-        unsafe {
-            enet::enet_peer_send(
-                self.peer.unwrap().as_ptr(),
-                channel as u8,
-                enet::enet_packet_create(
-                    data.as_ptr(),
-                    data.len(),
-                    enet::_ENetPacketFlag_ENET_PACKET_FLAG_RELIABLE,
-                ),
-            )
-        };
+        Ok(())
     }
 }
-
-unsafe impl Send for Client {}
-unsafe impl Sync for Client {}
@@ -1,11 +1,24 @@
 use serde::Deserialize;
 
-use crate::world::components::Team;
-use std::{fs, io, path::Path};
+use crate::{
+    lenet_server::{self, Address},
+    observer::ObserverSettings,
+};
+use core::fmt;
+use std::{
+    collections::BTreeMap,
+    error, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+pub use crate::world::components::Team;
 
 #[derive(Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
+    /// Serves the game to observers over HTTP if present.
+    pub observer: Option<ObserverConfig>,
 }
 
 impl Config {
@@ -17,8 +30,208 @@ impl Config {
 
 #[derive(Deserialize)]
 pub struct ServerConfig {
+    /// An IP address or hostname, optionally followed by a port. `*` or an empty string bind to
+    /// all interfaces.
     pub address: String,
-    pub port: u16,
+    /// The port to use if `address` doesn't specify one.
+    pub port: Option<u16>,
+    /// Records all decrypted traffic to this file, see [`capture`](crate::capture) for the format.
+    pub capture: Option<PathBuf>,
+    /// Records the input of the game to this file so it can be replayed with `--replay <file>`.
+    pub record_input: Option<PathBuf>,
+    /// How many seconds spectators lag behind the game.
+    #[serde(default)]
+    pub spectator_delay: f64,
+    /// How to deal with clients sending packets that can't be handled.
+    #[serde(default)]
+    pub faults: FaultConfig,
+    /// How often clients may send certain packets.
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    /// Where the keys and profiles of the players come from.
+    #[serde(default)]
+    pub auth: AuthConfig,
+}
+
+impl ServerConfig {
+    pub fn bind_address(&self) -> Result<Address, AddressError> {
+        let address = self.address.trim();
+        let address = match address.parse::<SocketAddr>() {
+            Ok(address) => Address::from(address),
+            Err(_) => {
+                let (host, port) = self.split_port(address)?;
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                if host.is_empty() || host == "*" {
+                    Address::any(port)
+                } else {
+                    Address::resolve(host, port)
+                        .map_err(|_| AddressError::Unresolvable(self.address.clone()))?
+                }
+            },
+        };
+        if address.ip().is_ipv6() && !lenet_server::IPV6_SUPPORTED {
+            return Err(AddressError::Ipv6Unsupported(self.address.clone()));
+        }
+        Ok(address)
+    }
+
+    /// Splits off the port of `host:port` and `[ipv6]:port`, a bare IPv6 address has no port.
+    fn split_port<'a>(&self, address: &'a str) -> Result<(&'a str, u16), AddressError> {
+        let (host, port) = if address.starts_with('[') {
+            let end = address
+                .find(']')
+                .ok_or_else(|| AddressError::Unresolvable(self.address.clone()))?;
+            match &address[end + 1..] {
+                "" => (&address[..=end], None),
+                rest if rest.starts_with(':') => (&address[..=end], Some(&rest[1..])),
+                _ => return Err(AddressError::InvalidPort(self.address.clone())),
+            }
+        } else if address.matches(':').count() == 1 {
+            let idx = address.find(':').unwrap();
+            (&address[..idx], Some(&address[idx + 1..]))
+        } else {
+            (address, None)
+        };
+        match port {
+            Some(port) => port
+                .parse()
+                .map(|port| (host, port))
+                .map_err(|_| AddressError::InvalidPort(self.address.clone())),
+            None => self
+                .port
+                .map(|port| (host, port))
+                .ok_or_else(|| AddressError::MissingPort(self.address.clone())),
+        }
+    }
+}
+
+/// Every packet a client sends that can't be decoded or handled counts as a fault and gets
+/// dropped. Faults are logged as warnings once a client caused more than `warn_after` of them,
+/// and once it caused more than `kick_after` it gets disconnected.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FaultConfig {
+    pub warn_after: u32,
+    /// Clients are never kicked if this is 0.
+    pub kick_after: u32,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig {
+            warn_after: 3,
+            kick_after: 50,
+        }
+    }
+}
+
+/// A token bucket, a client may send `burst` packets at once and `rate` packets per second of game
+/// time on average. A `rate` of 0 allows `burst` packets for the whole game.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+/// Rate limits by the name of the packet they apply to, like `CMapPing`. Packets sent over the
+/// limit are dropped, or handled but marked as throttled where the protocol supports it. Setting
+/// any limit replaces all of the defaults.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(transparent)]
+pub struct RateLimitConfig(pub BTreeMap<String, RateLimit>);
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let limits = [
+            ("CMapPing", 1.0, 5),
+            ("CNpcIssueOrderReq", 10.0, 20),
+            ("CPlayEmote", 0.5, 3),
+            ("CQueryStatusReq", 1.0, 5),
+            ("CWorldSendCameraServer", 30.0, 60),
+        ];
+        RateLimitConfig(
+            limits
+                .iter()
+                .map(|&(name, rate, burst)| (name.to_owned(), RateLimit { rate, burst }))
+                .collect(),
+        )
+    }
+}
+
+/// Picks the [`AuthProvider`](crate::auth::AuthProvider) of the game.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub provider: AuthKind,
+    /// The address of the service for the `socket` provider.
+    pub address: Option<SocketAddr>,
+    /// Gives every player a fresh random key for each game instead of the one the provider knows,
+    /// which main hands out through `keys_file`. Games with random keys can't be replayed.
+    pub random_keys: bool,
+    /// Writes a `<player id> <base64 key>` line for every player and spectator to this file once
+    /// the game is set up. Keys are never logged.
+    pub keys_file: Option<PathBuf>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthKind {
+    /// The keys and profiles listed with the players and spectators, the bytes of the key string
+    /// being the key.
+    #[default]
+    Static,
+    /// Same as `Static` but the keys are base64 encoded, the form the client is launched with.
+    Base64,
+    /// Asks a local service, see [`auth`](crate::auth).
+    Socket,
+}
+
+/// Why the configured server address can't be bound to.
+#[derive(Debug)]
+pub enum AddressError {
+    /// Neither an IP address nor a hostname that could be resolved.
+    Unresolvable(String),
+    InvalidPort(String),
+    /// Neither the address nor the config specify a port.
+    MissingPort(String),
+    /// The C LENet implementation only speaks IPv4.
+    Ipv6Unsupported(String),
+    /// A valid address that couldn't be bound to, e.g. because the port is already in use.
+    Unbindable(String),
+}
+
+impl error::Error for AddressError {}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::Unresolvable(address) => write!(
+                fmt,
+                "server address `{}` is neither an IP address nor a resolvable hostname",
+                address
+            ),
+            AddressError::InvalidPort(address) => {
+                write!(fmt, "server address `{}` has an invalid port", address)
+            },
+            AddressError::MissingPort(address) => write!(
+                fmt,
+                "server address `{}` has no port and no `port` is configured",
+                address
+            ),
+            AddressError::Ipv6Unsupported(address) => write!(
+                fmt,
+                "server address `{}` is an IPv6 address, which requires building with the \
+                 `pure-rust-enet` feature, use `0.0.0.0` or `*` to bind to all IPv4 interfaces",
+                address
+            ),
+            AddressError::Unbindable(address) => write!(
+                fmt,
+                "can't bind to server address `{}`, the port might be in use or need more \
+                 privileges",
+                address
+            ),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -26,17 +239,23 @@ pub struct Pla3yerConfig {
     pub entries: Vec<PlayerConfig>,
 }
 
+/// A player of the game. The name, key and profile are only read by the static auth providers,
+/// see [`AuthConfig`].
 #[derive(Deserialize)]
 pub struct PlayerConfig {
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
     pub key: String,
     pub player_id: u64,
     pub team: Team,
     pub champion: String,
     pub skin_id: u32,
+    #[serde(default)]
     pub summoner_level: u16,
     pub summoner_spell0: u32,
     pub summoner_spell1: u32,
+    #[serde(default)]
     pub profile_icon: i32,
 }
 
@@ -45,3 +264,171 @@ impl PlayerConfig {
         Ok(ron::de::from_str(&fs::read_to_string(path)?).expect("unexpected data"))
     }
 }
+
+#[derive(Deserialize)]
+pub struct ObserverConfig {
+    /// The address the HTTP endpoint listens on.
+    pub address: SocketAddr,
+    /// Chunks are saved to this directory so the game can be served again after it ended.
+    pub directory: Option<PathBuf>,
+    /// Seconds between chunks.
+    #[serde(default = "default_chunk_interval")]
+    pub chunk_interval: f64,
+    /// Seconds between keyframes.
+    #[serde(default = "default_keyframe_interval")]
+    pub keyframe_interval: f64,
+}
+
+impl ObserverConfig {
+    pub fn settings(&self) -> ObserverSettings {
+        ObserverSettings {
+            chunk_interval: self.chunk_interval,
+            keyframe_interval: self.keyframe_interval,
+        }
+    }
+}
+
+fn default_chunk_interval() -> f64 {
+    ObserverSettings::default().chunk_interval
+}
+
+fn default_keyframe_interval() -> f64 {
+    ObserverSettings::default().keyframe_interval
+}
+
+/// A game started at runtime by a [`GameHost`](crate::game_host::GameHost), everything main
+/// otherwise reads from separate files in one.
+#[derive(Deserialize)]
+pub struct GameConfig {
+    /// Identifies the game, no two running games of a host may share it.
+    pub name: String,
+    pub server: ServerConfig,
+    pub players: Vec<PlayerConfig>,
+    #[serde(default)]
+    pub spectators: Vec<SpectatorConfig>,
+}
+
+impl GameConfig {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> crate::error::Result<Self> {
+        ron::de::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| crate::error::Error::InvalidConfig(e.to_string()))
+    }
+}
+
+/// A spectator slot, spectators authenticate like players but don't take part in the game.
+#[derive(Deserialize)]
+pub struct SpectatorConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub key: String,
+    pub player_id: u64,
+}
+
+impl SpectatorConfig {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Vec<Self>> {
+        Ok(ron::de::from_str(&fs::read_to_string(path)?).expect("unexpected data"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    fn server(address: &str, port: Option<u16>) -> ServerConfig {
+        let mut config: ServerConfig = toml::from_str("address = ''").unwrap();
+        config.address = address.to_owned();
+        config.port = port;
+        config
+    }
+
+    type Case<'a, T> = (&'a str, Option<u16>, Result<T, &'a str>);
+
+    fn error_kind(e: &AddressError) -> &'static str {
+        match e {
+            AddressError::Unresolvable(_) => "unresolvable",
+            AddressError::InvalidPort(_) => "invalid port",
+            AddressError::MissingPort(_) => "missing port",
+            AddressError::Ipv6Unsupported(_) => "ipv6 unsupported",
+            AddressError::Unbindable(_) => "unbindable",
+        }
+    }
+
+    #[test]
+    fn ports_are_split_off() {
+        let cases: &[Case<(&str, u16)>] = &[
+            ("example.com:5119", None, Ok(("example.com", 5119))),
+            ("example.com", Some(5119), Ok(("example.com", 5119))),
+            ("example.com:80", Some(5119), Ok(("example.com", 80))),
+            ("[::1]:5119", None, Ok(("[::1]", 5119))),
+            ("[::1]", Some(5119), Ok(("[::1]", 5119))),
+            ("*:5119", None, Ok(("*", 5119))),
+            ("*", Some(5119), Ok(("*", 5119))),
+            (":5119", None, Ok(("", 5119))),
+            ("", Some(5119), Ok(("", 5119))),
+            // with more than one colon the port can't be told apart from the address
+            ("fe80::1", Some(5119), Ok(("fe80::1", 5119))),
+            ("fe80::1:5119", Some(80), Ok(("fe80::1:5119", 80))),
+            ("example.com", None, Err("missing port")),
+            ("[::1]", None, Err("missing port")),
+            ("fe80::1", None, Err("missing port")),
+            ("example.com:", Some(5119), Err("invalid port")),
+            ("example.com:port", None, Err("invalid port")),
+            ("example.com:65536", None, Err("invalid port")),
+            ("[::1]5119", None, Err("invalid port")),
+            ("[::1]:", None, Err("invalid port")),
+            ("[::1:5119", None, Err("unresolvable")),
+        ];
+        for &(address, port, expected) in cases {
+            let config = server(address, port);
+            let split = config.split_port(address);
+            assert_eq!(
+                split.as_ref().map_err(error_kind),
+                expected.as_ref().map_err(|e| *e),
+                "`{}` with port {:?}",
+                address,
+                port
+            );
+        }
+    }
+
+    #[test]
+    fn addresses_resolve() {
+        let v4 = |ip: [u8; 4], port| Ok(SocketAddr::from((Ipv4Addr::from(ip), port)));
+        let v6 = if lenet_server::IPV6_SUPPORTED {
+            Ok("[::1]:5119".parse().unwrap())
+        } else {
+            Err("ipv6 unsupported")
+        };
+        let cases: &[Case<SocketAddr>] = &[
+            ("127.0.0.1:5119", None, v4([127, 0, 0, 1], 5119)),
+            (" 127.0.0.1 ", Some(5119), v4([127, 0, 0, 1], 5119)),
+            ("localhost:5119", None, v4([127, 0, 0, 1], 5119)),
+            ("*:5119", None, v4([0, 0, 0, 0], 5119)),
+            ("*", Some(5119), v4([0, 0, 0, 0], 5119)),
+            ("[*]:5119", None, v4([0, 0, 0, 0], 5119)),
+            (":5119", None, v4([0, 0, 0, 0], 5119)),
+            ("", Some(5119), v4([0, 0, 0, 0], 5119)),
+            ("[::1]:5119", None, v6),
+            ("[::1]", Some(5119), v6),
+            ("::1", Some(5119), v6),
+            ("127.0.0.1", None, Err("missing port")),
+            ("no.such.host.invalid:5119", None, Err("unresolvable")),
+        ];
+        for &(address, port, expected) in cases {
+            let resolved = server(address, port).bind_address();
+            assert_eq!(
+                resolved
+                    .as_ref()
+                    .map(|a| SocketAddr::from(*a))
+                    .map_err(error_kind),
+                expected.as_ref().map(|a| *a).map_err(|e| *e),
+                "`{}` with port {:?}",
+                address,
+                port
+            );
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! The blowfish cipher the client encrypts its packets with.
+//!
+//! Packets are encrypted in ECB mode without padding, every full 8 byte block is encrypted on its
+//! own while the `len & 7` bytes that don't fill a block are sent in cleartext. Blocks are read as
+//! two big endian words.
+
+mod tables;
+
+use crate::auth::PlayerKey;
+
+pub const BLOCK_LEN: usize = 8;
+
+/// The expanded key schedule, it never changes after it was set up so one cipher can be shared by
+/// everything talking to the same client.
+#[derive(Clone)]
+pub struct Blowfish {
+    p: [u32; 18],
+    s: Box<[[u32; 256]; 4]>,
+}
+
+impl Blowfish {
+    /// `PlayerKey` makes sure the key has a length blowfish accepts.
+    pub fn new(key: &PlayerKey) -> Self {
+        Blowfish::with_key(key.as_bytes())
+    }
+
+    /// For keys that aren't player keys, like the ones spectator chunks are encrypted with. `key`
+    /// must not be empty.
+    pub fn with_key(key: &[u8]) -> Self {
+        let mut cipher = Blowfish {
+            p: tables::P,
+            s: Box::new(tables::S),
+        };
+        cipher.expand_key(key);
+        cipher
+    }
+
+    fn expand_key(&mut self, key: &[u8]) {
+        let mut key_bytes = key.iter().cycle();
+        for p in self.p.iter_mut() {
+            let word = (0..4).fold(0, |word, _| word << 8 | *key_bytes.next().unwrap() as u32);
+            *p ^= word;
+        }
+        let mut block = (0, 0);
+        for i in 0..9 {
+            block = self.encrypt_words(block.0, block.1);
+            self.p[2 * i] = block.0;
+            self.p[2 * i + 1] = block.1;
+        }
+        for i in 0..4 {
+            for j in 0..128 {
+                block = self.encrypt_words(block.0, block.1);
+                self.s[i][2 * j] = block.0;
+                self.s[i][2 * j + 1] = block.1;
+            }
+        }
+    }
+
+    #[inline]
+    fn round(&self, x: u32) -> u32 {
+        let a = self.s[0][(x >> 24) as usize];
+        let b = self.s[1][(x >> 16) as u8 as usize];
+        let c = self.s[2][(x >> 8) as u8 as usize];
+        let d = self.s[3][x as u8 as usize];
+        (a.wrapping_add(b) ^ c).wrapping_add(d)
+    }
+
+    fn encrypt_words(&self, mut l: u32, mut r: u32) -> (u32, u32) {
+        for i in 0..8 {
+            l ^= self.p[2 * i];
+            r ^= self.round(l);
+            r ^= self.p[2 * i + 1];
+            l ^= self.round(r);
+        }
+        (r ^ self.p[17], l ^ self.p[16])
+    }
+
+    fn decrypt_words(&self, mut l: u32, mut r: u32) -> (u32, u32) {
+        for i in (1..9).rev() {
+            l ^= self.p[2 * i + 1];
+            r ^= self.round(l);
+            r ^= self.p[2 * i];
+            l ^= self.round(r);
+        }
+        (r ^ self.p[0], l ^ self.p[1])
+    }
+
+    fn map_blocks(data: &mut [u8], f: impl Fn(u32, u32) -> (u32, u32)) {
+        for block in data.chunks_exact_mut(BLOCK_LEN) {
+            let l = u32::from_be_bytes([block[0], block[1], block[2], block[3]]);
+            let r = u32::from_be_bytes([block[4], block[5], block[6], block[7]]);
+            let (l, r) = f(l, r);
+            block[..4].copy_from_slice(&l.to_be_bytes());
+            block[4..].copy_from_slice(&r.to_be_bytes());
+        }
+    }
+
+    /// Encrypts every full block of `data`, leaving the bytes after the last one as they are.
+    pub fn encrypt_in_place(&self, data: &mut [u8]) {
+        Blowfish::map_blocks(data, |l, r| self.encrypt_words(l, r));
+    }
+
+    /// Reverses [`encrypt_in_place`](Self::encrypt_in_place).
+    pub fn decrypt_in_place(&self, data: &mut [u8]) {
+        Blowfish::map_blocks(data, |l, r| self.decrypt_words(l, r));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(key: &[u8]) -> Blowfish {
+        Blowfish::new(&PlayerKey::new(key.to_vec()).unwrap())
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn known_answers() {
+        // from the test vectors published along with blowfish
+        let vectors = [
+            ("0000000000000000", "0000000000000000", "4EF997456198DD78"),
+            ("FFFFFFFFFFFFFFFF", "FFFFFFFFFFFFFFFF", "51866FD5B85ECB8A"),
+            ("3000000000000000", "1000000000000001", "7D856F9A613063F2"),
+            ("0123456789ABCDEF", "1111111111111111", "61F9C3802281B096"),
+            ("FEDCBA9876543210", "0123456789ABCDEF", "0ACEAB0FC6A0A28D"),
+            ("7CA110454A1A6E57", "01A1D6D039776742", "59C68245EB05282B"),
+        ];
+        for (key, plain, encrypted) in &vectors {
+            let cipher = cipher(&hex(key));
+            let mut data = hex(plain);
+            cipher.encrypt_in_place(&mut data);
+            assert_eq!(data, hex(encrypted), "key {}", key);
+            cipher.decrypt_in_place(&mut data);
+            assert_eq!(data, hex(plain), "key {}", key);
+        }
+    }
+
+    #[test]
+    fn keychecks_match_the_client() {
+        let cipher = cipher(b"GLzvuWtyCfHyGhF2");
+        let mut check_id = 12u64.to_le_bytes();
+        cipher.encrypt_in_place(&mut check_id);
+        assert_eq!(check_id, [0xF2, 0x79, 0xEB, 0xC8, 0xDE, 0x1E, 0x2A, 0x78]);
+    }
+
+    #[test]
+    fn partial_blocks_stay_in_cleartext() {
+        let cipher = cipher(b"GLzvuWtyCfHyGhF2");
+        let plain = (0..21).collect::<Vec<u8>>();
+        let mut data = plain.clone();
+        cipher.encrypt_in_place(&mut data);
+        assert_eq!(data[..16], hex("3D30C902B71C0C3F80A21E48080C0A8A")[..]);
+        assert_eq!(data[16..], plain[16..]);
+        cipher.decrypt_in_place(&mut data);
+        assert_eq!(data, plain);
+
+        // packets shorter than a block aren't encrypted at all
+        let mut short = [1, 2, 3];
+        cipher.encrypt_in_place(&mut short);
+        assert_eq!(short, [1, 2, 3]);
+    }
+}
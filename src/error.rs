@@ -1,20 +1,35 @@
 use core::fmt;
 use std::{error, io};
 
+use crate::{client::ClientId, config::AddressError};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     SerializationError(rblitz_packets::Error),
+    Network(enet::Error),
     AuthError,
+    /// Waiting on the other side took longer than allowed.
+    Timeout,
+    Address(AddressError),
+    /// A config that was handed to us at runtime couldn't be used.
+    InvalidConfig(String),
+    /// A packet or event referred to a client that isn't part of the game.
+    UnknownClient(ClientId),
+    /// The auth provider doesn't know the player with this id.
+    UnknownPlayer(u64),
 }
 
 impl error::Error for Error {}
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        (self as &dyn fmt::Debug).fmt(fmt)
+        match self {
+            Error::Address(e) => e.fmt(fmt),
+            _ => (self as &dyn fmt::Debug).fmt(fmt),
+        }
     }
 }
 
@@ -29,3 +44,15 @@ impl From<rblitz_packets::Error> for Error {
         Error::SerializationError(e)
     }
 }
+
+impl From<enet::Error> for Error {
+    fn from(e: enet::Error) -> Self {
+        Error::Network(e)
+    }
+}
+
+impl From<AddressError> for Error {
+    fn from(e: AddressError) -> Self {
+        Error::Address(e)
+    }
+}
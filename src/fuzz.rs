@@ -0,0 +1,39 @@
+//! The server's handling of the bytes clients send, minus the game, for the fuzz targets in
+//! `fuzz/`.
+
+use rblitz_packets::packets::registry::{self, Reencode};
+
+use crate::{
+    error::Result,
+    packet::{
+        batch::{self, BATCH_PACKET_ID},
+        game::RawGamePacket,
+        KeyCheck,
+    },
+};
+
+/// Reads `data` like a packet received on one of the game channels, taking batches apart and
+/// decoding every packet as the client packet its id stands for.
+pub fn game_packet(data: &[u8]) -> Result<()> {
+    if data.first() == Some(&BATCH_PACKET_ID) {
+        for packet in batch::split(data)? {
+            client_packet(&packet)?;
+        }
+        Ok(())
+    } else {
+        client_packet(data)
+    }
+}
+
+fn client_packet(data: &[u8]) -> Result<()> {
+    let packet = RawGamePacket::from_slice(data)?;
+    if let Some(result) = registry::client_packet(packet.id, Reencode(packet.data)) {
+        result?;
+    }
+    Ok(())
+}
+
+/// Reads `data` like a packet received on the handshake channel.
+pub fn key_check(data: &[u8]) -> Option<[u8; KeyCheck::SIZE]> {
+    KeyCheck::from_bytes(data).map(KeyCheck::to_bytes)
+}
@@ -0,0 +1,215 @@
+//! Running several independent games in one process, each [`GameServer`] with its own world,
+//! port and players on a thread of its own.
+//!
+//! Games are started from a [`GameConfig`], either directly or at runtime through the
+//! [`control`] socket, and stay listed with their result after they ended until a game with the
+//! same name replaces them.
+
+#[cfg(unix)]
+pub mod control;
+
+use indexmap::IndexMap;
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    auth::{self, PlayerKey},
+    config::GameConfig,
+    error::{Error, Result},
+    game_server::{GameServer, GameSummary, ShutdownHandle},
+};
+
+pub use crate::lenet_server::Address;
+
+/// How often [`GameHost::run`] checks on its games and the control socket.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Debug)]
+pub enum GameStatus {
+    Running,
+    Finished(GameSummary),
+    /// The thread of the game panicked.
+    Failed(String),
+}
+
+pub struct HostedGame {
+    pub name: String,
+    pub address: Address,
+    /// The keys handed out to the players by their player ids if the game uses random keys.
+    pub keys: Vec<(u64, PlayerKey)>,
+    pub status: GameStatus,
+    shutdown: ShutdownHandle,
+    /// Only returns `None` if the game failed to start.
+    thread: Option<JoinHandle<Option<GameSummary>>>,
+}
+
+impl HostedGame {
+    pub fn is_running(&self) -> bool {
+        matches!(self.status, GameStatus::Running)
+    }
+}
+
+#[derive(Default)]
+pub struct GameHost {
+    games: IndexMap<String, HostedGame>,
+}
+
+impl GameHost {
+    pub fn new() -> Self {
+        GameHost::default()
+    }
+
+    /// Starts a game on a new thread, returning once it is listening for players.
+    pub fn start(&mut self, config: GameConfig) -> Result<Address> {
+        let name = config.name.clone();
+        if self.games.get(&name).is_some_and(HostedGame::is_running) {
+            return Err(Error::InvalidConfig(format!(
+                "a game named `{}` is already running",
+                name
+            )));
+        }
+        let address = config.server.bind_address()?;
+        let random_keys = config.server.auth.random_keys;
+        let (tx, rx) = mpsc::channel();
+        // the server isn't Send so it has to be created on the thread running it
+        let thread = thread::Builder::new()
+            .name(format!("game {}", name))
+            .spawn(move || {
+                let mut server = match launch(address, config) {
+                    Ok(server) => server,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return None;
+                    },
+                };
+                let keys = if random_keys {
+                    server.keys()
+                } else {
+                    Vec::new()
+                };
+                let _ = tx.send(Ok((server.shutdown_handle(), keys)));
+                Some(server.run())
+            })?;
+        let (shutdown, keys) = match rx.recv() {
+            Ok(Ok(started)) => started,
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                return Err(e);
+            },
+            Err(_) => return Err(Error::InvalidConfig(panic_message(thread.join()))),
+        };
+        log::info!("started game {} on {}", name, address);
+        self.games.insert(
+            name.clone(),
+            HostedGame {
+                name,
+                address,
+                keys,
+                status: GameStatus::Running,
+                shutdown,
+                thread: Some(thread),
+            },
+        );
+        Ok(address)
+    }
+
+    /// Asks a running game to end, returns whether there was one with that name.
+    pub fn stop(&self, name: &str) -> bool {
+        match self.games.get(name) {
+            Some(game) if game.is_running() => {
+                game.shutdown.shutdown();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn game(&self, name: &str) -> Option<&HostedGame> {
+        self.games.get(name)
+    }
+
+    pub fn games(&self) -> impl Iterator<Item = &HostedGame> {
+        self.games.values()
+    }
+
+    /// Collects the results of the games that ended since the last poll.
+    pub fn poll(&mut self) {
+        for game in self.games.values_mut() {
+            if !game.thread.as_ref().is_some_and(JoinHandle::is_finished) {
+                continue;
+            }
+            game.status = match game.thread.take().unwrap().join() {
+                Ok(Some(summary)) => {
+                    log::info!(
+                        "game {} ended after {:.0}s ({:?})",
+                        game.name,
+                        summary.game_time,
+                        summary.reason
+                    );
+                    GameStatus::Finished(summary)
+                },
+                result => {
+                    let message = panic_message(result);
+                    log::error!("game {} failed: {}", game.name, message);
+                    GameStatus::Failed(message)
+                },
+            };
+        }
+    }
+
+    /// Keeps polling the games and serving `control` until `shutdown` is requested, then ends all
+    /// games that are still running.
+    #[cfg(unix)]
+    pub fn run(&mut self, control: &mut control::ControlSocket, shutdown: &ShutdownHandle) {
+        while !shutdown.is_shutdown() {
+            control.poll(self);
+            self.poll();
+            thread::sleep(POLL_INTERVAL);
+        }
+        self.shutdown();
+    }
+
+    /// Ends all running games and waits for them.
+    pub fn shutdown(&mut self) {
+        for game in self.games.values() {
+            game.shutdown.shutdown();
+        }
+        while self.games.values().any(HostedGame::is_running) {
+            thread::sleep(POLL_INTERVAL);
+            self.poll();
+        }
+    }
+}
+
+/// Creates the server of a game along with everything its config asks for.
+fn launch(address: Address, config: GameConfig) -> Result<GameServer<'static, 'static>> {
+    let mut auth = auth::from_config(&config.server.auth, &config.players, &config.spectators)?;
+    let mut server = GameServer::new(address, config.players, &mut auth)?;
+    server.add_spectators(config.spectators, config.server.spectator_delay, &mut auth)?;
+    server.set_fault_policy(config.server.faults);
+    server.set_rate_limits(&config.server.rate_limits)?;
+    if let Some(path) = &config.server.capture {
+        server.capture_to(BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(path) = &config.server.record_input {
+        server.record_input_to(BufWriter::new(File::create(path)?))?;
+    }
+    Ok(server)
+}
+
+fn panic_message<T>(result: thread::Result<T>) -> String {
+    match result {
+        Ok(_) => "the game stopped while starting".to_owned(),
+        Err(panic) => panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "the game panicked".to_owned()),
+    }
+}
@@ -0,0 +1,165 @@
+//! A Unix socket to manage the games of a [`GameHost`] while it runs.
+//!
+//! Commands are sent one per line and every answer ends with a line starting with `ok` or
+//! `error`:
+//!
+//! - `start <path>` starts the game described by the RON file at `path`, see [`GameConfig`], and
+//!   answers with `ok <name> <address>`, preceded by a `key <player id> <base64 key>` line for
+//!   every player if the game hands out random keys
+//! - `stop <name>` asks a running game to end
+//! - `status` lists every game as `game <name> <address> <status>` before the final `ok`, the
+//!   status being `running`, `finished <reason> <game time>s <ticks> ticks` or `failed <error>`
+//!
+//! [`GameConfig`]: crate::config::GameConfig
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+use super::{GameHost, GameStatus};
+use crate::config::GameConfig;
+
+/// Commands are short, a connection sending longer lines is dropped.
+const MAX_LINE_LENGTH: usize = 4096;
+
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    connections: Vec<Connection>,
+}
+
+/// A client that stays connected between polls.
+struct Connection {
+    stream: UnixStream,
+    /// What was received after the last complete line.
+    pending: Vec<u8>,
+}
+
+impl ControlSocket {
+    /// Listens on `path`, replacing a socket left behind by a previous run.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        if fs::symlink_metadata(&path).is_ok() {
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        log::info!("accepting commands on {}", path.display());
+        Ok(ControlSocket {
+            listener,
+            path,
+            connections: Vec::new(),
+        })
+    }
+
+    /// Accepts new connections and answers the commands that arrived since the last poll.
+    pub fn poll(&mut self, host: &mut GameHost) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.connections.push(Connection {
+                        stream,
+                        pending: Vec::new(),
+                    }),
+                    Err(e) => log::error!("failed to set up a control connection: {}", e),
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::error!("failed to accept a control connection: {}", e);
+                    break;
+                },
+            }
+        }
+        self.connections
+            .retain_mut(|connection| match connection.serve(host) {
+                Ok(open) => open,
+                Err(e) => {
+                    log::debug!("control connection failed: {}", e);
+                    false
+                },
+            });
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Connection {
+    /// Answers every complete line received so far, returns whether the connection is still open.
+    fn serve(&mut self, host: &mut GameHost) -> io::Result<bool> {
+        let mut buf = [0; 1024];
+        let open = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break false,
+                Ok(read) => self.pending.extend_from_slice(&buf[..read]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break true,
+                Err(e) => return Err(e),
+            }
+        };
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line = self.pending.drain(..=end).collect::<Vec<_>>();
+            execute(
+                &mut self.stream,
+                host,
+                String::from_utf8_lossy(&line).trim(),
+            )?;
+        }
+        if self.pending.len() > MAX_LINE_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+        }
+        Ok(open)
+    }
+}
+
+fn execute(writer: &mut UnixStream, host: &mut GameHost, line: &str) -> io::Result<()> {
+    let mut args = line.splitn(2, ' ');
+    match (args.next().unwrap_or_default(), args.next()) {
+        ("", None) => (),
+        ("start", Some(path)) => {
+            let started = GameConfig::from_path(path).and_then(|config| {
+                let name = config.name.clone();
+                host.start(config).map(|address| (name, address))
+            });
+            match started {
+                Ok((name, address)) => {
+                    for (player_id, key) in &host.game(&name).unwrap().keys {
+                        writeln!(writer, "key {} {}", player_id, key.to_base64())?;
+                    }
+                    writeln!(writer, "ok {} {}", name, address)?
+                },
+                Err(e) => writeln!(writer, "error {}", e)?,
+            }
+        },
+        ("stop", Some(name)) => {
+            if host.stop(name) {
+                writeln!(writer, "ok")?;
+            } else {
+                writeln!(writer, "error no running game named {}", name)?;
+            }
+        },
+        ("status", None) => {
+            host.poll();
+            for game in host.games() {
+                write!(writer, "game {} {} ", game.name, game.address)?;
+                match &game.status {
+                    GameStatus::Running => writeln!(writer, "running")?,
+                    GameStatus::Finished(summary) => writeln!(
+                        writer,
+                        "finished {:?} {:.0}s {} ticks",
+                        summary.reason, summary.game_time, summary.ticks
+                    )?,
+                    GameStatus::Failed(e) => writeln!(writer, "failed {}", e)?,
+                }
+            }
+            writeln!(writer, "ok")?;
+        },
+        (command, _) => writeln!(writer, "error unknown command {}", command)?,
+    }
+    Ok(())
+}
@@ -1,90 +1,471 @@
+use rblitz_packets::packets::game::server::SExit;
+use shred::SystemData;
 use specs::{Dispatcher, DispatcherBuilder, World};
 
-use std::{net::Ipv4Addr, time::Instant};
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
-    client::ClientMap,
-    config::PlayerConfig,
-    lenet_server::LENetServer,
-    packet::{packet_dispatcher_sys::PacketDispatcher, packet_handler_system::PacketHandlerSys},
+    auth::{AuthProvider, PlayerKey},
+    capture::CaptureWriter,
+    client::{ClientMap, ClientStatus, Traffic},
+    config::{FaultConfig, PlayerConfig, RateLimitConfig, SpectatorConfig},
+    error::Result,
+    lenet_server::{Address, Event, NetworkThread},
+    observer::{ChunkRecorder, GameRecording, ObserverSettings, PLATFORM_ID},
+    packet::{
+        fault::Faults,
+        game,
+        packet_dispatcher_sys::{PacketDispatcher, PacketSender},
+        packet_handler_system::PacketHandlerSys,
+        rate_limit::RateLimits,
+        Channel,
+    },
+    replay::{InputRecord, InputWriter, Replay},
+    spectator::Spectators,
+    systems::{MovementSys, NetworkStatsSys},
     world::{
-        components::{NetId, SummonerSpells, Team, UnitName},
-        resources::GameTime,
+        components::{MovePath, NetId, Position, SummonerSpells, Team, UnitName},
+        resources::{GamePhase, GameTime, PacketCapture, Shutdown},
     },
 };
 
-const TICK_RATE: f64 = 1.0 / 30.0;
+pub(crate) const TICK_RATE: f64 = 1.0 / 30.0;
+/// There is only ever one game per server so far.
+pub(crate) const GAME_ID: u64 = 12314;
+/// How long to wait for clients to acknowledge their disconnection before dropping them.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Why a game server stopped running.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// Requested through a [`ShutdownHandle`].
+    Requested,
+    /// Every player lost connection.
+    AllDisconnected,
+    /// The replayed input ran out.
+    ReplayFinished,
+}
+
+/// Asks a running [`GameServer`] to shut down, this can be shared with other threads and is safe
+/// to use from within a signal handler.
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerSummary {
+    pub name: String,
+    pub player_id: u64,
+    /// Whether the player was still connected when the game ended.
+    pub connected: bool,
+    pub traffic: Traffic,
+}
+
+/// What [`GameServer::run`] returns once the game is over.
+#[derive(Clone, Debug)]
+pub struct GameSummary {
+    pub reason: ShutdownReason,
+    /// Game time in seconds.
+    pub game_time: f64,
+    pub ticks: u64,
+    /// Frames that started more than a tick late, a sign of the server not keeping up.
+    pub overruns: u64,
+    pub players: Vec<PlayerSummary>,
+    pub spectators: Vec<PlayerSummary>,
+}
+
+/// Where the events the game reacts to come from.
+enum Input {
+    Network(NetworkThread),
+    Replay(Replay),
+}
 
 pub struct GameServer<'a, 'b> {
     world: World,
-    server: LENetServer,
+    input: Input,
+    recording: Option<InputWriter<Box<dyn io::Write + Send + Sync>>>,
     packet_handler: PacketHandlerSys<'a>,
     dispatcher: Dispatcher<'a, 'b>,
+    shutdown: ShutdownHandle,
+    ticks: u64,
+    overruns: u64,
 }
 
-#[allow(clippy::cast_lossless)]
-fn to_enet_address(address: Ipv4Addr, port: u16) -> enet_sys::ENetAddress {
-    let octets = address.octets();
-    enet_sys::ENetAddress {
-        host: (octets[0] as u32)
-            | (octets[1] as u32) << 8
-            | (octets[2] as u32) << 16
-            | (octets[3] as u32) << 24,
-        port,
+impl<'a, 'b> GameServer<'a, 'b> {
+    /// Opens a server for `players`, who authenticate with the keys `auth` knows for them.
+    pub fn new(
+        address: Address,
+        players: Vec<PlayerConfig>,
+        auth: &mut dyn AuthProvider,
+    ) -> Result<Self> {
+        let network = NetworkThread::spawn(address)?;
+        log::info!("listening on {}", address);
+        GameServer::with_input(Input::Network(network), players, auth)
     }
-}
 
-impl<'a, 'b> GameServer<'a, 'b> {
-    pub fn new(address: Ipv4Addr, port: u16, players: Vec<PlayerConfig>) -> Result<Self, ()> {
-        let server = LENetServer::new(to_enet_address(address, port));
+    /// Replays the input recorded with [`record_input_to`](Self::record_input_to) instead of
+    /// opening a server. Ticks follow each other without waiting, and as long as `players` is the
+    /// config of the recorded game and `auth` knows the keys the players had, the game plays out
+    /// exactly the same way.
+    pub fn replay<R: io::Read>(
+        input: R,
+        players: Vec<PlayerConfig>,
+        auth: &mut dyn AuthProvider,
+    ) -> Result<Self> {
+        let replay = Replay::new(input)?;
+        GameServer::with_input(Input::Replay(replay), players, auth)
+    }
+
+    fn with_input(
+        input: Input,
+        players: Vec<PlayerConfig>,
+        auth: &mut dyn AuthProvider,
+    ) -> Result<Self> {
         let mut world = World::new();
         world.add_resource(GameTime(0.0));
+        world.add_resource(GamePhase::default());
+        world.add_resource(Shutdown::default());
+        world.add_resource(Spectators::default());
+        world.add_resource(Faults::default());
+        world.add_resource(RateLimits::default());
         // temporary
         {
             world.register::<NetId>();
             world.register::<Team>();
             world.register::<UnitName>();
             world.register::<SummonerSpells>();
+            world.register::<Position>();
+            world.register::<MovePath>();
         }
         let (packet_channel_send, packet_channel_receive) = crossbeam_channel::unbounded();
         world.add_resource(packet_channel_send);
         let mut dispatcher = DispatcherBuilder::new()
+            .with(NetworkStatsSys::default(), "network_stats", &[])
+            .with(MovementSys::default(), "movement", &[])
             .with_thread_local(PacketDispatcher::new(packet_channel_receive))
             .build();
         dispatcher.setup(&mut world.res);
-        ClientMap::init_from_config(&mut world, players);
+        ClientMap::init_from_config(&mut world, players, auth)?;
         Ok(GameServer {
             world,
+            input,
+            recording: None,
             packet_handler: PacketHandlerSys::new(),
-            server,
             dispatcher,
+            shutdown: ShutdownHandle::default(),
+            ticks: 0,
+            overruns: 0,
         })
     }
 
-    pub fn run(&mut self) {
-        let mut last_instant = Instant::now();
-        let mut delta_sum = 0.0;
-        loop {
-            let elapsed = last_instant.elapsed();
-            last_instant = Instant::now();
-            let delta =
-                (elapsed.as_secs() as f64) + (elapsed.subsec_nanos() as f64) / 1_000_000_000.0;
-            delta_sum += delta;
-            self.world.write_resource::<GameTime>().0 += delta;
-            self.packet_handler.run(&mut self.server, &self.world);
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Opens spectator slots, spectators receive everything broadcast to the players `delay`
+    /// seconds of game time late. Their keys come from `auth` just like the ones of the players.
+    pub fn add_spectators(
+        &mut self,
+        spectators: Vec<SpectatorConfig>,
+        delay: f64,
+        auth: &mut dyn AuthProvider,
+    ) -> Result<()> {
+        let first_id = self.world.read_resource::<ClientMap>().len() as u32;
+        let spectators = Spectators::new(spectators, first_id, delay, auth)?;
+        self.world.add_resource(spectators);
+        Ok(())
+    }
+
+    /// The keys the players and spectators have to connect with by their player ids, for handing
+    /// out random keys.
+    pub fn keys(&self) -> Vec<(u64, PlayerKey)> {
+        let clients = self.world.read_resource::<ClientMap>();
+        let spectators = self.world.read_resource::<Spectators>();
+        clients
+            .values()
+            .map(|c| (c.player_id, c.session.key().clone()))
+            .chain(
+                spectators
+                    .values()
+                    .map(|s| (s.player_id, s.session.key().clone())),
+            )
+            .collect()
+    }
+
+    /// Decides how many packets a client may send that can't be handled before it gets kicked,
+    /// see [`FaultConfig`].
+    pub fn set_fault_policy(&mut self, config: FaultConfig) {
+        self.world.add_resource(Faults::new(config));
+    }
+
+    /// Limits how often clients may send the packets in `config`, replacing the previous limits.
+    pub fn set_rate_limits(&mut self, config: &RateLimitConfig) -> Result<()> {
+        self.world.add_resource(RateLimits::new(config)?);
+        Ok(())
+    }
+
+    /// Cuts the spawn sequence and everything broadcast to the players from now on into chunks and
+    /// keyframes for observers, see [`observer`](crate::observer). They are held back for `delay`
+    /// seconds and also saved to `directory` if one is given. The returned recording can be served with an
+    /// [`ObserverServer`](crate::observer::http::ObserverServer).
+    pub fn observe(
+        &mut self,
+        settings: ObserverSettings,
+        delay: f64,
+        directory: Option<PathBuf>,
+    ) -> io::Result<Arc<RwLock<GameRecording>>> {
+        if let Some(directory) = &directory {
+            fs::create_dir_all(directory)?;
+        }
+        let recording = GameRecording::new(GAME_ID, PLATFORM_ID.to_owned(), &settings, delay);
+        let recording = Arc::new(RwLock::new(recording));
+        let game_time = self.world.read_resource::<GameTime>().0;
+        let spawn = game::spawn_sequence(self.world.system_data());
+        let recorder = ChunkRecorder::new(recording.clone(), directory, game_time, &spawn);
+        self.world.add_resource(recorder);
+        Ok(recording)
+    }
+
+    /// Records every packet exchanged from now on to `writer`, decrypted and in the format
+    /// described in [`capture`](crate::capture).
+    pub fn capture_to<W>(&mut self, writer: W) -> io::Result<()>
+    where
+        W: io::Write + Send + Sync + 'static,
+    {
+        let writer = CaptureWriter::new(Box::new(writer) as Box<dyn io::Write + Send + Sync>)?;
+        self.world.add_resource(PacketCapture::new(writer));
+        Ok(())
+    }
+
+    /// Records every event the game handles from now on to `writer` so the game can be replayed
+    /// later, see [`replay`](crate::replay) for the format.
+    pub fn record_input_to<W>(&mut self, writer: W) -> io::Result<()>
+    where
+        W: io::Write + Send + Sync + 'static,
+    {
+        self.recording = Some(InputWriter::new(Box::new(writer) as Box<_>)?);
+        Ok(())
+    }
+
+    /// Runs the game until it ends or a shutdown is requested, disconnecting all clients before
+    /// returning.
+    ///
+    /// Every frame advances the game by exactly one tick, live games wait for the wall clock to
+    /// catch up between frames while replays run them back to back. The game time of a frame only
+    /// depends on its tick, so a replay handles every event at the game time it was first handled
+    /// at.
+    pub fn run(&mut self) -> GameSummary {
+        let tick_duration = Duration::from_secs_f64(TICK_RATE);
+        let mut next_frame = Instant::now();
+        let reason = loop {
+            if self.shutdown.is_shutdown() {
+                break ShutdownReason::Requested;
+            }
+            if let Some(reason) = self.world.read_resource::<Shutdown>().0 {
+                break reason;
+            }
+            match &self.input {
+                Input::Network(_) => {
+                    let now = Instant::now();
+                    if next_frame > now {
+                        std::thread::sleep(next_frame - now);
+                    } else if now - next_frame > tick_duration {
+                        // the frames missed are caught up on instead of skipped, keeping the game
+                        // time in step with the wall clock
+                        self.overruns += 1;
+                    }
+                    next_frame += tick_duration;
+                },
+                Input::Replay(replay) if replay.is_finished() => {
+                    break ShutdownReason::ReplayFinished
+                },
+                Input::Replay(_) => (),
+            }
+            self.world.write_resource::<GameTime>().0 += TICK_RATE;
+            self.handle_input();
             self.dispatcher.dispatch_seq(&self.world.res);
+            // before the packets of this frame are recorded, so the first chunk of the running
+            // game starts with what was sent when it started
+            if let Some(mut recorder) = self.world.res.try_fetch_mut::<ChunkRecorder>() {
+                let game_time = self.world.read_resource::<GameTime>().0;
+                recorder.update(game_time, *self.world.read_resource::<GamePhase>());
+            }
             self.dispatcher.dispatch_thread_local(&self.world.res);
+            self.tick();
+            self.world.maintain();
+        };
+        log::info!("shutting down: {:?}", reason);
 
-            if delta_sum >= TICK_RATE {
-                delta_sum -= TICK_RATE;
-                self.tick();
-            }
+        let (players, spectators) = self.disconnect_clients();
+        if let Some(mut capture) = self.world.res.try_fetch_mut::<PacketCapture>() {
+            capture.flush();
+        }
+        if let Some(mut recorder) = self.world.res.try_fetch_mut::<ChunkRecorder>() {
+            recorder.finish(self.world.read_resource::<GameTime>().0);
+        }
+        if let Some(Err(e)) = self.recording.as_mut().map(InputWriter::flush) {
+            log::error!("failed to flush the input recording: {}", e);
+        }
+        GameSummary {
+            reason,
+            game_time: self.world.read_resource::<GameTime>().0,
+            ticks: self.ticks,
+            overruns: self.overruns,
+            players,
+            spectators,
+        }
+    }
 
-            self.world.maintain();
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+    }
 
-            std::thread::sleep_ms(1);
+    /// Handles the events that arrived since the last frame, or that were recorded for the
+    /// current tick when replaying.
+    fn handle_input(&mut self) {
+        loop {
+            let event = match &mut self.input {
+                Input::Network(network) => match network.try_recv() {
+                    Some(event) => event,
+                    None => break,
+                },
+                Input::Replay(replay) => match replay.next_event(self.ticks) {
+                    Some(event) => event,
+                    None => break,
+                },
+            };
+            // packets get decrypted in place, so they have to be recorded before handling them
+            let recorded = match (
+                &mut self.recording,
+                InputRecord::from_event(self.ticks, &event),
+            ) {
+                (Some(recording), Some(record)) => recording.write(&record),
+                _ => Ok(()),
+            };
+            if let Err(e) = recorded {
+                log::error!("input recording failed, no longer recording: {}", e);
+                self.recording = None;
+            }
+            self.packet_handler.handle_event(&self.world, event);
         }
     }
 
-    pub fn tick(&mut self) {}
+    /// Tells every connected client and spectator that the game is over and waits for them to
+    /// disconnect.
+    fn disconnect_clients(&mut self) -> (Vec<PlayerSummary>, Vec<PlayerSummary>) {
+        {
+            let clients = self.world.read_resource::<ClientMap>();
+            let net_ids = self.world.read_storage::<NetId>();
+            let sender = PacketSender::fetch(&self.world.res);
+            // by status rather than connection, so replays say goodbye to the same clients
+            let connected = clients
+                .iter()
+                .filter(|(_, c)| c.status != ClientStatus::Disconnected);
+            for (cid, client) in connected {
+                let net_id = net_ids.get(client.champion).map_or(0, |net_id| net_id.id());
+                sender.single_packet(
+                    *cid,
+                    Channel::Broadcast,
+                    net_id,
+                    &SExit { client_id: cid.0 },
+                );
+            }
+            let spectators = self.world.read_resource::<Spectators>();
+            for cid in spectators.watching() {
+                sender.single_packet(cid, Channel::Broadcast, 0, &SExit { client_id: cid.0 });
+            }
+        }
+        self.dispatcher.dispatch_thread_local(&self.world.res);
+        let players = self
+            .world
+            .read_resource::<ClientMap>()
+            .values()
+            .map(|client| {
+                if let Some(connection) = &client.session.connection {
+                    connection.disconnect_later();
+                }
+                PlayerSummary {
+                    name: client.name.clone(),
+                    player_id: client.player_id,
+                    connected: client.session.connection.is_some(),
+                    traffic: client.session.traffic(),
+                }
+            })
+            .collect();
+
+        let mut spectators = self.world.write_resource::<Spectators>();
+        let spectator_summaries = spectators
+            .values()
+            .map(|spectator| {
+                if let Some(connection) = &spectator.session.connection {
+                    connection.disconnect_later();
+                }
+                PlayerSummary {
+                    name: spectator.name.clone(),
+                    player_id: spectator.player_id,
+                    connected: spectator.session.connection.is_some(),
+                    traffic: spectator.session.traffic(),
+                }
+            })
+            .collect();
+
+        let deadline = Instant::now() + DISCONNECT_TIMEOUT;
+        let mut clients = self.world.write_resource::<ClientMap>();
+        if let Input::Network(network) = &mut self.input {
+            while (clients.values().any(|c| c.session.connection.is_some())
+                || spectators.values().any(|s| s.session.connection.is_some()))
+                && Instant::now() < deadline
+            {
+                if let Some(Event::Disconnected(cid)) =
+                    network.recv_timeout(Duration::from_millis(10))
+                {
+                    if let Some(client) = clients.get_mut(&cid) {
+                        client.status = ClientStatus::Disconnected;
+                        client.session.connection = None;
+                    } else if let Some(spectator) = spectators.get_mut(&cid) {
+                        spectator.status = ClientStatus::Disconnected;
+                        spectator.session.connection = None;
+                    }
+                }
+            }
+        }
+        for client in clients.values_mut() {
+            if let Some(connection) = client.session.connection.take() {
+                log::warn!(
+                    "player {} didn't acknowledge the disconnect",
+                    client.player_id
+                );
+                connection.disconnect_now();
+            }
+            client.status = ClientStatus::Disconnected;
+        }
+        for (_, spectator) in spectators.iter_mut() {
+            if let Some(connection) = spectator.session.connection.take() {
+                connection.disconnect_now();
+            }
+            spectator.status = ClientStatus::Disconnected;
+        }
+        // sends what is still queued before dropping all connections
+        if let Input::Network(network) = &mut self.input {
+            network.stop();
+        }
+        (players, spectator_summaries)
+    }
 }
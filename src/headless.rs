@@ -0,0 +1,372 @@
+//! A client that speaks just enough of the protocol to drive a [`GameServer`] without the game.
+//!
+//! It is meant for integration tests and tooling: everything blocks on the calling thread and
+//! nothing is simulated, the client only sends what it is told to and collects what the server
+//! sends back.
+//!
+//! [`GameServer`]: crate::game_server::GameServer
+
+use rblitz_packets::packets::{
+    game::{
+        client::{CCharSelected, CClientReady, CPingLoadInfo, CSyncVersion},
+        common::ConnectionInfo,
+        server::{SEndSpawn, SSyncVersion},
+    },
+    loading_screen::RequestJoinTeam,
+    PacketId,
+};
+use serde::Deserialize;
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    auth::PlayerKey,
+    crypto::Blowfish,
+    error::{Error, Result},
+    lenet_server::{backend, Address, Packet, PeerHandle},
+    packet::{
+        batch,
+        game::{GamePacket, RawGamePacket},
+        loading_screen::LoadingScreenPacket,
+        KeyCheck,
+    },
+};
+
+pub use crate::{lenet_server::PacketMode, packet::Channel};
+
+/// The game opens all of its channels when connecting.
+const CHANNEL_COUNT: usize = 8;
+
+/// A packet received from the server, already decrypted and split out of its batch.
+#[derive(Clone, Debug)]
+pub struct ServerPacket {
+    pub channel: Channel,
+    /// How enet delivered the packet, taken from the flags it arrived with.
+    pub mode: PacketMode,
+    /// Whether the packet arrived in a batch along with others.
+    pub batched: bool,
+    pub id: u8,
+    /// Always 0 for packets that aren't game packets, e.g. the ones of the loading screen.
+    pub sender_net_id: u32,
+    /// The payload following the id and sender net id.
+    pub data: Vec<u8>,
+}
+
+impl ServerPacket {
+    /// Deserializes the packet if its id is the one of `P`.
+    pub fn decode<P>(&self) -> Option<Result<P>>
+    where
+        P: PacketId + for<'de> Deserialize<'de>,
+    {
+        if self.id == P::ID {
+            Some(rblitz_packets::from_bytes(&self.data).map_err(Into::into))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct HeadlessClient {
+    host: backend::Host<()>,
+    peer: PeerHandle<()>,
+    blowfish: Blowfish,
+    player_id: u64,
+    client_id: Option<u32>,
+    connected: bool,
+    received: VecDeque<ServerPacket>,
+}
+
+impl HeadlessClient {
+    /// Connects to the server and authenticates with the blowfish `key` of the player.
+    pub fn connect(
+        address: Address,
+        key: &[u8],
+        player_id: u64,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let deadline = Instant::now() + timeout;
+        let key = PlayerKey::new(key.to_vec()).map_err(|_| Error::AuthError)?;
+        let blowfish = Blowfish::new(&key);
+        let mut host = backend::Host::new(None, 1)?;
+        let peer = host.connect(address, CHANNEL_COUNT)?;
+        let mut client = HeadlessClient {
+            host,
+            peer,
+            blowfish,
+            player_id,
+            client_id: None,
+            connected: false,
+            received: VecDeque::new(),
+        };
+        while !client.connected {
+            client.service(deadline)?;
+        }
+
+        let mut check_id = player_id.to_le_bytes();
+        client.blowfish.encrypt_in_place(&mut check_id);
+        let keycheck = KeyCheck {
+            action: 0,
+            pad: [0; 3],
+            client_id: 0,
+            player_id,
+            check_id,
+        };
+        // the keycheck is the only packet that isn't encrypted as the server doesn't know who we
+        // are yet
+        client.send_raw(Channel::Handshake, &keycheck.to_bytes())?;
+        while client.client_id.is_none() {
+            client.service(deadline)?;
+        }
+        Ok(client)
+    }
+
+    /// The client id the server assigned to us during the handshake.
+    pub fn client_id(&self) -> u32 {
+        self.client_id.unwrap_or_default()
+    }
+
+    pub fn player_id(&self) -> u64 {
+        self.player_id
+    }
+
+    /// Walks through the loading screen like the game does, returning once the server spawned
+    /// the heroes and got told we are ready. The game itself only starts once every player is
+    /// ready, which is announced with an `SStartGame`.
+    pub fn load(&mut self, version: &str, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let client_id = self.client_id();
+        self.send(
+            Channel::ClientToServer,
+            0,
+            &CSyncVersion {
+                time_last_client: 0.0,
+                client_id,
+                version: version.to_owned(),
+            },
+        )?;
+        self.wait_for_until::<SSyncVersion>(deadline)?;
+        self.send_loading_screen(&RequestJoinTeam {
+            client_id,
+            ..Default::default()
+        })?;
+        self.send(Channel::ClientToServer, 0, &CCharSelected)?;
+        self.wait_for_until::<SEndSpawn>(deadline)?;
+        self.send(
+            Channel::ClientToServer,
+            0,
+            &CPingLoadInfo {
+                connection_info: ConnectionInfo {
+                    client_id,
+                    player_id: self.player_id,
+                    percentage: 100.0,
+                    ready: true,
+                    ..Default::default()
+                },
+            },
+        )?;
+        self.send(Channel::ClientToServer, 0, &CClientReady)
+    }
+
+    /// Encrypts and sends a game packet.
+    pub fn send<P>(&mut self, channel: Channel, sender_net_id: u32, packet: &P) -> Result<()>
+    where
+        P: GamePacket,
+    {
+        self.send_encrypted(channel, &mut packet.to_bytes(sender_net_id))
+    }
+
+    /// Encrypts and sends a packet on the loading screen channel.
+    pub fn send_loading_screen<P>(&mut self, packet: &P) -> Result<()>
+    where
+        P: LoadingScreenPacket,
+    {
+        self.send_encrypted(Channel::LoadingScreen, &mut packet.to_bytes())
+    }
+
+    /// Encrypts and sends arbitrary bytes on any channel, including ones the server doesn't know,
+    /// to see how it copes with packets it can't handle.
+    pub fn send_bytes(&mut self, channel_id: u8, data: &[u8]) -> Result<()> {
+        let mut data = data.to_vec();
+        self.blowfish.encrypt_in_place(&mut data);
+        let packet = Packet::new(&data, PacketMode::Reliable)?;
+        self.peer.send(channel_id, packet)?;
+        self.host.flush();
+        Ok(())
+    }
+
+    fn send_encrypted(&mut self, channel: Channel, data: &mut [u8]) -> Result<()> {
+        self.blowfish.encrypt_in_place(data);
+        self.send_raw(channel, data)
+    }
+
+    fn send_raw(&mut self, channel: Channel, data: &[u8]) -> Result<()> {
+        let packet = Packet::new(data, channel.default_mode())?;
+        self.peer.send(channel as u8, packet)?;
+        self.host.flush();
+        Ok(())
+    }
+
+    /// Returns the next packet the server sent, waiting up to `timeout` for one to arrive.
+    pub fn recv(&mut self, timeout: Duration) -> Result<Option<ServerPacket>> {
+        let deadline = Instant::now() + timeout;
+        while self.received.is_empty() {
+            match self.service(deadline) {
+                Ok(()) => (),
+                Err(Error::Timeout) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self.received.pop_front())
+    }
+
+    /// Iterates over the packets the server sends, ending once none arrived for `timeout`.
+    pub fn packets(&mut self, timeout: Duration) -> Packets<'_> {
+        Packets {
+            client: self,
+            timeout,
+        }
+    }
+
+    /// Waits for the server to send a `P`. Packets received in the meantime are kept and can
+    /// still be retrieved through [`recv`](Self::recv).
+    pub fn wait_for<P>(&mut self, timeout: Duration) -> Result<P>
+    where
+        P: PacketId + for<'de> Deserialize<'de>,
+    {
+        self.wait_for_until(Instant::now() + timeout)
+    }
+
+    fn wait_for_until<P>(&mut self, deadline: Instant) -> Result<P>
+    where
+        P: PacketId + for<'de> Deserialize<'de>,
+    {
+        let mut checked = 0;
+        loop {
+            let found =
+                self.received.iter().skip(checked).position(|packet| {
+                    packet.id == P::ID && packet.channel != Channel::LoadingScreen
+                });
+            if let Some(idx) = found {
+                let packet = self.received.remove(checked + idx).unwrap();
+                return packet.decode().unwrap();
+            }
+            checked = self.received.len();
+            self.service(deadline)?;
+        }
+    }
+
+    /// Disconnects from the server, waiting up to `timeout` for it to acknowledge.
+    pub fn disconnect(mut self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        self.peer.disconnect(0);
+        while self.connected {
+            match self.service(deadline) {
+                Ok(()) => (),
+                // the disconnection we asked for
+                Err(_) if !self.connected => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a single event of the host.
+    fn service(&mut self, deadline: Instant) -> Result<()> {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::Timeout);
+        }
+        let timeout = deadline - now;
+        let timeout = timeout.as_secs() as u32 * 1000 + timeout.subsec_millis().max(1);
+        match self.host.service(timeout)? {
+            Some(backend::Event::Connect(_)) => self.connected = true,
+            Some(backend::Event::Disconnect { .. }) => {
+                self.connected = false;
+                return Err(enet::Error::PeerDisconnected.into());
+            },
+            Some(backend::Event::Receive {
+                channel_id,
+                mut packet,
+                ..
+            }) => {
+                self.blowfish.decrypt_in_place(&mut packet);
+                match Channel::try_from(channel_id) {
+                    Some(channel) => self.receive(channel, packet.mode(), &packet)?,
+                    None => log::warn!("received a packet on unknown channel {}", channel_id),
+                }
+            },
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, channel: Channel, mode: PacketMode, data: &[u8]) -> Result<()> {
+        match channel {
+            Channel::Handshake => {
+                if let Some(keycheck) = KeyCheck::from_bytes(data) {
+                    if keycheck.player_id == self.player_id {
+                        self.client_id = Some(keycheck.client_id);
+                    }
+                }
+            },
+            _ if channel.carries_game_packets() => {
+                if data.first() == Some(&batch::BATCH_PACKET_ID) {
+                    for packet in batch::split(data)? {
+                        self.receive_game_packet(channel, mode, true, &packet)?;
+                    }
+                } else {
+                    self.receive_game_packet(channel, mode, false, data)?;
+                }
+            },
+            _ => {
+                if let Some((&id, data)) = data.split_first() {
+                    self.received.push_back(ServerPacket {
+                        channel,
+                        mode,
+                        batched: false,
+                        id,
+                        sender_net_id: 0,
+                        data: data.to_vec(),
+                    });
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn receive_game_packet(
+        &mut self,
+        channel: Channel,
+        mode: PacketMode,
+        batched: bool,
+        data: &[u8],
+    ) -> Result<()> {
+        let packet = RawGamePacket::from_slice(data)?;
+        self.received.push_back(ServerPacket {
+            channel,
+            mode,
+            batched,
+            id: packet.id,
+            sender_net_id: packet.sender_net_id,
+            data: packet.data.to_vec(),
+        });
+        Ok(())
+    }
+}
+
+/// See [`HeadlessClient::packets`].
+pub struct Packets<'a> {
+    client: &'a mut HeadlessClient,
+    timeout: Duration,
+}
+
+impl<'a> Iterator for Packets<'a> {
+    type Item = Result<ServerPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.client.recv(self.timeout).transpose()
+    }
+}
@@ -1,122 +1,352 @@
-use enet_sys as enet;
+//! The LENet server runs on a network thread of its own which owns the host and all of its peers.
+//! The game thread receives [`Event`]s from it and sends packets back through the [`Connection`]s
+//! of its clients, so neither side waits on the other.
 
-use core::{mem, ops, ptr, slice};
+#[cfg(not(feature = "pure-rust-enet"))]
+pub(crate) use enet as backend;
+#[cfg(feature = "pure-rust-enet")]
+pub(crate) use enet::pure as backend;
 
-use crate::{client::ClientId, packet::KeyCheck};
+pub use self::backend::{Address, Packet, PacketMode, PeerHandle, PeerStats};
 
-pub struct PacketData(*mut enet::ENetPacket);
+use self::backend::Host;
 
-impl ops::Deref for PacketData {
-    type Target = [u8];
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
 
-    fn deref(&self) -> &Self::Target {
-        unsafe { slice::from_raw_parts((*self.0).data, (*self.0).dataLength) }
-    }
-}
+use std::{
+    collections::HashMap,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
-impl ops::DerefMut for PacketData {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { slice::from_raw_parts_mut((*self.0).data, (*self.0).dataLength) }
-    }
-}
+use crate::{
+    client::ClientId,
+    config::AddressError,
+    error::{Error, Result},
+    packet::KeyCheck,
+};
 
-impl Drop for PacketData {
-    fn drop(&mut self) {
-        unsafe { enet::enet_packet_destroy(self.0) };
-    }
-}
+/// Whether the selected LENet implementation can bind to IPv6 addresses.
+pub const IPV6_SUPPORTED: bool = cfg!(feature = "pure-rust-enet");
+
+/// How long the network thread waits for incoming data before checking for commands again.
+const SERVICE_TIMEOUT: u32 = 1;
+/// How often the connection quality of every client is handed to the game.
+const STATS_INTERVAL: Duration = Duration::from_millis(500);
 
 pub enum Event {
     NoEvent,
-    Connected(KeyCheck, *mut enet::ENetPeer),
+    /// A client sent its keycheck, the connection is missing when replaying.
+    Connected(KeyCheck, Option<PendingConnection>),
     Disconnected(ClientId),
     // cid, channel, data
-    Packet(ClientId, u8, PacketData),
+    Packet(ClientId, u8, Packet),
+    /// The connection quality of a client as measured by enet.
+    Stats(ClientId, PeerStats),
+}
+
+/// What the game asks of the network thread.
+enum Command {
+    Accept(u32, ClientId),
+    Reject(u32),
+    Send(ClientId, u8, PacketMode, Box<[u8]>),
+    Disconnect(ClientId),
+    DisconnectLater(ClientId),
+    DisconnectNow(ClientId),
+    Stop,
+}
+
+/// What the server knows about a peer, attached to it as user data.
+#[derive(Copy, Clone, Debug)]
+enum PeerTag {
+    /// The peer sent a keycheck the game has yet to answer.
+    Pending(u32),
+    Client(ClientId),
+}
+
+/// A peer that sent a keycheck, the game either accepts it as the connection of a client or
+/// rejects it. Until then everything else the peer sends is ignored.
+pub struct PendingConnection {
+    id: u32,
+    commands: Sender<Command>,
+}
+
+impl PendingConnection {
+    /// Makes the peer the connection of `cid`, replacing the one the client had before.
+    pub fn accept(&self, cid: ClientId) -> Connection {
+        let _ = self.commands.send(Command::Accept(self.id, cid));
+        Connection {
+            cid,
+            commands: self.commands.clone(),
+        }
+    }
+
+    /// Drops the peer right away.
+    pub fn reject(&self) {
+        let _ = self.commands.send(Command::Reject(self.id));
+    }
+}
+
+/// The connection of a client, everything sent through it is handed to the network thread in
+/// order.
+pub struct Connection {
+    cid: ClientId,
+    commands: Sender<Command>,
+}
+
+impl Connection {
+    pub fn send(&self, channel: u8, mode: PacketMode, data: &[u8]) {
+        let _ = self
+            .commands
+            .send(Command::Send(self.cid, channel, mode, data.into()));
+    }
+
+    pub fn disconnect(&self) {
+        let _ = self.commands.send(Command::Disconnect(self.cid));
+    }
+
+    /// Disconnects once all queued packets are sent.
+    pub fn disconnect_later(&self) {
+        let _ = self.commands.send(Command::DisconnectLater(self.cid));
+    }
+
+    /// Disconnects without waiting for the client to acknowledge it.
+    pub fn disconnect_now(&self) {
+        let _ = self.commands.send(Command::DisconnectNow(self.cid));
+    }
 }
 
 pub struct LENetServer {
-    host: *mut enet::ENetHost,
+    host: Host<PeerTag>,
+    commands: Sender<Command>,
+    /// Peers that sent a keycheck by the id their [`PendingConnection`] refers to them with.
+    pending: HashMap<u32, PeerHandle<PeerTag>>,
+    next_pending: u32,
+    peers: HashMap<ClientId, PeerHandle<PeerTag>>,
 }
 
 impl LENetServer {
-    pub fn new(address: enet::ENetAddress) -> Self {
-        LENetServer {
-            host: unsafe { enet::enet_host_create(&address, 32, 0, 0) },
-        }
+    fn new(address: Address, commands: Sender<Command>) -> Result<Self> {
+        let host = Host::new(Some(address), 32).map_err(|e| match e {
+            backend::Error::HostCreation => AddressError::Unbindable(address.to_string()).into(),
+            e => Error::from(e),
+        })?;
+        Ok(LENetServer {
+            host,
+            commands,
+            pending: HashMap::new(),
+            next_pending: 0,
+            peers: HashMap::new(),
+        })
+    }
+
+    /// Sends all queued packets right away.
+    fn flush(&mut self) {
+        self.host.flush();
     }
 
-    pub fn service(&mut self, timeout: u32) -> Result<Event, &'static str> {
-        let mut event: enet::ENetEvent = unsafe { mem::zeroed() };
-        let result = unsafe { enet::enet_host_service(self.host, &mut event, timeout) };
-        if result < 0 {
-            return Err("something bad happened");
+    fn service(&mut self, timeout: u32) -> Result<Event> {
+        loop {
+            let event = match self.host.service(timeout)? {
+                Some(event) => event,
+                None => return Ok(Event::NoEvent),
+            };
+            match event {
+                // peers only get a client id once the game accepted their keycheck
+                backend::Event::Connect(_) => (),
+                backend::Event::Disconnect { data, .. } => match data {
+                    Some(PeerTag::Client(cid)) => {
+                        self.peers.remove(&cid);
+                        return Ok(Event::Disconnected(cid));
+                    },
+                    Some(PeerTag::Pending(id)) => {
+                        self.pending.remove(&id);
+                    },
+                    None => (),
+                },
+                backend::Event::Receive {
+                    peer,
+                    channel_id,
+                    packet,
+                } => match peer.data() {
+                    Some(PeerTag::Client(cid)) => {
+                        return Ok(Event::Packet(cid, channel_id, packet))
+                    },
+                    tag if channel_id == 0 => {
+                        let keycheck = match KeyCheck::from_bytes(&packet) {
+                            Some(keycheck) => keycheck,
+                            None => continue,
+                        };
+                        let id = match tag {
+                            // a keycheck sent again before the game answered the first one
+                            Some(PeerTag::Pending(id)) => id,
+                            _ => {
+                                let id = self.next_pending;
+                                self.next_pending = self.next_pending.wrapping_add(1);
+                                peer.set_data(Some(PeerTag::Pending(id)))?;
+                                self.pending.insert(id, peer);
+                                id
+                            },
+                        };
+                        let connection = PendingConnection {
+                            id,
+                            commands: self.commands.clone(),
+                        };
+                        return Ok(Event::Connected(keycheck, Some(connection)));
+                    },
+                    _ => (),
+                },
+            }
         }
-        match event.type_ {
-            enet::_ENetEventType_ENET_EVENT_TYPE_CONNECT => {
-                set_peer_data::<ClientId>(event.peer, None);
-                Ok(Event::NoEvent)
+    }
+
+    fn execute(&mut self, command: Command) -> Result<()> {
+        match command {
+            Command::Accept(id, cid) => {
+                if let Some(peer) = self.pending.remove(&id) {
+                    peer.set_data(Some(PeerTag::Client(cid)))?;
+                    if let Some(stale) = self.peers.insert(cid, peer) {
+                        // the client reconnected before its old connection timed out, detach the
+                        // old peer so its disconnection doesn't get mistaken for one of the new
+                        // connection
+                        let _ = stale.set_data(None);
+                        stale.disconnect_now(0);
+                    }
+                }
+            },
+            Command::Reject(id) => {
+                if let Some(peer) = self.pending.remove(&id) {
+                    let _ = peer.set_data(None);
+                    peer.disconnect_now(0);
+                }
             },
-            enet::_ENetEventType_ENET_EVENT_TYPE_DISCONNECT => {
-                if let Some(cid) = peer_data(event.peer) {
-                    set_peer_data::<ClientId>(event.peer, None);
-                    Ok(Event::Disconnected(*cid))
-                } else {
-                    Ok(Event::NoEvent)
+            Command::Send(cid, channel, mode, data) => {
+                if let Some(peer) = self.peers.get(&cid) {
+                    let result =
+                        Packet::new(&data, mode).and_then(|packet| peer.send(channel, packet));
+                    if let Err(e) = result {
+                        log::error!(
+                            "failed to send packet to client {} on channel {}: {}",
+                            cid.0,
+                            channel,
+                            e
+                        );
+                    }
                 }
             },
-            enet::_ENetEventType_ENET_EVENT_TYPE_RECEIVE => {
-                let data = unsafe {
-                    slice::from_raw_parts_mut((*event.packet).data, (*event.packet).dataLength)
-                };
-                let channel = event.channelID;
-                if let Some(cid) = peer_data(event.peer) {
-                    Ok(Event::Packet(*cid, channel, PacketData(event.packet)))
-                } else if event.channelID != 0
-                    || unsafe { (*event.packet).dataLength as usize } < mem::size_of::<KeyCheck>()
-                {
-                    unsafe { enet::enet_packet_destroy(event.packet) };
-                    Ok(Event::NoEvent)
-                } else {
-                    let packet = unsafe { *(data.as_ptr() as *const _ as *const KeyCheck) };
-                    unsafe { enet::enet_packet_destroy(event.packet) };
-                    Ok(Event::Connected(packet, event.peer))
+            Command::Disconnect(cid) => {
+                if let Some(peer) = self.peers.get(&cid) {
+                    peer.disconnect(0);
                 }
             },
-            _ => Ok(Event::NoEvent),
+            Command::DisconnectLater(cid) => {
+                if let Some(peer) = self.peers.get(&cid) {
+                    peer.disconnect_later(0);
+                }
+            },
+            Command::DisconnectNow(cid) => {
+                // no disconnect event follows, so the peer is gone right away
+                if let Some(peer) = self.peers.remove(&cid) {
+                    peer.disconnect_now(0);
+                }
+            },
+            Command::Stop => unreachable!("handled by the network thread"),
         }
+        Ok(())
     }
-}
 
-impl Drop for LENetServer {
-    fn drop(&mut self) {
-        unsafe { enet::enet_host_destroy(self.host) };
-    }
-}
+    /// Services the host until the game stops the thread or goes away.
+    fn run(mut self, commands: Receiver<Command>, events: Sender<Event>) {
+        let mut next_stats = Instant::now() + STATS_INTERVAL;
+        loop {
+            let mut executed = false;
+            loop {
+                match commands.try_recv() {
+                    Ok(Command::Stop) | Err(TryRecvError::Disconnected) => {
+                        self.flush();
+                        return;
+                    },
+                    Ok(command) => {
+                        if let Err(e) = self.execute(command) {
+                            log::error!("{}", e);
+                        }
+                        executed = true;
+                    },
+                    Err(TryRecvError::Empty) => break,
+                }
+            }
+            if executed {
+                self.flush();
+            }
 
-pub fn set_peer_data<T>(peer: *mut enet::ENetPeer, data: Option<T>) {
-    unsafe {
-        let data_ptr = (*peer).data as *mut T;
-        if !data_ptr.is_null() {
-            Box::<T>::from_raw(data_ptr);
+            match self.service(SERVICE_TIMEOUT) {
+                Ok(Event::NoEvent) => (),
+                Ok(event) => {
+                    if events.send(event).is_err() {
+                        return;
+                    }
+                },
+                Err(e) => log::error!("{}", e),
+            }
+
+            if Instant::now() >= next_stats {
+                next_stats += STATS_INTERVAL;
+                for (cid, peer) in &self.peers {
+                    if let Ok(stats) = peer.stats() {
+                        let _ = events.send(Event::Stats(*cid, stats));
+                    }
+                }
+            }
         }
-        (*peer).data = match data {
-            Some(data) => Box::into_raw(Box::new(data)) as *mut _,
-            None => ptr::null_mut(),
-        };
     }
 }
 
-fn peer_data<'a, T>(peer: *mut enet::ENetPeer) -> Option<&'a T> {
-    unsafe {
-        let data_ptr = (*peer).data as *const T;
-        if data_ptr.is_null() {
-            None
-        } else {
-            Some(&(*data_ptr))
+/// The handle of the thread running an [`LENetServer`], stopping it when dropped.
+pub struct NetworkThread {
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NetworkThread {
+    /// Binds a server to `address` and starts servicing it.
+    pub fn spawn(address: Address) -> Result<Self> {
+        let (commands_tx, commands_rx) = crossbeam_channel::unbounded();
+        let (events_tx, events_rx) = crossbeam_channel::unbounded();
+        let server = LENetServer::new(address, commands_tx.clone())?;
+        let thread = thread::Builder::new()
+            .name(format!("network {}", address))
+            .spawn(move || server.run(commands_rx, events_tx))?;
+        Ok(NetworkThread {
+            commands: commands_tx,
+            events: events_rx,
+            thread: Some(thread),
+        })
+    }
+
+    /// The next event if one arrived already.
+    pub fn try_recv(&self) -> Option<Event> {
+        self.events.try_recv().ok()
+    }
+
+    /// Waits up to `timeout` for the next event.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Event> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Sends everything still queued and stops the thread, dropping all connections.
+    pub fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.commands.send(Command::Stop);
+            if thread.join().is_err() {
+                log::error!("the network thread panicked");
+            }
         }
     }
 }
 
-// no problem here since service(the only method) takes &mut self
-unsafe impl core::marker::Send for LENetServer {}
-unsafe impl core::marker::Sync for LENetServer {}
+impl Drop for NetworkThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
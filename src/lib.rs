@@ -1,13 +1,23 @@
 #![deny(bare_trait_objects)]
 #![allow(clippy::cast_lossless)]
 
+pub mod auth;
+pub mod capture;
 pub mod config;
+pub mod error;
+#[doc(hidden)]
+pub mod fuzz;
+pub mod game_host;
 pub mod game_server;
+pub mod headless;
+pub mod observer;
+pub mod replay;
 
 mod client;
-mod error;
+mod crypto;
 mod lenet_server;
 mod nav_grid;
 mod packet;
+mod spectator;
 mod systems;
 mod world;
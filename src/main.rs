@@ -1,33 +1,205 @@
-use rblitz::{config, game_server};
+use rblitz::{
+    auth::{self, PlayerKey},
+    config,
+    game_server::GameServer,
+    observer::{http::ObserverServer, GameRecording},
+};
+#[cfg(unix)]
+use rblitz::{
+    game_host::{control::ControlSocket, GameHost},
+    game_server::ShutdownHandle,
+};
 
-struct EnetInit;
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    process,
+    sync::{mpsc, Arc, RwLock},
+};
 
-impl EnetInit {
-    fn new() -> Self {
-        unsafe { enet_sys::enet_initialize() };
-        EnetInit
+const CONFIG: &str = "config/server.toml";
+const PLAYER_CONFIG: &str = "config/players.ron";
+/// Optional, the game can be played without any spectator slots.
+const SPECTATOR_CONFIG: &str = "config/spectators.ron";
+
+fn main() {
+    setup_logger().unwrap();
+    let mut args = std::env::args().skip(1);
+    let (mode, path) = (args.next(), args.next());
+    // hosted games bring their own config, none of the files are needed
+    #[cfg(unix)]
+    {
+        if let (Some("--host"), Some(path)) = (mode.as_deref(), &path) {
+            host_games(path.as_ref());
+            return;
+        }
+    }
+    let config = config::Config::from_path(CONFIG);
+    let config::Config {
+        server: serverc,
+        observer,
+    } = exit_on_error(config, "read", CONFIG);
+    if let (Some("--serve"), Some(path)) = (mode.as_deref(), &path) {
+        serve_recording(path.as_ref(), observer.as_ref());
+        return;
+    }
+    let pconfig = config::PlayerConfig::from_path(PLAYER_CONFIG);
+    let pconfig = exit_on_error(pconfig, "read", PLAYER_CONFIG);
+    let spectators = if Path::new(SPECTATOR_CONFIG).exists() {
+        let spectators = config::SpectatorConfig::from_path(SPECTATOR_CONFIG);
+        exit_on_error(spectators, "read", SPECTATOR_CONFIG)
+    } else {
+        Vec::new()
+    };
+    match mode.as_deref() {
+        // the input was encrypted with the keys of the recorded game, fresh ones can't read it
+        Some("--replay") if serverc.auth.random_keys => {
+            log::error!("games with random keys can't be replayed, disable `random_keys`");
+            process::exit(1)
+        },
+        None if serverc.auth.random_keys && serverc.auth.keys_file.is_none() => {
+            log::error!("random keys need a `keys_file` to hand them out through");
+            process::exit(1)
+        },
+        _ => (),
+    }
+    let mut auth = auth::from_config(&serverc.auth, &pconfig, &spectators).unwrap_or_else(|e| {
+        log::error!("{}", e);
+        process::exit(1)
+    });
+    let mut server = match (mode.as_deref(), path) {
+        (None, _) => {
+            let address = serverc.bind_address().unwrap_or_else(|e| {
+                log::error!("{}", e);
+                process::exit(1)
+            });
+            let mut server = GameServer::new(address, pconfig, &mut auth).unwrap_or_else(|e| {
+                log::error!("{}", e);
+                process::exit(1)
+            });
+            if let Some(path) = &serverc.record_input {
+                let result = create(path).and_then(|file| server.record_input_to(file));
+                exit_on_error(result, "record the input to", path.display());
+            }
+            server
+        },
+        (Some("--replay"), Some(path)) => {
+            let input = File::open(&path).map(BufReader::new);
+            let input = exit_on_error(input, "open", &path);
+            log::info!("replaying {}", path);
+            GameServer::replay(input, pconfig, &mut auth).unwrap()
+        },
+        _ => {
+            log::error!(
+                "usage: rblitz [--replay <input log> | --serve <observer directory> | --host \
+                 <control socket>]"
+            );
+            process::exit(1)
+        },
+    };
+    server.set_fault_policy(serverc.faults);
+    if let Err(e) = server.set_rate_limits(&serverc.rate_limits) {
+        log::error!("{}", e);
+        process::exit(1)
+    }
+    if let Err(e) = server.add_spectators(spectators, serverc.spectator_delay, &mut auth) {
+        log::error!("{}", e);
+        process::exit(1)
+    }
+    if let Some(path) = &serverc.auth.keys_file {
+        let result = create(path).and_then(|file| write_keys(file, &server.keys()));
+        exit_on_error(result, "write the keys to", path.display());
+    }
+    if let Some(path) = &serverc.capture {
+        let result = create(path).and_then(|file| server.capture_to(file));
+        exit_on_error(result, "capture the packets to", path.display());
+    }
+    let _observer_server = observer.map(|observer| {
+        let directory = observer.directory.clone().unwrap_or_default();
+        let recording = server.observe(
+            observer.settings(),
+            serverc.spectator_delay,
+            observer.directory.clone(),
+        );
+        let recording = exit_on_error(recording, "create", directory.display());
+        let observer_server = listen_for_observers(&observer);
+        observer_server.add_game(recording);
+        observer_server
+    });
+    let shutdown = server.shutdown_handle();
+    ctrlc::set_handler(move || shutdown.shutdown()).expect("failed to set the signal handler");
+    let summary = server.run();
+    log::info!(
+        "game ended after {:.0}s ({:?})",
+        summary.game_time,
+        summary.reason
+    );
+    for player in summary.players.iter().chain(&summary.spectators) {
+        log::info!(
+            "{} ({}): {}B in, {}B out",
+            player.name,
+            player.player_id,
+            player.traffic.bytes_in,
+            player.traffic.bytes_out
+        );
     }
 }
 
-impl Drop for EnetInit {
-    fn drop(&mut self) {
-        unsafe { enet_sys::enet_deinitialize() };
+/// Writes a `<player id> <base64 key>` line for every player and spectator.
+fn write_keys(mut file: impl Write, keys: &[(u64, PlayerKey)]) -> std::io::Result<()> {
+    for (player_id, key) in keys {
+        writeln!(file, "{} {}", player_id, key.to_base64())?;
     }
+    file.flush()
 }
 
-fn main() {
-    let _init = EnetInit::new();
-    setup_logger().unwrap();
-    let config::Config { server: serverc } =
-        config::Config::from_path("config/server.toml").unwrap();
-    let pconfig = config::PlayerConfig::from_path("config/players.ron").unwrap();
-    let mut server = game_server::GameServer::new(
-        serverc.address.parse().expect("invalid server ip address"),
-        serverc.port,
-        pconfig,
-    )
-    .unwrap();
-    server.run();
+fn create(path: &Path) -> std::io::Result<BufWriter<File>> {
+    File::create(path).map(BufWriter::new)
+}
+
+/// Runs the games started through the control socket until interrupted.
+#[cfg(unix)]
+fn host_games(socket: &Path) {
+    let control = ControlSocket::bind(socket);
+    let mut control = exit_on_error(control, "listen for commands on", socket.display());
+    let shutdown = ShutdownHandle::default();
+    {
+        let shutdown = shutdown.clone();
+        ctrlc::set_handler(move || shutdown.shutdown()).expect("failed to set the signal handler");
+    }
+    GameHost::new().run(&mut control, &shutdown);
+}
+
+/// Serves a game saved by the observer endpoint until interrupted.
+fn serve_recording(directory: &Path, observer: Option<&config::ObserverConfig>) {
+    let observer = observer.unwrap_or_else(|| {
+        log::error!("serving a game requires an [observer] section in the config");
+        process::exit(1)
+    });
+    let recording = GameRecording::load(directory);
+    let recording = exit_on_error(recording, "load", directory.display());
+    let server = listen_for_observers(observer);
+    server.add_game(Arc::new(RwLock::new(recording)));
+    let (tx, rx) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = tx.send(());
+    })
+    .expect("failed to set the signal handler");
+    let _ = rx.recv();
+}
+
+fn listen_for_observers(observer: &config::ObserverConfig) -> ObserverServer {
+    let server = ObserverServer::bind(observer.address);
+    exit_on_error(server, "listen for observers on", observer.address)
+}
+
+fn exit_on_error<T>(result: std::io::Result<T>, action: &str, target: impl fmt::Display) -> T {
+    result.unwrap_or_else(|e| {
+        log::error!("failed to {} {}: {}", action, target, e);
+        process::exit(1)
+    })
 }
 
 fn setup_logger() -> Result<(), fern::InitError> {
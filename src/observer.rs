@@ -0,0 +1,568 @@
+//! Cutting the broadcast stream of a game into chunks and keyframes for the observer endpoint of
+//! the game's spectator mode, see [`http`] for the REST layout and [`GameServer::observe`].
+//!
+//! The recording starts with the spawn sequence creating the heroes, followed by everything
+//! broadcast to the players and spectators, all appended to the current chunk which gets closed
+//! every `chunk_interval` seconds. Chunks closed before the game started are the startup chunks
+//! the client has to play through before anything else. Once the game ran for `keyframe_interval`
+//! seconds, a keyframe follows the chunk closed every `keyframe_interval` seconds. The world can't
+//! be serialized yet, so instead of a snapshot a keyframe holds the blocks of all chunks up to it,
+//! which brings a client to the same state.
+//!
+//! Chunks and keyframes are a sequence of blocks the way the client reads them, every block holds
+//! a single game packet:
+//!
+//! | field  | type         | description                                               |
+//! |--------|--------------|-----------------------------------------------------------|
+//! | marker | `u8`         | the enet channel, the flags of the upper 4 bits are unset |
+//! | time   | `f32`        | the game time in seconds the packet was sent at           |
+//! | length | `i32`        | the length of the payload                                 |
+//! | type   | `u8`         | the packet id                                             |
+//! | params | `i32`        | the net id of the sender                                  |
+//! | data   | `[u8; len]`  | the payload of the packet                                 |
+//!
+//! The blocks are compressed with gzip and encrypted with blowfish in ECB mode with PKCS#5 padding
+//! before being served, using a key chosen for every game. The metadata hands it out as
+//! `encryptionKey`, encrypted the same way with the game id as the key and encoded in base64,
+//! [`decode`] reverses both.
+//!
+//! With a directory configured, the blocks of every chunk are written to `chunk-<id>.bin` in it as
+//! soon as it is closed and `game.json` is kept up to date with the key and the chunk and keyframe
+//! list, a finished game can be served again with [`GameRecording::load`].
+//!
+//! [`GameServer::observe`]: crate::game_server::GameServer::observe
+
+pub mod http;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    crypto::{Blowfish, BLOCK_LEN},
+    packet::{game::RawGamePacket, Channel},
+    world::resources::GamePhase,
+};
+
+/// The platform observers have to ask for in their requests.
+pub const PLATFORM_ID: &str = "RBLZ1";
+const METADATA_FILE: &str = "game.json";
+const CHUNK_KEY_LEN: usize = 16;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub id: u32,
+    /// Game time in seconds.
+    pub start_time: f64,
+    /// Game time in seconds.
+    pub end_time: f64,
+    /// The latest keyframe before this chunk, 0 if there is none yet.
+    pub keyframe_id: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyFrameInfo {
+    pub id: u32,
+    /// Game time in seconds.
+    pub time: f64,
+    /// The first chunk following this keyframe.
+    pub next_chunk_id: u32,
+}
+
+/// The chunks and keyframes of a single game, shared between the game and the observer endpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameRecording {
+    pub game_id: u64,
+    pub platform_id: String,
+    /// The key chunks and keyframes are encrypted with, in the form the metadata hands it out.
+    pub encryption_key: String,
+    /// Seconds between chunks.
+    pub chunk_interval: f64,
+    /// Seconds between keyframes.
+    pub keyframe_interval: f64,
+    /// Seconds chunks are held back for after being closed.
+    pub delay: f64,
+    /// The latest game time the recording has seen.
+    pub game_time: f64,
+    /// The last chunk recorded before the game started, 0 while loading.
+    pub end_startup_chunk_id: u32,
+    /// The first chunk of the running game, 0 while loading.
+    pub start_game_chunk_id: u32,
+    pub ended: bool,
+    pub chunks: Vec<ChunkInfo>,
+    pub keyframes: Vec<KeyFrameInfo>,
+    /// The blocks of every chunk, encoded once they are requested.
+    #[serde(skip)]
+    data: Vec<Vec<u8>>,
+}
+
+impl GameRecording {
+    pub fn new(game_id: u64, platform_id: String, config: &ObserverSettings, delay: f64) -> Self {
+        let mut chunk_key = vec![0; CHUNK_KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut chunk_key);
+        GameRecording {
+            game_id,
+            platform_id,
+            encryption_key: base64::encode(&encrypt(&game_cipher(game_id), &chunk_key)),
+            chunk_interval: config.chunk_interval,
+            keyframe_interval: config.keyframe_interval,
+            delay,
+            game_time: 0.0,
+            end_startup_chunk_id: 0,
+            start_game_chunk_id: 0,
+            ended: false,
+            chunks: Vec::new(),
+            keyframes: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    /// Loads a game saved to `directory`, the whole game is available right away.
+    pub fn load<P: AsRef<Path>>(directory: P) -> io::Result<Self> {
+        let directory = directory.as_ref();
+        let metadata = fs::read(directory.join(METADATA_FILE))?;
+        let mut recording: GameRecording = serde_json::from_slice(&metadata)?;
+        recording.data = recording
+            .chunks
+            .iter()
+            .map(|chunk| fs::read(chunk_path(directory, chunk.id)))
+            .collect::<io::Result<_>>()?;
+        recording.ended = true;
+        Ok(recording)
+    }
+
+    /// The chunks observers may see, all of them once the game ended.
+    pub fn available_chunks(&self) -> &[ChunkInfo] {
+        let count = if self.ended {
+            self.chunks.len()
+        } else {
+            self.chunks
+                .iter()
+                .take_while(|chunk| chunk.end_time + self.delay <= self.game_time)
+                .count()
+        };
+        &self.chunks[..count]
+    }
+
+    /// The keyframes whose chunks are all available.
+    pub fn available_keyframes(&self) -> &[KeyFrameInfo] {
+        let chunks = self.available_chunks().len() as u32;
+        let count = self
+            .keyframes
+            .iter()
+            .take_while(|keyframe| keyframe.next_chunk_id <= chunks + 1)
+            .count();
+        &self.keyframes[..count]
+    }
+
+    /// A chunk the way the client downloads it.
+    pub fn chunk(&self, id: u32) -> Option<Vec<u8>> {
+        let idx = self
+            .available_chunks()
+            .iter()
+            .position(|chunk| chunk.id == id)?;
+        Some(self.encode(&self.data[idx]))
+    }
+
+    /// A keyframe the way the client downloads it.
+    pub fn keyframe(&self, id: u32) -> Option<Vec<u8>> {
+        let keyframe = self.available_keyframes().iter().find(|k| k.id == id)?;
+        let chunks = keyframe.next_chunk_id as usize - 1;
+        Some(self.encode(&self.data[..chunks].concat()))
+    }
+
+    fn encode(&self, blocks: &[u8]) -> Vec<u8> {
+        // the key was encrypted by us, it always decrypts
+        let chunk_key = decrypt_key(self.game_id, &self.encryption_key).unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        // writing to a vec can't fail
+        let _ = encoder.write_all(blocks);
+        let compressed = encoder.finish().unwrap_or_default();
+        encrypt(&Blowfish::with_key(&chunk_key), &compressed)
+    }
+
+    fn save(&self, directory: &Path) -> io::Result<()> {
+        fs::write(directory.join(METADATA_FILE), serde_json::to_vec(self)?)
+    }
+}
+
+fn chunk_path(directory: &Path, id: u32) -> PathBuf {
+    directory.join(format!("chunk-{}.bin", id))
+}
+
+/// A single packet of a chunk or keyframe.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    /// Game time in seconds.
+    pub time: f32,
+    pub channel: u8,
+    pub packet_id: u8,
+    pub sender_net_id: u32,
+    pub data: Vec<u8>,
+}
+
+impl Block {
+    /// Reads all blocks of a chunk or keyframe that went through [`decode`].
+    pub fn read_all(mut data: &[u8]) -> io::Result<Vec<Block>> {
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            let marker = data.read_u8()?;
+            if marker >> 4 != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported block flags {:#X}", marker >> 4),
+                ));
+            }
+            let time = data.read_f32::<LE>()?;
+            let len = data.read_i32::<LE>()?;
+            let packet_id = data.read_u8()?;
+            let sender_net_id = data.read_i32::<LE>()? as u32;
+            if len < 0 || len as usize > data.len() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let (payload, rest) = data.split_at(len as usize);
+            blocks.push(Block {
+                time,
+                channel: marker,
+                packet_id,
+                sender_net_id,
+                data: payload.to_vec(),
+            });
+            data = rest;
+        }
+        Ok(blocks)
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        // writing to a vec can't fail
+        let _ = buf.write_u8(self.channel & 0x0F);
+        let _ = buf.write_f32::<LE>(self.time);
+        let _ = buf.write_i32::<LE>(self.data.len() as i32);
+        let _ = buf.write_u8(self.packet_id);
+        let _ = buf.write_i32::<LE>(self.sender_net_id as i32);
+        buf.extend_from_slice(&self.data);
+    }
+}
+
+/// Decrypts and decompresses a chunk or keyframe of the game `game_id` with the `encryption_key`
+/// from its metadata, leaving the blocks for [`Block::read_all`].
+pub fn decode(game_id: u64, encryption_key: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let chunk_key = decrypt_key(game_id, encryption_key)?;
+    let compressed = decrypt(&Blowfish::with_key(&chunk_key), data)?;
+    let mut blocks = Vec::new();
+    GzDecoder::new(&compressed[..]).read_to_end(&mut blocks)?;
+    Ok(blocks)
+}
+
+/// The key the chunk keys of a game are encrypted with.
+fn game_cipher(game_id: u64) -> Blowfish {
+    Blowfish::with_key(game_id.to_string().as_bytes())
+}
+
+fn decrypt_key(game_id: u64, encryption_key: &str) -> io::Result<Vec<u8>> {
+    let encrypted = base64::decode(encryption_key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let chunk_key = decrypt(&game_cipher(game_id), &encrypted)?;
+    if chunk_key.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the chunk key is empty",
+        ));
+    }
+    Ok(chunk_key)
+}
+
+/// Pads `data` to full blocks with PKCS#5 and encrypts them.
+fn encrypt(cipher: &Blowfish, data: &[u8]) -> Vec<u8> {
+    let padding = BLOCK_LEN - data.len() % BLOCK_LEN;
+    let mut encrypted = data.to_vec();
+    encrypted.resize(data.len() + padding, padding as u8);
+    cipher.encrypt_in_place(&mut encrypted);
+    encrypted
+}
+
+/// Reverses [`encrypt`].
+fn decrypt(cipher: &Blowfish, data: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid padding");
+    if data.is_empty() || !data.len().is_multiple_of(BLOCK_LEN) {
+        return Err(invalid());
+    }
+    let mut decrypted = data.to_vec();
+    cipher.decrypt_in_place(&mut decrypted);
+    let padding = *decrypted.last().unwrap() as usize;
+    if padding == 0 || padding > BLOCK_LEN {
+        return Err(invalid());
+    }
+    decrypted.truncate(decrypted.len() - padding);
+    Ok(decrypted)
+}
+
+/// How the broadcast stream is cut up.
+#[derive(Copy, Clone, Debug)]
+pub struct ObserverSettings {
+    /// Seconds between chunks.
+    pub chunk_interval: f64,
+    /// Seconds between keyframes, rounded up to the next chunk.
+    pub keyframe_interval: f64,
+}
+
+impl Default for ObserverSettings {
+    fn default() -> Self {
+        ObserverSettings {
+            chunk_interval: 30.0,
+            keyframe_interval: 60.0,
+        }
+    }
+}
+
+/// Only present while observing, collects the broadcasts of the current chunk.
+pub struct ChunkRecorder {
+    recording: Arc<RwLock<GameRecording>>,
+    directory: Option<PathBuf>,
+    current: Vec<u8>,
+    chunk_start: f64,
+    started: bool,
+    next_keyframe: f64,
+}
+
+impl ChunkRecorder {
+    /// The recording starts with the `spawn` sequence at `game_time`.
+    pub(crate) fn new(
+        recording: Arc<RwLock<GameRecording>>,
+        directory: Option<PathBuf>,
+        game_time: f64,
+        spawn: &[Box<[u8]>],
+    ) -> Self {
+        let mut recorder = ChunkRecorder {
+            recording,
+            directory,
+            current: Vec::new(),
+            chunk_start: game_time,
+            started: false,
+            next_keyframe: 0.0,
+        };
+        for packet in spawn {
+            recorder.record(game_time, Channel::Broadcast, packet);
+        }
+        recorder
+    }
+
+    /// Appends a broadcast to the current chunk.
+    pub(crate) fn record(&mut self, game_time: f64, channel: Channel, data: &[u8]) {
+        let packet = match RawGamePacket::from_slice(data) {
+            Ok(packet) => packet,
+            Err(e) => {
+                log::error!("can't record a broadcast on {:?}: {:?}", channel, e);
+                return;
+            },
+        };
+        let block = Block {
+            time: game_time as f32,
+            channel: channel as u8,
+            packet_id: packet.id,
+            sender_net_id: packet.sender_net_id,
+            data: packet.data.to_vec(),
+        };
+        block.write(&mut self.current);
+    }
+
+    /// Closes the current chunk if it is due, which it always is once the game started so the
+    /// startup chunks end there.
+    pub(crate) fn update(&mut self, game_time: f64, phase: GamePhase) {
+        if let GamePhase::Running { .. } = phase {
+            if !self.started {
+                self.started = true;
+                self.next_keyframe = game_time + self.recording.read().unwrap().keyframe_interval;
+                self.close_chunk(game_time);
+                let mut recording = self.recording.write().unwrap();
+                recording.end_startup_chunk_id = recording.chunks.len() as u32;
+                recording.start_game_chunk_id = recording.end_startup_chunk_id + 1;
+            }
+        }
+        if game_time - self.chunk_start >= self.recording.read().unwrap().chunk_interval {
+            self.close_chunk(game_time);
+        }
+        self.recording.write().unwrap().game_time = game_time;
+    }
+
+    /// Closes the last chunk and marks the game as ended.
+    pub(crate) fn finish(&mut self, game_time: f64) {
+        self.close_chunk(game_time);
+        let mut recording = self.recording.write().unwrap();
+        recording.game_time = game_time;
+        recording.ended = true;
+        save_metadata(&mut self.directory, &recording);
+    }
+
+    fn close_chunk(&mut self, game_time: f64) {
+        let mut recording = self.recording.write().unwrap();
+        let id = recording.chunks.len() as u32 + 1;
+        let keyframe_id = recording.keyframes.len() as u32;
+        recording.chunks.push(ChunkInfo {
+            id,
+            start_time: self.chunk_start,
+            end_time: game_time,
+            keyframe_id,
+        });
+        let data = std::mem::take(&mut self.current);
+        if let Some(directory) = &self.directory {
+            if let Err(e) = fs::write(chunk_path(directory, id), &data) {
+                log::error!("failed to save chunk {}, no longer saving: {}", id, e);
+                self.directory = None;
+            }
+        }
+        recording.data.push(data);
+        self.chunk_start = game_time;
+
+        if self.started && game_time >= self.next_keyframe {
+            let interval = recording.keyframe_interval;
+            recording.keyframes.push(KeyFrameInfo {
+                id: keyframe_id + 1,
+                time: game_time,
+                next_chunk_id: id + 1,
+            });
+            while self.next_keyframe <= game_time {
+                self.next_keyframe += interval;
+            }
+        }
+        save_metadata(&mut self.directory, &recording);
+    }
+}
+
+fn save_metadata(directory: &mut Option<PathBuf>, recording: &GameRecording) {
+    if let Some(path) = directory {
+        if let Err(e) = recording.save(path) {
+            log::error!("failed to save the game metadata, no longer saving: {}", e);
+            *directory = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder(
+        settings: ObserverSettings,
+        delay: f64,
+    ) -> (ChunkRecorder, Arc<RwLock<GameRecording>>) {
+        let recording = Arc::new(RwLock::new(GameRecording::new(
+            1,
+            "RBLZ".to_owned(),
+            &settings,
+            delay,
+        )));
+        let spawn = [vec![9, 1, 0, 0, 0].into_boxed_slice()];
+        let recorder = ChunkRecorder::new(recording.clone(), None, 0.0, &spawn);
+        (recorder, recording)
+    }
+
+    fn blocks(recording: &GameRecording, data: &[u8]) -> Vec<Block> {
+        let data = decode(recording.game_id, &recording.encryption_key, data).unwrap();
+        Block::read_all(&data).unwrap()
+    }
+
+    #[test]
+    fn chunks_and_keyframes_follow_the_intervals() {
+        let settings = ObserverSettings {
+            chunk_interval: 1.0,
+            keyframe_interval: 2.0,
+        };
+        let (mut recorder, recording) = recorder(settings, 0.0);
+        recorder.record(0.5, Channel::Broadcast, &[1, 2, 0, 0, 0, 3]);
+        recorder.update(0.5, GamePhase::Loading);
+        recorder.update(1.5, GamePhase::Running { started_at: 1.5 });
+        for frame in 1..=20 {
+            let game_time = 1.5 + frame as f64 * 0.25;
+            recorder.record(game_time, Channel::Broadcast, &[frame, 0, 0, 0, 0]);
+            recorder.update(game_time, GamePhase::Running { started_at: 1.5 });
+        }
+
+        let recording = recording.read().unwrap();
+        assert_eq!(recording.end_startup_chunk_id, 1);
+        assert_eq!(recording.start_game_chunk_id, 2);
+        let ids = recording.chunks.iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2, 3, 4, 5, 6]);
+        let keyframes = recording
+            .keyframes
+            .iter()
+            .map(|k| (k.id, k.next_chunk_id))
+            .collect::<Vec<_>>();
+        assert_eq!(keyframes, [(1, 4), (2, 6)]);
+        let startup = blocks(&recording, &recording.chunk(1).unwrap());
+        assert_eq!(
+            startup,
+            [
+                Block {
+                    time: 0.0,
+                    channel: Channel::Broadcast as u8,
+                    packet_id: 9,
+                    sender_net_id: 1,
+                    data: Vec::new(),
+                },
+                Block {
+                    time: 0.5,
+                    channel: Channel::Broadcast as u8,
+                    packet_id: 1,
+                    sender_net_id: 2,
+                    data: vec![3],
+                },
+            ]
+        );
+        // a keyframe holds everything up to it
+        let chunks = (1..6)
+            .flat_map(|id| blocks(&recording, &recording.chunk(id).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(blocks(&recording, &recording.keyframe(2).unwrap()), chunks);
+        let keyframe = blocks(&recording, &recording.keyframe(1).unwrap());
+        assert_eq!(keyframe[..], chunks[..keyframe.len()]);
+        assert!(keyframe.len() < chunks.len());
+    }
+
+    #[test]
+    fn chunks_are_held_back_by_the_delay() {
+        let (mut recorder, recording) = recorder(ObserverSettings::default(), 10.0);
+        recorder.update(0.0, GamePhase::Running { started_at: 0.0 });
+        recorder.update(30.0, GamePhase::Running { started_at: 0.0 });
+        assert_eq!(recording.read().unwrap().chunks.len(), 2);
+        assert_eq!(recording.read().unwrap().available_chunks().len(), 1);
+        assert!(recording.read().unwrap().chunk(2).is_none());
+        recorder.update(39.0, GamePhase::Running { started_at: 0.0 });
+        assert_eq!(recording.read().unwrap().available_chunks().len(), 1);
+        recorder.update(40.0, GamePhase::Running { started_at: 0.0 });
+        assert_eq!(recording.read().unwrap().available_chunks().len(), 2);
+    }
+
+    #[test]
+    fn the_chunk_key_is_encrypted_with_the_game_id() {
+        let recording = GameRecording::new(12314, "RBLZ".to_owned(), &Default::default(), 0.0);
+        let encrypted = base64::decode(&recording.encryption_key).unwrap();
+        assert_eq!(
+            encrypted.len(),
+            24,
+            "16 bytes of key and a block of padding"
+        );
+        let mut chunk_key = encrypted.clone();
+        Blowfish::with_key(b"12314").decrypt_in_place(&mut chunk_key);
+        assert_eq!(chunk_key[CHUNK_KEY_LEN..], [8; 8]);
+
+        // served chunks are gzip encrypted with the chunk key
+        let chunk = recording.encode(&[1, 2, 3]);
+        let mut compressed = chunk.clone();
+        Blowfish::with_key(&chunk_key[..CHUNK_KEY_LEN]).decrypt_in_place(&mut compressed);
+        assert_eq!(compressed[..2], [0x1F, 0x8B]);
+        assert_eq!(
+            decode(12314, &recording.encryption_key, &chunk).unwrap(),
+            [1, 2, 3]
+        );
+        assert!(decode(12315, &recording.encryption_key, &chunk).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+//! A small HTTP listener serving recorded games with the REST layout of the game's spectator mode:
+//!
+//! - `/observer-mode/rest/consumer/version`
+//! - `/observer-mode/rest/consumer/getGameMetaData/<platform>/<game id>/<anything>/token`
+//! - `/observer-mode/rest/consumer/getLastChunkInfo/<platform>/<game id>/<anything>/token`
+//! - `/observer-mode/rest/consumer/getGameDataChunk/<platform>/<game id>/<chunk id>/token`
+//! - `/observer-mode/rest/consumer/getKeyFrame/<platform>/<game id>/<keyframe id>/token`
+//!
+//! Times in the JSON answers are in milliseconds like the game expects them. Only `GET` is
+//! supported and every connection is closed after answering a single request.
+
+use serde_json::json;
+
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::GameRecording;
+
+const PREFIX: &str = "/observer-mode/rest/consumer/";
+const VERSION: &str = "1.82.89";
+/// Requests are tiny, anything larger isn't meant for us.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the listener checks whether it should shut down.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+type Games = Arc<RwLock<Vec<Arc<RwLock<GameRecording>>>>>;
+
+/// Serves the games added to it from a background thread until dropped.
+pub struct ObserverServer {
+    address: SocketAddr,
+    games: Games,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ObserverServer {
+    pub fn bind(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        log::info!("observer endpoint listening on http://{}", address);
+        let games = Games::default();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let games = games.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || listen(listener, &games, &shutdown))
+        };
+        Ok(ObserverServer {
+            address,
+            games,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn add_game(&self, recording: Arc<RwLock<GameRecording>>) {
+        self.games.write().unwrap().push(recording);
+    }
+}
+
+impl Drop for ObserverServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn listen(listener: TcpListener, games: &Games, shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = serve(stream, games) {
+                    log::debug!("observer request failed: {}", e);
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => log::error!("failed to accept an observer connection: {}", e),
+        }
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(value: serde_json::Value) -> Self {
+        Response {
+            status: "200 OK",
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn binary(body: Vec<u8>) -> Self {
+        Response {
+            status: "200 OK",
+            content_type: "application/octet-stream",
+            body,
+        }
+    }
+
+    fn error(status: &'static str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain",
+            body: status.as_bytes().to_vec(),
+        }
+    }
+}
+
+fn serve(mut stream: TcpStream, games: &Games) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        let read = stream.read(&mut buf)?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..read]);
+        if request.len() > MAX_REQUEST_SIZE {
+            break;
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let response = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) => route(games, path),
+        (Some(_), Some(_)) => Response::error("405 Method Not Allowed"),
+        _ => Response::error("400 Bad Request"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+fn route(games: &Games, path: &str) -> Response {
+    let path = match path.strip_prefix(PREFIX) {
+        Some(path) => path,
+        None => return Response::error("404 Not Found"),
+    };
+    let (method, platform_id, game_id, param) = match *path.split('/').collect::<Vec<_>>() {
+        ["version"] => {
+            return Response {
+                status: "200 OK",
+                content_type: "text/plain",
+                body: VERSION.as_bytes().to_vec(),
+            }
+        },
+        [method, platform_id, game_id, param, "token"] => (method, platform_id, game_id, param),
+        _ => return Response::error("404 Not Found"),
+    };
+    let games = games.read().unwrap();
+    let recording = games.iter().find(|recording| {
+        let recording = recording.read().unwrap();
+        recording.platform_id == platform_id && game_id.parse() == Ok(recording.game_id)
+    });
+    let recording = match recording {
+        Some(recording) => recording.read().unwrap(),
+        None => return Response::error("404 Not Found"),
+    };
+    let found = match method {
+        "getGameMetaData" => Some(Response::json(metadata(&recording))),
+        "getLastChunkInfo" => Some(Response::json(last_chunk_info(&recording))),
+        "getGameDataChunk" => param
+            .parse()
+            .ok()
+            .and_then(|id| recording.chunk(id))
+            .map(Response::binary),
+        "getKeyFrame" => param
+            .parse()
+            .ok()
+            .and_then(|id| recording.keyframe(id))
+            .map(Response::binary),
+        _ => None,
+    };
+    found.unwrap_or_else(|| Response::error("404 Not Found"))
+}
+
+fn millis(seconds: f64) -> u64 {
+    (seconds.max(0.0) * 1000.0) as u64
+}
+
+fn metadata(recording: &GameRecording) -> serde_json::Value {
+    let chunks = recording.available_chunks();
+    let keyframes = recording.available_keyframes();
+    let (end_game_chunk_id, end_game_keyframe_id) = if recording.ended {
+        (
+            chunks.last().map_or(0, |c| c.id) as i64,
+            keyframes.last().map_or(0, |k| k.id) as i64,
+        )
+    } else {
+        (-1, -1)
+    };
+    json!({
+        "gameKey": {
+            "gameId": recording.game_id,
+            "platformId": recording.platform_id,
+        },
+        "gameServerAddress": "",
+        "port": 0,
+        "encryptionKey": recording.encryption_key,
+        "decodedEncryptionKey": "",
+        "chunkTimeInterval": millis(recording.chunk_interval),
+        "keyFrameTimeInterval": millis(recording.keyframe_interval),
+        "delayTime": millis(recording.delay),
+        "startTime": "",
+        "createTime": "",
+        "gameEnded": recording.ended,
+        "gameLength": millis(recording.game_time),
+        "lastChunkId": chunks.last().map_or(0, |c| c.id),
+        "lastKeyFrameId": keyframes.last().map_or(0, |k| k.id),
+        "endStartupChunkId": recording.end_startup_chunk_id,
+        "startGameChunkId": recording.start_game_chunk_id,
+        "endGameChunkId": end_game_chunk_id,
+        "endGameKeyFrameId": end_game_keyframe_id,
+        "pendingAvailableChunkInfo": chunks.iter().map(|chunk| json!({
+            "chunkId": chunk.id,
+            "duration": millis(chunk.end_time - chunk.start_time),
+            "receivedTime": "",
+        })).collect::<Vec<_>>(),
+        "pendingAvailableKeyFrameInfo": keyframes.iter().map(|keyframe| json!({
+            "keyFrameId": keyframe.id,
+            "nextChunkId": keyframe.next_chunk_id,
+            "receivedTime": "",
+        })).collect::<Vec<_>>(),
+        "clientAddedLag": 0,
+        "clientBackFetchingEnabled": false,
+        "clientBackFetchingFreq": 1000,
+        "interestScore": 0,
+        "featuredGame": false,
+    })
+}
+
+fn last_chunk_info(recording: &GameRecording) -> serde_json::Value {
+    let chunk = recording.available_chunks().last();
+    let keyframe = recording.available_keyframes().last();
+    // seconds since the last chunk became available
+    let available_since = chunk.map_or(0.0, |c| recording.game_time - c.end_time - recording.delay);
+    let next_available_chunk = if recording.ended {
+        0
+    } else {
+        millis(recording.chunk_interval - available_since)
+    };
+    json!({
+        "chunkId": chunk.map_or(0, |c| c.id),
+        "availableSince": millis(available_since),
+        "nextAvailableChunk": next_available_chunk,
+        "keyFrameId": keyframe.map_or(0, |k| k.id),
+        "nextChunkId": keyframe.map_or(0, |k| k.next_chunk_id),
+        "endStartupChunkId": recording.end_startup_chunk_id,
+        "startGameChunkId": recording.start_game_chunk_id,
+        "endGameChunkId": if recording.ended { chunk.map_or(0, |c| c.id) } else { 0 },
+        "duration": chunk.map_or(0, |c| millis(c.end_time - c.start_time)),
+    })
+}
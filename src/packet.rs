@@ -1,11 +1,17 @@
+pub mod batch;
 pub mod chat;
+pub mod fault;
 pub mod game;
 pub mod loading_screen;
 pub mod packet_dispatcher_sys;
 pub mod packet_handler_system;
+pub mod rate_limit;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::lenet_server::PacketMode;
 
 #[derive(Debug, Copy, Clone)]
-#[repr(packed)]
 pub struct KeyCheck {
     pub action: u8,
     pub pad: [u8; 3],
@@ -14,8 +20,37 @@ pub struct KeyCheck {
     pub check_id: [u8; 8],
 }
 
+impl KeyCheck {
+    pub const SIZE: usize = 24;
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < Self::SIZE {
+            return None;
+        }
+        let mut check_id = [0; 8];
+        check_id.copy_from_slice(&data[16..24]);
+        Some(KeyCheck {
+            action: data[0],
+            pad: [data[1], data[2], data[3]],
+            client_id: LittleEndian::read_u32(&data[4..8]),
+            player_id: LittleEndian::read_u64(&data[8..16]),
+            check_id,
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut data = [0; Self::SIZE];
+        data[0] = self.action;
+        data[1..4].copy_from_slice(&self.pad);
+        LittleEndian::write_u32(&mut data[4..8], self.client_id);
+        LittleEndian::write_u64(&mut data[8..16], self.player_id);
+        data[16..24].copy_from_slice(&self.check_id);
+        data
+    }
+}
+
 #[repr(u8)]
-#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub enum Channel {
     Handshake = 0x0,
     ClientToServer = 0x1,
@@ -27,8 +62,30 @@ pub enum Channel {
 }
 
 impl Channel {
+    /// The delivery mode packets sent on this channel use unless told otherwise.
+    #[inline]
+    pub fn default_mode(self) -> PacketMode {
+        match self {
+            Channel::BroadcastUnreliable => PacketMode::Unreliable,
+            _ => PacketMode::Reliable,
+        }
+    }
+
+    /// Whether the channel carries game packets, that is packets starting with their id and sender
+    /// net id.
+    #[inline]
+    pub fn carries_game_packets(self) -> bool {
+        match self {
+            Channel::ClientToServer
+            | Channel::SyncClock
+            | Channel::Broadcast
+            | Channel::BroadcastUnreliable => true,
+            Channel::Handshake | Channel::Chat | Channel::LoadingScreen => false,
+        }
+    }
+
     #[inline]
-    fn try_from(u8: u8) -> Option<Self> {
+    pub(crate) fn try_from(u8: u8) -> Option<Self> {
         match u8 {
             0 => Some(Channel::Handshake),
             1 => Some(Channel::ClientToServer),
@@ -0,0 +1,250 @@
+//! The container the client uses to pack several game packets into a single enet packet.
+//!
+//! All integers are little endian:
+//!
+//! ```text
+//! u8  0xFF
+//! u8  number of packets
+//! the first packet:
+//!     u8  size of the whole packet, that is including its id and sender net id
+//!     u8  packet id
+//!     u32 sender net id
+//!     ..  payload
+//! every following packet:
+//!     u8  flags, the upper 6 bits hold the payload size or 0x3F if the size follows as a u8
+//!         0x1 the packet id differs from the previous one and follows as a u8
+//!         0x2 the sender net id follows as an i8 delta to the previous one instead of a u32
+//!     [u8 packet id]
+//!     i8 sender net id delta | u32 sender net id
+//!     [u8 payload size]
+//!     ..  payload
+//! ```
+
+use byteorder::{ReadBytesExt, LE};
+
+use std::io;
+
+use crate::{error::Result, packet::game::RawGamePacket};
+
+pub const BATCH_PACKET_ID: u8 = 0xFF;
+/// Batches stay below the LENet MTU so they never have to be fragmented.
+pub const MAX_BATCH_SIZE: usize = 1200;
+
+const RAW_HEADER_SIZE: usize = 1 + 4;
+const FLAG_NEW_ID: u8 = 0x1;
+const FLAG_NET_ID_DELTA: u8 = 0x2;
+const SIZE_ESCAPE: u8 = 0x3F;
+
+pub struct BatchBuilder {
+    data: Vec<u8>,
+    count: u8,
+    id: u8,
+    sender_net_id: u32,
+}
+
+impl BatchBuilder {
+    pub fn new() -> Self {
+        BatchBuilder {
+            data: vec![BATCH_PACKET_ID, 0],
+            count: 0,
+            id: 0,
+            sender_net_id: 0,
+        }
+    }
+
+    /// Appends a serialized game packet, returns false and leaves the batch untouched if it
+    /// doesn't fit.
+    pub fn push(&mut self, packet: &[u8]) -> bool {
+        let raw = match RawGamePacket::from_slice(packet) {
+            Ok(raw) if self.count < u8::MAX => raw,
+            _ => return false,
+        };
+        let mut header = Vec::with_capacity(RAW_HEADER_SIZE + 2);
+        let payload = if self.count == 0 {
+            if packet.len() > u8::MAX as usize {
+                return false;
+            }
+            header.push(packet.len() as u8);
+            packet
+        } else {
+            if raw.data.len() > u8::MAX as usize {
+                return false;
+            }
+            let mut flags = 0;
+            header.push(0);
+            if raw.id != self.id {
+                flags |= FLAG_NEW_ID;
+                header.push(raw.id);
+            }
+            let delta = raw.sender_net_id.wrapping_sub(self.sender_net_id) as i32;
+            if delta >= i8::MIN as i32 && delta <= i8::MAX as i32 {
+                flags |= FLAG_NET_ID_DELTA;
+                header.push(delta as i8 as u8);
+            } else {
+                header.extend_from_slice(&raw.sender_net_id.to_le_bytes());
+            }
+            if raw.data.len() < SIZE_ESCAPE as usize {
+                flags |= (raw.data.len() as u8) << 2;
+            } else {
+                flags |= SIZE_ESCAPE << 2;
+                header.push(raw.data.len() as u8);
+            }
+            header[0] = flags;
+            raw.data
+        };
+        if self.data.len() + header.len() + payload.len() > MAX_BATCH_SIZE {
+            return false;
+        }
+        self.data.extend_from_slice(&header);
+        self.data.extend_from_slice(payload);
+        self.count += 1;
+        self.data[1] = self.count;
+        self.id = raw.id;
+        self.sender_net_id = raw.sender_net_id;
+        true
+    }
+
+    /// Returns the finished batch, a batch holding a single packet is unwrapped into just that
+    /// packet again as there is nothing to gain from the container.
+    pub fn finish(self) -> Box<[u8]> {
+        if self.count == 1 {
+            self.data[3..].into()
+        } else {
+            self.data.into_boxed_slice()
+        }
+    }
+}
+
+/// Splits a batch back into its packets, each prefixed with its id and sender net id like the
+/// packets that weren't batched.
+pub fn split(mut data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    if data.read_u8()? != BATCH_PACKET_ID {
+        return Err(invalid_data("not a batch packet"));
+    }
+    let count = data.read_u8()?;
+    let mut packets = Vec::with_capacity(count as usize);
+    if count == 0 {
+        return Ok(packets);
+    }
+
+    let size = data.read_u8()? as usize;
+    if size < RAW_HEADER_SIZE {
+        return Err(invalid_data("batched packet too small"));
+    }
+    let first = take(&mut data, size)?;
+    let RawGamePacket {
+        mut id,
+        mut sender_net_id,
+        ..
+    } = RawGamePacket::from_slice(first)?;
+    packets.push(first.to_vec());
+
+    for _ in 1..count {
+        let flags = data.read_u8()?;
+        if flags & FLAG_NEW_ID != 0 {
+            id = data.read_u8()?;
+        }
+        sender_net_id = if flags & FLAG_NET_ID_DELTA != 0 {
+            sender_net_id.wrapping_add(data.read_i8()? as u32)
+        } else {
+            data.read_u32::<LE>()?
+        };
+        let size = match flags >> 2 {
+            SIZE_ESCAPE => data.read_u8()?,
+            size => size,
+        };
+        let payload = take(&mut data, size as usize)?;
+
+        let mut packet = Vec::with_capacity(RAW_HEADER_SIZE + payload.len());
+        packet.push(id);
+        packet.extend_from_slice(&sender_net_id.to_le_bytes());
+        packet.extend_from_slice(payload);
+        packets.push(packet);
+    }
+    Ok(packets)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+fn invalid_data(msg: &str) -> crate::error::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(id: u8, sender_net_id: u32, payload_len: usize) -> Vec<u8> {
+        let mut packet = vec![id];
+        packet.extend_from_slice(&sender_net_id.to_le_bytes());
+        packet.extend((0..payload_len).map(|i| i as u8 ^ id));
+        packet
+    }
+
+    fn roundtrip(packets: &[Vec<u8>]) {
+        let mut builder = BatchBuilder::new();
+        for packet in packets {
+            assert!(builder.push(packet));
+        }
+        assert_eq!(split(&builder.finish()).unwrap(), packets);
+    }
+
+    #[test]
+    fn splits_into_original_packets() {
+        roundtrip(&[
+            packet(0x10, 0x4000_0001, 0),
+            // same id and a close net id
+            packet(0x10, 0x4000_0002, 12),
+            // new id and a net id too far off for a delta
+            packet(0x20, 0xFF00_0000, 3),
+            packet(0x20, 0xFF00_0000 - 128, 3),
+            // payloads too large for the size bits
+            packet(0x30, 0x4000_0001, SIZE_ESCAPE as usize),
+            packet(0x30, 0x4000_0001, 255),
+        ]);
+    }
+
+    #[test]
+    fn single_packet_is_unwrapped() {
+        let packet = packet(0x10, 7, 20);
+        let mut builder = BatchBuilder::new();
+        assert!(builder.push(&packet));
+        assert_eq!(&*builder.finish(), &packet[..]);
+    }
+
+    #[test]
+    fn respects_size_limits() {
+        let mut builder = BatchBuilder::new();
+        assert!(!builder.push(&packet(0x10, 0, 251)));
+        assert!(!builder.push(&[0x10, 0]));
+
+        assert!(builder.push(&packet(0x10, 0, 250)));
+        assert!(!builder.push(&packet(0x10, 0, 256)));
+        let mut packets = vec![packet(0x10, 0, 250)];
+        while builder.push(&packet(0x11, 1, 200)) {
+            packets.push(packet(0x11, 1, 200));
+        }
+        assert!(packets.len() > 2);
+        let batch = builder.finish();
+        assert!(batch.len() <= MAX_BATCH_SIZE);
+        assert_eq!(split(&batch).unwrap(), packets);
+    }
+
+    #[test]
+    fn rejects_truncated_batches() {
+        let mut builder = BatchBuilder::new();
+        assert!(builder.push(&packet(0x10, 0, 10)));
+        assert!(builder.push(&packet(0x10, 0, 10)));
+        let batch = builder.finish();
+        for len in 0..batch.len() {
+            assert!(split(&batch[..len]).is_err());
+        }
+    }
+}
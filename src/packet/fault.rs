@@ -0,0 +1,86 @@
+//! Packets received from clients are untrusted, anything that can't be decoded or handled is
+//! dropped and counted against the client that sent it according to its [`FaultConfig`].
+
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::{client::ClientId, config::FaultConfig, error::Error};
+
+/// Why a received packet couldn't be handled.
+#[derive(Debug)]
+pub enum PacketFault {
+    UnknownChannel(u8),
+    /// The packet or a packet within its batch couldn't be decoded.
+    Malformed(Error),
+    /// The packet was decoded but its handler failed.
+    Rejected {
+        id: u8,
+        error: Error,
+    },
+}
+
+impl PacketFault {
+    /// Sorts out whether a handler failed on decoding the packet or on what it contained.
+    pub(crate) fn from_handler(id: u8, error: Error) -> Self {
+        match error {
+            Error::SerializationError(_) | Error::Io(_) => PacketFault::Malformed(error),
+            error => PacketFault::Rejected { id, error },
+        }
+    }
+}
+
+impl fmt::Display for PacketFault {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacketFault::UnknownChannel(channel) => write!(fmt, "unknown channel {}", channel),
+            PacketFault::Malformed(e) => write!(fmt, "malformed packet: {}", e),
+            PacketFault::Rejected { id, error } => {
+                write!(fmt, "packet 0x{:X} rejected: {}", id, error)
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Drop the packet, only logging it for debugging.
+    Drop,
+    Warn,
+    /// Drop the packet and disconnect the client.
+    Kick,
+}
+
+/// The faults every client caused so far.
+#[derive(Default)]
+pub struct Faults {
+    config: FaultConfig,
+    strikes: HashMap<ClientId, u32>,
+}
+
+impl Faults {
+    pub fn new(config: FaultConfig) -> Self {
+        Faults {
+            config,
+            strikes: HashMap::new(),
+        }
+    }
+
+    /// Counts a fault of `cid` and decides what to do about it. Kicking a client forgives its
+    /// faults so it can reconnect.
+    pub fn strike(&mut self, cid: ClientId) -> FaultAction {
+        let strikes = self.strikes.entry(cid).or_insert(0);
+        *strikes += 1;
+        if self.config.kick_after != 0 && *strikes > self.config.kick_after {
+            self.strikes.remove(&cid);
+            FaultAction::Kick
+        } else if *strikes > self.config.warn_after {
+            FaultAction::Warn
+        } else {
+            FaultAction::Drop
+        }
+    }
+
+    pub fn strikes(&self, cid: ClientId) -> u32 {
+        self.strikes.get(&cid).copied().unwrap_or_default()
+    }
+}
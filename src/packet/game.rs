@@ -3,19 +3,29 @@
 
 use byteorder::{ReadBytesExt, LE};
 use serde::{Deserialize, Serialize};
-use shred::{ReadExpect, Resources, SystemData, WriteExpect};
-use specs::ReadStorage;
+use shred::{Read, ReadExpect, Resources, SystemData, Write, WriteExpect};
+use specs::{Entities, Join, ReadStorage, WriteStorage};
 
 use rblitz_packets::{
-    packets::game::{answer::SQueryStatusAns, common::*, request::CQueryStatusReq, *},
+    packets::game::{
+        answer::SQueryStatusAns,
+        bitfield::MapPingBitfield,
+        common::*,
+        request::{CNpcIssueOrderReq, CQueryStatusReq},
+        *,
+    },
     PacketId, Vector2,
 };
 
 use crate::{
     client::{ClientId, ClientMap, ClientStatus},
-    error::Result,
-    packet::{packet_dispatcher_sys::PacketSender, Channel},
-    world::components::{NetId, SummonerSpells, Team, UnitName},
+    error::{Error, Result},
+    packet::{packet_dispatcher_sys::PacketSender, rate_limit::RateLimits, Channel},
+    systems::movement,
+    world::{
+        components::{MovePath, NetId, Position, SummonerSpells, Team, UnitName},
+        resources::{GamePhase, GameTime, NetworkStats},
+    },
 };
 
 pub trait PacketHandler<'a> {
@@ -26,6 +36,9 @@ pub trait PacketHandler<'a> {
         sender_net_id: u32,
         data: &[u8],
     ) -> Result<()>;
+
+    /// See [`PacketHandlerImpl::THROTTLES`].
+    fn throttles(&self) -> bool;
 }
 
 pub struct PacketHandlerDummy<P: GamePacket>(pub core::marker::PhantomData<P>);
@@ -45,6 +58,10 @@ where
         log::trace!("[RECEIVED] {:?}", packet);
         packet.handle_self(T::Data::fetch(res), cid, sender_net_id)
     }
+
+    fn throttles(&self) -> bool {
+        T::THROTTLES
+    }
 }
 
 // clean up the super trait requirements
@@ -61,6 +78,9 @@ impl<T> GamePacket for T where T: PacketId + Serialize + std::fmt::Debug {}
 
 pub trait PacketHandlerImpl<'a>: GamePacket + for<'de> Deserialize<'de> {
     type Data: SystemData<'a>;
+    /// Whether the packet still gets handled when sent over its rate limit, for packets the
+    /// protocol can mark as throttled. The handler finds out through [`RateLimits::is_limited`].
+    const THROTTLES: bool = false;
 
     fn handle_self(self, data: Self::Data, cid: ClientId, sender_net_id: u32) -> Result<()>;
 }
@@ -91,14 +111,63 @@ impl<'a> PacketHandlerImpl<'a> for CQueryStatusReq {
 }
 
 impl<'a> PacketHandlerImpl<'a> for CReconnect {
-    type Data = PacketSender<'a>;
-    fn handle_self(self, sender: Self::Data, cid: ClientId, _: u32) -> Result<()> {
+    type Data = (
+        Read<'a, GameTime>,
+        Read<'a, GamePhase>,
+        UnitData<'a>,
+        WriteExpect<'a, ClientMap>,
+        PacketSender<'a>,
+    );
+    fn handle_self(
+        self,
+        (game_time, phase, units, mut clients, sender): Self::Data,
+        cid: ClientId,
+        _: u32,
+    ) -> Result<()> {
         sender.single_packet(
             cid,
             Channel::ClientToServer,
             0,
             &SReconnect { client_id: cid.0 },
         );
+        // a client that only lost its connection still has the world loaded and can skip the
+        // loading screen, everyone else goes through it again and gets resynced once ready
+        if let GamePhase::Running { started_at } = *phase {
+            if !self.is_full_reconnect
+                && clients.get(&cid).map(|c| c.status) == Some(ClientStatus::Loading)
+            {
+                resync_client(&mut clients, &units, &sender, cid, game_time.0 - started_at);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> PacketHandlerImpl<'a> for CNpcIssueOrderReq {
+    type Data = (
+        Read<'a, GamePhase>,
+        ReadExpect<'a, ClientMap>,
+        WriteStorage<'a, MovePath>,
+    );
+    fn handle_self(
+        self,
+        (phase, clients, mut paths): Self::Data,
+        cid: ClientId,
+        _: u32,
+    ) -> Result<()> {
+        // units only get to move once the game runs, and moving is the only order so far
+        if *phase == GamePhase::Loading || self.order_type != Self::ORDER_MOVE_TO {
+            return Ok(());
+        }
+        let champion = clients.get(&cid).ok_or(Error::UnknownClient(cid))?.champion;
+        // FIXME walk around obstacles once the nav grid gets loaded
+        let target = Position {
+            x: self.position.x,
+            y: self.position.z,
+        };
+        if paths.insert(champion, MovePath(vec![target])).is_err() {
+            log::error!("the champion of client {} is gone", cid.0);
+        }
         Ok(())
     }
 }
@@ -158,19 +227,33 @@ impl<'a> PacketHandlerImpl<'a> for CSyncVersion {
 
 impl<'a> PacketHandlerImpl<'a> for CClientReady {
     type Data = (
-        ReadStorage<'a, NetId>,
+        Read<'a, GameTime>,
+        Write<'a, GamePhase>,
+        UnitData<'a>,
         WriteExpect<'a, ClientMap>,
         PacketSender<'a>,
     );
     fn handle_self(
         self,
-        (net_ids, mut clients, sender): Self::Data,
+        (game_time, mut phase, units, mut clients, sender): Self::Data,
         cid: ClientId,
         _: u32,
     ) -> Result<()> {
-        clients.get_mut(&cid).unwrap().status = ClientStatus::Ready;
+        if let GamePhase::Running { started_at } = *phase {
+            if clients.get(&cid).map(|c| c.status) == Some(ClientStatus::Loading) {
+                resync_client(&mut clients, &units, &sender, cid, game_time.0 - started_at);
+            }
+            return Ok(());
+        }
+        clients
+            .get_mut(&cid)
+            .ok_or(Error::UnknownClient(cid))?
+            .status = ClientStatus::Ready;
         if clients.values().all(|c| c.status == ClientStatus::Ready) {
             log::info!("All clients ready, starting game");
+            *phase = GamePhase::Running {
+                started_at: game_time.0,
+            };
             sender.broadcast_all(
                 Channel::Broadcast,
                 0,
@@ -178,87 +261,173 @@ impl<'a> PacketHandlerImpl<'a> for CClientReady {
                     tournament_pause_enabled: false,
                 },
             );
-            for (cid, c) in clients.iter_mut() {
+            for c in clients.values_mut() {
                 c.status = ClientStatus::Connected;
-                let net_id = net_ids.get(c.champion).unwrap();
-                sender.single_packet(
-                    *cid,
-                    Channel::Broadcast,
-                    net_id.id(),
-                    &SOnEnterVisibilityClient {
-                        entries: Vec::new(),
-                        look_at_pos: None,
-                        movement_data: MovementData::Stop(MovementDataStop {
-                            position: Vector2 { x: 26.0, y: 280.0 },
-                            forward: Vector2 { x: 26.0, y: 280.0 },
-                        }),
-                    },
-                );
+            }
+            for (net_id, visibility) in unit_visibility(&units) {
+                for cid in clients.keys() {
+                    sender.single_packet(*cid, Channel::Broadcast, net_id, &visibility);
+                }
+                sender.broadcast_spectators(Channel::Broadcast, net_id, &visibility);
             }
         }
         Ok(())
     }
 }
 
-// reply with spawn packets here, client only replies with CClientReady after a SSpawnEnd
-impl<'a> PacketHandlerImpl<'a> for CCharSelected {
-    type Data = (
-        ReadStorage<'a, SummonerSpells>,
-        ReadStorage<'a, Team>,
-        ReadStorage<'a, NetId>,
-        ReadStorage<'a, UnitName>,
-        ReadExpect<'a, ClientMap>,
-        PacketSender<'a>,
-    );
-    fn handle_self(
-        self,
-        (summoner_spells, teams, net_ids, unit_names, clients, sender): Self::Data,
-        cid: ClientId,
-        _: u32,
-    ) -> Result<()> {
-        let mut hero_data: [(SCreateHero, SAvatarInfo); 12] = Default::default();
-        for ((cid, client), hero_data) in clients.iter().zip(hero_data.iter_mut()) {
-            let net_id = net_ids.get(client.champion).unwrap();
-            let sums = summoner_spells.get(client.champion).unwrap();
+/// What [`unit_visibility`] reads from the world.
+pub(crate) type UnitData<'a> = (
+    Entities<'a>,
+    ReadStorage<'a, NetId>,
+    ReadStorage<'a, Position>,
+    ReadStorage<'a, MovePath>,
+);
 
-            *hero_data = (
-                SCreateHero {
-                    unit_net_id: net_id.id(),
-                    client_id: cid.0,
-                    net_node_id: net_id.node_id() as u8,
-                    skill_level: 0,
-                    team_is_order: *teams.get(client.champion).unwrap() == Team::Order,
-                    is_bot: false,
-                    bot_rank: 0,
-                    // FIXME
-                    spawn_position_index: cid.0 as u8 % 5,
-                    skin_id: client.champ_skin_id,
-                    name: client.name.clone(),
-                    skin: unit_names.get(client.champion).unwrap().0.clone(),
+/// Shows every unit to a client where it currently is, walking along its path if it moves.
+/// Returns the net id each packet has to be sent with.
+fn unit_visibility(
+    (entities, net_ids, positions, paths): &UnitData,
+) -> Vec<(u32, SOnEnterVisibilityClient)> {
+    (entities, net_ids, positions)
+        .join()
+        .map(|(entity, net_id, position)| {
+            let movement_data = match paths.get(entity) {
+                Some(path) => MovementData::Normal {
+                    sync_id: 0,
+                    data: movement(net_id, position, path),
                 },
-                SAvatarInfo {
-                    summoner_spell_ids: [sums.0, sums.1],
-                    level: 1,
-                    ..Default::default()
+                None => {
+                    let position = Vector2 {
+                        x: position.x,
+                        y: position.y,
+                    };
+                    MovementData::Stop {
+                        sync_id: 0,
+                        data: MovementDataStop {
+                            position,
+                            forward: position,
+                        },
+                    }
                 },
-            );
-        }
+            };
+            let visibility = SOnEnterVisibilityClient {
+                entries: Vec::new(),
+                look_at_pos: None,
+                movement_data,
+            };
+            (net_id.id(), visibility)
+        })
+        .collect()
+}
 
-        sender.single_packet(
-            cid,
-            Channel::Broadcast,
-            0,
-            &SStartSpawn {
-                bot_count_order: 0,
-                bot_count_chaos: 0,
-            },
-        );
-        // FIXME make a function for this loop kinda thing in PacketSender?
-        for (create, avatar) in hero_data.iter().take(clients.len()) {
-            sender.single_packet(cid, Channel::Broadcast, 0, create);
-            sender.single_packet(cid, Channel::Broadcast, 0, avatar);
-        }
-        sender.single_packet(cid, Channel::Broadcast, 0, &SEndSpawn);
+/// Lets a client that reconnected rejoin the running game and tells everyone else it is back.
+///
+/// The heroes were spawned while the client loaded, or are still around if it only lost its
+/// connection, so the resync shows every unit as it is now and syncs the clock. Units don't have
+/// levels, buffs, items or timers in the world yet, once they do those have to be streamed here
+/// as well.
+fn resync_client(
+    clients: &mut ClientMap,
+    units: &UnitData,
+    sender: &PacketSender,
+    cid: ClientId,
+    game_time: f64,
+) {
+    log::info!("client {} reconnected, resyncing", cid.0);
+    clients.get_mut(&cid).unwrap().status = ClientStatus::Connected;
+    sender.single_packet(
+        cid,
+        Channel::Broadcast,
+        0,
+        &SStartGame {
+            tournament_pause_enabled: false,
+        },
+    );
+    for (net_id, visibility) in unit_visibility(units) {
+        sender.single_packet(cid, Channel::Broadcast, net_id, &visibility);
+    }
+    sender.single_packet(
+        cid,
+        Channel::Broadcast,
+        0,
+        &SSyncSimTime {
+            sync_time: game_time as f32,
+        },
+    );
+    sender.single_packet(cid, Channel::Broadcast, 0, &SReconnectDone);
+
+    let others = clients
+        .iter()
+        .filter(|(other, c)| **other != cid && c.status != ClientStatus::Disconnected)
+        .map(|(other, _)| *other)
+        .collect();
+    sender.broadcast_group(
+        others,
+        Channel::Broadcast,
+        0,
+        &SConnected { client_id: cid.0 },
+    );
+}
+
+/// What [`spawn_sequence`] reads from the world.
+pub(crate) type HeroData<'a> = (
+    ReadStorage<'a, SummonerSpells>,
+    ReadStorage<'a, Team>,
+    ReadStorage<'a, NetId>,
+    ReadStorage<'a, UnitName>,
+    ReadExpect<'a, ClientMap>,
+);
+
+/// The packets creating every hero, from `SStartSpawn` to `SEndSpawn`.
+pub(crate) fn spawn_sequence(
+    (summoner_spells, teams, net_ids, unit_names, clients): HeroData,
+) -> Vec<Box<[u8]>> {
+    let mut packets = vec![SStartSpawn {
+        bot_count_order: 0,
+        bot_count_chaos: 0,
+    }
+    .to_bytes(0)];
+    for (cid, client) in clients.iter() {
+        let net_id = net_ids.get(client.champion).unwrap();
+        let sums = summoner_spells.get(client.champion).unwrap();
+        let create = SCreateHero {
+            unit_net_id: net_id.id(),
+            client_id: cid.0,
+            net_node_id: net_id.node_id() as u8,
+            skill_level: 0,
+            team_is_order: *teams.get(client.champion).unwrap() == Team::Order,
+            is_bot: false,
+            bot_rank: 0,
+            // FIXME
+            spawn_position_index: cid.0 as u8 % 5,
+            skin_id: client.champ_skin_id,
+            name: client.name.clone(),
+            skin: unit_names.get(client.champion).unwrap().0.clone(),
+        };
+        let avatar = SAvatarInfo {
+            summoner_spell_ids: [sums.0, sums.1],
+            level: 1,
+            ..Default::default()
+        };
+        packets.push(create.to_bytes(0));
+        packets.push(avatar.to_bytes(0));
+    }
+    packets.push(SEndSpawn.to_bytes(0));
+    packets
+}
+
+/// Sends the [`spawn_sequence`] to `cid`, a loading client or a spectator that just joined.
+pub(crate) fn spawn_heroes((heroes, sender): (HeroData, PacketSender), cid: ClientId) {
+    for packet in spawn_sequence(heroes) {
+        sender.single(cid, Channel::Broadcast, packet);
+    }
+}
+
+// reply with spawn packets here, client only replies with CClientReady after a SSpawnEnd
+impl<'a> PacketHandlerImpl<'a> for CCharSelected {
+    type Data = (HeroData<'a>, PacketSender<'a>);
+    fn handle_self(self, data: Self::Data, cid: ClientId, _: u32) -> Result<()> {
+        spawn_heroes(data, cid);
         Ok(())
     }
 }
@@ -266,10 +435,20 @@ impl<'a> PacketHandlerImpl<'a> for CCharSelected {
 // Turns out riot is just horrible and uses some weird interpolation for the loading percentage
 // client side which results in completely inaccurate loading progression
 impl<'a> PacketHandlerImpl<'a> for CPingLoadInfo {
-    type Data = (ReadExpect<'a, ClientMap>, PacketSender<'a>);
-    fn handle_self(mut self, (clients, sender): Self::Data, cid: ClientId, _: u32) -> Result<()> {
-        let client = clients.get(&cid).unwrap();
+    type Data = (
+        ReadExpect<'a, ClientMap>,
+        Read<'a, NetworkStats>,
+        PacketSender<'a>,
+    );
+    fn handle_self(
+        mut self,
+        (clients, network_stats, sender): Self::Data,
+        cid: ClientId,
+        _: u32,
+    ) -> Result<()> {
+        let client = clients.get(&cid).ok_or(Error::UnknownClient(cid))?;
         self.connection_info.player_id = client.player_id;
+        self.connection_info.ping = network_stats.ping(cid);
         sender.broadcast_all(
             Channel::Broadcast,
             0,
@@ -284,7 +463,11 @@ impl<'a> PacketHandlerImpl<'a> for CPingLoadInfo {
 impl<'a> PacketHandlerImpl<'a> for CExit {
     type Data = WriteExpect<'a, ClientMap>;
     fn handle_self(self, mut clients: Self::Data, cid: ClientId, _: u32) -> Result<()> {
-        clients.get_mut(&cid).unwrap().disconnect();
+        clients
+            .get_mut(&cid)
+            .ok_or(Error::UnknownClient(cid))?
+            .session
+            .disconnect();
         Ok(())
     }
 }
@@ -297,6 +480,77 @@ impl<'a> PacketHandlerImpl<'a> for CWorldSendCameraServer {
     }
 }
 
+impl<'a> PacketHandlerImpl<'a> for CMapPing {
+    type Data = (
+        Read<'a, RateLimits>,
+        ReadStorage<'a, NetId>,
+        ReadStorage<'a, Team>,
+        ReadExpect<'a, ClientMap>,
+        PacketSender<'a>,
+    );
+    const THROTTLES: bool = true;
+
+    fn handle_self(
+        self,
+        (rate_limits, net_ids, teams, clients, sender): Self::Data,
+        cid: ClientId,
+        _: u32,
+    ) -> Result<()> {
+        let champion = clients.get(&cid).ok_or(Error::UnknownClient(cid))?.champion;
+        let net_id = net_ids.get(champion).map_or(0, |net_id| net_id.id());
+        let throttled = rate_limits.is_limited(cid, Self::ID);
+        let ping = SMapPing {
+            position: self.position,
+            target_net_id: self.target_net_id,
+            source_net_id: net_id,
+            bitfield: MapPingBitfield {
+                ping_category: self.ping_category & 0x0F,
+                play_audio: !throttled,
+                show_chat: !throttled,
+                ping_throttled: throttled,
+            },
+        };
+        // a throttled ping only goes back to its sender so the client can tell it apart
+        if throttled {
+            sender.single_packet(cid, Channel::Broadcast, net_id, &ping);
+        } else {
+            let team = teams.get(champion);
+            let allies = clients
+                .iter()
+                .filter(|(_, c)| teams.get(c.champion) == team)
+                .map(|(cid, _)| *cid)
+                .collect();
+            sender.broadcast_group(allies, Channel::Broadcast, net_id, &ping);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> PacketHandlerImpl<'a> for CPlayEmote {
+    type Data = (
+        ReadStorage<'a, NetId>,
+        ReadExpect<'a, ClientMap>,
+        PacketSender<'a>,
+    );
+    fn handle_self(
+        self,
+        (net_ids, clients, sender): Self::Data,
+        cid: ClientId,
+        _: u32,
+    ) -> Result<()> {
+        let champion = clients.get(&cid).ok_or(Error::UnknownClient(cid))?.champion;
+        let net_id = net_ids.get(champion).map_or(0, |net_id| net_id.id());
+        sender.broadcast_all(
+            Channel::Broadcast,
+            net_id,
+            &SPlayEmote {
+                emote_id: self.emote_id,
+            },
+        );
+        Ok(())
+    }
+}
+
 impl<'a> PacketHandlerImpl<'a> for CWorldLockCameraServer {
     type Data = ();
     #[inline]
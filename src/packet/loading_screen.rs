@@ -10,6 +10,7 @@ pub trait LoadingScreenPacket: PacketId + Serialize + Sized + std::fmt::Debug {
     }
 }
 
+impl LoadingScreenPacket for RequestJoinTeam {}
 impl LoadingScreenPacket for RequestReskin {}
 impl LoadingScreenPacket for RequestRename {}
 impl LoadingScreenPacket for TeamRosterUpdate {}
@@ -1,38 +1,111 @@
 use crate::{
-    client::{ClientId, ClientMap},
-    packet::{game::GamePacket, Channel},
+    capture::Direction,
+    client::{ClientId, ClientMap, ClientStatus},
+    lenet_server::PacketMode,
+    observer::ChunkRecorder,
+    packet::{batch::BatchBuilder, game::GamePacket, Channel},
+    spectator::Spectators,
+    world::resources::{GamePhase, GameTime, PacketCapture},
 };
 use crossbeam_channel::{Receiver, Sender};
-use shred::{System, SystemData, WriteExpect};
+use shred::{Read, System, SystemData, Write, WriteExpect};
+
+use indexmap::IndexMap;
+
+/// How the dispatcher hands a queued packet over to enet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Delivery {
+    pub mode: PacketMode,
+    /// Whether the packet may be coalesced with the other game packets queued for the same client
+    /// and channel this tick, batches only hold packets of the same mode.
+    pub batched: bool,
+}
+
+impl Delivery {
+    pub fn new(mode: PacketMode) -> Self {
+        Delivery {
+            mode,
+            batched: true,
+        }
+    }
+
+    /// Opts the packet out of batching, it is sent on its own right away.
+    pub fn unbatched(self) -> Self {
+        Delivery {
+            batched: false,
+            ..self
+        }
+    }
+}
+
+impl From<Channel> for Delivery {
+    fn from(channel: Channel) -> Self {
+        Delivery::new(channel.default_mode())
+    }
+}
 
 pub struct PacketSender<'a>(shred::Fetch<'a, Sender<Command>>);
 
+/// The plain methods send batched with the [`Channel::default_mode`] of the given channel, the
+/// `*_with` variants take an explicit [`Delivery`] instead.
 impl<'a> PacketSender<'a> {
     pub fn single(&self, cid: ClientId, channel: Channel, data: Box<[u8]>) {
-        if let Err(e) = self.0.send(Command::Single(cid, channel, data)) {
-            log::warn!("{}", e);
-        }
+        self.single_with(cid, channel, Delivery::from(channel), data);
+    }
+
+    pub fn single_with(
+        &self,
+        cid: ClientId,
+        channel: Channel,
+        delivery: Delivery,
+        data: Box<[u8]>,
+    ) {
+        self.send(Command::Single(cid, channel, delivery, data));
     }
 
     pub fn single_packet<P>(&self, cid: ClientId, channel: Channel, sender_net_id: u32, packet: &P)
     where
         P: GamePacket,
+    {
+        self.single_packet_with(cid, channel, Delivery::from(channel), sender_net_id, packet);
+    }
+
+    pub fn single_packet_with<P>(
+        &self,
+        cid: ClientId,
+        channel: Channel,
+        delivery: Delivery,
+        sender_net_id: u32,
+        packet: &P,
+    ) where
+        P: GamePacket,
     {
         log::trace!("[SENT][{}] {:?}", cid.0, packet);
-        self.single(cid, channel, packet.to_bytes(sender_net_id));
+        self.single_with(cid, channel, delivery, packet.to_bytes(sender_net_id));
     }
 
     pub fn broadcast_all<P>(&self, channel: Channel, sender_net_id: u32, packet: &P)
     where
         P: GamePacket,
+    {
+        self.broadcast_all_with(channel, Delivery::from(channel), sender_net_id, packet);
+    }
+
+    pub fn broadcast_all_with<P>(
+        &self,
+        channel: Channel,
+        delivery: Delivery,
+        sender_net_id: u32,
+        packet: &P,
+    ) where
+        P: GamePacket,
     {
         log::trace!("[BROADCAST] {:?}", packet);
-        if let Err(e) = self.0.send(Command::BroadcastAll(
+        self.send(Command::BroadcastAll(
             channel,
+            delivery,
             packet.to_bytes(sender_net_id),
-        )) {
-            log::warn!("{}", e);
-        }
+        ));
     }
 
     pub fn broadcast_group<P>(
@@ -43,13 +116,51 @@ impl<'a> PacketSender<'a> {
         packet: &P,
     ) where
         P: GamePacket,
+    {
+        self.broadcast_group_with(
+            cids,
+            channel,
+            Delivery::from(channel),
+            sender_net_id,
+            packet,
+        );
+    }
+
+    pub fn broadcast_group_with<P>(
+        &self,
+        cids: Box<[ClientId]>,
+        channel: Channel,
+        delivery: Delivery,
+        sender_net_id: u32,
+        packet: &P,
+    ) where
+        P: GamePacket,
     {
         log::trace!("[BROADCAST] {:?}", packet);
-        if let Err(e) = self.0.send(Command::BroadcastGroup(
+        self.send(Command::BroadcastGroup(
             cids,
             channel,
+            delivery,
+            packet.to_bytes(sender_net_id),
+        ));
+    }
+
+    /// Sends a packet to the spectators alone, with the same delay as the broadcasts. For what
+    /// the players get through [`single_packet`](Self::single_packet) only.
+    pub fn broadcast_spectators<P>(&self, channel: Channel, sender_net_id: u32, packet: &P)
+    where
+        P: GamePacket,
+    {
+        log::trace!("[SPECTATORS] {:?}", packet);
+        self.send(Command::BroadcastSpectators(
+            channel,
+            Delivery::from(channel),
             packet.to_bytes(sender_net_id),
-        )) {
+        ));
+    }
+
+    fn send(&self, cmd: Command) {
+        if let Err(e) = self.0.send(cmd) {
             log::warn!("{}", e);
         }
     }
@@ -71,43 +182,275 @@ impl<'a> SystemData<'a> for PacketSender<'a> {
 }
 
 pub enum Command {
-    Single(ClientId, Channel, Box<[u8]>),
-    BroadcastGroup(Box<[ClientId]>, Channel, Box<[u8]>),
-    BroadcastAll(Channel, Box<[u8]>),
+    Single(ClientId, Channel, Delivery, Box<[u8]>),
+    BroadcastGroup(Box<[ClientId]>, Channel, Delivery, Box<[u8]>),
+    BroadcastAll(Channel, Delivery, Box<[u8]>),
+    BroadcastSpectators(Channel, Delivery, Box<[u8]>),
 }
 
-pub struct PacketDispatcher(Receiver<Command>);
+/// Sends out everything queued through [`PacketSender`], coalescing the game packets each client
+/// receives on the same channel within one run into batches that get encrypted and sent at once.
+pub struct PacketDispatcher {
+    recv: Receiver<Command>,
+    batches: Batches,
+}
 
 impl PacketDispatcher {
     pub fn new(recv: Receiver<Command>) -> Self {
-        PacketDispatcher(recv)
+        PacketDispatcher {
+            recv,
+            batches: Batches::default(),
+        }
     }
 }
 
 impl<'a> System<'a> for PacketDispatcher {
-    type SystemData = WriteExpect<'a, ClientMap>;
+    type SystemData = (
+        WriteExpect<'a, ClientMap>,
+        Write<'a, Spectators>,
+        Read<'a, GameTime>,
+        Read<'a, GamePhase>,
+        Option<Write<'a, PacketCapture>>,
+        Option<Write<'a, ChunkRecorder>>,
+    );
 
-    fn run(&mut self, mut client_map: Self::SystemData) {
-        for cmd in self.0.try_iter() {
+    fn run(
+        &mut self,
+        (mut client_map, mut spectators, game_time, phase, mut capture, mut recorder): Self::SystemData,
+    ) {
+        let game_time = game_time.0;
+        // spectators joining later still need what was sent when the game started
+        let startup = match *phase {
+            GamePhase::Loading => false,
+            GamePhase::Running { started_at } => started_at == game_time,
+        };
+        let mut out = Outgoing {
+            client_map: &mut client_map,
+            spectators: &mut spectators,
+            capture: capture.as_deref_mut(),
+            game_time,
+        };
+        for cmd in self.recv.try_iter() {
             match cmd {
-                Command::Single(cid, channel, mut packet) => {
-                    if let Some(client) = client_map.get_mut(&cid) {
-                        client.send_data(channel, &mut packet);
-                    }
+                Command::Single(cid, channel, delivery, packet) => {
+                    self.batches.queue(&mut out, cid, channel, delivery, packet);
                 },
-                Command::BroadcastGroup(cids, channel, packet) => {
+                Command::BroadcastGroup(cids, channel, delivery, packet) => {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(game_time, channel, &packet);
+                    }
+                    out.spectators
+                        .delay(game_time, startup, channel, delivery, packet.clone());
                     for cid in cids.iter() {
-                        if let Some(client) = client_map.get_mut(cid) {
-                            client.send_data(channel, &mut packet.clone());
-                        }
+                        self.batches
+                            .queue(&mut out, *cid, channel, delivery, packet.clone());
                     }
                 },
-                Command::BroadcastAll(channel, packet) => {
-                    for client in client_map.values_mut() {
-                        client.send_data(channel, &mut packet.clone());
+                Command::BroadcastAll(channel, delivery, packet) => {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(game_time, channel, &packet);
+                    }
+                    out.spectators
+                        .delay(game_time, startup, channel, delivery, packet.clone());
+                    let cids = out.client_map.keys().cloned().collect::<Vec<_>>();
+                    for cid in cids {
+                        self.batches
+                            .queue(&mut out, cid, channel, delivery, packet.clone());
                     }
                 },
+                Command::BroadcastSpectators(channel, delivery, packet) => {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(game_time, channel, &packet);
+                    }
+                    out.spectators
+                        .delay(game_time, startup, channel, delivery, packet);
+                },
+            }
+        }
+        let watching = out.spectators.watching();
+        for (channel, delivery, packet) in out.spectators.release(game_time) {
+            for cid in &watching {
+                self.batches
+                    .queue(&mut out, *cid, channel, delivery, packet.clone());
             }
         }
+        self.batches.flush_all(&mut out);
+    }
+}
+
+/// Hands finished packets over to the clients and spectators, recording them first if a capture
+/// is running.
+struct Outgoing<'r> {
+    client_map: &'r mut ClientMap,
+    spectators: &'r mut Spectators,
+    capture: Option<&'r mut PacketCapture>,
+    game_time: f64,
+}
+
+impl<'r> Outgoing<'r> {
+    fn send(&mut self, cid: ClientId, channel: Channel, mode: PacketMode, data: &mut [u8]) {
+        if let Some(client) = self.client_map.get_mut(&cid) {
+            // checking the status rather than the connection still captures replayed clients
+            if let Some(capture) = &mut self.capture {
+                if client.status != ClientStatus::Disconnected {
+                    capture.record(Direction::Outbound, cid, channel, self.game_time, data);
+                }
+            }
+            client.session.send_data(channel, mode, data);
+        } else if let Some(spectator) = self.spectators.get_mut(&cid) {
+            if let Some(capture) = &mut self.capture {
+                if spectator.status != ClientStatus::Disconnected {
+                    capture.record(Direction::Outbound, cid, channel, self.game_time, data);
+                }
+            }
+            spectator.session.send_data(channel, mode, data);
+        }
+    }
+}
+
+struct Batch {
+    mode: PacketMode,
+    builder: BatchBuilder,
+}
+
+#[derive(Default)]
+// kept in insertion order so that replays flush them in the same order every time
+struct Batches(IndexMap<(ClientId, Channel), Batch>);
+
+impl Batches {
+    fn queue(
+        &mut self,
+        out: &mut Outgoing,
+        cid: ClientId,
+        channel: Channel,
+        delivery: Delivery,
+        mut packet: Box<[u8]>,
+    ) {
+        let key = (cid, channel);
+        if delivery.batched && channel.carries_game_packets() {
+            if let Some(batch) = self.0.get_mut(&key) {
+                if batch.mode == delivery.mode && batch.builder.push(&packet) {
+                    return;
+                }
+            }
+            // whatever was batched so far has to go out first to keep the order intact
+            self.flush(out, key);
+            let mut builder = BatchBuilder::new();
+            if builder.push(&packet) {
+                let mode = delivery.mode;
+                self.0.insert(key, Batch { mode, builder });
+                return;
+            }
+        } else {
+            self.flush(out, key);
+        }
+        out.send(cid, channel, delivery.mode, &mut packet);
+    }
+
+    fn flush(&mut self, out: &mut Outgoing, key: (ClientId, Channel)) {
+        if let Some(batch) = self.0.swap_remove(&key) {
+            out.send(key.0, key.1, batch.mode, &mut batch.builder.finish());
+        }
+    }
+
+    fn flush_all(&mut self, out: &mut Outgoing) {
+        for ((cid, channel), batch) in self.0.drain(..) {
+            out.send(cid, channel, batch.mode, &mut batch.builder.finish());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{Identity, PlayerKey},
+        capture::{CaptureReader, CaptureWriter},
+        client::Client,
+        packet::batch::BATCH_PACKET_ID,
+    };
+    use rblitz_packets::{packets::game::server::SSyncSimTime, PacketId};
+    use specs::{Builder, RunNow, World};
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Dispatches `deliveries` to a single client, returning what was handed over to it.
+    fn dispatch(deliveries: &[Delivery]) -> Vec<Vec<u8>> {
+        let mut world = World::new();
+        let champion = world.create_entity().build();
+        let identity = Identity {
+            key: PlayerKey::random(),
+            name: "Test".to_owned(),
+            profile_icon: 0,
+            summoner_level: 1,
+        };
+        let mut client = Client::new(identity, champion, 12, 0);
+        client.status = ClientStatus::Connected;
+        let mut clients = indexmap::IndexMap::new();
+        clients.insert(ClientId(0), client);
+        let buffer = SharedBuffer::default();
+        let writer = CaptureWriter::new(Box::new(buffer.clone()) as Box<_>).unwrap();
+        world.add_resource(ClientMap::from(clients));
+        world.add_resource(Spectators::default());
+        world.add_resource(GameTime(0.0));
+        world.add_resource(GamePhase::default());
+        world.add_resource(PacketCapture::new(writer));
+
+        let (send, recv) = crossbeam_channel::unbounded();
+        for (i, delivery) in deliveries.iter().enumerate() {
+            let packet = SSyncSimTime {
+                sync_time: i as f32,
+            };
+            let cmd = Command::Single(
+                ClientId(0),
+                Channel::BroadcastUnreliable,
+                *delivery,
+                packet.to_bytes(0),
+            );
+            send.send(cmd).unwrap();
+        }
+        PacketDispatcher::new(recv).run_now(&world.res);
+
+        let data = buffer.0.lock().unwrap().clone();
+        CaptureReader::new(&data[..])
+            .unwrap()
+            .map(|record| record.unwrap().data)
+            .collect()
+    }
+
+    #[test]
+    fn packets_of_a_tick_are_batched() {
+        let delivery = Delivery::new(PacketMode::Unreliable);
+        let sent = dispatch(&[delivery, delivery]);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0][0], BATCH_PACKET_ID);
+    }
+
+    #[test]
+    fn unbatched_packets_are_sent_on_their_own() {
+        let delivery = Delivery::new(PacketMode::Unreliable);
+        let sent = dispatch(&[delivery, delivery.unbatched(), delivery, delivery]);
+        let ids = sent.iter().map(|data| data[0]).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [SSyncSimTime::ID, SSyncSimTime::ID, BATCH_PACKET_ID],
+            "the unbatched packet has to go out between the others in order"
+        );
+        assert_eq!(&sent[1][5..], &1f32.to_le_bytes());
     }
 }
@@ -1,16 +1,26 @@
 use indexmap::IndexMap;
+use rblitz_packets::packets::game::server::SOnDisconnected;
 use shred::SystemData;
 use specs::World;
 
 use crate::{
+    capture::Direction,
     client::{ClientId, ClientMap, ClientStatus},
-    lenet_server::{Event, LENetServer},
+    game_server::ShutdownReason,
+    lenet_server::Event,
     packet::{
-        game::{PacketHandler, PacketHandlerDummy, PacketHandlerImpl, RawGamePacket},
+        batch,
+        fault::{FaultAction, Faults, PacketFault},
+        game::{self, PacketHandler, PacketHandlerDummy, PacketHandlerImpl, RawGamePacket},
         packet_dispatcher_sys::PacketSender,
+        rate_limit::RateLimits,
         Channel,
     },
-    world::components::{Team, UnitName},
+    spectator::Spectators,
+    world::{
+        components::{NetId, Team, UnitName},
+        resources::{GameTime, PacketCapture, Shutdown},
+    },
 };
 
 /// We consider this a system obviously, but we won't register it in the system due to how
@@ -29,13 +39,45 @@ impl<'r> PacketHandlerSys<'r> {
         this
     }
 
-    pub fn handle_packet(&self, world: &World, channel: u8, cid: ClientId, data: &mut [u8]) {
-        let channel = Channel::try_from(channel).expect("unknown channel received");
-        world
-            .read_resource::<ClientMap>()
-            .get(&cid)
-            .unwrap()
-            .decrypt(data);
+    pub fn handle_packet(
+        &self,
+        world: &World,
+        channel: u8,
+        cid: ClientId,
+        data: &mut [u8],
+    ) -> Result<(), PacketFault> {
+        let channel = Channel::try_from(channel).ok_or(PacketFault::UnknownChannel(channel))?;
+        let spectating = {
+            let mut spectators = world.write_resource::<Spectators>();
+            match spectators.get_mut(&cid) {
+                Some(spectator) => {
+                    spectator.session.receive_data(data);
+                    true
+                },
+                None => match world.write_resource::<ClientMap>().get_mut(&cid) {
+                    Some(client) => {
+                        client.session.receive_data(data);
+                        false
+                    },
+                    None => {
+                        log::error!("received a packet of unknown client {:?}", cid);
+                        return Ok(());
+                    },
+                },
+            }
+        };
+        if let Some(mut capture) = world.res.try_fetch_mut::<PacketCapture>() {
+            let game_time = world.read_resource::<GameTime>().0;
+            capture.record(Direction::Inbound, cid, channel, game_time, data);
+        }
+        if spectating {
+            log::trace!(
+                "Ignoring packet of spectator {:?} on channel {:?}",
+                cid,
+                channel
+            );
+            return Ok(());
+        }
         match channel {
             //handled outside of this
             Channel::Handshake => (),
@@ -43,17 +85,20 @@ impl<'r> PacketHandlerSys<'r> {
             | Channel::SyncClock
             | Channel::Broadcast
             | Channel::BroadcastUnreliable => {
-                let packet = RawGamePacket::from_slice(data).unwrap();
-                if let Some(handler) = self.game_handlers.get(&packet.id) {
-                    handler
-                        .handle(&world.res, cid, packet.sender_net_id, packet.data)
-                        .unwrap();
+                if data.first() == Some(&batch::BATCH_PACKET_ID) {
+                    let packets = batch::split(data).map_err(PacketFault::Malformed)?;
+                    // a bad packet doesn't keep the rest of its batch from being handled
+                    let mut fault = None;
+                    for packet in packets {
+                        if let Err(e) = self.handle_game_packet(world, channel, cid, &packet) {
+                            fault.get_or_insert(e);
+                        }
+                    }
+                    if let Some(fault) = fault {
+                        return Err(fault);
+                    }
                 } else {
-                    log::debug!(
-                        "Unhandled Packet 0x{:X} received on channel {:?}",
-                        packet.id,
-                        channel,
-                    );
+                    self.handle_game_packet(world, channel, cid, data)?;
                 }
             },
             Channel::Chat => (),
@@ -69,6 +114,73 @@ impl<'r> PacketHandlerSys<'r> {
                 }
             },
         }
+        Ok(())
+    }
+
+    fn handle_game_packet(
+        &self,
+        world: &World,
+        channel: Channel,
+        cid: ClientId,
+        data: &[u8],
+    ) -> Result<(), PacketFault> {
+        let packet = RawGamePacket::from_slice(data).map_err(PacketFault::Malformed)?;
+        let handler = self.game_handlers.get(&packet.id);
+        let game_time = world.read_resource::<GameTime>().0;
+        // the rate limits log packets over the limit themselves
+        if !world
+            .write_resource::<RateLimits>()
+            .check(cid, packet.id, game_time)
+            && !handler.is_some_and(|handler| handler.throttles())
+        {
+            return Ok(());
+        }
+        if let Some(handler) = handler {
+            handler
+                .handle(&world.res, cid, packet.sender_net_id, packet.data)
+                .map_err(|e| PacketFault::from_handler(packet.id, e))?;
+        } else {
+            log::debug!(
+                "Unhandled Packet 0x{:X} received on channel {:?}",
+                packet.id,
+                channel,
+            );
+        }
+        Ok(())
+    }
+
+    /// Drops a packet `cid` sent that couldn't be handled and counts it against the client.
+    fn handle_fault(&self, world: &World, cid: ClientId, channel: u8, fault: PacketFault) {
+        let mut faults = world.write_resource::<Faults>();
+        match faults.strike(cid) {
+            FaultAction::Drop => log::debug!(
+                "dropped a packet of client {} on channel {}: {}",
+                cid.0,
+                channel,
+                fault
+            ),
+            FaultAction::Warn => log::warn!(
+                "dropped a packet of client {} on channel {}, {} faults so far: {}",
+                cid.0,
+                channel,
+                faults.strikes(cid),
+                fault
+            ),
+            FaultAction::Kick => {
+                log::warn!(
+                    "kicking client {} for sending too many bad packets, the last one on channel \
+                     {}: {}",
+                    cid.0,
+                    channel,
+                    fault
+                );
+                if let Some(client) = world.write_resource::<ClientMap>().get_mut(&cid) {
+                    client.session.disconnect();
+                } else if let Some(spectator) = world.write_resource::<Spectators>().get_mut(&cid) {
+                    spectator.session.disconnect();
+                }
+            },
+        }
     }
 
     fn register_game_handler<P>(&mut self)
@@ -88,49 +200,98 @@ impl<'r> PacketHandlerSys<'r> {
         );
     }
 
-    pub fn run(&self, server: &mut LENetServer, world: &World) {
-        loop {
-            match server.service(0) {
-                Ok(event) => match event {
-                    Event::Connected(keycheck, peer) => {
-                        let mut clients = world.write_resource::<ClientMap>();
-                        let cid = clients
-                            .iter_mut()
-                            .find(|(_, c)| c.player_id == keycheck.player_id)
-                            .and_then(|(cid, client)| {
-                                if client.auth(*cid, keycheck, peer).is_ok() {
-                                    client.status = ClientStatus::Loading;
-                                    Some(*cid)
-                                } else {
-                                    None
-                                }
-                            });
-                        match cid {
-                            Some(cid) => clients.broadcast_keycheck(cid),
-                            None => unsafe { enet_sys::enet_peer_disconnect_now(peer, 0) },
+    pub fn handle_event(&self, world: &World, event: Event) {
+        match event {
+            Event::Connected(keycheck, connection) => {
+                let sender = PacketSender::fetch(&world.res);
+                let mut clients = world.write_resource::<ClientMap>();
+                let cid = clients
+                    .iter_mut()
+                    .find(|(_, c)| c.player_id == keycheck.player_id)
+                    .and_then(|(cid, client)| {
+                        if client
+                            .auth(&sender, *cid, keycheck, connection.as_ref())
+                            .is_ok()
+                        {
+                            client.status = ClientStatus::Loading;
+                            Some(*cid)
+                        } else {
+                            None
                         }
-                    },
-                    Event::Disconnected(cid) => {
-                        log::info!("Disconnected: {:?}", cid);
-                        let mut clients = world.write_resource::<ClientMap>();
-                        let client = clients.get_mut(&cid).unwrap();
-                        client.status = ClientStatus::Disconnected;
-                        client.peer = None;
-                        if clients
-                            .values()
-                            .all(|c| c.status == ClientStatus::Disconnected)
+                    });
+                if cid.is_none() {
+                    let mut spectators = world.write_resource::<Spectators>();
+                    let spectator = spectators
+                        .iter_mut()
+                        .find(|(_, s)| s.player_id == keycheck.player_id);
+                    if let Some((&cid, spectator)) = spectator {
+                        if spectator
+                            .auth(&sender, cid, keycheck, connection.as_ref())
+                            .is_ok()
                         {
-                            // FIXME shutdown server gracefully
-                            panic!("All players lost connection, terminating server");
+                            drop((sender, clients, spectators));
+                            spawn_spectator(world, cid);
+                            return;
                         }
+                    }
+                }
+                match (cid, connection) {
+                    (Some(cid), _) => clients.broadcast_keycheck(&sender, cid),
+                    (None, Some(connection)) => connection.reject(),
+                    (None, None) => (),
+                }
+            },
+            Event::Disconnected(cid) => {
+                log::info!("Disconnected: {:?}", cid);
+                if let Some(spectator) = world.write_resource::<Spectators>().get_mut(&cid) {
+                    spectator.status = ClientStatus::Disconnected;
+                    spectator.session.connection = None;
+                    return;
+                }
+                let mut clients = world.write_resource::<ClientMap>();
+                let client = match clients.get_mut(&cid) {
+                    Some(client) => client,
+                    None => {
+                        log::error!("unknown client {:?} disconnected", cid);
+                        return;
                     },
-                    Event::Packet(cid, channel, mut packet) => {
-                        self.handle_packet(world, channel, cid, &mut *packet)
-                    },
-                    Event::NoEvent => break,
-                },
-                Err(e) => log::error!("{:?}", e),
-            }
+                };
+                client.status = ClientStatus::Disconnected;
+                client.session.connection = None;
+                client.peer_stats = None;
+                // during the loading screen as well, so the others see who they wait for
+                let net_id = world
+                    .read_storage::<NetId>()
+                    .get(client.champion)
+                    .unwrap()
+                    .id();
+                PacketSender::fetch(&world.res).broadcast_all(
+                    Channel::Broadcast,
+                    net_id,
+                    &SOnDisconnected,
+                );
+                if clients
+                    .values()
+                    .all(|c| c.status == ClientStatus::Disconnected)
+                {
+                    log::info!("All players lost connection");
+                    world
+                        .write_resource::<Shutdown>()
+                        .0
+                        .get_or_insert(ShutdownReason::AllDisconnected);
+                }
+            },
+            Event::Packet(cid, channel, mut packet) => {
+                if let Err(fault) = self.handle_packet(world, channel, cid, &mut packet) {
+                    self.handle_fault(world, cid, channel, fault);
+                }
+            },
+            Event::Stats(cid, stats) => {
+                if let Some(client) = world.write_resource::<ClientMap>().get_mut(&cid) {
+                    client.peer_stats = Some(stats);
+                }
+            },
+            Event::NoEvent => (),
         }
     }
 
@@ -143,9 +304,24 @@ impl<'r> PacketHandlerSys<'r> {
         self.register_game_handler::<CCharSelected>();
         self.register_game_handler::<CPingLoadInfo>();
         self.register_game_handler::<CClientReady>();
+        self.register_game_handler::<CReconnect>();
         self.register_game_handler::<CWorldSendCameraServer>();
         self.register_game_handler::<CSendSelectedObjID>();
+        self.register_game_handler::<CNpcIssueOrderReq>();
         self.register_game_handler::<CExit>();
         self.register_game_handler::<CWorldLockCameraServer>();
+        self.register_game_handler::<CMapPing>();
+        self.register_game_handler::<CPlayEmote>();
+    }
+}
+
+/// Spectators get no packets of the loading screen, so they are spawned as soon as they
+/// joined. Those joining after the game started also get the startup packets they missed.
+fn spawn_spectator(world: &World, cid: ClientId) {
+    game::spawn_heroes(world.system_data(), cid);
+    let spectators = world.read_resource::<Spectators>();
+    let sender = world.system_data::<PacketSender>();
+    for (channel, delivery, packet) in spectators.startup() {
+        sender.single_with(cid, *channel, *delivery, packet.clone());
     }
 }
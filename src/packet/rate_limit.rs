@@ -0,0 +1,183 @@
+//! Token buckets limiting how often every client may send a packet, see [`RateLimitConfig`].
+
+use rblitz_packets::packets::registry::{self, Name};
+
+use std::collections::HashMap;
+
+use crate::{
+    client::ClientId,
+    config::{RateLimit, RateLimitConfig},
+    error::{Error, Result},
+};
+
+/// The id of the packet clients send named `name`, the names used in the config.
+fn packet_id(name: &str) -> Option<u8> {
+    (0..=u8::MAX).find(|&id| registry::client_packet(id, Name) == Some(name))
+}
+
+struct Bucket {
+    tokens: f64,
+    /// Game time of the last refill.
+    refilled_at: f64,
+    /// Whether the last packet was over the limit.
+    limited: bool,
+}
+
+/// The buckets of every client along with how often each client went over a limit.
+#[derive(Default)]
+pub struct RateLimits {
+    limits: HashMap<u8, RateLimit>,
+    buckets: HashMap<(ClientId, u8), Bucket>,
+    violations: HashMap<ClientId, u64>,
+}
+
+impl RateLimits {
+    pub fn new(config: &RateLimitConfig) -> Result<Self> {
+        let mut limits = HashMap::new();
+        for (name, limit) in &config.0 {
+            let id = packet_id(name).ok_or_else(|| {
+                Error::InvalidConfig(format!("no client packet named `{}` to rate limit", name))
+            })?;
+            if !(limit.rate >= 0.0 && limit.rate.is_finite()) {
+                return Err(Error::InvalidConfig(format!(
+                    "the rate limit of `{}` needs a finite rate of at least 0",
+                    name
+                )));
+            }
+            limits.insert(id, *limit);
+        }
+        Ok(RateLimits {
+            limits,
+            ..Default::default()
+        })
+    }
+
+    /// Takes a token from the bucket of `cid` for the packet `id`, returns whether the packet is
+    /// within the limit. Packets without a limit always are.
+    pub fn check(&mut self, cid: ClientId, id: u8, game_time: f64) -> bool {
+        let limit = match self.limits.get(&id) {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let bucket = self.buckets.entry((cid, id)).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            refilled_at: game_time,
+            limited: false,
+        });
+        bucket.tokens = (bucket.tokens + (game_time - bucket.refilled_at) * limit.rate)
+            .min(f64::from(limit.burst));
+        bucket.refilled_at = game_time;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.limited = false;
+            return true;
+        }
+
+        if bucket.limited {
+            log::debug!("client {} is still flooding packet 0x{:X}", cid.0, id);
+        } else {
+            log::warn!(
+                "client {} exceeded the rate limit of packet 0x{:X} ({}/s, burst {})",
+                cid.0,
+                id,
+                limit.rate,
+                limit.burst
+            );
+        }
+        bucket.limited = true;
+        *self.violations.entry(cid).or_insert(0) += 1;
+        false
+    }
+
+    /// Whether the last packet `id` of `cid` was over the limit.
+    pub fn is_limited(&self, cid: ClientId, id: u8) -> bool {
+        self.buckets
+            .get(&(cid, id))
+            .is_some_and(|bucket| bucket.limited)
+    }
+
+    /// How many packets of `cid` were over a limit so far.
+    pub fn violations(&self, cid: ClientId) -> u64 {
+        self.violations.get(&cid).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rblitz_packets::{
+        packets::game::{
+            client::{CMapPing, CPlayEmote},
+            request::CBuyItemReq,
+        },
+        PacketId,
+    };
+
+    fn limits(rate: f64, burst: u32) -> RateLimits {
+        let config = RateLimitConfig(
+            vec![("CMapPing".to_owned(), RateLimit { rate, burst })]
+                .into_iter()
+                .collect(),
+        );
+        RateLimits::new(&config).unwrap()
+    }
+
+    #[test]
+    fn bursts_then_refills_over_time() {
+        let mut limits = limits(2.0, 3);
+        let cid = ClientId(0);
+        assert!((0..3).all(|_| limits.check(cid, CMapPing::ID, 0.0)));
+        assert!(!limits.check(cid, CMapPing::ID, 0.0));
+        assert!(limits.is_limited(cid, CMapPing::ID));
+        // other clients and packets have buckets of their own
+        assert!(limits.check(ClientId(1), CMapPing::ID, 0.0));
+        assert!((0..10).all(|_| limits.check(cid, CPlayEmote::ID, 0.0)));
+
+        assert!(limits.check(cid, CMapPing::ID, 0.5));
+        assert!(!limits.is_limited(cid, CMapPing::ID));
+        assert!(!limits.check(cid, CMapPing::ID, 0.5));
+        // the bucket never holds more than the burst
+        assert!((0..3).all(|_| limits.check(cid, CMapPing::ID, 100.0)));
+        assert!(!limits.check(cid, CMapPing::ID, 100.0));
+        assert_eq!(limits.violations(cid), 3);
+    }
+
+    #[test]
+    fn zero_rates_never_refill() {
+        let mut limits = limits(0.0, 2);
+        let cid = ClientId(0);
+        assert!((0..2).all(|_| limits.check(cid, CMapPing::ID, 0.0)));
+        assert!(!limits.check(cid, CMapPing::ID, 1000.0));
+
+        for &rate in &[-1.0, f64::NAN, f64::INFINITY] {
+            let config = RateLimitConfig(
+                vec![("CMapPing".to_owned(), RateLimit { rate, burst: 1 })]
+                    .into_iter()
+                    .collect(),
+            );
+            assert!(RateLimits::new(&config).is_err(), "rate {}", rate);
+        }
+    }
+
+    #[test]
+    fn unknown_packets_are_rejected() {
+        let config = RateLimitConfig(
+            vec![(
+                "CFlood".to_owned(),
+                RateLimit {
+                    rate: 1.0,
+                    burst: 1,
+                },
+            )]
+            .into_iter()
+            .collect(),
+        );
+        assert!(RateLimits::new(&config).is_err());
+        assert!(RateLimits::new(&RateLimitConfig::default()).is_ok());
+        // any packet clients send can be limited, but only those
+        assert_eq!(packet_id("CMapPing"), Some(CMapPing::ID));
+        assert_eq!(packet_id("CBuyItemReq"), Some(CBuyItemReq::ID));
+        assert_eq!(packet_id("SStartGame"), None);
+    }
+}
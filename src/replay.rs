@@ -0,0 +1,313 @@
+//! Recording and replaying the input of a game, see [`GameServer::record_input_to`] and
+//! [`GameServer::replay`].
+//!
+//! Everything the game reacts to is an enet event, and every frame of the game is one tick of game
+//! time, so recording the events along with the tick they were handled in is enough to run the
+//! same game again without any clients. Packets are recorded
+//! as they arrived, still encrypted, so replaying requires the same player config.
+//!
+//! A log starts with the 8 byte magic `RBLZRPL\0` followed by the format version as a `u16`, then
+//! one record per event until the end of the file. All integers are little endian.
+//!
+//! | field | type  | description                                                                 |
+//! |-------|-------|-----------------------------------------------------------------------------|
+//! | tick  | `u64` | the tick the event was handled in                                           |
+//! | kind  | `u8`  | 0 for a connection, 1 for a disconnection, 2 for a packet, 3 for statistics |
+//!
+//! followed by the data of the event:
+//!
+//! - connection: the 24 byte keycheck the client authenticated with
+//! - disconnection: the client id as a `u32`
+//! - packet: the client id as a `u32`, the channel as a `u8`, the length of the packet as a `u32`
+//!   and the packet itself
+//! - statistics: the client id as a `u32`, the round trip time and its variance in milliseconds as
+//!   `u32`s and the packet loss as an `f32`, the ping clients are shown comes from them
+//!
+//! [`GameServer::record_input_to`]: crate::game_server::GameServer::record_input_to
+//! [`GameServer::replay`]: crate::game_server::GameServer::replay
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    lenet_server::{Event, Packet, PacketMode, PeerStats},
+    packet::KeyCheck,
+};
+
+pub use crate::client::ClientId;
+
+pub const MAGIC: [u8; 8] = *b"RBLZRPL\0";
+pub const VERSION: u16 = 2;
+
+#[derive(Clone, Debug)]
+pub enum InputEvent {
+    Connected(KeyCheck),
+    Disconnected(ClientId),
+    /// A packet as received, before decryption.
+    Packet(ClientId, u8, Vec<u8>),
+    Stats(ClientId, PeerStats),
+}
+
+#[derive(Clone, Debug)]
+pub struct InputRecord {
+    pub tick: u64,
+    pub event: InputEvent,
+}
+
+pub struct InputWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> InputWriter<W> {
+    /// Starts a new log by writing the header.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_u16::<LE>(VERSION)?;
+        Ok(InputWriter { writer })
+    }
+
+    pub fn write(&mut self, record: &InputRecord) -> io::Result<()> {
+        self.writer.write_u64::<LE>(record.tick)?;
+        match &record.event {
+            InputEvent::Connected(keycheck) => {
+                self.writer.write_u8(0)?;
+                self.writer.write_all(&keycheck.to_bytes())
+            },
+            InputEvent::Disconnected(cid) => {
+                self.writer.write_u8(1)?;
+                self.writer.write_u32::<LE>(cid.0)
+            },
+            InputEvent::Packet(cid, channel, data) => {
+                self.writer.write_u8(2)?;
+                self.writer.write_u32::<LE>(cid.0)?;
+                self.writer.write_u8(*channel)?;
+                self.writer.write_u32::<LE>(data.len() as u32)?;
+                self.writer.write_all(data)
+            },
+            InputEvent::Stats(cid, stats) => {
+                self.writer.write_u8(3)?;
+                self.writer.write_u32::<LE>(cid.0)?;
+                self.writer.write_u32::<LE>(stats.round_trip_time)?;
+                self.writer
+                    .write_u32::<LE>(stats.round_trip_time_variance)?;
+                self.writer.write_f32::<LE>(stats.packet_loss)
+            },
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads the records of a log, iterating over it yields them in the order they were written.
+pub struct InputReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> InputReader<R> {
+    /// Checks the header of the log.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("not an input log"));
+        }
+        let version = reader.read_u16::<LE>()?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported input log version {}",
+                version
+            )));
+        }
+        Ok(InputReader { reader })
+    }
+
+    /// Reads the next record, returning `None` once the log ended.
+    pub fn read_record(&mut self) -> io::Result<Option<InputRecord>> {
+        let tick = match self.reader.read_u64::<LE>() {
+            Ok(tick) => tick,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let event = match self.reader.read_u8()? {
+            0 => {
+                let mut keycheck = [0; KeyCheck::SIZE];
+                self.reader.read_exact(&mut keycheck)?;
+                InputEvent::Connected(KeyCheck::from_bytes(&keycheck).unwrap())
+            },
+            1 => InputEvent::Disconnected(ClientId(self.reader.read_u32::<LE>()?)),
+            2 => {
+                let cid = ClientId(self.reader.read_u32::<LE>()?);
+                let channel = self.reader.read_u8()?;
+                let len = self.reader.read_u32::<LE>()?;
+                let mut data = Vec::new();
+                self.reader
+                    .by_ref()
+                    .take(len as u64)
+                    .read_to_end(&mut data)?;
+                if data.len() != len as usize {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                InputEvent::Packet(cid, channel, data)
+            },
+            3 => InputEvent::Stats(
+                ClientId(self.reader.read_u32::<LE>()?),
+                PeerStats {
+                    round_trip_time: self.reader.read_u32::<LE>()?,
+                    round_trip_time_variance: self.reader.read_u32::<LE>()?,
+                    packet_loss: self.reader.read_f32::<LE>()?,
+                },
+            ),
+            kind => return Err(invalid_data(format!("invalid event kind {}", kind))),
+        };
+        Ok(Some(InputRecord { tick, event }))
+    }
+}
+
+impl<R: Read> Iterator for InputReader<R> {
+    type Item = io::Result<InputRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Stands in for the network when replaying, handing out the recorded events at their ticks.
+pub(crate) struct Replay {
+    records: VecDeque<InputRecord>,
+}
+
+impl Replay {
+    pub fn new<R: Read>(reader: R) -> io::Result<Self> {
+        Ok(Replay {
+            records: InputReader::new(reader)?.collect::<io::Result<_>>()?,
+        })
+    }
+
+    /// The next event that was handled in or before `tick`.
+    pub fn next_event(&mut self, tick: u64) -> Option<Event> {
+        if self.records.front()?.tick > tick {
+            return None;
+        }
+        let event = match self.records.pop_front()?.event {
+            InputEvent::Connected(keycheck) => Event::Connected(keycheck, None),
+            InputEvent::Disconnected(cid) => Event::Disconnected(cid),
+            InputEvent::Packet(cid, channel, data) => {
+                // the mode only matters for sending
+                let packet = Packet::new(&data, PacketMode::Reliable).expect("out of memory");
+                Event::Packet(cid, channel, packet)
+            },
+            InputEvent::Stats(cid, stats) => Event::Stats(cid, stats),
+        };
+        Some(event)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl InputRecord {
+    /// The record of an event the server is about to handle, `None` for events that don't change
+    /// the game.
+    pub(crate) fn from_event(tick: u64, event: &Event) -> Option<Self> {
+        let event = match event {
+            Event::NoEvent => return None,
+            Event::Connected(keycheck, _) => InputEvent::Connected(*keycheck),
+            Event::Disconnected(cid) => InputEvent::Disconnected(*cid),
+            Event::Packet(cid, channel, packet) => {
+                InputEvent::Packet(*cid, *channel, packet.to_vec())
+            },
+            Event::Stats(cid, stats) => InputEvent::Stats(*cid, *stats),
+        };
+        Some(InputRecord { tick, event })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_roundtrip() {
+        let keycheck = KeyCheck {
+            action: 0,
+            pad: [0; 3],
+            client_id: 0,
+            player_id: 12,
+            check_id: [1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        let records = vec![
+            InputRecord {
+                tick: 0,
+                event: InputEvent::Connected(keycheck),
+            },
+            InputRecord {
+                tick: 3,
+                event: InputEvent::Packet(ClientId(1), 1, vec![0xC5, 0, 0, 0, 0, 9]),
+            },
+            InputRecord {
+                tick: 15,
+                event: InputEvent::Stats(
+                    ClientId(1),
+                    PeerStats {
+                        round_trip_time: 42,
+                        round_trip_time_variance: 3,
+                        packet_loss: 0.25,
+                    },
+                ),
+            },
+            InputRecord {
+                tick: 70,
+                event: InputEvent::Disconnected(ClientId(1)),
+            },
+        ];
+        let mut writer = InputWriter::new(Vec::new()).unwrap();
+        for record in &records {
+            writer.write(record).unwrap();
+        }
+        let data = writer.into_inner();
+
+        let read = InputReader::new(&data[..])
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(format!("{:?}", read), format!("{:?}", records));
+    }
+
+    #[test]
+    fn events_are_held_back_until_their_tick() {
+        let mut writer = InputWriter::new(Vec::new()).unwrap();
+        for tick in &[0, 0, 2] {
+            writer
+                .write(&InputRecord {
+                    tick: *tick,
+                    event: InputEvent::Disconnected(ClientId(0)),
+                })
+                .unwrap();
+        }
+        let mut replay = Replay::new(&writer.into_inner()[..]).unwrap();
+        assert!(replay.next_event(0).is_some());
+        assert!(replay.next_event(0).is_some());
+        assert!(replay.next_event(0).is_none());
+        assert!(replay.next_event(1).is_none());
+        assert!(replay.next_event(2).is_some());
+        assert!(replay.is_finished());
+    }
+}
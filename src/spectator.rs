@@ -0,0 +1,162 @@
+//! Spectators authenticate like players but have no champion, everything they send is ignored and
+//! they receive the packets broadcast to the players with a delay. The heroes are spawned for
+//! them as soon as they join.
+
+use std::collections::VecDeque;
+
+use crate::{
+    auth::{AuthProvider, PlayerKey},
+    client::{ClientId, ClientStatus, Session},
+    config::SpectatorConfig,
+    error::Result,
+    lenet_server::PendingConnection,
+    packet::{
+        packet_dispatcher_sys::{Delivery, PacketSender},
+        Channel, KeyCheck,
+    },
+};
+
+pub struct Spectator {
+    pub session: Session,
+    pub name: String,
+    pub player_id: u64,
+    pub status: ClientStatus,
+}
+
+impl Spectator {
+    pub fn new(key: PlayerKey, name: String, player_id: u64) -> Self {
+        Spectator {
+            session: Session::new(key),
+            name,
+            player_id,
+            status: ClientStatus::Disconnected,
+        }
+    }
+
+    /// Same as [`Client::auth`](crate::client::Client::auth).
+    pub fn auth(
+        &mut self,
+        sender: &PacketSender,
+        cid: ClientId,
+        keycheck: KeyCheck,
+        connection: Option<&PendingConnection>,
+    ) -> Result<()> {
+        self.session
+            .auth(sender, cid, self.player_id, keycheck, connection)?;
+        log::info!("spectator {:?} authenticated [{:?}]", cid.0, keycheck);
+        self.status = ClientStatus::Connected;
+        Ok(())
+    }
+}
+
+/// A broadcast waiting for the spectator delay to pass.
+struct DelayedPacket {
+    game_time: f64,
+    startup: bool,
+    channel: Channel,
+    delivery: Delivery,
+    data: Box<[u8]>,
+}
+
+/// All spectator slots of the game along with the broadcasts they have yet to receive.
+#[derive(Default)]
+pub struct Spectators {
+    spectators: indexmap::IndexMap<ClientId, Spectator>,
+    /// Seconds of game time spectators lag behind.
+    delay: f64,
+    delayed: VecDeque<DelayedPacket>,
+    /// The released packets of the tick the game started, for spectators joining afterwards.
+    startup: Vec<(Channel, Delivery, Box<[u8]>)>,
+}
+
+impl Spectators {
+    /// Spectators get the client ids following the ones of the players, `first_id`. Their keys
+    /// and names come from `auth`.
+    pub fn new(
+        configs: Vec<SpectatorConfig>,
+        first_id: u32,
+        delay: f64,
+        auth: &mut dyn AuthProvider,
+    ) -> Result<Self> {
+        let mut spectators = indexmap::IndexMap::new();
+        for (idx, s) in configs.into_iter().enumerate() {
+            let identity = auth.identify(s.player_id)?;
+            spectators.insert(
+                ClientId(first_id + idx as u32),
+                Spectator::new(identity.key, identity.name, s.player_id),
+            );
+        }
+        Ok(Spectators {
+            spectators,
+            delay,
+            delayed: VecDeque::new(),
+            startup: Vec::new(),
+        })
+    }
+
+    pub fn get_mut(&mut self, cid: &ClientId) -> Option<&mut Spectator> {
+        self.spectators.get_mut(cid)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&ClientId, &mut Spectator)> {
+        self.spectators.iter_mut()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Spectator> {
+        self.spectators.values()
+    }
+
+    /// Holds back a broadcast until the delay passed. `startup` packets are kept around after
+    /// their release, see [`startup`](Self::startup).
+    pub fn delay(
+        &mut self,
+        game_time: f64,
+        startup: bool,
+        channel: Channel,
+        delivery: Delivery,
+        data: Box<[u8]>,
+    ) {
+        if self.spectators.is_empty() {
+            return;
+        }
+        self.delayed.push_back(DelayedPacket {
+            game_time,
+            startup,
+            channel,
+            delivery,
+            data,
+        });
+    }
+
+    /// Takes the broadcasts whose delay passed at `game_time`, oldest first.
+    pub fn release(&mut self, game_time: f64) -> Vec<(Channel, Delivery, Box<[u8]>)> {
+        let mut released = Vec::new();
+        while let Some(packet) = self.delayed.front() {
+            if packet.game_time + self.delay > game_time {
+                break;
+            }
+            let packet = self.delayed.pop_front().unwrap();
+            if packet.startup {
+                self.startup
+                    .push((packet.channel, packet.delivery, packet.data.clone()));
+            }
+            released.push((packet.channel, packet.delivery, packet.data));
+        }
+        released
+    }
+
+    /// The startup packets released so far. A spectator joining after the game started missed
+    /// them and has to be sent these first, anything else released before it joined is gone.
+    pub fn startup(&self) -> &[(Channel, Delivery, Box<[u8]>)] {
+        &self.startup
+    }
+
+    /// The spectators that are currently watching.
+    pub fn watching(&self) -> Vec<ClientId> {
+        self.spectators
+            .iter()
+            .filter(|(_, s)| s.status != ClientStatus::Disconnected)
+            .map(|(cid, _)| *cid)
+            .collect()
+    }
+}
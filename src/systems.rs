@@ -1 +1,159 @@
+use rblitz_packets::{
+    packets::game::{
+        common::{CompressedWaypoint, MovementDataNormal, MAX_WAYPOINTS},
+        SWaypointGroup,
+    },
+    Vector2,
+};
+use specs::{Entities, Join, Read, ReadExpect, ReadStorage, System, Write, WriteStorage};
 
+use crate::{
+    client::ClientMap,
+    game_server::TICK_RATE,
+    packet::{
+        packet_dispatcher_sys::{Delivery, PacketSender},
+        rate_limit::RateLimits,
+        Channel,
+    },
+    world::{
+        components::{MovePath, NetId, Position},
+        resources::{ClientNetworkStats, GameTime, NetworkStats},
+    },
+};
+
+/// Seconds of game time between two log entries of the network statistics.
+const NETWORK_STATS_LOG_INTERVAL: f64 = 10.0;
+
+/// Copies the connection quality enet measured and the traffic counted by the clients into the
+/// [`NetworkStats`] resource.
+#[derive(Default)]
+pub struct NetworkStatsSys {
+    last_log: f64,
+}
+
+impl<'a> System<'a> for NetworkStatsSys {
+    type SystemData = (
+        ReadExpect<'a, ClientMap>,
+        Read<'a, GameTime>,
+        Read<'a, RateLimits>,
+        Write<'a, NetworkStats>,
+    );
+
+    fn run(&mut self, (clients, game_time, rate_limits, mut network_stats): Self::SystemData) {
+        network_stats.clear();
+        for (cid, client) in clients.iter() {
+            // cleared on disconnection, replays restore them from the input log
+            let peer_stats = match client.peer_stats {
+                Some(stats) => stats,
+                None => continue,
+            };
+            let traffic = client.session.traffic();
+            network_stats.insert(
+                *cid,
+                ClientNetworkStats {
+                    bytes_in: traffic.bytes_in,
+                    bytes_out: traffic.bytes_out,
+                    packets_in: traffic.packets_in,
+                    packets_out: traffic.packets_out,
+                    rate_limited: rate_limits.violations(*cid),
+                    ..peer_stats.into()
+                },
+            );
+        }
+
+        if game_time.0 - self.last_log >= NETWORK_STATS_LOG_INTERVAL {
+            self.last_log = game_time.0;
+            for (cid, stats) in network_stats.iter() {
+                log::debug!("network stats of client {}: {}", cid.0, stats);
+            }
+        }
+    }
+}
+
+/// How far units walk per second until they have stats.
+const MOVE_SPEED: f32 = 325.0;
+/// The middle of the map, which compressed waypoints are relative to.
+// FIXME take it from the nav grid once the map gets loaded
+const MAP_CENTER: Vector2 = Vector2 {
+    x: 7000.0,
+    y: 7000.0,
+};
+
+/// Walks units along their [`MovePath`] and broadcasts where every moving unit is headed once per
+/// tick. The group is sent unreliably, a lost one is superseded by the group of the next tick.
+#[derive(Default)]
+pub struct MovementSys {
+    sync_id: i32,
+}
+
+impl<'a> System<'a> for MovementSys {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, NetId>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, MovePath>,
+        PacketSender<'a>,
+    );
+
+    fn run(&mut self, (entities, net_ids, mut positions, mut paths, sender): Self::SystemData) {
+        let mut movements = Vec::new();
+        let mut arrived = Vec::new();
+        for (entity, net_id, position, path) in
+            (&entities, &net_ids, &mut positions, &mut paths).join()
+        {
+            walk(position, &mut path.0, MOVE_SPEED * TICK_RATE as f32);
+            movements.push(movement(net_id, position, path));
+            if path.0.is_empty() {
+                arrived.push(entity);
+            }
+        }
+        for entity in arrived {
+            paths.remove(entity);
+        }
+
+        if !movements.is_empty() {
+            self.sync_id = self.sync_id.wrapping_add(1);
+            // the group already bundles all moving units and only grows with them
+            let channel = Channel::BroadcastUnreliable;
+            sender.broadcast_all_with(
+                channel,
+                Delivery::from(channel).unbatched(),
+                0,
+                &SWaypointGroup {
+                    sync_id: self.sync_id,
+                    movements,
+                },
+            );
+        }
+    }
+}
+
+/// The path of a unit at `position` in the form the client walks it, starting where the unit is.
+pub(crate) fn movement(net_id: &NetId, position: &Position, path: &MovePath) -> MovementDataNormal {
+    let waypoints = std::iter::once(position)
+        .chain(&path.0)
+        .take(MAX_WAYPOINTS)
+        .map(|p| CompressedWaypoint::from_world(Vector2 { x: p.x, y: p.y }, MAP_CENTER))
+        .collect();
+    MovementDataNormal {
+        teleport_net_id: net_id.id(),
+        waypoints,
+        ..Default::default()
+    }
+}
+
+/// Moves `position` `distance` along `path`, dropping the waypoints it reaches.
+fn walk(position: &mut Position, path: &mut Vec<Position>, mut distance: f32) {
+    while let Some(&next) = path.first() {
+        let (dx, dy) = (next.x - position.x, next.y - position.y);
+        let left = (dx * dx + dy * dy).sqrt();
+        if left > distance {
+            position.x += dx / left * distance;
+            position.y += dy / left * distance;
+            return;
+        }
+        *position = next;
+        distance -= left;
+        path.remove(0);
+    }
+}
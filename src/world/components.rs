@@ -34,14 +34,28 @@ impl Component for Health {
 
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
 pub struct Position {
-    x: f32,
-    y: f32,
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Position {
+    /// Where every unit spawns until the map has spawn points.
+    pub const SPAWN: Position = Position { x: 26.0, y: 280.0 };
 }
 
 impl Component for Position {
     type Storage = DenseVecStorage<Self>;
 }
 
+/// The waypoints a unit is still walking towards, the next one first. Removed once the unit
+/// arrived.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MovePath(pub Vec<Position>);
+
+impl Component for MovePath {
+    type Storage = HashMapStorage<Self>;
+}
+
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
 pub struct NetId(u32);
 
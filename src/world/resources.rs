@@ -1,2 +1,140 @@
+use indexmap::IndexMap;
+
+use core::{fmt, ops};
+use std::io::Write;
+
+use crate::{
+    capture::{CaptureWriter, Direction},
+    client::ClientId,
+    game_server::ShutdownReason,
+    lenet_server::PeerStats,
+    packet::Channel,
+};
+
 #[derive(Default)]
 pub struct GameTime(pub f64);
+
+/// Whether the game is still waiting for players to load or is already running.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum GamePhase {
+    #[default]
+    Loading,
+    /// Running since the given [`GameTime`].
+    Running { started_at: f64 },
+}
+
+/// Set to end the game, the server disconnects all clients and stops once the current frame is
+/// done.
+#[derive(Default)]
+pub struct Shutdown(pub Option<ShutdownReason>);
+
+/// Only present while capturing, see [`GameServer::capture_to`].
+///
+/// [`GameServer::capture_to`]: crate::game_server::GameServer::capture_to
+pub struct PacketCapture(Option<CaptureWriter<Box<dyn Write + Send + Sync>>>);
+
+impl PacketCapture {
+    pub fn new(writer: CaptureWriter<Box<dyn Write + Send + Sync>>) -> Self {
+        PacketCapture(Some(writer))
+    }
+
+    /// Records a decrypted packet. A failed write is logged and ends the capture, the game itself
+    /// keeps running.
+    pub fn record(
+        &mut self,
+        direction: Direction,
+        cid: ClientId,
+        channel: Channel,
+        game_time: f64,
+        data: &[u8],
+    ) {
+        if let Some(writer) = &mut self.0 {
+            if let Err(e) = writer.write(direction, cid, channel, game_time, data) {
+                log::error!("packet capture failed, no longer capturing: {}", e);
+                self.0 = None;
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(Err(e)) = self.0.as_mut().map(CaptureWriter::flush) {
+            log::error!("failed to flush the packet capture: {}", e);
+        }
+    }
+}
+
+/// Connection quality and traffic of a single client.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ClientNetworkStats {
+    /// Round trip time in milliseconds.
+    pub round_trip_time: u32,
+    /// Mean deviation of the round trip time in milliseconds.
+    pub round_trip_time_variance: u32,
+    /// Fraction of reliable packets that had to be resent, between 0 and 1.
+    pub packet_loss: f32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    /// Packets the client sent over their rate limit.
+    pub rate_limited: u64,
+}
+
+impl From<PeerStats> for ClientNetworkStats {
+    fn from(stats: PeerStats) -> Self {
+        ClientNetworkStats {
+            round_trip_time: stats.round_trip_time,
+            round_trip_time_variance: stats.round_trip_time_variance,
+            packet_loss: stats.packet_loss,
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Display for ClientNetworkStats {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "rtt {}+-{}ms, loss {:.1}%, in {}B/{} packets, out {}B/{} packets, {} rate limited",
+            self.round_trip_time,
+            self.round_trip_time_variance,
+            self.packet_loss * 100.0,
+            self.bytes_in,
+            self.packets_in,
+            self.bytes_out,
+            self.packets_out,
+            self.rate_limited,
+        )
+    }
+}
+
+/// Network statistics of all currently connected clients. The traffic counters are current as of
+/// the last frame, the connection quality only changes when the network thread reports it, about
+/// every 500ms.
+#[derive(Default)]
+pub struct NetworkStats {
+    clients: IndexMap<ClientId, ClientNetworkStats>,
+}
+
+impl NetworkStats {
+    /// The round trip time of a client in the form the client expects for its ping display.
+    pub fn ping(&self, cid: ClientId) -> u16 {
+        self.clients
+            .get(&cid)
+            .map_or(0, |stats| stats.round_trip_time.min(0x7FFF) as u16)
+    }
+}
+
+impl ops::Deref for NetworkStats {
+    type Target = IndexMap<ClientId, ClientNetworkStats>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.clients
+    }
+}
+
+impl ops::DerefMut for NetworkStats {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.clients
+    }
+}
@@ -0,0 +1,29 @@
+use rblitz::{
+    auth::{KeyEncoding, StaticAuth},
+    config::{AddressError, PlayerConfig},
+    error::Error,
+    game_server::GameServer,
+};
+
+use std::net::{Ipv4Addr, UdpSocket};
+
+#[test]
+fn taken_ports_are_address_errors() {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let address = socket.local_addr().unwrap();
+    let players = PlayerConfig::from_path("config/players.ron").unwrap();
+    let mut auth = StaticAuth::from_config(KeyEncoding::Raw, &players, &[]).unwrap();
+    let error = match GameServer::new(address.into(), players, &mut auth) {
+        Ok(_) => panic!("bound to {} while it was taken", address),
+        Err(e) => e,
+    };
+    match &error {
+        Error::Address(AddressError::Unbindable(unbindable)) => {
+            assert_eq!(unbindable, &address.to_string())
+        },
+        e => panic!("expected an address error, got {:?}", e),
+    }
+    assert!(error
+        .to_string()
+        .starts_with("can't bind to server address"));
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use rblitz::{
+    auth::{KeyEncoding, PlayerKey, RandomKeys, SocketAuth, StaticAuth},
+    config::PlayerConfig,
+    error::Error,
+    game_server::GameServer,
+    headless::HeadlessClient,
+};
+use rblitz_packets::packets::game::server::SStartGame;
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use crate::common::{free_address, spawn_server_with_auth};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to give a client with the wrong key before giving up on it.
+const REJECTED_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Stands in for a platform service knowing the players in `keys`, answering the lookups of a
+/// single connection.
+fn serve_identities(keys: HashMap<u64, PlayerKey>) -> SocketAddr {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        for line in BufReader::new(stream).lines() {
+            let request: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+            let player_id = request["player_id"].as_u64().unwrap();
+            let reply = match keys.get(&player_id) {
+                Some(key) => serde_json::json!({
+                    "key": key.to_base64(),
+                    "name": format!("Summoner{}", player_id),
+                    "profile_icon": 7,
+                    "summoner_level": 42,
+                }),
+                None => serde_json::json!({ "error": "no such player" }),
+            };
+            writeln!(writer, "{}", reply).unwrap();
+        }
+    });
+    address
+}
+
+fn play(address: SocketAddr, logins: &[(PlayerKey, u64)]) -> Vec<HeadlessClient> {
+    let mut players = logins
+        .iter()
+        .map(|(key, player_id)| {
+            HeadlessClient::connect(address.into(), key.as_bytes(), *player_id, TIMEOUT).unwrap()
+        })
+        .collect::<Vec<_>>();
+    for player in &mut players {
+        player.load("Version 4.20.0.315", TIMEOUT).unwrap();
+    }
+    for player in &mut players {
+        player.wait_for::<SStartGame>(TIMEOUT).unwrap();
+    }
+    players
+}
+
+#[test]
+fn players_authenticate_with_keys_from_a_service() {
+    let logins = [(PlayerKey::random(), 12), (PlayerKey::random(), 513)];
+    let service = serve_identities(logins.iter().map(|(k, id)| (*id, k.clone())).collect());
+    let server = spawn_server_with_auth(SocketAuth::new(service), |_| ());
+
+    // the keys of the config are no good anymore
+    let stale = HeadlessClient::connect(
+        server.address.into(),
+        b"GLzvuWtyCfHyGhF2",
+        12,
+        REJECTED_TIMEOUT,
+    );
+    assert!(stale.is_err());
+
+    for player in play(server.address, &logins) {
+        player.disconnect(TIMEOUT).unwrap();
+    }
+    let summary = server.stop();
+    let names = summary
+        .players
+        .iter()
+        .map(|p| p.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["Summoner12", "Summoner513"]);
+}
+
+#[test]
+fn players_unknown_to_the_service_fail_the_game() {
+    let service = serve_identities(vec![(12, PlayerKey::random())].into_iter().collect());
+    let players = PlayerConfig::from_path("config/players.ron").unwrap();
+    let result = GameServer::new(
+        free_address().into(),
+        players,
+        &mut SocketAuth::new(service),
+    );
+    assert!(matches!(result, Err(Error::UnknownPlayer(513))));
+}
+
+#[test]
+fn random_keys_are_fresh_for_every_game() {
+    let players = PlayerConfig::from_path("config/players.ron").unwrap();
+    let mut games = Vec::new();
+    for _ in 0..2 {
+        let auth = StaticAuth::from_config(KeyEncoding::Raw, &players, &[]).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let server = spawn_server_with_auth(RandomKeys::new(auth), move |server| {
+            tx.send(server.keys()).unwrap()
+        });
+        games.push((server, rx.recv().unwrap()));
+    }
+    assert_ne!(games[0].1, games[1].1);
+
+    for (server, keys) in games {
+        let logins = keys
+            .into_iter()
+            .map(|(player_id, key)| (key, player_id))
+            .collect::<Vec<_>>();
+        assert_eq!(logins.len(), 2);
+        for player in play(server.address, &logins) {
+            player.disconnect(TIMEOUT).unwrap();
+        }
+        server.stop();
+    }
+}
@@ -0,0 +1,61 @@
+use rblitz_packets::{
+    from_bytes,
+    packets::game::{
+        bitfield::*,
+        request::RemoveItemReq,
+        server::{SDampenerSwitch, SEndGame, SNpcInstantStopAttack, SStopAnimation},
+    },
+    to_bytes,
+};
+
+/// Every byte decodes and encodes back to itself, minus the bits no field covers.
+fn assert_symmetric<T>(used: u8)
+where
+    T: serde::Serialize + for<'de> serde::Deserialize<'de>,
+{
+    for byte in 0..=u8::MAX {
+        let decoded: T = from_bytes(&[byte]).unwrap();
+        assert_eq!(to_bytes(&decoded).unwrap(), [byte & used], "{:#04X}", byte);
+    }
+}
+
+#[test]
+fn bitfields_encode_what_they_decode() {
+    assert_symmetric::<TeamSurrenderVoteBitfield>(0x03);
+    assert_symmetric::<MapPingBitfield>(0x7F);
+    assert_symmetric::<SpawnMinionBitfield>(0x07);
+    assert_symmetric::<CastInfoBitfield>(0x1F);
+    assert_symmetric::<SpellSlotBitfield>(0xFF);
+    assert_symmetric::<ShieldProperties>(0x07);
+    assert_symmetric::<CharSpawnPetBitfield>(0x03);
+    assert_symmetric::<SStopAnimation>(0x07);
+    assert_symmetric::<SNpcInstantStopAttack>(0x1F);
+    assert_symmetric::<SEndGame>(0x03);
+    assert_symmetric::<RemoveItemReq>(0xFF);
+}
+
+#[test]
+fn fields_keep_to_their_bits() {
+    let ping = MapPingBitfield {
+        ping_category: 0x1F,
+        show_chat: true,
+        ..Default::default()
+    };
+    assert_eq!(to_bytes(&ping).unwrap(), [0x2F]);
+
+    let stop = SStopAnimation {
+        fade: true,
+        stop_all: true,
+        ..Default::default()
+    };
+    assert_eq!(to_bytes(&stop).unwrap(), [0x05]);
+
+    let switch = SDampenerSwitch {
+        duration: 0x8123,
+        state: true,
+    };
+    let data = to_bytes(&switch).unwrap();
+    assert_eq!(data, 0x8123u16.to_le_bytes());
+    let decoded: SDampenerSwitch = from_bytes(&data).unwrap();
+    assert_eq!((decoded.duration, decoded.state), (0x0123, true));
+}
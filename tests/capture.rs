@@ -0,0 +1,66 @@
+mod common;
+
+use rblitz::{
+    capture::{CaptureReader, Direction},
+    headless::{Channel, HeadlessClient},
+};
+use rblitz_packets::{
+    packets::game::{
+        client::CSyncVersion,
+        server::{SSyncVersion, SWorldSendGameNumber},
+    },
+    PacketId,
+};
+
+use std::{io, time::Duration};
+
+use crate::common::{spawn_server, SharedBuffer};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn captures_decrypted_traffic() {
+    let buffer = SharedBuffer::default();
+    let capture = buffer.clone();
+    let server = spawn_server(move |server| server.capture_to(capture).unwrap());
+    let mut client =
+        HeadlessClient::connect(server.address.into(), b"GLzvuWtyCfHyGhF2", 12, TIMEOUT).unwrap();
+    client
+        .send(
+            Channel::ClientToServer,
+            0,
+            &CSyncVersion {
+                client_id: client.client_id(),
+                version: "Version 4.20.0.315".to_owned(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    client.wait_for::<SSyncVersion>(TIMEOUT).unwrap();
+    server.stop();
+
+    let data = buffer.contents();
+    let records = CaptureReader::new(&data[..])
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    // the handshake is captured as well, the keycheck answer comes first
+    let keycheck = records
+        .iter()
+        .find(|r| r.direction == Direction::Outbound && r.channel == Channel::Handshake)
+        .unwrap();
+    assert_eq!(keycheck.data[8..16], 12u64.to_le_bytes());
+    assert!(records.iter().any(|r| r.direction == Direction::Outbound
+        && r.channel == Channel::Broadcast
+        && r.data[0] == SWorldSendGameNumber::ID));
+    let request = records
+        .iter()
+        .find(|r| r.direction == Direction::Inbound)
+        .unwrap();
+    assert_eq!(request.channel, Channel::ClientToServer);
+    assert_eq!(request.client_id.0, client.client_id());
+    assert_eq!(request.data[0], CSyncVersion::ID);
+    assert!(records.iter().any(|r| r.direction == Direction::Outbound
+        && r.channel == Channel::Broadcast
+        && r.data[0] == SSyncVersion::ID));
+}
@@ -0,0 +1,62 @@
+//! The casts spells and missiles are sent with, whole packets are pinned in
+//! `tests/fixtures/packets.txt`.
+
+use rblitz_packets::{
+    from_bytes,
+    packets::game::common::{CastInfo, CastTargetInfo, MAX_CAST_TARGETS},
+    to_bytes,
+};
+
+fn cast(targets: usize) -> CastInfo {
+    CastInfo {
+        spell_slot: 3,
+        mana_cost: 45.5,
+        targets: (0..targets as u32)
+            .map(|i| CastTargetInfo {
+                unit_net_id: 0x4000_0000 + i,
+                hit_result: 1,
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn casts_lead_with_their_size() {
+    for &targets in &[0, 1, MAX_CAST_TARGETS] {
+        let encoded = to_bytes(&cast(targets)).unwrap();
+        assert_eq!(encoded.len(), 102 + 5 * targets);
+        assert_eq!(
+            usize::from(u16::from_le_bytes([encoded[0], encoded[1]])),
+            encoded.len()
+        );
+        let decoded: CastInfo = from_bytes(&encoded).unwrap();
+        assert_eq!(decoded.targets.len(), targets);
+        assert_eq!(decoded.spell_slot, 3);
+        assert_eq!(decoded.mana_cost, 45.5);
+    }
+}
+
+#[test]
+fn target_count_is_checked() {
+    assert!(to_bytes(&cast(MAX_CAST_TARGETS + 1)).is_err());
+
+    let mut encoded = to_bytes(&cast(0)).unwrap();
+    // the target count follows the ids, the level and the two target positions
+    assert_eq!(encoded[55], 0);
+    encoded[55] = MAX_CAST_TARGETS as u8 + 1;
+    encoded.extend(vec![0; 5 * (MAX_CAST_TARGETS + 1)]);
+    assert!(from_bytes::<CastInfo>(&encoded).is_err());
+}
+
+#[test]
+fn sizes_have_to_match_the_cast() {
+    let mut encoded = to_bytes(&cast(1)).unwrap();
+    encoded[0] -= 1;
+    assert!(from_bytes::<CastInfo>(&encoded).is_err());
+
+    // a size counting a target too many, as though the count was off
+    let mut encoded = to_bytes(&cast(0)).unwrap();
+    encoded[0] += 5;
+    assert!(from_bytes::<CastInfo>(&encoded).is_err());
+}
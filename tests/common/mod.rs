@@ -0,0 +1,89 @@
+// every test crate only uses some of these
+#![allow(dead_code)]
+
+use rblitz::{
+    auth::{AuthProvider, KeyEncoding, StaticAuth},
+    config::PlayerConfig,
+    game_server::{GameServer, GameSummary, ShutdownHandle},
+};
+
+use std::{
+    io::{self, Write},
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+pub struct TestServer {
+    pub address: SocketAddr,
+    pub shutdown: ShutdownHandle,
+    pub thread: thread::JoinHandle<GameSummary>,
+}
+
+impl TestServer {
+    pub fn stop(self) -> GameSummary {
+        self.shutdown.shutdown();
+        self.thread.join().unwrap()
+    }
+}
+
+/// Starts a server for the players of the default config on a free local port, `setup` runs on
+/// the server before it starts running.
+pub fn spawn_server<F>(setup: F) -> TestServer
+where
+    F: FnOnce(&mut GameServer) + Send + 'static,
+{
+    let players = PlayerConfig::from_path("config/players.ron").unwrap();
+    let auth = StaticAuth::from_config(KeyEncoding::Raw, &players, &[]).unwrap();
+    spawn_server_with_auth(auth, setup)
+}
+
+/// Same as [`spawn_server`] but the keys and profiles of the players come from `auth`.
+pub fn spawn_server_with_auth<A, F>(mut auth: A, setup: F) -> TestServer
+where
+    A: AuthProvider + Send + 'static,
+    F: FnOnce(&mut GameServer) + Send + 'static,
+{
+    let address = free_address();
+    let (tx, rx) = mpsc::channel();
+    // the server isn't Send so it has to be created on the thread running it
+    let thread = thread::spawn(move || {
+        let players = PlayerConfig::from_path("config/players.ron").unwrap();
+        let mut server = GameServer::new(address.into(), players, &mut auth).unwrap();
+        setup(&mut server);
+        tx.send(server.shutdown_handle()).unwrap();
+        server.run()
+    });
+    TestServer {
+        address,
+        shutdown: rx.recv().unwrap(),
+        thread,
+    }
+}
+
+/// A local address with a port that nothing is listening on.
+pub fn free_address() -> SocketAddr {
+    UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|socket| socket.local_addr())
+        .unwrap()
+}
+
+/// A buffer that can be handed to the server while the test keeps access to it.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+//! The decoders of the fuzz targets in `fuzz/` fed random bytes, so they get some coverage even
+//! without cargo-fuzz.
+
+use proptest::{collection::vec, prelude::*, sample::select};
+use rblitz_packets::{
+    packets::{
+        game::{client::CSyncVersion, server::SNpcDeathEventHistory},
+        registry::{self, Reencode},
+        PacketId,
+    },
+    to_bytes, Result,
+};
+
+type Lookup = fn(u8, &[u8]) -> Option<Result<Vec<u8>>>;
+
+fn client(id: u8, data: &[u8]) -> Option<Result<Vec<u8>>> {
+    registry::client_packet(id, Reencode(data))
+}
+
+fn server(id: u8, data: &[u8]) -> Option<Result<Vec<u8>>> {
+    registry::server_packet(id, Reencode(data))
+}
+
+fn loading_screen(id: u8, data: &[u8]) -> Option<Result<Vec<u8>>> {
+    registry::loading_screen_packet(id, Reencode(data))
+}
+
+/// The ids `lookup` knows a packet for.
+fn ids(lookup: Lookup) -> Vec<u8> {
+    (0..=u8::MAX)
+        .filter(|&id| lookup(id, &[]).is_some())
+        .collect()
+}
+
+fn packet(lookup: Lookup) -> impl Strategy<Value = (u8, Vec<u8>)> {
+    (select(ids(lookup)), vec(any::<u8>(), 0..512))
+}
+
+/// Payloads that are too short, all zeroes, or claim every length to be as long as can be.
+fn every_packet_handles_degenerate_payloads(lookup: Lookup) {
+    for id in ids(lookup) {
+        for len in 0..64 {
+            for &fill in &[0, 0xFF] {
+                let _ = lookup(id, &vec![fill; len]);
+            }
+        }
+    }
+}
+
+#[test]
+fn all_packets_are_registered() {
+    assert_eq!(ids(client).len(), 31);
+    assert_eq!(ids(server).len(), 189);
+    assert_eq!(ids(loading_screen).len(), 4);
+}
+
+#[test]
+fn degenerate_payloads() {
+    every_packet_handles_degenerate_payloads(client);
+    every_packet_handles_degenerate_payloads(server);
+    every_packet_handles_degenerate_payloads(loading_screen);
+}
+
+#[test]
+fn wire_lengths_dont_preallocate() {
+    let mut data = vec![0; 16];
+    data.extend_from_slice(&u32::MAX.to_le_bytes());
+    let result = server(SNpcDeathEventHistory::ID, &data).unwrap();
+    assert!(result.is_err());
+}
+
+#[test]
+fn fixed_strings_without_null_keep_their_length() {
+    let mut data = vec![0; 8];
+    data.extend_from_slice(&[b'a'; 128]);
+    let version: CSyncVersion = rblitz_packets::from_bytes(&data).unwrap();
+    assert_eq!(version.version.len(), 128);
+
+    let version = CSyncVersion {
+        version: "Version 4.20.0.315".to_owned(),
+        ..Default::default()
+    };
+    let data = to_bytes(&version).unwrap();
+    assert_eq!(data.len(), 8 + 128);
+    let decoded: CSyncVersion = rblitz_packets::from_bytes(&data).unwrap();
+    assert_eq!(decoded.version, version.version);
+}
+
+proptest! {
+    #[test]
+    fn client_packets((id, payload) in packet(client)) {
+        let _ = client(id, &payload);
+    }
+
+    #[test]
+    fn server_packets((id, payload) in packet(server)) {
+        let _ = server(id, &payload);
+    }
+
+    #[test]
+    fn loading_screen_packets((id, payload) in packet(loading_screen)) {
+        let _ = loading_screen(id, &payload);
+    }
+
+    #[test]
+    fn game_packets(data in vec(any::<u8>(), 0..512)) {
+        let _ = rblitz::fuzz::game_packet(&data);
+        let _ = rblitz::fuzz::key_check(&data);
+    }
+
+    #[test]
+    fn batched_game_packets(count: u8, data in vec(any::<u8>(), 0..1024)) {
+        let batch = [&[0xFF, count][..], &data].concat();
+        let _ = rblitz::fuzz::game_packet(&batch);
+    }
+}
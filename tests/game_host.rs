@@ -0,0 +1,152 @@
+mod common;
+
+use rblitz::{
+    config::GameConfig,
+    game_host::{Address, GameHost, GameStatus},
+    game_server::ShutdownReason,
+    headless::HeadlessClient,
+};
+use rblitz_packets::packets::game::server::SStartGame;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::common::free_address;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const EXAMPLE: &str = "config/games/example.ron";
+
+fn game_config(name: &str) -> GameConfig {
+    let mut config = GameConfig::from_path(EXAMPLE).unwrap();
+    config.name = name.to_owned();
+    config.server.address = free_address().to_string();
+    config
+}
+
+fn play(address: Address) -> Vec<HeadlessClient> {
+    let mut players = [
+        (&b"GLzvuWtyCfHyGhF2"[..], 12),
+        (&b"GLzvuWtyCfHyGhF3"[..], 513),
+    ]
+    .iter()
+    .map(|&(key, player_id)| HeadlessClient::connect(address, key, player_id, TIMEOUT).unwrap())
+    .collect::<Vec<_>>();
+    for player in &mut players {
+        player.load("Version 4.20.0.315", TIMEOUT).unwrap();
+    }
+    for player in &mut players {
+        player.wait_for::<SStartGame>(TIMEOUT).unwrap();
+    }
+    players
+}
+
+fn wait_until_finished(host: &mut GameHost, name: &str) -> GameStatus {
+    let deadline = Instant::now() + TIMEOUT;
+    while host.game(name).unwrap().is_running() {
+        assert!(Instant::now() < deadline, "game {} didn't end", name);
+        thread::sleep(Duration::from_millis(10));
+        host.poll();
+    }
+    host.game(name).unwrap().status.clone()
+}
+
+#[test]
+fn games_run_independently() {
+    let mut host = GameHost::new();
+    let first = host.start(game_config("first")).unwrap();
+    let second = host.start(game_config("second")).unwrap();
+    assert!(host.start(game_config("second")).is_err());
+
+    let _first_players = play(first);
+    let _second_players = play(second);
+
+    assert!(host.stop("first"));
+    match wait_until_finished(&mut host, "first") {
+        GameStatus::Finished(summary) => assert_eq!(summary.reason, ShutdownReason::Requested),
+        status => panic!("unexpected status {:?}", status),
+    }
+    assert!(host.game("second").unwrap().is_running());
+    assert!(!host.stop("first"));
+    // a finished game can be replaced
+    host.start(game_config("first")).unwrap();
+
+    host.shutdown();
+    assert!(host.games().all(|game| !game.is_running()));
+}
+
+#[cfg(unix)]
+#[test]
+fn games_are_managed_through_the_control_socket() {
+    use rblitz::{game_host::control::ControlSocket, game_server::ShutdownHandle};
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+    };
+
+    let directory = std::env::temp_dir().join(format!("rblitz-host-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let game_file = directory.join("game.ron");
+    let address = free_address();
+    let game = fs::read_to_string(EXAMPLE)
+        .unwrap()
+        .replace("\"127.0.0.1\"", &format!("\"{}\"", address))
+        .replace("\"example\"", "\"controlled\"");
+    fs::write(&game_file, game).unwrap();
+
+    let socket = directory.join("control.sock");
+    let mut control = ControlSocket::bind(&socket).unwrap();
+    let shutdown = ShutdownHandle::default();
+    let host = {
+        let shutdown = shutdown.clone();
+        thread::spawn(move || GameHost::new().run(&mut control, &shutdown))
+    };
+
+    let stream = UnixStream::connect(&socket).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut command = |command: String| {
+        writeln!(&stream, "{}", command).unwrap();
+        let mut answer = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end().to_owned();
+            let done = line.starts_with("ok") || line.starts_with("error");
+            answer.push(line);
+            if done {
+                return answer;
+            }
+        }
+    };
+
+    let started = command(format!("start {}", game_file.display()));
+    assert_eq!(started, [format!("ok controlled {}", address)]);
+    let _players = play(address.into());
+    assert_eq!(
+        command("status".to_owned()),
+        [
+            format!("game controlled {} running", address),
+            "ok".to_owned()
+        ]
+    );
+    assert!(command("start /does/not/exist".to_owned())[0].starts_with("error"));
+    assert!(command("launch".to_owned())[0].starts_with("error"));
+    assert_eq!(command("stop controlled".to_owned()), ["ok"]);
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let status = command("status".to_owned());
+        if status[0].contains("finished Requested") {
+            break;
+        }
+        assert!(Instant::now() < deadline, "game didn't end: {:?}", status);
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    shutdown.shutdown();
+    host.join().unwrap();
+    assert!(!socket.exists());
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use rblitz::headless::{Channel, HeadlessClient};
+use rblitz_packets::packets::game::{
+    client::CSyncVersion,
+    server::{SStartGame, SSyncVersion},
+};
+
+use std::time::Duration;
+
+use crate::common::spawn_server;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn clients_load_into_the_game() {
+    let server = spawn_server(|_| ());
+    let mut clients = [
+        (&b"GLzvuWtyCfHyGhF2"[..], 12),
+        (&b"GLzvuWtyCfHyGhF3"[..], 513),
+    ]
+    .iter()
+    .map(|&(key, player_id)| {
+        HeadlessClient::connect(server.address.into(), key, player_id, TIMEOUT).unwrap()
+    })
+    .collect::<Vec<_>>();
+    assert_ne!(clients[0].client_id(), clients[1].client_id());
+
+    for client in &mut clients {
+        client.load("Version 4.20.0.315", TIMEOUT).unwrap();
+    }
+    for client in &mut clients {
+        client.wait_for::<SStartGame>(TIMEOUT).unwrap();
+    }
+    for client in clients {
+        client.disconnect(TIMEOUT).unwrap();
+    }
+
+    server.stop();
+}
+
+#[test]
+fn packets_can_be_decoded_from_the_stream() {
+    let server = spawn_server(|_| ());
+    let mut client =
+        HeadlessClient::connect(server.address.into(), b"GLzvuWtyCfHyGhF2", 12, TIMEOUT).unwrap();
+    client
+        .send(
+            Channel::ClientToServer,
+            0,
+            &CSyncVersion {
+                client_id: client.client_id(),
+                version: "Version 4.20.0.315".to_owned(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    let answer = client
+        .packets(TIMEOUT)
+        .filter_map(|packet| packet.unwrap().decode::<SSyncVersion>())
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(answer.is_version_ok);
+
+    server.stop();
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use rblitz::{
+    config::FaultConfig,
+    game_server::ShutdownReason,
+    headless::{Channel, HeadlessClient},
+};
+use rblitz_packets::{
+    packets::game::{
+        answer::SQueryStatusAns,
+        client::{CPingLoadInfo, CSyncVersion},
+        request::CQueryStatusReq,
+        server::SStartGame,
+    },
+    PacketId,
+};
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::common::{spawn_server, TestServer};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// One past the channels the server knows.
+const CHANNEL_COUNT: u8 = 8;
+
+fn start(faults: FaultConfig) -> (TestServer, Vec<HeadlessClient>) {
+    let server = spawn_server(move |server| server.set_fault_policy(faults));
+    let players = [
+        (&b"GLzvuWtyCfHyGhF2"[..], 12),
+        (&b"GLzvuWtyCfHyGhF3"[..], 513),
+    ]
+    .iter()
+    .map(|&(key, player_id)| {
+        HeadlessClient::connect(server.address.into(), key, player_id, TIMEOUT).unwrap()
+    })
+    .collect();
+    (server, players)
+}
+
+/// Packets that are cut short, claim more than they hold or make no sense at all.
+fn garbage() -> Vec<Vec<u8>> {
+    let mut packets = vec![
+        vec![],
+        vec![0xFF],
+        // a batch whose first packet is larger than the batch
+        vec![0xFF, 2, 200, CQueryStatusReq::ID, 0, 0, 0, 0],
+        // a batch whose first packet is too small to hold its header
+        vec![0xFF, 2, 1, CQueryStatusReq::ID],
+        vec![CQueryStatusReq::ID, 0, 0],
+        vec![CSyncVersion::ID, 0, 0, 0, 0, 1, 2],
+        vec![CPingLoadInfo::ID, 0, 0, 0, 0, 7],
+    ];
+    // noise that doesn't start with the id of a packet the server handles
+    let mut state = 0x2545_F491_u32;
+    for len in &[3, 8, 13, 64] {
+        let noise = (0..*len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<u8>>();
+        packets.push([&[0xFE][..], &noise].concat());
+    }
+    packets
+}
+
+fn assert_still_answers(player: &mut HeadlessClient) {
+    player
+        .send(Channel::ClientToServer, 0, &CQueryStatusReq)
+        .unwrap();
+    assert!(player.wait_for::<SQueryStatusAns>(TIMEOUT).unwrap().is_ok);
+}
+
+#[test]
+fn garbage_on_every_channel_is_dropped() {
+    let (server, mut players) = start(FaultConfig {
+        warn_after: 0,
+        kick_after: 0,
+    });
+    for player in &mut players {
+        player.load("Version 4.20.0.315", TIMEOUT).unwrap();
+    }
+    for player in &mut players {
+        player.wait_for::<SStartGame>(TIMEOUT).unwrap();
+    }
+
+    for channel in 0..CHANNEL_COUNT {
+        for packet in garbage() {
+            players[0].send_bytes(channel, &packet).unwrap();
+        }
+    }
+    // channels are independent of each other, give the server time to get through all of them
+    thread::sleep(Duration::from_millis(500));
+    for player in &mut players {
+        assert_still_answers(player);
+    }
+
+    for player in players {
+        player.disconnect(TIMEOUT).unwrap();
+    }
+    let summary = server.thread.join().unwrap();
+    assert_eq!(summary.reason, ShutdownReason::AllDisconnected);
+}
+
+#[test]
+fn clients_sending_too_many_bad_packets_get_kicked() {
+    let (server, mut players) = start(FaultConfig {
+        warn_after: 1,
+        kick_after: 3,
+    });
+    let mut culprit = players.remove(0);
+    for _ in 0..3 {
+        culprit
+            .send_bytes(Channel::ClientToServer as u8, &[CQueryStatusReq::ID])
+            .unwrap();
+    }
+    // the client is still tolerated
+    assert_still_answers(&mut culprit);
+
+    culprit
+        .send_bytes(Channel::ClientToServer as u8, &[CQueryStatusReq::ID])
+        .unwrap();
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        assert!(Instant::now() < deadline, "client wasn't kicked");
+        if culprit.recv(TIMEOUT).is_err() {
+            break;
+        }
+    }
+    assert_still_answers(&mut players[0]);
+
+    // the kicked client is dropped right away, without waiting for it to acknowledge
+    let summary = server.stop();
+    let connected = summary
+        .players
+        .iter()
+        .map(|p| p.connected)
+        .collect::<Vec<_>>();
+    assert_eq!(connected, [false, true]);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use rblitz::headless::{Channel, HeadlessClient, PacketMode};
+use rblitz_packets::{
+    packets::game::{
+        common::CompressedWaypoint,
+        request::CNpcIssueOrderReq,
+        server::{SStartGame, SWaypointGroup},
+    },
+    PacketId, Vector2, Vector3,
+};
+
+use std::time::Duration;
+
+use crate::common::spawn_server;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const VERSION: &str = "Version 4.20.0.315";
+const MAP_CENTER: Vector2 = Vector2 {
+    x: 7000.0,
+    y: 7000.0,
+};
+
+#[test]
+fn paths_are_broadcast_unreliably_every_tick() {
+    let server = spawn_server(|_| ());
+    let mut players = [
+        (&b"GLzvuWtyCfHyGhF2"[..], 12),
+        (&b"GLzvuWtyCfHyGhF3"[..], 513),
+    ]
+    .iter()
+    .map(|&(key, player_id)| {
+        HeadlessClient::connect(server.address.into(), key, player_id, TIMEOUT).unwrap()
+    })
+    .collect::<Vec<_>>();
+    for player in &mut players {
+        player.load(VERSION, TIMEOUT).unwrap();
+    }
+    for player in &mut players {
+        let packet = player
+            .packets(TIMEOUT)
+            .map(Result::unwrap)
+            .find(|packet| packet.id == SStartGame::ID)
+            .unwrap();
+        assert_eq!(packet.mode, PacketMode::Reliable);
+    }
+
+    // a hundred units to the right of the spawn, a third of a second away
+    let order = CNpcIssueOrderReq {
+        order_type: CNpcIssueOrderReq::ORDER_MOVE_TO,
+        position: Vector3 {
+            x: 126.0,
+            y: 0.0,
+            z: 280.0,
+        },
+        ..Default::default()
+    };
+    players[0].send(Channel::ClientToServer, 0, &order).unwrap();
+    let target = CompressedWaypoint::from_world(Vector2 { x: 126.0, y: 280.0 }, MAP_CENTER);
+
+    for player in &mut players {
+        let mut groups = 0;
+        loop {
+            let packet = player
+                .packets(TIMEOUT)
+                .map(Result::unwrap)
+                .find(|packet| packet.id == SWaypointGroup::ID)
+                .expect("the unit never arrived");
+            assert_eq!(packet.channel, Channel::BroadcastUnreliable);
+            assert_eq!(packet.mode, PacketMode::Unreliable);
+            assert!(!packet.batched);
+            groups += 1;
+            let group = packet.decode::<SWaypointGroup>().unwrap().unwrap();
+            assert_eq!(group.movements.len(), 1);
+            let waypoints = &group.movements[0].waypoints;
+            assert_eq!(waypoints.last(), Some(&target));
+            if waypoints.len() == 1 {
+                break;
+            }
+        }
+        assert!(groups > 1, "the path was only sent once");
+    }
+
+    for player in players {
+        player.disconnect(TIMEOUT).unwrap();
+    }
+    server.stop();
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use rblitz::{
+    headless::HeadlessClient,
+    observer::{self, http::ObserverServer, Block, GameRecording, ObserverSettings, PLATFORM_ID},
+};
+use rblitz_packets::{
+    packets::game::server::{
+        SAvatarInfo, SCreateHero, SEndSpawn, SOnEnterVisibilityClient, SStartGame, SStartSpawn,
+    },
+    PacketId,
+};
+
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use crate::common::spawn_server;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const SETTINGS: ObserverSettings = ObserverSettings {
+    chunk_interval: 0.25,
+    keyframe_interval: 0.5,
+};
+
+fn get(address: SocketAddr, path: &str) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(response[..split].to_vec()).unwrap();
+    let status = head.lines().next().unwrap().split_once(' ').unwrap().1;
+    (status.to_owned(), response[split + 4..].to_vec())
+}
+
+fn get_json(address: SocketAddr, method: &str, param: u32) -> serde_json::Value {
+    let path = format!(
+        "/observer-mode/rest/consumer/{}/{}/12314/{}/token",
+        method, PLATFORM_ID, param
+    );
+    let (status, body) = get(address, &path);
+    assert_eq!(status, "200 OK");
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// The blocks of a chunk or keyframe as served.
+fn blocks(encryption_key: &str, data: &[u8]) -> Vec<Block> {
+    let data = observer::decode(12314, encryption_key, data).unwrap();
+    Block::read_all(&data).unwrap()
+}
+
+fn packet_ids(blocks: &[Block]) -> Vec<u8> {
+    blocks.iter().map(|block| block.packet_id).collect()
+}
+
+#[test]
+fn observers_can_follow_the_game() {
+    let directory = std::env::temp_dir().join(format!("rblitz-observer-{}", std::process::id()));
+    let (tx, rx) = mpsc::channel();
+    let server = {
+        let directory = directory.clone();
+        spawn_server(move |server| {
+            let recording = server.observe(SETTINGS, 0.0, Some(directory)).unwrap();
+            tx.send(recording).unwrap();
+        })
+    };
+    let observer = ObserverServer::bind((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+    observer.add_game(rx.recv().unwrap());
+    let address = observer.local_addr();
+
+    let (status, version) = get(address, "/observer-mode/rest/consumer/version");
+    assert_eq!(status, "200 OK");
+    assert!(!version.is_empty());
+    let (status, _) = get(
+        address,
+        "/observer-mode/rest/consumer/getGameMetaData/OTHER/12314/0/token",
+    );
+    assert_eq!(status, "404 Not Found");
+
+    let mut players = [
+        (&b"GLzvuWtyCfHyGhF2"[..], 12),
+        (&b"GLzvuWtyCfHyGhF3"[..], 513),
+    ]
+    .iter()
+    .map(|&(key, player_id)| {
+        HeadlessClient::connect(server.address.into(), key, player_id, TIMEOUT).unwrap()
+    })
+    .collect::<Vec<_>>();
+    for player in &mut players {
+        player.load("Version 4.20.0.315", TIMEOUT).unwrap();
+    }
+    for player in &mut players {
+        player.wait_for::<SStartGame>(TIMEOUT).unwrap();
+    }
+    thread::sleep(Duration::from_secs(1));
+
+    let metadata = get_json(address, "getGameMetaData", 0);
+    assert_eq!(metadata["gameKey"]["gameId"], 12314);
+    assert_eq!(metadata["gameEnded"], false);
+    assert_eq!(metadata["chunkTimeInterval"], 250);
+    let encryption_key = metadata["encryptionKey"].as_str().unwrap().to_owned();
+    assert!(!encryption_key.is_empty());
+    let start_game_chunk_id = metadata["startGameChunkId"].as_u64().unwrap() as u32;
+    assert!(start_game_chunk_id > 1);
+    let last_chunk = get_json(address, "getLastChunkInfo", 0);
+    let last_chunk_id = last_chunk["chunkId"].as_u64().unwrap() as u32;
+    let keyframe_id = last_chunk["keyFrameId"].as_u64().unwrap() as u32;
+    assert!(last_chunk_id > start_game_chunk_id);
+    assert!(keyframe_id >= 1);
+
+    let chunk = |id: u32| {
+        let path = format!(
+            "/observer-mode/rest/consumer/getGameDataChunk/{}/12314/{}/token",
+            PLATFORM_ID, id
+        );
+        let (status, body) = get(address, &path);
+        assert_eq!(status, "200 OK");
+        body
+    };
+    let chunks = (1..=last_chunk_id)
+        .map(|id| blocks(&encryption_key, &chunk(id)))
+        .collect::<Vec<_>>();
+    let started = chunks
+        .iter()
+        .position(|blocks| packet_ids(blocks).contains(&SStartGame::ID))
+        .unwrap() as u32
+        + 1;
+    assert_eq!(started, start_game_chunk_id);
+    let keyframe = |id: u32| {
+        let path = format!(
+            "/observer-mode/rest/consumer/getKeyFrame/{}/12314/{}/token",
+            PLATFORM_ID, id
+        );
+        let (status, body) = get(address, &path);
+        assert_eq!(status, "200 OK");
+        body
+    };
+    let spawn = [
+        SStartSpawn::ID,
+        SCreateHero::ID,
+        SAvatarInfo::ID,
+        SCreateHero::ID,
+        SAvatarInfo::ID,
+        SEndSpawn::ID,
+    ];
+    assert!(packet_ids(&chunks[0]).starts_with(&spawn));
+    let start = [
+        SStartGame::ID,
+        SOnEnterVisibilityClient::ID,
+        SOnEnterVisibilityClient::ID,
+    ];
+    assert!(packet_ids(&chunks[start_game_chunk_id as usize - 1])
+        .windows(start.len())
+        .any(|ids| ids == start));
+    // every keyframe holds all chunks up to it
+    let keyframes = metadata["pendingAvailableKeyFrameInfo"].as_array().unwrap();
+    assert!(!keyframes.is_empty());
+    for (idx, info) in keyframes.iter().enumerate() {
+        let next_chunk_id = info["nextChunkId"].as_u64().unwrap() as usize;
+        assert_eq!(
+            blocks(&encryption_key, &keyframe(idx as u32 + 1)),
+            chunks[..next_chunk_id - 1].concat()
+        );
+    }
+
+    for player in players {
+        player.disconnect(TIMEOUT).unwrap();
+    }
+    server.stop();
+
+    // the saved game can be served again
+    let saved = GameRecording::load(&directory).unwrap();
+    assert!(saved.ended);
+    assert_eq!(saved.encryption_key, encryption_key);
+    assert_eq!(
+        saved.chunk(start_game_chunk_id).unwrap(),
+        chunk(start_game_chunk_id)
+    );
+    assert_eq!(saved.keyframe(1).unwrap(), keyframe(1));
+    fs::remove_dir_all(&directory).unwrap();
+}
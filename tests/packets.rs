@@ -0,0 +1,261 @@
+//! Every packet encoded, decoded and encoded again, and the bytes of some packets pinned in
+//! `tests/fixtures/packets.txt`. Those are assembled by hand, the packets of an actual game are
+//! pinned by `tests/fixtures/movement.cap`, a capture of the server and two headless clients
+//! loading, starting and walking a hero. Neither has been checked against the client, so they
+//! catch unintended changes to the wire format, not layouts that disagree with the client.
+
+use proptest::{collection::vec, prelude::*, sample::select};
+use rblitz::capture::{self, CaptureReader, Channel};
+use rblitz_packets::{
+    from_bytes,
+    packets::{
+        registry::{self, Name, PacketVisitor},
+        PacketId,
+    },
+    to_bytes,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use std::{collections::HashSet, fmt};
+
+/// Packets that can't be decoded at all yet, the maps of SSetAnimStates still have to be figured
+/// out.
+const UNDECODABLE: &[&str] = &["SSetAnimStates"];
+
+/// Packets with a cast, which has to lead with its actual size so zeroes don't decode. Their
+/// fixtures do.
+const SIZED: &[&str] = &["SMissileReplication", "SNpcCastSpellAns"];
+
+fn name<P>() -> &'static str {
+    std::any::type_name::<P>().rsplit("::").next().unwrap()
+}
+
+/// Decodes its bytes as the packet visited, then checks that encoding the packet gives bytes
+/// that decode to a packet with the same encoding. Returns the first encoding, or `None` if the
+/// bytes weren't a packet to begin with.
+struct Roundtrip<'a>(&'a [u8]);
+
+impl PacketVisitor for Roundtrip<'_> {
+    type Output = Option<Vec<u8>>;
+
+    fn visit<P>(self) -> Option<Vec<u8>>
+    where
+        P: PacketId + Serialize + DeserializeOwned + fmt::Debug,
+    {
+        let packet: P = from_bytes(self.0).ok()?;
+        let encoded = to_bytes(&packet)
+            .unwrap_or_else(|e| panic!("{} failed to encode {:?}: {}", name::<P>(), packet, e));
+        let decoded: P = from_bytes(&encoded).unwrap_or_else(|e| {
+            panic!(
+                "{} failed to decode its own {:02X?}: {}",
+                name::<P>(),
+                encoded,
+                e
+            )
+        });
+        assert_eq!(
+            to_bytes(&decoded).unwrap(),
+            encoded,
+            "{} encoded {:?} differently after decoding it",
+            name::<P>(),
+            packet
+        );
+        Some(encoded)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Direction {
+    Client,
+    Server,
+    LoadingScreen,
+}
+
+fn visit<V: PacketVisitor>(direction: Direction, id: u8, visitor: V) -> Option<V::Output> {
+    match direction {
+        Direction::Client => registry::client_packet(id, visitor),
+        Direction::Server => registry::server_packet(id, visitor),
+        Direction::LoadingScreen => registry::loading_screen_packet(id, visitor),
+    }
+}
+
+fn ids(direction: Direction) -> Vec<u8> {
+    (0..=u8::MAX)
+        .filter(|&id| visit(direction, id, Name).is_some())
+        .collect()
+}
+
+/// Mostly zeroes and text so strings end and lengths stay short once in a while.
+fn payload() -> impl Strategy<Value = Vec<u8>> {
+    let byte = prop_oneof![3 => Just(0u8), 2 => 0x20u8..0x7F, 1 => Just(1u8), 4 => any::<u8>()];
+    vec(byte, 0..256)
+}
+
+#[test]
+fn every_packet_roundtrips() {
+    let zeroes = [0; 4096];
+    for &direction in &[
+        Direction::Client,
+        Direction::Server,
+        Direction::LoadingScreen,
+    ] {
+        for id in ids(direction) {
+            let name = visit(direction, id, Name).unwrap();
+            // packets ending in a list only decode if the bytes are a multiple of its elements
+            let encoded =
+                (0..8).find_map(|trim| visit(direction, id, Roundtrip(&zeroes[trim..])).unwrap());
+            assert_eq!(
+                encoded.is_some(),
+                !UNDECODABLE.contains(&name) && !SIZED.contains(&name),
+                "{} doesn't decode from zeroes",
+                name
+            );
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(1024))]
+
+    #[test]
+    fn client_packets_roundtrip(id in select(ids(Direction::Client)), data in payload()) {
+        visit(Direction::Client, id, Roundtrip(&data));
+    }
+
+    #[test]
+    fn server_packets_roundtrip(id in select(ids(Direction::Server)), data in payload()) {
+        visit(Direction::Server, id, Roundtrip(&data));
+    }
+
+    #[test]
+    fn loading_screen_packets_roundtrip(
+        id in select(ids(Direction::LoadingScreen)),
+        data in payload(),
+    ) {
+        visit(Direction::LoadingScreen, id, Roundtrip(&data));
+    }
+}
+
+/// Checks its packet decodes from `data` to `expected` and encodes back to `data`.
+struct Golden<'a> {
+    name: &'a str,
+    data: &'a [u8],
+    expected: &'a str,
+}
+
+impl PacketVisitor for Golden<'_> {
+    type Output = ();
+
+    fn visit<P>(self)
+    where
+        P: PacketId + Serialize + DeserializeOwned + fmt::Debug,
+    {
+        assert_eq!(name::<P>(), self.name, "the id of {} is off", self.name);
+        let packet: P = from_bytes(self.data)
+            .unwrap_or_else(|e| panic!("{} failed to decode: {}", self.name, e));
+        assert_eq!(format!("{:?}", packet), self.expected);
+        assert_eq!(
+            to_bytes(&packet).unwrap(),
+            self.data,
+            "{} encodes differently",
+            self.name
+        );
+    }
+}
+
+fn packet_id(direction: Direction, name: &str) -> u8 {
+    ids(direction)
+        .into_iter()
+        .find(|&id| visit(direction, id, Name) == Some(name))
+        .unwrap_or_else(|| panic!("no {:?} packet named {}", direction, name))
+}
+
+#[test]
+fn golden_packets() {
+    let fixtures = include_str!("fixtures/packets.txt");
+    let mut checked = 0;
+    for fixture in fixtures.split("\n\n").filter(|f| !f.starts_with('#')) {
+        let mut lines = fixture.lines();
+        let header = lines.next().unwrap();
+        let (direction, name) = match header.split(' ').collect::<Vec<_>>()[..] {
+            ["client", name] => (Direction::Client, name),
+            ["server", name] => (Direction::Server, name),
+            ["loading_screen", name] => (Direction::LoadingScreen, name),
+            _ => panic!("malformed fixture header `{}`", header),
+        };
+        let mut data = Vec::new();
+        let mut expected = None;
+        for line in lines {
+            if let Some(debug) = line.strip_prefix("= ") {
+                expected = Some(debug);
+            } else {
+                data.extend(
+                    line.split_whitespace()
+                        .map(|byte| u8::from_str_radix(byte, 16).unwrap()),
+                );
+            }
+        }
+        let golden = Golden {
+            name,
+            data: &data,
+            expected: expected.unwrap_or_else(|| panic!("{} is missing its value", name)),
+        };
+        visit(direction, packet_id(direction, name), golden);
+        checked += 1;
+    }
+    assert_eq!(checked, 29);
+}
+
+/// Checks its packet decodes from the captured bytes and encodes back to them.
+struct Captured<'a>(&'a [u8]);
+
+impl PacketVisitor for Captured<'_> {
+    type Output = &'static str;
+
+    fn visit<P>(self) -> &'static str
+    where
+        P: PacketId + Serialize + DeserializeOwned + fmt::Debug,
+    {
+        let packet: P = from_bytes(self.0)
+            .unwrap_or_else(|e| panic!("captured {} failed to decode: {}", name::<P>(), e));
+        assert_eq!(
+            to_bytes(&packet).unwrap(),
+            self.0,
+            "captured {} encodes differently",
+            name::<P>()
+        );
+        name::<P>()
+    }
+}
+
+#[test]
+fn captured_packets() {
+    let data = include_bytes!("fixtures/movement.cap");
+    let mut names = HashSet::new();
+    for record in CaptureReader::new(&data[..]).unwrap() {
+        let record = record.unwrap();
+        // loading screen packets only lead with their id
+        let (direction, header, packets) = match (record.channel, record.direction) {
+            (Channel::Handshake, _) | (Channel::Chat, _) => continue,
+            (Channel::LoadingScreen, _) => (Direction::LoadingScreen, 1, vec![record.data]),
+            (_, capture::Direction::Inbound) => (Direction::Client, 5, record.packets().unwrap()),
+            (_, capture::Direction::Outbound) => (Direction::Server, 5, record.packets().unwrap()),
+        };
+        for packet in packets {
+            let name =
+                visit(direction, packet[0], Captured(&packet[header..])).unwrap_or_else(|| {
+                    panic!("captured unknown {:?} packet {:#04X}", direction, packet[0])
+                });
+            names.insert(name);
+        }
+    }
+    for name in &[
+        "CSyncVersion",
+        "CPingLoadInfo",
+        "SStartGame",
+        "CNpcIssueOrderReq",
+        "SWaypointGroup",
+    ] {
+        assert!(names.contains(name), "{} wasn't captured", name);
+    }
+}
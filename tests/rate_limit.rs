@@ -0,0 +1,116 @@
+mod common;
+
+use rblitz::{
+    config::{RateLimit, RateLimitConfig},
+    headless::{Channel, HeadlessClient, ServerPacket},
+};
+use rblitz_packets::{
+    packets::game::{
+        answer::SQueryStatusAns,
+        client::{CMapPing, CPlayEmote},
+        request::CQueryStatusReq,
+        server::{SMapPing, SPlayEmote, SStartGame},
+    },
+    PacketId,
+};
+
+use std::time::Duration;
+
+use crate::common::spawn_server;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for packets that may not come.
+const QUIET: Duration = Duration::from_millis(300);
+
+/// Every `P` the player receives until the server stays quiet.
+fn received<P>(player: &mut HeadlessClient) -> Vec<P>
+where
+    P: PacketId + for<'de> serde::Deserialize<'de>,
+{
+    player
+        .packets(QUIET)
+        .map(Result::unwrap)
+        .filter(|packet: &ServerPacket| packet.channel != Channel::LoadingScreen)
+        .filter_map(|packet| packet.decode::<P>())
+        .map(Result::unwrap)
+        .collect()
+}
+
+#[test]
+fn packets_over_the_limit_are_dropped_or_throttled() {
+    let limits = RateLimitConfig(
+        vec![("CQueryStatusReq", 2), ("CMapPing", 1), ("CPlayEmote", 1)]
+            .into_iter()
+            .map(|(name, burst)| (name.to_owned(), RateLimit { rate: 0.0, burst }))
+            .collect(),
+    );
+    let server = spawn_server(move |server| server.set_rate_limits(&limits).unwrap());
+    let mut players = [
+        (&b"GLzvuWtyCfHyGhF2"[..], 12),
+        (&b"GLzvuWtyCfHyGhF3"[..], 513),
+    ]
+    .iter()
+    .map(|&(key, player_id)| {
+        HeadlessClient::connect(server.address.into(), key, player_id, TIMEOUT).unwrap()
+    })
+    .collect::<Vec<_>>();
+    for player in &mut players {
+        player.load("Version 4.20.0.315", TIMEOUT).unwrap();
+    }
+    for player in &mut players {
+        player.wait_for::<SStartGame>(TIMEOUT).unwrap();
+    }
+    for player in &mut players {
+        received::<SStartGame>(player);
+    }
+
+    for _ in 0..4 {
+        players[0]
+            .send(Channel::ClientToServer, 0, &CQueryStatusReq)
+            .unwrap();
+    }
+    assert_eq!(received::<SQueryStatusAns>(&mut players[0]).len(), 2);
+
+    for _ in 0..3 {
+        players[0]
+            .send(Channel::ClientToServer, 0, &CPlayEmote { emote_id: 2 })
+            .unwrap();
+    }
+    assert_eq!(received::<SPlayEmote>(&mut players[0]).len(), 1);
+    assert_eq!(received::<SPlayEmote>(&mut players[1]).len(), 1);
+
+    // pings over the limit only make it back to their sender, marked as throttled
+    for _ in 0..2 {
+        players[0]
+            .send(
+                Channel::ClientToServer,
+                0,
+                &CMapPing {
+                    ping_category: 3,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+    }
+    let pings = received::<SMapPing>(&mut players[0]);
+    let throttled = pings
+        .iter()
+        .map(|ping| ping.bitfield.ping_throttled)
+        .collect::<Vec<_>>();
+    assert_eq!(throttled, [false, true]);
+    assert!(pings.iter().all(|ping| ping.bitfield.ping_category == 3));
+    let ally_pings = received::<SMapPing>(&mut players[1]);
+    assert_eq!(ally_pings.len(), 1);
+    assert!(ally_pings[0].bitfield.play_audio);
+
+    // the limits are per client
+    players[1]
+        .send(Channel::ClientToServer, 0, &CQueryStatusReq)
+        .unwrap();
+    assert_eq!(received::<SQueryStatusAns>(&mut players[1]).len(), 1);
+
+    for player in players {
+        player.disconnect(TIMEOUT).unwrap();
+    }
+    server.stop();
+}
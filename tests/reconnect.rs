@@ -0,0 +1,144 @@
+mod common;
+
+use rblitz::headless::{Channel, HeadlessClient, ServerPacket};
+use rblitz_packets::{
+    packets::game::{
+        client::CReconnect,
+        common::{CompressedWaypoint, MovementData},
+        request::CNpcIssueOrderReq,
+        server::{
+            SConnected, SOnDisconnected, SOnEnterVisibilityClient, SReconnect, SReconnectDone,
+            SStartGame, SSyncSimTime, SWaypointGroup,
+        },
+    },
+    PacketId, Vector2, Vector3,
+};
+
+use std::{net::SocketAddr, time::Duration};
+
+use crate::common::spawn_server;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const VERSION: &str = "Version 4.20.0.315";
+
+fn connect(address: SocketAddr, key: &[u8], player_id: u64) -> HeadlessClient {
+    HeadlessClient::connect(address.into(), key, player_id, TIMEOUT).unwrap()
+}
+
+#[test]
+fn dropped_clients_are_resynced() {
+    let server = spawn_server(|_| ());
+    let mut first = connect(server.address, b"GLzvuWtyCfHyGhF2", 12);
+    let mut second = connect(server.address, b"GLzvuWtyCfHyGhF3", 513);
+    for client in [&mut first, &mut second].iter_mut() {
+        client.load(VERSION, TIMEOUT).unwrap();
+    }
+    for client in [&mut first, &mut second].iter_mut() {
+        client.wait_for::<SStartGame>(TIMEOUT).unwrap();
+    }
+    // far enough to still be walking once the resync is done
+    let target = Vector3 {
+        x: 5026.0,
+        y: 0.0,
+        z: 280.0,
+    };
+    let order = CNpcIssueOrderReq {
+        order_type: CNpcIssueOrderReq::ORDER_MOVE_TO,
+        position: target,
+        ..Default::default()
+    };
+    second.send(Channel::ClientToServer, 0, &order).unwrap();
+    second.wait_for::<SWaypointGroup>(TIMEOUT).unwrap();
+
+    let client_id = first.client_id();
+    first.disconnect(TIMEOUT).unwrap();
+    second.wait_for::<SOnDisconnected>(TIMEOUT).unwrap();
+
+    // a client that only lost its connection still has the world loaded
+    let mut first = connect(server.address, b"GLzvuWtyCfHyGhF2", 12);
+    assert_eq!(first.client_id(), client_id);
+    first
+        .send(
+            Channel::ClientToServer,
+            0,
+            &CReconnect {
+                is_full_reconnect: false,
+            },
+        )
+        .unwrap();
+    first.wait_for::<SReconnect>(TIMEOUT).unwrap();
+    let mut resync = Vec::new();
+    while resync.last().map(|packet: &ServerPacket| packet.id) != Some(SReconnectDone::ID) {
+        let packet = first
+            .recv(TIMEOUT)
+            .unwrap()
+            .expect("the resync ended early");
+        // the game number of the handshake comes first
+        if packet.channel == Channel::Broadcast
+            && (packet.id == SStartGame::ID || !resync.is_empty())
+        {
+            resync.push(packet);
+        }
+    }
+    assert_eq!(
+        resync.iter().map(|packet| packet.id).collect::<Vec<_>>(),
+        [
+            SStartGame::ID,
+            SOnEnterVisibilityClient::ID,
+            SOnEnterVisibilityClient::ID,
+            SSyncSimTime::ID,
+            SReconnectDone::ID,
+        ]
+    );
+    // the first hero still stands at the spawn, the second one is on its way
+    let visibility = |packet: &ServerPacket| {
+        let visibility = packet.decode::<SOnEnterVisibilityClient>().unwrap();
+        visibility.unwrap().movement_data
+    };
+    match visibility(&resync[1]) {
+        MovementData::Stop { data, .. } => {
+            assert_eq!(data.position, Vector2 { x: 26.0, y: 280.0 })
+        },
+        movement => panic!("the first hero should stand still, not {:?}", movement),
+    }
+    match visibility(&resync[2]) {
+        MovementData::Normal { data, .. } => {
+            let center = Vector2 {
+                x: 7000.0,
+                y: 7000.0,
+            };
+            let spawn = CompressedWaypoint::from_world(Vector2 { x: 26.0, y: 280.0 }, center);
+            let target = CompressedWaypoint::from_world(
+                Vector2 {
+                    x: 5026.0,
+                    y: 280.0,
+                },
+                center,
+            );
+            assert_eq!(data.waypoints.len(), 2);
+            assert_ne!(data.waypoints[0], spawn, "the hero was shown at its spawn");
+            assert_eq!(data.waypoints[1], target);
+        },
+        movement => panic!("the second hero should be walking, not {:?}", movement),
+    }
+    let connected = second.wait_for::<SConnected>(TIMEOUT).unwrap();
+    assert_eq!(connected.client_id, client_id);
+
+    first.disconnect(TIMEOUT).unwrap();
+    second.disconnect(TIMEOUT).unwrap();
+    server.thread.join().unwrap();
+}
+
+#[test]
+fn drops_during_loading_are_announced() {
+    let server = spawn_server(|_| ());
+    let first = connect(server.address, b"GLzvuWtyCfHyGhF2", 12);
+    let mut second = connect(server.address, b"GLzvuWtyCfHyGhF3", 513);
+    second.load(VERSION, TIMEOUT).unwrap();
+
+    first.disconnect(TIMEOUT).unwrap();
+    second.wait_for::<SOnDisconnected>(TIMEOUT).unwrap();
+
+    second.disconnect(TIMEOUT).unwrap();
+    server.thread.join().unwrap();
+}
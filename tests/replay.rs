@@ -0,0 +1,119 @@
+mod common;
+
+use rblitz::{
+    auth::{KeyEncoding, StaticAuth},
+    capture::{CaptureReader, Direction, Record},
+    config::{PlayerConfig, RateLimitConfig},
+    game_server::{GameServer, ShutdownReason},
+    headless::{Channel, HeadlessClient},
+};
+use rblitz_packets::{
+    packets::game::{
+        client::{CMapPing, CPlayEmote, CWorldSendCameraServer},
+        server::{SMapPing, SStartGame},
+    },
+    PacketId,
+};
+
+use std::{io, time::Duration};
+
+use crate::common::{spawn_server, SharedBuffer};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn read_capture(data: &[u8]) -> Vec<Record> {
+    CaptureReader::new(data)
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap()
+}
+
+/// Replays `input` and returns the capture of it.
+fn replay(input: &[u8]) -> Vec<u8> {
+    let players = PlayerConfig::from_path("config/players.ron").unwrap();
+    let mut auth = StaticAuth::from_config(KeyEncoding::Raw, &players, &[]).unwrap();
+    let mut server = GameServer::replay(input, players, &mut auth).unwrap();
+    server.set_rate_limits(&RateLimitConfig::default()).unwrap();
+    let capture = SharedBuffer::default();
+    server.capture_to(capture.clone()).unwrap();
+    let summary = server.run();
+    // the recorded game ended with everyone leaving, which has to happen again
+    assert_eq!(summary.reason, ShutdownReason::AllDisconnected);
+    capture.contents()
+}
+
+#[test]
+fn replays_play_out_like_the_recorded_game() {
+    let input = SharedBuffer::default();
+    let capture = SharedBuffer::default();
+    let server = {
+        let (input, capture) = (input.clone(), capture.clone());
+        spawn_server(move |server| {
+            server.record_input_to(input).unwrap();
+            server.capture_to(capture).unwrap();
+            server.set_rate_limits(&RateLimitConfig::default()).unwrap();
+        })
+    };
+    let mut clients = [
+        (&b"GLzvuWtyCfHyGhF2"[..], 12),
+        (&b"GLzvuWtyCfHyGhF3"[..], 513),
+    ]
+    .iter()
+    .map(|&(key, player_id)| {
+        HeadlessClient::connect(server.address.into(), key, player_id, TIMEOUT).unwrap()
+    })
+    .collect::<Vec<_>>();
+    for client in &mut clients {
+        client.load("Version 4.20.0.315", TIMEOUT).unwrap();
+    }
+    for client in &mut clients {
+        client.wait_for::<SStartGame>(TIMEOUT).unwrap();
+        let client_id = client.client_id();
+        client
+            .send(
+                Channel::ClientToServer,
+                0,
+                &CWorldSendCameraServer {
+                    client_id,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        client
+            .send(Channel::ClientToServer, 0, &CPlayEmote { emote_id: 2 })
+            .unwrap();
+        // more than the burst the pings are limited to, the limits have to run out the same way
+        for _ in 0..8 {
+            client
+                .send(Channel::ClientToServer, 0, &CMapPing::default())
+                .unwrap();
+        }
+        let throttled = client
+            .packets(TIMEOUT)
+            .map(Result::unwrap)
+            .filter(|packet| packet.id == SMapPing::ID)
+            .map(|packet| packet.decode::<SMapPing>().unwrap().unwrap())
+            .find(|ping| ping.bitfield.ping_throttled);
+        assert!(throttled.is_some(), "the pings never hit their limit");
+    }
+    for client in clients {
+        client.disconnect(TIMEOUT).unwrap();
+    }
+    let summary = server.thread.join().unwrap();
+    assert_eq!(summary.reason, ShutdownReason::AllDisconnected);
+
+    let input = input.contents();
+    let replayed = replay(&input);
+    assert_eq!(replayed, replay(&input), "replays aren't deterministic");
+
+    // the replay receives and sends exactly what the server did, at the same game time
+    let recorded = read_capture(&capture.contents());
+    for &direction in &[Direction::Inbound, Direction::Outbound] {
+        assert!(recorded.iter().any(|r| r.direction == direction));
+    }
+    let replayed = read_capture(&replayed);
+    for (i, (recorded, replayed)) in recorded.iter().zip(&replayed).enumerate() {
+        assert_eq!(replayed, recorded, "record {} differs", i);
+    }
+    assert_eq!(replayed.len(), recorded.len());
+}
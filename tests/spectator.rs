@@ -0,0 +1,162 @@
+mod common;
+
+use rblitz::{
+    auth::{KeyEncoding, StaticAuth},
+    config::SpectatorConfig,
+    game_server::GameServer,
+    headless::{Channel, HeadlessClient},
+};
+use rblitz_packets::{
+    packets::game::{
+        answer::SQueryStatusAns,
+        request::CQueryStatusReq,
+        server::{
+            SAvatarInfo, SCreateHero, SEndSpawn, SOnEnterVisibilityClient, SStartGame, SStartSpawn,
+        },
+    },
+    PacketId,
+};
+
+use std::time::{Duration, Instant};
+
+use crate::common::{spawn_server, TestServer};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const DELAY: f64 = 1.0;
+const KEY: &[u8] = b"GLzvuWtyCfHyGhF4";
+const PLAYER_ID: u64 = 1024;
+
+fn spectator_config() -> Vec<SpectatorConfig> {
+    vec![SpectatorConfig {
+        name: "Caster".to_owned(),
+        key: String::from_utf8(KEY.to_vec()).unwrap(),
+        player_id: PLAYER_ID,
+    }]
+}
+
+fn add_spectators(server: &mut GameServer) {
+    let spectators = spectator_config();
+    let mut auth = StaticAuth::from_config(KeyEncoding::Raw, &[], &spectators).unwrap();
+    server.add_spectators(spectators, DELAY, &mut auth).unwrap();
+}
+
+fn connect_players(server: &TestServer) -> Vec<HeadlessClient> {
+    [
+        (&b"GLzvuWtyCfHyGhF2"[..], 12),
+        (&b"GLzvuWtyCfHyGhF3"[..], 513),
+    ]
+    .iter()
+    .map(|&(key, player_id)| {
+        HeadlessClient::connect(server.address.into(), key, player_id, TIMEOUT).unwrap()
+    })
+    .collect()
+}
+
+/// Collects the ids of the spawn and startup packets the spectator receives until both heroes
+/// became visible.
+fn spawn_and_startup(spectator: &mut HeadlessClient) -> Vec<u8> {
+    const IDS: &[u8] = &[
+        SStartSpawn::ID,
+        SCreateHero::ID,
+        SAvatarInfo::ID,
+        SEndSpawn::ID,
+        SStartGame::ID,
+        SOnEnterVisibilityClient::ID,
+    ];
+    let mut ids = Vec::new();
+    while ids
+        .iter()
+        .filter(|&&id| id == SOnEnterVisibilityClient::ID)
+        .count()
+        < 2
+    {
+        let packet = spectator
+            .recv(TIMEOUT)
+            .unwrap()
+            .expect("the spectator missed the start");
+        if packet.channel == Channel::Broadcast && IDS.contains(&packet.id) {
+            ids.push(packet.id);
+        }
+    }
+    ids
+}
+
+const SPAWNED_AND_STARTED: &[u8] = &[
+    SStartSpawn::ID,
+    SCreateHero::ID,
+    SAvatarInfo::ID,
+    SCreateHero::ID,
+    SAvatarInfo::ID,
+    SEndSpawn::ID,
+    SStartGame::ID,
+    SOnEnterVisibilityClient::ID,
+    SOnEnterVisibilityClient::ID,
+];
+
+#[test]
+fn spectators_watch_with_a_delay() {
+    let server = spawn_server(add_spectators);
+    let mut spectator =
+        HeadlessClient::connect(server.address.into(), KEY, PLAYER_ID, TIMEOUT).unwrap();
+    let mut players = connect_players(&server);
+    assert!(players
+        .iter()
+        .all(|p| p.client_id() != spectator.client_id()));
+
+    // the game starts without waiting for the spectator
+    for player in &mut players {
+        player.load("Version 4.20.0.315", TIMEOUT).unwrap();
+    }
+    for player in &mut players {
+        player.wait_for::<SStartGame>(TIMEOUT).unwrap();
+    }
+    let started = Instant::now();
+    assert_eq!(spawn_and_startup(&mut spectator), SPAWNED_AND_STARTED);
+    assert!(started.elapsed() >= Duration::from_secs_f64(DELAY * 0.8));
+
+    for player in players {
+        player.disconnect(TIMEOUT).unwrap();
+    }
+    let summary = server.stop();
+    assert_eq!(summary.spectators.len(), 1);
+    assert!(summary.spectators[0].traffic.packets_out > 0);
+}
+
+#[test]
+fn late_spectators_catch_up_on_the_start() {
+    let server = spawn_server(add_spectators);
+    let mut players = connect_players(&server);
+    for player in &mut players {
+        player.load("Version 4.20.0.315", TIMEOUT).unwrap();
+    }
+    for player in &mut players {
+        player.wait_for::<SStartGame>(TIMEOUT).unwrap();
+    }
+    // long enough for the start to be released to nobody
+    std::thread::sleep(Duration::from_secs_f64(DELAY * 1.5));
+
+    let mut spectator =
+        HeadlessClient::connect(server.address.into(), KEY, PLAYER_ID, TIMEOUT).unwrap();
+    assert_eq!(spawn_and_startup(&mut spectator), SPAWNED_AND_STARTED);
+
+    for player in players {
+        player.disconnect(TIMEOUT).unwrap();
+    }
+    server.stop();
+}
+
+#[test]
+fn spectator_packets_are_ignored() {
+    let server = spawn_server(add_spectators);
+    let mut spectator =
+        HeadlessClient::connect(server.address.into(), KEY, PLAYER_ID, TIMEOUT).unwrap();
+    spectator
+        .send(Channel::ClientToServer, 0, &CQueryStatusReq)
+        .unwrap();
+    assert!(spectator
+        .packets(Duration::from_millis(500))
+        .all(|packet| packet.unwrap().id != SQueryStatusAns::ID));
+
+    let summary = server.stop();
+    assert_eq!(summary.spectators[0].traffic.packets_in, 1);
+}
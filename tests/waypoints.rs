@@ -0,0 +1,204 @@
+//! The compression of the paths units move along, the layouts of whole movement packets are
+//! pinned in `tests/fixtures/packets.txt` and the paths of a hero walking in
+//! `tests/fixtures/movement.cap`.
+
+use rblitz::capture::{CaptureReader, Direction};
+use rblitz_packets::{
+    from_bytes,
+    packets::game::{
+        common::{
+            CompressedWaypoint, MovementData, MovementDataNormal, MovementDataWithSpeed,
+            MAX_WAYPOINTS,
+        },
+        server::SWaypointGroup,
+    },
+    to_bytes, PacketId, Vector2,
+};
+
+fn path(waypoints: &[(i16, i16)]) -> MovementDataNormal {
+    MovementDataNormal {
+        teleport_net_id: 0x4000_0001,
+        waypoints: waypoints
+            .iter()
+            .map(|&(x, y)| CompressedWaypoint { x, y })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn waypoints(data: &MovementDataNormal) -> Vec<(i16, i16)> {
+    data.waypoints.iter().map(|w| (w.x, w.y)).collect()
+}
+
+#[test]
+fn waypoints_are_relative_where_they_fit() {
+    let data = path(&[(0, 0), (127, -128), (-1, 0), (-1, 300), (-32768, 32767)]);
+    let encoded = to_bytes(&data).unwrap();
+    #[rustfmt::skip]
+    assert_eq!(encoded, [
+        0x0A, 0x00, 0x01, 0x00, 0x00, 0x40,
+        // a bit per coordinate after the first waypoint, set where the coordinate is relative
+        0x17,
+        0x00, 0x00, 0x00, 0x00,
+        0x7F, 0x80,
+        0x80, 0x00, 0x00,
+        0x00, 0x2C, 0x01,
+        0x00, 0x80, 0xFF, 0x7F,
+    ]);
+    assert_eq!(waypoints(&from_bytes(&encoded).unwrap()), waypoints(&data));
+}
+
+#[test]
+fn relative_waypoints_wrap_like_the_client() {
+    // one waypoint at the edge of the grid and one step to the right of it
+    let data = [
+        0x04, 0x00, 0, 0, 0, 0, 0x03, 0xFF, 0x7F, 0x00, 0x00, 0x01, 0x00,
+    ];
+    let decoded: MovementDataNormal = from_bytes(&data).unwrap();
+    assert_eq!(waypoints(&decoded), [(32767, 0), (-32768, 0)]);
+}
+
+#[test]
+fn paths_without_waypoints_are_just_the_bitfield() {
+    let mut data = path(&[]);
+    data.has_teleport_id = true;
+    assert_eq!(to_bytes(&data).unwrap(), [0x01, 0x00]);
+    let decoded: MovementDataNormal = from_bytes(&[0x01, 0x00]).unwrap();
+    assert!(decoded.has_teleport_id);
+    assert!(decoded.waypoints.is_empty());
+}
+
+#[test]
+fn path_length_is_limited_and_checked() {
+    let longest = (0..MAX_WAYPOINTS as i16)
+        .map(|i| (i * 200, -i))
+        .collect::<Vec<_>>();
+    let encoded = to_bytes(&path(&longest)).unwrap();
+    assert_eq!(waypoints(&from_bytes(&encoded).unwrap()), longest);
+
+    let too_long = (0..=MAX_WAYPOINTS as i16)
+        .map(|i| (i, i))
+        .collect::<Vec<_>>();
+    assert!(to_bytes(&path(&too_long)).is_err());
+
+    // a path ending early is an error rather than a shorter path
+    let truncated = &encoded[..encoded.len() - 1];
+    assert!(from_bytes::<MovementDataNormal>(truncated).is_err());
+}
+
+#[test]
+fn speed_params_come_before_the_waypoints() {
+    let data = MovementDataWithSpeed {
+        teleport_net_id: 0x4000_0001,
+        has_teleport_id: true,
+        teleport_id: 3,
+        waypoints: vec![CompressedWaypoint { x: -5, y: 9 }],
+        ..Default::default()
+    };
+    let encoded = to_bytes(&data).unwrap();
+    let speed_params = to_bytes(&data.speed_params).unwrap();
+    assert_eq!(encoded[..7], [0x03, 0x00, 0x01, 0x00, 0x00, 0x40, 0x03]);
+    assert_eq!(encoded[7..7 + speed_params.len()], speed_params[..]);
+    assert_eq!(encoded[7 + speed_params.len()..], [0xFB, 0xFF, 0x09, 0x00]);
+
+    let decoded: MovementDataWithSpeed = from_bytes(&encoded).unwrap();
+    assert_eq!(decoded.teleport_id, 3);
+    assert_eq!(decoded.waypoints, data.waypoints);
+}
+
+#[test]
+fn movement_data_leads_with_its_kind_and_sync_id() {
+    let none = MovementData::None { sync_id: 9 };
+    assert_eq!(to_bytes(&none).unwrap(), [0x00, 0x09, 0x00, 0x00, 0x00]);
+
+    let normal = MovementData::Normal {
+        sync_id: 9,
+        data: path(&[(1, 2)]),
+    };
+    let encoded = to_bytes(&normal).unwrap();
+    assert_eq!(encoded[..5], [0x02, 0x09, 0x00, 0x00, 0x00]);
+    assert_eq!(encoded[5..], to_bytes(&path(&[(1, 2)])).unwrap()[..]);
+    let decoded: MovementData = from_bytes(&encoded).unwrap();
+    assert_eq!(decoded.sync_id(), 9);
+    match decoded {
+        MovementData::Normal { data, .. } => assert_eq!(waypoints(&data), [(1, 2)]),
+        other => panic!("decoded {:?}", other),
+    }
+}
+
+#[test]
+fn grid_coordinates_are_relative_to_the_middle_of_the_map() {
+    let center = Vector2 {
+        x: 7000.0,
+        y: 7000.0,
+    };
+    let waypoint = CompressedWaypoint::from_world(
+        Vector2 {
+            x: 7100.0,
+            y: 6899.2,
+        },
+        center,
+    );
+    assert_eq!(waypoint, CompressedWaypoint { x: 50, y: -50 });
+    assert_eq!(
+        waypoint.to_world(center),
+        Vector2 {
+            x: 7100.0,
+            y: 6900.0
+        }
+    );
+    assert_eq!(
+        CompressedWaypoint::default().to_world(center),
+        center,
+        "the origin is the middle of the map"
+    );
+}
+
+#[test]
+fn captured_paths_decode() {
+    let data = include_bytes!("fixtures/movement.cap");
+    let mut groups = Vec::new();
+    for record in CaptureReader::new(&data[..]).unwrap() {
+        let record = record.unwrap();
+        if record.direction == Direction::Outbound && record.client_id.0 == 0 {
+            for packet in record.packets().unwrap() {
+                if packet[0] == SWaypointGroup::ID {
+                    groups.push(packet[5..].to_vec());
+                }
+            }
+        }
+    }
+
+    // the hero standing at its spawn and the spot it was ordered to, in the first group sent
+    #[rustfmt::skip]
+    assert_eq!(groups[0], [
+        0x01, 0x00, 0x00, 0x00,
+        0x01, 0x00,
+        0x04, 0x00, 0x01, 0x00, 0x00, 0x40,
+        0x03,
+        0x66, 0xF2, 0xE0, 0xF2,
+        0x2D, 0x00,
+    ]);
+    let target = CompressedWaypoint { x: -3437, y: -3360 };
+    let groups = groups
+        .iter()
+        .map(|group| from_bytes::<SWaypointGroup>(group).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        waypoints(&groups[0].movements[0]),
+        [(-3482, -3360), (-3437, -3360)]
+    );
+
+    // the hero walking there, its path starting where it is every tick
+    let mut walked = -3482;
+    for (group, sync_id) in groups.iter().zip(1..) {
+        assert_eq!(group.sync_id, sync_id);
+        assert_eq!(group.movements.len(), 1);
+        let path = &group.movements[0];
+        assert_eq!(path.teleport_net_id, 0x4000_0001);
+        assert_eq!(path.waypoints.last(), Some(&target));
+        assert!(path.waypoints[0].x >= walked);
+        walked = path.waypoints[0].x;
+    }
+    assert_eq!(groups.last().unwrap().movements[0].waypoints, [target]);
+}